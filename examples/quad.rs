@@ -184,7 +184,8 @@ fn run(
             min: Vec2::new(30., -132.),
             max: Vec2::new(62., -100.),
         },
-        radius,
+        radii: radius.into(),
+        ..default()
     };
     ctx.fill(rounded_rect, &brush);
 
@@ -230,13 +231,13 @@ fn run(
     // 40.)); let brush = ctx.solid_brush(Color::srgb(0.7, 0.7, 0.7));
     // let rrect = RoundedRect {
     //     rect,
-    //     radius: 4.,
+    //     radii: CornerRadii::all(4.),
     // };
     // ctx.fill(rrect, &brush);
     // let brush = ctx.solid_brush(Color::srgb(0.6, 0.6, 0.6));
     // let rrect = RoundedRect {
     //     rect: rect.inset(0.5),
-    //     radius: 4.5,
+    //     radii: CornerRadii::all(4.5),
     // };
     // ctx.stroke(rrect, &brush, 1.);
 
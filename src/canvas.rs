@@ -38,7 +38,7 @@ use bevy::{
         system::{Commands, Query, ResMut},
     },
     log::trace,
-    math::{bounding::Aabb2d, Rect, UVec2, Vec2, Vec3},
+    math::{bounding::Aabb2d, Affine2, Mat2, Rect, Rot2, UVec2, Vec2, Vec3},
     prelude::*,
     render::{camera::Camera, texture::Image},
     sprite::TextureAtlasLayout,
@@ -49,7 +49,13 @@ use bytemuck::{Pod, Zeroable};
 
 use crate::{
     render::{ExtractedCanvas, ExtractedText, PreparedPrimitive},
-    render_context::{ImageScaling, RenderContext, TextLayout},
+    render_context::{
+        Gradient, ImagePattern, ImageRepeat, ImageScaling, RenderContext, TextLayout,
+    },
+    shapes::{
+        ArrowHead, CornerRadii, DashPattern, LineCap, LineJoin, Path, Polygon, StrokeAlignment,
+        MITER_LIMIT,
+    },
     ShapeRef,
 };
 
@@ -83,6 +89,24 @@ pub enum GpuPrimitiveKind {
     Line = 2,
     /// Quarter pie.
     QuarterPie = 3,
+    /// Axis-aligned ellipse, filled or stroked.
+    Ellipse = 4,
+    /// Arbitrary path made of line segments, filled or stroked.
+    Path = 5,
+    /// Arc, annulus sector, or pie, filled or stroked.
+    Arc = 6,
+    /// Connected line segments, with joins and caps.
+    Polyline = 7,
+    /// Polygon with a bounded number of vertices, filled or stroked.
+    Polygon = 8,
+    /// Star or regular polygon, filled or stroked.
+    Star = 9,
+    /// Capsule (pill), filled or stroked.
+    Capsule = 10,
+    /// Line segment with arrow heads.
+    Arrow = 11,
+    /// Marker opening or closing a layer group.
+    Layer = 12,
 }
 
 /// Drawing primitives.
@@ -101,7 +125,26 @@ pub enum Primitive {
     Rect(RectPrimitive),
     /// A text with a color.
     Text(TextPrimitive),
+    /// A quarter of an ellipse, with a color.
     QuarterPie(QuarterPiePrimitive),
+    /// An ellipse with a color, either filled or stroked.
+    Ellipse(EllipsePrimitive),
+    /// An arbitrary path with a color, either filled or stroked.
+    Path(PathPrimitive),
+    /// An arc, annulus sector, or pie with a color, either filled or stroked.
+    Arc(ArcPrimitive),
+    /// Connected line segments with a color and thickness, joins, and caps.
+    Polyline(PolylinePrimitive),
+    /// A polygon with a color, either filled or stroked.
+    Polygon(PolygonPrimitive),
+    /// A star or regular polygon with a color, either filled or stroked.
+    Star(StarPrimitive),
+    /// A capsule with a color, either filled or stroked.
+    Capsule(CapsulePrimitive),
+    /// An arrow between two points, with a color, thickness, and heads.
+    Arrow(ArrowPrimitive),
+    /// A marker opening or closing a group of primitives composited together.
+    Layer(LayerPrimitive),
 }
 
 impl Primitive {
@@ -112,30 +155,169 @@ impl Primitive {
             Primitive::Rect(_) => GpuPrimitiveKind::Rect,
             Primitive::Text(_) => GpuPrimitiveKind::Glyph,
             Primitive::QuarterPie(_) => GpuPrimitiveKind::QuarterPie,
+            Primitive::Ellipse(_) => GpuPrimitiveKind::Ellipse,
+            Primitive::Path(_) => GpuPrimitiveKind::Path,
+            Primitive::Arc(_) => GpuPrimitiveKind::Arc,
+            Primitive::Polyline(_) => GpuPrimitiveKind::Polyline,
+            Primitive::Polygon(_) => GpuPrimitiveKind::Polygon,
+            Primitive::Star(_) => GpuPrimitiveKind::Star,
+            Primitive::Capsule(_) => GpuPrimitiveKind::Capsule,
+            Primitive::Arrow(_) => GpuPrimitiveKind::Arrow,
+            Primitive::Layer(_) => GpuPrimitiveKind::Layer,
         }
     }
 
     /// Get the AABB of a primitive.
     ///
-    /// This is mainly used internally for tiling. There's no guarantee that the
-    /// AABB is tightly fitting; instead it only needs to be conservative and
-    /// enclose all the primitive.
+    /// There's no guarantee that the AABB is tightly fitting; instead it only
+    /// needs to be conservative and enclose all the primitive geometry. The
+    /// [`Effects`] of the primitive, stored in its canvas, are not included.
     pub fn aabb(&self) -> Aabb2d {
         match self {
             Primitive::Line(l) => l.aabb(),
             Primitive::Rect(r) => r.aabb(),
             Primitive::Text(_) => panic!("Cannot compute text AABB intrinsically."),
             Primitive::QuarterPie(q) => q.aabb(),
+            Primitive::Ellipse(e) => e.aabb(),
+            Primitive::Path(p) => p.aabb(),
+            Primitive::Arc(a) => a.aabb(),
+            Primitive::Polyline(p) => p.aabb(),
+            Primitive::Polygon(p) => p.aabb(),
+            Primitive::Star(s) => s.aabb(),
+            Primitive::Capsule(c) => c.aabb(),
+            Primitive::Arrow(a) => a.aabb(),
+            Primitive::Layer(l) => l.aabb(),
+        }
+    }
+
+    /// Get the AABB of a primitive used for tiling.
+    ///
+    /// This is the [`aabb()`] grown to enclose the [`Effects`] of the
+    /// primitive, and cut by its clip rectangle, if any. It may be empty.
+    ///
+    /// [`aabb()`]: Primitive::aabb
+    pub(crate) fn tile_aabb(&self, tables: &EffectTables) -> Aabb2d {
+        let effects = self.effects(tables);
+        effects.clip_aabb(effects.grow_aabb(self.aabb()))
+    }
+
+    /// Get the region inside the AABB of a primitive where it never draws
+    /// anything, if any.
+    ///
+    /// Tiles entirely inside that hole are not assigned the primitive, so
+    /// stroking a large rectangle doesn't cost anything to the tiles inside it.
+    pub(crate) fn tile_hole(&self, tables: &EffectTables) -> Option<Aabb2d> {
+        match self {
+            Primitive::Rect(r) => r.tile_hole(tables.effects(r.effects)),
+            _ => None,
+        }
+    }
+
+    /// Get the ID of the effects applied to the primitive, if any.
+    pub(crate) fn effects_id(&self) -> Option<u32> {
+        match self {
+            Primitive::Line(l) => l.effects,
+            Primitive::Rect(r) => r.effects,
+            Primitive::Text(t) => t.effects,
+            Primitive::QuarterPie(q) => q.effects,
+            Primitive::Ellipse(e) => e.effects,
+            Primitive::Path(p) => p.effects,
+            Primitive::Arc(a) => a.effects,
+            Primitive::Polyline(p) => p.effects,
+            Primitive::Polygon(p) => p.effects,
+            Primitive::Star(s) => s.effects,
+            Primitive::Capsule(c) => c.effects,
+            Primitive::Arrow(a) => a.effects,
+            Primitive::Layer(l) => l.effects,
+        }
+    }
+
+    /// Get the effects applied to the primitive, from the side tables of its
+    /// canvas.
+    pub(crate) fn effects<'a>(&self, tables: &'a EffectTables) -> &'a Effects {
+        tables.effects(self.effects_id())
+    }
+
+    /// Get the effects applied to the primitive for modification, storing new
+    /// empty effects into the side tables of its canvas if it has none yet.
+    pub(crate) fn effects_mut<'a>(&mut self, tables: &'a mut EffectTables) -> &'a mut Effects {
+        tables.effects_mut(self.effects_id_mut())
+    }
+
+    /// Get the ID of the effects applied to the primitive for modification.
+    fn effects_id_mut(&mut self) -> &mut Option<u32> {
+        match self {
+            Primitive::Line(l) => &mut l.effects,
+            Primitive::Rect(r) => &mut r.effects,
+            Primitive::Text(t) => &mut t.effects,
+            Primitive::QuarterPie(q) => &mut q.effects,
+            Primitive::Ellipse(e) => &mut e.effects,
+            Primitive::Path(p) => &mut p.effects,
+            Primitive::Arc(a) => &mut a.effects,
+            Primitive::Polyline(p) => &mut p.effects,
+            Primitive::Polygon(p) => &mut p.effects,
+            Primitive::Star(s) => &mut s.effects,
+            Primitive::Capsule(c) => &mut c.effects,
+            Primitive::Arrow(a) => &mut a.effects,
+            Primitive::Layer(l) => &mut l.effects,
         }
     }
 
+    /// Does the primitive have any effect?
+    pub(crate) fn has_effects(&self, tables: &EffectTables) -> bool {
+        !self.effects(tables).is_empty()
+    }
+
     /// Is the primitive textured?
-    pub fn is_textured(&self) -> bool {
+    ///
+    /// This is the case of rectangles with an image, and of any primitive
+    /// filled with an [`ImagePattern`], except text.
+    pub(crate) fn is_textured(&self, tables: &EffectTables) -> bool {
+        match self {
+            Primitive::Text(_) => false,
+            Primitive::Rect(RectPrimitive { image: Some(_), .. }) => true,
+            _ => self.effects(tables).pattern.is_some(),
+        }
+    }
+
+    /// Get the image the primitive is textured with, if any.
+    ///
+    /// For rectangles with both an image and an [`ImagePattern`], the image
+    /// takes precedence.
+    pub(crate) fn image(&self, tables: &EffectTables) -> Option<AssetId<Image>> {
+        match self {
+            Primitive::Text(_) => None, // not in the sense of regular texture mapping
+            Primitive::Rect(RectPrimitive {
+                image: Some(id), ..
+            }) => Some(*id),
+            _ => self
+                .effects(tables)
+                .pattern
+                .map(|id| tables.patterns[id as usize].image()),
+        }
+    }
+
+    /// Get the transform from the shader's pixel coordinates to the UV
+    /// coordinates of the primitive's image, and its repeat mode.
+    fn uv_transform(
+        &self,
+        tables: &EffectTables,
+        canvas_translation: Vec2,
+        scale_factor: f32,
+    ) -> Option<(Affine2, ImageRepeat)> {
         match self {
-            Primitive::Line(_) => false,
-            Primitive::Rect(r) => r.is_textured(),
-            Primitive::Text(_) => false, // not in the sense of regular texture mapping
-            Primitive::QuarterPie(_) => false,
+            Primitive::Text(_) => None,
+            Primitive::Rect(r) if r.is_textured() => Some((
+                r.uv_transform(canvas_translation, scale_factor),
+                ImageRepeat::Clamp,
+            )),
+            _ => self.effects(tables).pattern.map(|id| {
+                let pattern = &tables.patterns[id as usize];
+                (
+                    pattern.uv_transform(canvas_translation, scale_factor),
+                    pattern.repeat(),
+                )
+            }),
         }
     }
 
@@ -144,39 +326,151 @@ impl Primitive {
         match self {
             Primitive::Line(l) => l.is_bordered(),
             Primitive::Rect(r) => r.is_bordered(),
-            Primitive::Text(_) => false,
-            Primitive::QuarterPie(_) => false,
+            Primitive::Text(t) => t.is_bordered(),
+            Primitive::QuarterPie(q) => q.is_bordered(),
+            Primitive::Ellipse(e) => e.is_bordered(),
+            Primitive::Path(p) => p.is_bordered(),
+            Primitive::Arc(a) => a.is_bordered(),
+            Primitive::Polyline(p) => p.is_bordered(),
+            Primitive::Polygon(p) => p.is_bordered(),
+            Primitive::Star(s) => s.is_bordered(),
+            Primitive::Capsule(c) => c.is_bordered(),
+            Primitive::Arrow(a) => a.is_bordered(),
+            Primitive::Layer(_) => false,
+        }
+    }
+
+    /// Apply a transform to the primitive, including its border, but not its
+    /// [`Effects`] which are stored in the [`EffectTables`] of the canvas.
+    ///
+    /// Points are transformed exactly, so the geometry of paths, polylines, and
+    /// polygons follows any affine transform, as do the sides of lines. Other
+    /// primitives keep their shape and are only exact for similarity transforms
+    /// (translation, rotation, and uniform scale): their other lengths, like
+    /// corner radii, stroke and border widths, glow spread, and shadow blur,
+    /// are scaled by the mean scale of the transform. Text is rotated and
+    /// scaled by resampling its glyphs, which are rasterized at their font
+    /// size.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2, paths: &mut [Path]) {
+        match self {
+            Primitive::Line(l) => l.apply_transform(transform),
+            Primitive::Rect(r) => r.apply_transform(transform),
+            Primitive::Text(t) => t.apply_transform(transform),
+            Primitive::QuarterPie(q) => q.apply_transform(transform),
+            Primitive::Ellipse(e) => e.apply_transform(transform),
+            Primitive::Path(p) => p.apply_transform(transform, paths),
+            Primitive::Arc(a) => a.apply_transform(transform),
+            Primitive::Polyline(p) => p.apply_transform(transform, paths),
+            Primitive::Polygon(p) => p.apply_transform(transform),
+            Primitive::Star(s) => s.apply_transform(transform),
+            Primitive::Capsule(c) => c.apply_transform(transform),
+            Primitive::Arrow(a) => a.apply_transform(transform),
+            // Layer bounds are computed in canvas space from the already transformed
+            // primitives of the group
+            Primitive::Layer(_) => {}
         }
     }
 
     /// Internal primitive info for drawing a primitive.
-    pub(crate) fn info(&self, texts: &[ExtractedText]) -> PrimitiveInfo {
-        match &self {
+    ///
+    /// The row count includes the rows of the texture, if any, and of the
+    /// [`Effects`], which trail each sub-primitive in that order.
+    pub(crate) fn info(
+        &self,
+        texts: &[ExtractedText],
+        paths: &[FlattenedPath],
+        tables: &EffectTables,
+    ) -> PrimitiveInfo {
+        let mut info = match &self {
             Primitive::Line(l) => l.info(),
             Primitive::Rect(r) => r.info(),
             Primitive::Text(t) => t.info(texts),
             Primitive::QuarterPie(q) => q.info(),
+            Primitive::Ellipse(e) => e.info(),
+            Primitive::Path(p) => p.info(paths),
+            Primitive::Arc(a) => a.info(),
+            Primitive::Polyline(p) => p.info(paths),
+            Primitive::Polygon(p) => p.info(),
+            Primitive::Star(s) => s.info(),
+            Primitive::Capsule(c) => c.info(),
+            Primitive::Arrow(a) => a.info(),
+            Primitive::Layer(l) => l.info(),
+        };
+        if info.row_count > 0 {
+            if self.is_textured(tables) {
+                info.row_count += ROW_COUNT_TEX;
+            }
+            info.row_count += self.effects(tables).row_count(tables);
         }
+        info
     }
 
     /// Serialize a primitive and write its binary blob into the given buffer,
     /// ready to be consumed by the GPU shader.
     ///
     /// Anything written here must be kept in sync format-wise with what is read
-    /// back in the shader.
+    /// back in the shader. The `mask_indices` are the packed indices of the mask
+    /// primitives of the canvas, indexed by mask ID.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn write(
         &self,
         texts: &[ExtractedText],
+        paths: &[FlattenedPath],
+        tables: &EffectTables,
+        mask_indices: &[u32],
         prim: &mut [MaybeUninit<f32>],
         canvas_translation: Vec2,
         scale_factor: f32,
     ) {
+        // Text writes the effects after each glyph itself; all other primitives have a
+        // single sub-primitive, so the texture and effects rows are simply the last ones.
+        if let Primitive::Text(t) = &self {
+            t.write(
+                texts,
+                tables,
+                mask_indices,
+                prim,
+                canvas_translation,
+                scale_factor,
+            );
+            return;
+        }
+        let effects = self.effects(tables);
+        let split = prim.len() - effects.row_count(tables) as usize;
+        let (prim, effects_prim) = prim.split_at_mut(split);
+        let uv_transform = self.uv_transform(tables, canvas_translation, scale_factor);
+        let split = prim.len()
+            - if uv_transform.is_some() {
+                ROW_COUNT_TEX as usize
+            } else {
+                0
+            };
+        let (prim, tex_prim) = prim.split_at_mut(split);
+        if let Some((uv_transform, repeat)) = uv_transform {
+            write_texture(tex_prim, uv_transform, repeat);
+        }
         match &self {
             Primitive::Line(l) => l.write(prim, canvas_translation, scale_factor),
             Primitive::Rect(r) => r.write(prim, canvas_translation, scale_factor),
-            Primitive::Text(t) => t.write(texts, prim, canvas_translation, scale_factor),
+            Primitive::Text(_) => unreachable!(),
             Primitive::QuarterPie(q) => q.write(prim, canvas_translation, scale_factor),
+            Primitive::Ellipse(e) => e.write(prim, canvas_translation, scale_factor),
+            Primitive::Path(p) => p.write(paths, prim, canvas_translation, scale_factor),
+            Primitive::Arc(a) => a.write(prim, canvas_translation, scale_factor),
+            Primitive::Polyline(p) => p.write(paths, prim, canvas_translation, scale_factor),
+            Primitive::Polygon(p) => p.write(prim, canvas_translation, scale_factor),
+            Primitive::Star(s) => s.write(prim, canvas_translation, scale_factor),
+            Primitive::Capsule(c) => c.write(prim, canvas_translation, scale_factor),
+            Primitive::Arrow(a) => a.write(prim, canvas_translation, scale_factor),
+            Primitive::Layer(l) => l.write(prim),
         };
+        effects.write(
+            effects_prim,
+            tables,
+            mask_indices,
+            canvas_translation,
+            scale_factor,
+        );
     }
 }
 
@@ -204,930 +498,4937 @@ impl From<QuarterPiePrimitive> for Primitive {
     }
 }
 
-/// A line between two points, with a color and thickness.
-///
-/// This is essentially an oriented rectangle.
-#[derive(Debug, Default, Clone, Copy)]
-pub struct LinePrimitive {
-    /// The starting point of the line.
-    pub start: Vec2,
-    /// The ending point of the line.
-    pub end: Vec2,
-    /// The line color.
-    pub color: Color,
-    /// The line thickness. Must be greater than zero.
-    ///
-    /// The line shape extends equally by `thickness / 2.` on both sides of the
-    /// mathematical (infinitely thin) line joining the start and end points.
-    pub thickness: f32,
-    /// Size of the border, if any, or zero if no border. The borders always
-    /// expand inside the line. Negative values or zero mean no border.
-    pub border_width: f32,
-    /// Border color, if any (ignored if `border_width <= 0.`).
-    pub border_color: Color,
+impl From<EllipsePrimitive> for Primitive {
+    fn from(ellipse: EllipsePrimitive) -> Self {
+        Self::Ellipse(ellipse)
+    }
 }
 
-impl LinePrimitive {
-    /// The AABB of the line primitive.
-    pub fn aabb(&self) -> Aabb2d {
-        let dir = (self.end - self.start).normalize();
-        let tg = Vec2::new(-dir.y, dir.x);
-        let e = self.thickness / 2.;
-        let p0 = self.start + tg * e;
-        let p1 = self.start - tg * e;
-        let p2 = self.end + tg * e;
-        let p3 = self.end - tg * e;
-        let min = p0.min(p1).min(p2).min(p3);
-        let max = p0.max(p1).max(p2).max(p3);
-        Aabb2d { min, max }
+impl From<PathPrimitive> for Primitive {
+    fn from(path: PathPrimitive) -> Self {
+        Self::Path(path)
     }
+}
 
-    /// Is the primitive bordered?
-    pub fn is_bordered(&self) -> bool {
-        self.border_width > 0.
+impl From<ArcPrimitive> for Primitive {
+    fn from(arc: ArcPrimitive) -> Self {
+        Self::Arc(arc)
     }
+}
 
-    fn info(&self) -> PrimitiveInfo {
-        PrimitiveInfo {
-            row_count: 6 + if self.is_bordered() { 2 } else { 0 },
-            sub_prim_count: 1,
-        }
+impl From<PolylinePrimitive> for Primitive {
+    fn from(polyline: PolylinePrimitive) -> Self {
+        Self::Polyline(polyline)
     }
+}
 
-    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
-        prim[0].write((self.start.x + canvas_translation.x) * scale_factor);
-        prim[1].write((self.start.y + canvas_translation.y) * scale_factor);
-        prim[2].write((self.end.x + canvas_translation.x) * scale_factor);
-        prim[3].write((self.end.y + canvas_translation.y) * scale_factor);
-        prim[4].write(bytemuck::cast(self.color.to_linear().as_u32()));
-        prim[5].write(self.thickness * scale_factor);
-        if self.is_bordered() {
-            assert_eq!(8, prim.len());
-            prim[6].write(self.border_width * scale_factor);
-            prim[7].write(bytemuck::cast(self.border_color.to_linear().as_u32()));
-        } else {
-            assert_eq!(6, prim.len());
-        }
+impl From<PolygonPrimitive> for Primitive {
+    fn from(polygon: PolygonPrimitive) -> Self {
+        Self::Polygon(polygon)
     }
 }
 
-/// An axis-aligned rectangle with a color, optional rounded corners, and
-/// optional texture.
-#[derive(Debug, Default, Clone, Copy)]
-pub struct RectPrimitive {
-    /// Position and size of the rectangle in its canvas space.
-    ///
-    /// For rounded rectangles, this is the AABB (the radius and borders are
-    /// included).
-    pub rect: Rect,
-    /// Rounded corners radius. Set to zero to disable rounded corners.
-    pub radius: f32,
-    /// Uniform rectangle color.
-    pub color: Color,
-    /// Optional handle to the image used for texturing the rectangle.
-    pub image: Option<AssetId<Image>>,
-    /// Image size, populated from actual texture size and scaling.
-    pub image_size: Vec2,
-    /// Scaling for the image (if any).
-    pub image_scaling: ImageScaling,
-    /// Flip the image (if any) along the horizontal axis.
-    pub flip_x: bool,
-    /// Flip the image (if any) along the vertical axis.
-    pub flip_y: bool,
-    /// Size of the border, if any, or zero if no border. The borders always
-    /// expand inside the rectangle. Negative values or zero mean no border.
-    pub border_width: f32,
-    /// Border color, if any (ignored if `border_width <= 0.`).
-    pub border_color: Color,
+impl From<StarPrimitive> for Primitive {
+    fn from(star: StarPrimitive) -> Self {
+        Self::Star(star)
+    }
 }
 
-impl RectPrimitive {
-    /// Number of primitive buffer rows (4 bytes) per primitive.
-    const ROW_COUNT_BASE: u32 = 6;
-    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
-    /// when textured. Those extra rows follow the base ones.
-    const ROW_COUNT_TEX: u32 = 4;
-    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
-    /// when bordered. Those extra rows follow the texture ones, or the base
-    /// ones if there's no texture.
-    const ROW_COUNT_BORDER: u32 = 2;
+impl From<CapsulePrimitive> for Primitive {
+    fn from(capsule: CapsulePrimitive) -> Self {
+        Self::Capsule(capsule)
+    }
+}
 
-    /// Get the AABB of this rectangle.
-    pub fn aabb(&self) -> Aabb2d {
-        Aabb2d {
-            min: self.rect.min,
-            max: self.rect.max,
-        }
+impl From<ArrowPrimitive> for Primitive {
+    fn from(arrow: ArrowPrimitive) -> Self {
+        Self::Arrow(arrow)
     }
+}
 
-    /// Is this primitive textured?
-    ///
-    /// True if [`RectPrimitive::image`] is `Some`.
-    pub const fn is_textured(&self) -> bool {
-        self.image.is_some()
+impl From<LayerPrimitive> for Primitive {
+    fn from(layer: LayerPrimitive) -> Self {
+        Self::Layer(layer)
     }
+}
 
-    /// Is the primitive bordered?
-    pub fn is_bordered(&self) -> bool {
-        self.border_width > 0.
+/// A glow around a primitive.
+///
+/// The glow is a halo of the given color drawn underneath the primitive, which
+/// fades out smoothly as the distance to the primitive's edge grows, until it
+/// vanishes at [`spread`].
+///
+/// [`spread`]: Glow::spread
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Glow {
+    /// Color of the glow, at the edge of the primitive.
+    pub color: Color,
+    /// Distance from the edge of the primitive where the glow vanishes.
+    pub spread: f32,
+}
+
+/// A drop shadow underneath a primitive.
+///
+/// The shadow is a copy of the primitive shape, offset and blurred, drawn with
+/// a single color underneath the primitive.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Shadow {
+    /// Offset of the shadow relative to the primitive. The canvas Y axis
+    /// points down the screen, so a positive Y offset moves the shadow down.
+    pub offset: Vec2,
+    /// Blur radius. The edge of the shadow fades out over that distance on
+    /// each side of the (offset) edge of the primitive. Zero gives a sharp,
+    /// anti-aliased edge.
+    pub blur_radius: f32,
+    /// Color of the shadow.
+    pub color: Color,
+}
+
+impl Shadow {
+    fn write(&self, prim: &mut [MaybeUninit<f32>], scale_factor: f32) {
+        prim[0].write(self.offset.x * scale_factor);
+        prim[1].write(self.offset.y * scale_factor);
+        prim[2].write(self.blur_radius.max(0.) * scale_factor);
+        prim[3].write(bytemuck::cast(self.color.to_linear().as_u32()));
     }
+}
 
-    #[inline]
-    fn row_count(&self) -> u32 {
-        let mut rows = Self::ROW_COUNT_BASE;
-        if self.is_textured() {
-            rows += Self::ROW_COUNT_TEX;
-        }
-        if self.is_bordered() {
-            rows += Self::ROW_COUNT_BORDER;
+/// Dashes along a stroke primitive.
+///
+/// The [`DashPattern`] is evaluated from the arc length along the stroke,
+/// measured from [`origin`] either along a straight line, or around a circle
+/// if [`radius`] is not zero. Strokes made of several primitives continue the
+/// pattern from one primitive to the next by offsetting [`start`].
+///
+/// [`origin`]: Dash::origin
+/// [`radius`]: Dash::radius
+/// [`start`]: Dash::start
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Dash {
+    /// Dash pattern along the stroke.
+    pub pattern: DashPattern,
+    /// Arc length at the stroke point closest to [`origin`].
+    ///
+    /// [`origin`]: Dash::origin
+    pub start: f32,
+    /// For straight strokes, the point where the arc length is [`start`]. For
+    /// circular strokes, the center of the circle.
+    ///
+    /// [`start`]: Dash::start
+    pub origin: Vec2,
+    /// For straight strokes, the unit direction of the stroke. For circular
+    /// strokes, the unit direction from the center toward the point where the
+    /// arc length is [`start`].
+    ///
+    /// [`start`]: Dash::start
+    pub direction: Vec2,
+    /// Zero for straight strokes. For circular strokes, the radius of the
+    /// middle of the stroke, positive if the arc length increases from the X
+    /// axis toward the Y axis, and negative otherwise.
+    pub radius: f32,
+}
+
+impl Dash {
+    /// Number of primitive buffer rows (4 bytes) for the dash header, before
+    /// the lengths of the pattern.
+    const ROW_COUNT_HEADER: u32 = 8;
+
+    /// Create dashes along a straight stroke starting at `origin` in the given
+    /// `direction`.
+    pub fn straight(pattern: DashPattern, start: f32, origin: Vec2, direction: Vec2) -> Self {
+        Self {
+            pattern,
+            start,
+            origin,
+            direction: direction.normalize_or_zero(),
+            radius: 0.,
         }
-        rows
     }
 
-    fn info(&self) -> PrimitiveInfo {
-        PrimitiveInfo {
-            row_count: self.row_count(),
-            sub_prim_count: 1,
+    /// Create dashes along a circular stroke around `center`, where the arc
+    /// length is `start` in the given `direction` from the center. The sign of
+    /// `radius` gives the direction of increasing arc length, see
+    /// [`Dash::radius`].
+    pub fn circular(
+        pattern: DashPattern,
+        start: f32,
+        center: Vec2,
+        direction: Vec2,
+        radius: f32,
+    ) -> Self {
+        Self {
+            pattern,
+            start,
+            origin: center,
+            direction: direction.normalize_or_zero(),
+            radius,
         }
     }
 
-    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
-        assert_eq!(
-            self.row_count() as usize,
-            prim.len(),
-            "Invalid buffer size {} to write RectPrimitive (needs {})",
-            prim.len(),
-            self.row_count()
-        );
+    /// Transform the dashes along with the stroke they cut.
+    fn apply_transform(&mut self, transform: &Affine2) {
+        let scale = transform_scale(transform);
+        self.pattern = self.pattern.scaled(scale);
+        self.start *= scale;
+        self.origin = transform.transform_point2(self.origin);
+        self.direction = transform
+            .transform_vector2(self.direction)
+            .normalize_or_zero();
+        // A mirroring transform reverses the direction of circular strokes
+        self.radius *= scale * transform.matrix2.determinant().signum();
+    }
 
-        let half_min = self.rect.min * (0.5 * scale_factor);
-        let half_max = self.rect.max * (0.5 * scale_factor);
-        let center = half_min + half_max + canvas_translation * scale_factor;
-        let half_size = half_max - half_min;
-        prim[0].write(center.x);
-        prim[1].write(center.y);
-        prim[2].write(half_size.x);
-        prim[3].write(half_size.y);
-        prim[4].write(self.radius * scale_factor);
-        prim[5].write(bytemuck::cast(self.color.to_linear().as_u32()));
-        let mut idx = 6;
-        if self.is_textured() {
-            prim[idx + 0].write(0.5);
-            prim[idx + 1].write(0.5);
-            prim[idx + 2].write(1. / self.image_size.x);
-            prim[idx + 3].write(1. / self.image_size.y);
-            idx += 4;
-        }
-        if self.is_bordered() {
-            prim[idx + 0].write(self.border_width * scale_factor);
-            prim[idx + 1].write(bytemuck::cast(self.border_color.to_linear().as_u32()));
+    /// Number of primitive buffer rows (4 bytes) needed to store the dash.
+    fn row_count(&self) -> u32 {
+        Self::ROW_COUNT_HEADER + self.pattern.lengths().len() as u32
+    }
+
+    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
+        let origin = (self.origin + canvas_translation) * scale_factor;
+        let lengths = self.pattern.lengths();
+        prim[0].write(self.start * scale_factor);
+        prim[1].write(origin.x);
+        prim[2].write(origin.y);
+        prim[3].write(self.direction.x);
+        prim[4].write(self.direction.y);
+        prim[5].write(self.radius * scale_factor);
+        prim[6].write(self.pattern.phase() * scale_factor);
+        prim[7].write(bytemuck::cast(lengths.len() as u32));
+        for (i, length) in lengths.iter().enumerate() {
+            prim[Self::ROW_COUNT_HEADER as usize + i].write(length * scale_factor);
         }
     }
 }
 
-/// A reference to a text with a color.
-///
-/// The text primitive is not stored directly inside this struct. Instead, the
-/// struct stores an [`id`] field indexing the text into its [`Canvas`]. This
-/// extra indirection allows storing all texts together for convenience, as they
-/// require extra pre-processing compared to other primitives.
+/// Maximum number of nested clip masks cutting a single primitive. Extra masks
+/// are ignored.
+pub const MAX_CLIP_MASKS: usize = 4;
+
+/// Clip masks cutting a primitive.
 ///
-/// [`id`]: crate::canvas::TextPrimitive::id
-#[derive(Debug, Clone, Copy)]
-pub struct TextPrimitive {
-    /// Unique ID of the text inside its owner [`Canvas`].
-    pub id: u32,
-    /// TODO - Vec2 instead?
-    pub rect: Rect,
+/// Each mask is a primitive stored in the [`Canvas`] and referenced by its
+/// mask ID. The coverage of the masked primitive is multiplied by the coverage
+/// of each of its masks, so nested masks intersect. Masks are usually created
+/// by [`RenderContext::push_clip_shape()`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClipMasks {
+    /// Mask IDs. Only the first `count` are valid.
+    ids: [u32; MAX_CLIP_MASKS],
+    /// Number of valid mask IDs in `ids`.
+    count: usize,
 }
 
-impl TextPrimitive {
-    /// Number of elements used by each single glyph in the primitive element
-    /// buffer.
-    pub const ROW_PER_GLYPH: u32 = RectPrimitive::ROW_COUNT_BASE + RectPrimitive::ROW_COUNT_TEX;
+impl ClipMasks {
+    /// No mask at all.
+    pub const NONE: Self = Self {
+        ids: [0; MAX_CLIP_MASKS],
+        count: 0,
+    };
 
-    /// Get the AABB of this text.
-    pub fn aabb(&self, canvas: &ExtractedCanvas) -> Aabb2d {
-        let text = &canvas.texts[self.id as usize];
-        let mut aabb = Aabb2d {
-            min: self.rect.min,
-            max: self.rect.max,
-        };
-        trace!("Text #{:?} aabb={:?}", self.id, aabb);
-        for glyph in &text.glyphs {
-            aabb.min = aabb.min.min(self.rect.min + glyph.offset);
-            aabb.max = aabb.max.max(self.rect.min + glyph.offset + glyph.size);
-            trace!(
-                "  > add glyph offset={:?} size={:?}, new aabb {:?}",
-                glyph.offset,
-                glyph.size,
-                aabb
-            );
-        }
-        aabb
+    /// IDs of the masks inside their owner [`Canvas`].
+    pub fn ids(&self) -> &[u32] {
+        &self.ids[..self.count]
     }
 
-    fn info(&self, texts: &[ExtractedText]) -> PrimitiveInfo {
-        let index = self.id as usize;
-        if index < texts.len() {
-            let glyph_count = texts[index].glyphs.len() as u32;
-            PrimitiveInfo {
-                row_count: Self::ROW_PER_GLYPH,
-                sub_prim_count: glyph_count,
-            }
-        } else {
-            PrimitiveInfo {
-                row_count: 0,
-                sub_prim_count: 0,
-            }
+    /// Is there no mask at all?
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Add a mask by ID, unless it's already present.
+    ///
+    /// If there are already [`MAX_CLIP_MASKS`] masks, the new one is ignored
+    /// and this returns `false`.
+    pub(crate) fn push(&mut self, id: u32) -> bool {
+        if self.ids().contains(&id) {
+            return true;
+        }
+        if self.count == MAX_CLIP_MASKS {
+            return false;
         }
+        self.ids[self.count] = id;
+        self.count += 1;
+        true
     }
+}
 
-    fn write(
-        &self,
-        texts: &[ExtractedText],
-        prim: &mut [MaybeUninit<f32>],
-        canvas_translation: Vec2,
-        scale_factor: f32,
-    ) {
-        let index = self.id as usize;
-        let glyphs = &texts[index].glyphs;
-        let glyph_count = glyphs.len();
-        assert_eq!(glyph_count * Self::ROW_PER_GLYPH as usize, prim.len());
-        let mut ip = 0;
-        //let inv_scale_factor = 1. / scale_factor;
-        for i in 0..glyph_count {
-            let x = glyphs[i].offset.x + (self.rect.min.x + canvas_translation.x) * scale_factor;
-            let y = glyphs[i].offset.y + (self.rect.min.y + canvas_translation.y) * scale_factor;
-            let hw = glyphs[i].size.x / 2.0;
-            let hh = glyphs[i].size.y / 2.0;
-
-            // let x = x * inv_scale_factor;
-            // let y = y * inv_scale_factor;
-            // let hw = hw * inv_scale_factor;
-            // let hh = hh * inv_scale_factor;
+/// Effects applied to a primitive, in addition to its regular fill.
+///
+/// Effects are common to all primitives, and are evaluated from the signed
+/// distance function of the primitive in the shader. Because some effects
+/// extend outside the primitive itself, the AABB of the primitive is grown to
+/// ensure the effects are covered by tiling.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Effects {
+    /// Optional glow around the primitive.
+    pub glow: Option<Glow>,
+    /// Optional drop shadow underneath the primitive.
+    pub shadow: Option<Shadow>,
+    /// Optional inner shadow, drawn inside the edge of the primitive on top of
+    /// its fill, and inside its border if any. The shadow is the outside of the
+    /// shape moved by [`Shadow::offset`], so a positive offset along an axis
+    /// darkens the opposite edge; for example, a positive Y offset darkens the
+    /// top edge.
+    ///
+    /// Only rectangles (including rounded ones) support an inset shadow; this
+    /// is ignored on other primitives.
+    pub inset_shadow: Option<Shadow>,
+    /// ID of the optional [`Gradient`] replacing the uniform color of the
+    /// primitive, as returned by [`Canvas::add_gradient()`].
+    ///
+    /// This is ignored on text, whose glyphs have their own colors.
+    pub gradient: Option<u32>,
+    /// ID of the optional [`Gradient`] replacing the uniform border color of
+    /// the primitive, as returned by [`Canvas::add_gradient()`].
+    pub border_gradient: Option<u32>,
+    /// ID of the optional [`ImagePattern`] replacing the uniform color of the
+    /// primitive, as returned by [`Canvas::add_pattern()`].
+    ///
+    /// Unlike other effects, the pattern is written in the texture rows of the
+    /// primitive, and makes it textured. This is ignored on text.
+    pub pattern: Option<u32>,
+    /// ID of the optional [`Dash`] cutting the primitive along its stroke, as
+    /// returned by [`Canvas::add_dash()`].
+    ///
+    /// This is ignored on text.
+    pub dash: Option<u32>,
+    /// Optional clip rectangle, in canvas space, outside of which the primitive
+    /// and all its other effects are cut.
+    ///
+    /// This is usually set by [`RenderContext::push_clip_rect()`], and is not
+    /// affected by the transform of the render context.
+    pub clip: Option<Rect>,
+    /// Optional clip masks multiplying the coverage of the primitive and all
+    /// its other effects.
+    pub masks: ClipMasks,
+    /// Optional opacity of the whole primitive including all its other effects,
+    /// from 0 (transparent) to 1 (opaque).
+    ///
+    /// This is usually set from the global alpha of the [`RenderContext`].
+    pub opacity: Option<f32>,
+}
 
-            // Glyph position is center of rect, we need bottom-left corner
-            //let x = x - w / 2.;
-            //let y = y - h / 2.;
+impl Effects {
+    /// No effect at all.
+    pub const NONE: Self = Self {
+        glow: None,
+        shadow: None,
+        inset_shadow: None,
+        gradient: None,
+        border_gradient: None,
+        pattern: None,
+        dash: None,
+        clip: None,
+        masks: ClipMasks::NONE,
+        opacity: None,
+    };
 
-            // FIXME - hard-coded texture size
-            let uv_x = glyphs[i].uv_rect.min.x / 1024.0;
-            let uv_y = glyphs[i].uv_rect.min.y / 1024.0;
-            let uv_w = glyphs[i].uv_rect.max.x / 1024.0 - uv_x;
-            let uv_h = glyphs[i].uv_rect.max.y / 1024.0 - uv_y;
+    /// Flag set in the first effects row if the primitive has a [`Glow`].
+    ///
+    /// This must be kept in sync with the value inside the primitive shader.
+    const FLAG_GLOW: u32 = 1 << 0;
+    /// Flag set in the first effects row if the primitive has a [`Shadow`].
+    ///
+    /// This must be kept in sync with the value inside the primitive shader.
+    const FLAG_SHADOW: u32 = 1 << 1;
+    /// Flag set in the first effects row if the primitive has an inset
+    /// [`Shadow`].
+    ///
+    /// This must be kept in sync with the value inside the primitive shader.
+    const FLAG_INSET_SHADOW: u32 = 1 << 2;
+    /// Flag set in the first effects row if the primitive has a fill
+    /// [`Gradient`].
+    ///
+    /// This must be kept in sync with the value inside the primitive shader.
+    const FLAG_GRADIENT: u32 = 1 << 3;
+    /// Flag set in the first effects row if the primitive has a border
+    /// [`Gradient`].
+    ///
+    /// This must be kept in sync with the value inside the primitive shader.
+    const FLAG_BORDER_GRADIENT: u32 = 1 << 4;
+    /// Flag set in the first effects row if the primitive has a [`Dash`].
+    ///
+    /// This must be kept in sync with the value inside the primitive shader.
+    const FLAG_DASH: u32 = 1 << 5;
+    /// Flag set in the first effects row if the primitive has a clip rectangle.
+    ///
+    /// This must be kept in sync with the value inside the primitive shader.
+    const FLAG_CLIP: u32 = 1 << 6;
+    /// Flag set in the first effects row if the primitive has [`ClipMasks`].
+    ///
+    /// This must be kept in sync with the value inside the primitive shader.
+    const FLAG_MASK: u32 = 1 << 7;
+    /// Flag set in the first effects row if the primitive has an opacity.
+    ///
+    /// This must be kept in sync with the value inside the primitive shader.
+    const FLAG_OPACITY: u32 = 1 << 8;
 
-            // Glyph UV is flipped vertically
-            // let uv_y = uv_y + uv_h;
-            // let uv_h = -uv_h;
+    /// Number of primitive buffer rows (4 bytes) for the effect flags. Those
+    /// rows are only present if there's any effect.
+    const ROW_COUNT_FLAGS: u32 = 1;
+    /// Number of primitive buffer rows (4 bytes) for a glow effect.
+    const ROW_COUNT_GLOW: u32 = 2;
+    /// Number of primitive buffer rows (4 bytes) for a shadow effect, either a
+    /// drop shadow or an inset one.
+    const ROW_COUNT_SHADOW: u32 = 4;
+    /// Number of primitive buffer rows (4 bytes) for a clip rectangle.
+    const ROW_COUNT_CLIP: u32 = 4;
+    /// Number of primitive buffer rows (4 bytes) for an opacity.
+    const ROW_COUNT_OPACITY: u32 = 1;
 
-            // center pos
-            // we round() here to work around a bug: if the pixel rect is not aligned on the
-            // screen pixel grid, the UV coordinates may end up being < 0.5 or >
-            // w + 0.5, which then bleeds into adjacent pixels. it looks like
-            // the rasterizing of the glyphs already adds 1 pixel border, so we should
-            // remove that border in the SDF rect, so that we never sample the
-            // texture beyond half that 1 px border, which would linearly blend
-            // with the next pixel (outside the glyph rect).
-            prim[ip + 0].write(x.round() + hw);
-            prim[ip + 1].write(y.round() + hh);
+    /// Is there no effect at all?
+    ///
+    /// The [`pattern`] is not considered, as it's not written in the effects
+    /// rows.
+    ///
+    /// [`pattern`]: Effects::pattern
+    pub fn is_empty(&self) -> bool {
+        self.glow.is_none()
+            && self.shadow.is_none()
+            && self.inset_shadow.is_none()
+            && self.gradient.is_none()
+            && self.border_gradient.is_none()
+            && self.dash.is_none()
+            && self.clip.is_none()
+            && self.masks.is_empty()
+            && self.opacity.is_none()
+    }
 
-            // half size
-            prim[ip + 2].write(hw);
-            prim[ip + 3].write(hh);
+    /// Grow an AABB to cover the effects extending outside the primitive.
+    ///
+    /// The inset shadow is always contained inside the primitive, so doesn't
+    /// contribute.
+    pub fn grow_aabb(&self, aabb: Aabb2d) -> Aabb2d {
+        let margin = self.glow.map(|g| g.spread).unwrap_or(0.).max(0.);
+        let mut min = aabb.min - margin;
+        let mut max = aabb.max + margin;
+        if let Some(shadow) = &self.shadow {
+            let blur = shadow.blur_radius.max(0.);
+            min = min.min(aabb.min + shadow.offset - blur);
+            max = max.max(aabb.max + shadow.offset + blur);
+        }
+        Aabb2d { min, max }
+    }
 
-            // radius
-            prim[ip + 4].write(0.);
+    /// Intersect an AABB with the clip rectangle, if any.
+    ///
+    /// The result is empty, with `min` greater than `max`, if the AABB is
+    /// entirely outside the clip rectangle.
+    pub fn clip_aabb(&self, aabb: Aabb2d) -> Aabb2d {
+        match &self.clip {
+            Some(clip) => Aabb2d {
+                min: aabb.min.max(clip.min),
+                max: aabb.max.min(clip.max),
+            },
+            None => aabb,
+        }
+    }
 
-            // color
-            prim[ip + 5].write(bytemuck::cast(glyphs[i].color));
+    /// Transform the effects along with the primitive they apply to.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        let scale = transform_scale(transform);
+        if let Some(glow) = &mut self.glow {
+            glow.spread *= scale;
+        }
+        for shadow in [&mut self.shadow, &mut self.inset_shadow]
+            .into_iter()
+            .flatten()
+        {
+            shadow.offset = transform.transform_vector2(shadow.offset);
+            shadow.blur_radius *= scale;
+        }
+    }
 
-            // uv_offset (at center pos)
-            prim[ip + 6].write(uv_x + uv_w / 2.0);
-            prim[ip + 7].write(uv_y + uv_h / 2.0);
+    /// Number of primitive buffer rows (4 bytes) needed to store the effects.
+    ///
+    /// The effects rows follow all the other rows of the primitive, including
+    /// border and texture ones. The variable-size gradient, dash, and mask rows
+    /// come last.
+    pub(crate) fn row_count(&self, tables: &EffectTables) -> u32 {
+        if self.is_empty() {
+            return 0;
+        }
+        let mut rows = Self::ROW_COUNT_FLAGS;
+        if self.glow.is_some() {
+            rows += Self::ROW_COUNT_GLOW;
+        }
+        if self.shadow.is_some() {
+            rows += Self::ROW_COUNT_SHADOW;
+        }
+        if self.inset_shadow.is_some() {
+            rows += Self::ROW_COUNT_SHADOW;
+        }
+        if self.clip.is_some() {
+            rows += Self::ROW_COUNT_CLIP;
+        }
+        if self.opacity.is_some() {
+            rows += Self::ROW_COUNT_OPACITY;
+        }
+        if let Some(id) = self.gradient {
+            rows += tables.gradients[id as usize].row_count();
+        }
+        if let Some(id) = self.border_gradient {
+            rows += tables.gradients[id as usize].row_count();
+        }
+        if let Some(id) = self.dash {
+            rows += tables.dashes[id as usize].row_count();
+        }
+        if !self.masks.is_empty() {
+            rows += 1 + self.masks.ids().len() as u32;
+        }
+        rows
+    }
 
-            // uv_scale
-            prim[ip + 8].write(1.0 / 1024.0);
-            prim[ip + 9].write(1.0 / 1024.0);
+    /// Write the effects rows.
+    ///
+    /// The `mask_indices` are the packed indices of the mask primitives of the
+    /// canvas, indexed by mask ID.
+    pub(crate) fn write(
+        &self,
+        prim: &mut [MaybeUninit<f32>],
+        tables: &EffectTables,
+        mask_indices: &[u32],
+        canvas_translation: Vec2,
+        scale_factor: f32,
+    ) {
+        assert_eq!(
+            self.row_count(tables) as usize,
+            prim.len(),
+            "Invalid buffer size {} to write Effects (needs {})",
+            prim.len(),
+            self.row_count(tables)
+        );
+        if self.is_empty() {
+            return;
+        }
 
-            ip += Self::ROW_PER_GLYPH as usize;
+        let mut flags = 0;
+        let mut idx = 1;
+        if let Some(glow) = &self.glow {
+            flags |= Self::FLAG_GLOW;
+            prim[idx].write(bytemuck::cast(glow.color.to_linear().as_u32()));
+            prim[idx + 1].write(glow.spread * scale_factor);
+            idx += 2;
+        }
+        if let Some(shadow) = &self.shadow {
+            flags |= Self::FLAG_SHADOW;
+            shadow.write(&mut prim[idx..idx + 4], scale_factor);
+            idx += 4;
+        }
+        if let Some(shadow) = &self.inset_shadow {
+            flags |= Self::FLAG_INSET_SHADOW;
+            shadow.write(&mut prim[idx..idx + 4], scale_factor);
+            idx += 4;
+        }
+        if let Some(clip) = &self.clip {
+            flags |= Self::FLAG_CLIP;
+            let min = (clip.min + canvas_translation) * scale_factor;
+            let max = (clip.max + canvas_translation) * scale_factor;
+            prim[idx].write(min.x);
+            prim[idx + 1].write(min.y);
+            prim[idx + 2].write(max.x);
+            prim[idx + 3].write(max.y);
+            idx += 4;
         }
+        if let Some(opacity) = self.opacity {
+            flags |= Self::FLAG_OPACITY;
+            prim[idx].write(opacity.clamp(0., 1.));
+            idx += 1;
+        }
+        if let Some(id) = self.gradient {
+            let gradient = &tables.gradients[id as usize];
+            flags |= Self::FLAG_GRADIENT;
+            let rows = gradient.row_count() as usize;
+            gradient.write(&mut prim[idx..idx + rows], canvas_translation, scale_factor);
+            idx += rows;
+        }
+        if let Some(id) = self.border_gradient {
+            let gradient = &tables.gradients[id as usize];
+            flags |= Self::FLAG_BORDER_GRADIENT;
+            let rows = gradient.row_count() as usize;
+            gradient.write(&mut prim[idx..idx + rows], canvas_translation, scale_factor);
+            idx += rows;
+        }
+        if let Some(id) = self.dash {
+            let dash = &tables.dashes[id as usize];
+            flags |= Self::FLAG_DASH;
+            let rows = dash.row_count() as usize;
+            dash.write(&mut prim[idx..idx + rows], canvas_translation, scale_factor);
+            idx += rows;
+        }
+        if !self.masks.is_empty() {
+            flags |= Self::FLAG_MASK;
+            let ids = self.masks.ids();
+            prim[idx].write(bytemuck::cast(ids.len() as u32));
+            for (i, id) in ids.iter().enumerate() {
+                prim[idx + 1 + i].write(bytemuck::cast(mask_indices[*id as usize]));
+            }
+            idx += 1 + ids.len();
+        }
+        debug_assert_eq!(idx, prim.len());
+        prim[0].write(bytemuck::cast(flags));
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct QuarterPiePrimitive {
-    /// Origin of the pie.
-    pub origin: Vec2,
-    /// Radii of the (elliptical) pie.
-    pub radii: Vec2,
-    /// Uniform rectangle color.
-    pub color: Color,
-    /// Flip the quarter pie along the horizontal axis.
-    pub flip_x: bool,
-    /// Flip the quarter pie along the vertical axis.
-    pub flip_y: bool,
+/// Side tables of the [`Effects`] of the primitives, and of the effects too
+/// large to be stored inline in those [`Effects`], which reference them by ID.
+///
+/// This keeps primitives small, with or without effects. Each primitive has
+/// its own [`Effects`] entry, if any, so modifying it never affects another
+/// primitive. Other entries can be shared by several primitives, like the
+/// pieces of a dashed stroke share the gradient of their brush.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct EffectTables {
+    /// Effects of the primitives.
+    pub effects: Vec<Effects>,
+    /// Fill and border gradients.
+    pub gradients: Vec<Gradient>,
+    /// Dashes along strokes.
+    pub dashes: Vec<Dash>,
+    /// Image patterns.
+    pub patterns: Vec<ImagePattern>,
 }
 
-impl Default for QuarterPiePrimitive {
-    fn default() -> Self {
-        Self {
-            origin: Vec2::ZERO,
-            radii: Vec2::ONE,
-            color: Color::default(),
-            flip_x: false,
-            flip_y: false,
+impl EffectTables {
+    /// Store the effects of a primitive, and return their ID, or `None` if
+    /// there's no effect at all.
+    pub fn add_effects(&mut self, effects: Effects) -> Option<u32> {
+        if effects == Effects::NONE {
+            return None;
         }
+        self.effects.push(effects);
+        Some(self.effects.len() as u32 - 1)
     }
-}
 
-impl QuarterPiePrimitive {
-    /// Number of primitive buffer rows (4 bytes) per primitive.
-    const ROW_COUNT: u32 = 5;
+    /// Get the effects with the given ID, or no effect at all if `None`.
+    pub fn effects(&self, id: Option<u32>) -> &Effects {
+        id.map_or(&Effects::NONE, |id| &self.effects[id as usize])
+    }
 
-    pub fn aabb(&self) -> Aabb2d {
-        Aabb2d {
-            min: self.origin - self.radii,
-            max: self.origin + self.radii,
-        }
+    /// Get the effects with the given ID for modification, storing new empty
+    /// effects first if `id` is `None`, and updating it to their ID.
+    pub fn effects_mut(&mut self, id: &mut Option<u32>) -> &mut Effects {
+        let id = *id.get_or_insert_with(|| {
+            self.effects.push(Effects::NONE);
+            self.effects.len() as u32 - 1
+        });
+        &mut self.effects[id as usize]
     }
 
-    /// The pie center.
-    pub fn center(&self) -> Vec3 {
-        self.origin.extend(0.)
+    /// Store a gradient, and return its ID.
+    pub fn add_gradient(&mut self, gradient: Gradient) -> u32 {
+        self.gradients.push(gradient);
+        self.gradients.len() as u32 - 1
     }
 
-    #[inline]
-    const fn row_count(&self) -> u32 {
-        Self::ROW_COUNT
+    /// Store dashes, and return their ID.
+    pub fn add_dash(&mut self, dash: Dash) -> u32 {
+        self.dashes.push(dash);
+        self.dashes.len() as u32 - 1
     }
 
-    fn info(&self) -> PrimitiveInfo {
-        PrimitiveInfo {
-            row_count: self.row_count(),
-            sub_prim_count: 1,
+    /// Store an image pattern, and return its ID.
+    pub fn add_pattern(&mut self, pattern: ImagePattern) -> u32 {
+        self.patterns.push(pattern);
+        self.patterns.len() as u32 - 1
+    }
+
+    /// Transform the effects with the given IDs, and the entries they
+    /// reference, along with the primitives they apply to.
+    ///
+    /// Entries are only ever referenced by the primitives drawn after they
+    /// were added, so this transforms all gradients, dashes, and patterns from
+    /// the first one referenced, each of them once even if shared.
+    pub fn apply_transform(&mut self, ids: impl Iterator<Item = u32>, transform: &Affine2) {
+        let mut first_gradient = self.gradients.len();
+        let mut first_dash = self.dashes.len();
+        let mut first_pattern = self.patterns.len();
+        for id in ids {
+            let effects = &mut self.effects[id as usize];
+            effects.apply_transform(transform);
+            for id in [effects.gradient, effects.border_gradient]
+                .into_iter()
+                .flatten()
+            {
+                first_gradient = first_gradient.min(id as usize);
+            }
+            if let Some(id) = effects.dash {
+                first_dash = first_dash.min(id as usize);
+            }
+            if let Some(id) = effects.pattern {
+                first_pattern = first_pattern.min(id as usize);
+            }
+        }
+        for gradient in &mut self.gradients[first_gradient..] {
+            gradient.apply_transform(transform);
+        }
+        for dash in &mut self.dashes[first_dash..] {
+            dash.apply_transform(transform);
+        }
+        for pattern in &mut self.patterns[first_pattern..] {
+            pattern.apply_transform(transform);
         }
     }
 
-    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
-        assert_eq!(self.row_count() as usize, prim.len());
-        let radii_mask = BVec2::new(self.flip_x, self.flip_y);
-        let signed_radii = Vec2::select(radii_mask, -self.radii, self.radii);
-        prim[0].write((self.origin.x + canvas_translation.x) * scale_factor);
-        prim[1].write((self.origin.y + canvas_translation.y) * scale_factor);
-        prim[2].write(signed_radii.x * scale_factor);
-        prim[3].write(signed_radii.y * scale_factor);
-        prim[4].write(bytemuck::cast(self.color.to_linear().as_u32()));
+    /// Remove all entries.
+    pub fn clear(&mut self) {
+        self.effects.clear();
+        self.gradients.clear();
+        self.dashes.clear();
+        self.patterns.clear();
     }
 }
 
-/// Drawing surface for 2D graphics.
+/// Write the border rows of a primitive: width, outset, and color.
 ///
-/// This component should attached to the same entity as a [`Camera`] and an
-/// [`OrthographicProjection`].
+/// The outset is the part of the border width extending outside the edge of
+/// the primitive, as determined by the border alignment.
+fn write_border(
+    prim: &mut [MaybeUninit<f32>],
+    width: f32,
+    alignment: StrokeAlignment,
+    color: Color,
+    scale_factor: f32,
+) {
+    prim[0].write(width * scale_factor);
+    prim[1].write(alignment.outset(width) * scale_factor);
+    prim[2].write(bytemuck::cast(color.to_linear().as_u32()));
+}
+
+/// Number of primitive buffer rows (4 bytes) for the texture of a textured
+/// primitive. Those rows follow the border ones, or the base ones if there's no
+/// border.
+const ROW_COUNT_TEX: u32 = 7;
+
+/// Write the texture rows of a primitive: the transform from pixel to UV
+/// coordinates, and the repeat mode.
+fn write_texture(prim: &mut [MaybeUninit<f32>], uv_transform: Affine2, repeat: ImageRepeat) {
+    prim[0].write(uv_transform.matrix2.x_axis.x);
+    prim[1].write(uv_transform.matrix2.x_axis.y);
+    prim[2].write(uv_transform.matrix2.y_axis.x);
+    prim[3].write(uv_transform.matrix2.y_axis.y);
+    prim[4].write(uv_transform.translation.x);
+    prim[5].write(uv_transform.translation.y);
+    prim[6].write(bytemuck::cast(repeat as u32));
+}
+
+/// Offset of the middle of a stroke relative to the edge of the primitive, as
+/// determined by the stroke alignment. Positive values point outside.
+fn stroke_offset(width: f32, alignment: StrokeAlignment) -> f32 {
+    alignment.outset(width) - width.max(0.) / 2.
+}
+
+/// Scale applied to lengths by the linear part of a transform.
 ///
-/// By default the dimensions of the canvas are automatically computed and
-/// updated based on that projection.
-#[derive(Component)]
-pub struct Canvas {
-    /// The canvas dimensions relative to its origin.
-    ///
-    /// Currently ignored.
-    rect: Rect,
-    /// Optional background color to clear the canvas with.
-    ///
-    /// This only has an effect starting from the next [`clear()`] call. If a
-    /// background color is set, it's used to clear the canvas each frame.
-    /// Otherwise, the canvas retains its default transparent black color (0.0,
-    /// 0.0, 0.0, 0.0).
-    ///
-    /// [`clear()`]: crate::Canvas::clear
-    pub background_color: Option<Color>,
-    /// Collection of drawn primitives.
-    primitives: Vec<Primitive>,
-    /// Collection of allocated texts.
-    pub(crate) text_layouts: Vec<TextLayout>,
-    /// Atlas layout. Needs to be a separate asset resource due to Bevy's API
-    /// only.
-    pub(crate) atlas_layout: Handle<TextureAtlasLayout>,
+/// This is exact for similarity transforms, and otherwise the geometric mean of
+/// the scales along both axes.
+pub(crate) fn transform_scale(transform: &Affine2) -> f32 {
+    transform.matrix2.determinant().abs().sqrt()
 }
 
-impl Default for Canvas {
-    fn default() -> Self {
-        Self {
-            rect: Rect::default(),
-            background_color: None,
-            primitives: vec![],
-            text_layouts: vec![],
-            atlas_layout: Handle::default(),
-        }
+/// Scale applied by the linear part of a transform to lengths across a
+/// direction, that is to the distance between two lines parallel to `dir`.
+///
+/// This is exact for any affine transform. `dir` doesn't need to be
+/// normalized; the mean scale is returned if it's zero.
+fn transform_normal_scale(transform: &Affine2, dir: Vec2) -> f32 {
+    let dir = dir.normalize_or_zero();
+    let len = transform.transform_vector2(dir).length();
+    if len > 0. {
+        transform.matrix2.determinant().abs() / len
+    } else {
+        transform_scale(transform)
     }
 }
 
-impl Canvas {
-    /// Create a new canvas with given dimensions.
-    ///
-    /// FIXME - Currently the rectangle is ignored; all canvases are
-    /// full-screen.
-    pub fn new(rect: Rect) -> Self {
-        Self { rect, ..default() }
-    }
+/// Is the transform a similarity, that is a combination of a translation, a
+/// rotation, a uniform scale, and possibly a reflection?
+///
+/// Only those transforms scale all lengths by the same amount in all
+/// directions.
+pub(crate) fn is_similarity(transform: &Affine2) -> bool {
+    let x = transform.matrix2.x_axis;
+    let y = transform.matrix2.y_axis;
+    let eps = 1e-4 * x.length_squared().max(y.length_squared());
+    x.dot(y).abs() <= eps && (x.length_squared() - y.length_squared()).abs() <= eps
+}
 
-    /// Change the dimensions of the canvas.
-    ///
-    /// This is called automatically if the [`Canvas`] is on the same entity as
-    /// an [`OrthographicProjection`].
-    pub fn set_rect(&mut self, rect: Rect) {
-        // if let Some(color) = self.background_color {
-        //     if self.rect != rect {
-        //         TODO - clear new area if any? or resize the clear() rect?!
-        //     }
-        // }
-        self.rect = rect;
+/// Intersect two clip rectangles.
+///
+/// Unlike [`Rect::intersect()`], disjoint rectangles produce an inverted
+/// rectangle, with `min` greater than `max`, which clips everything out.
+pub(crate) fn intersect_clip(a: Rect, b: Rect) -> Rect {
+    Rect {
+        min: a.min.max(b.min),
+        max: a.max.min(b.max),
     }
+}
 
-    /// Get the dimensions of the canvas relative to its origin.
-    ///
-    /// FIXME - Currently this is always [`OrthographicProjection::area`].
-    pub fn rect(&self) -> Rect {
-        self.rect
-    }
+/// Does the transform mirror the shapes, like a negative scale on one axis?
+fn is_reflection(transform: &Affine2) -> bool {
+    transform.matrix2.determinant() < 0.
+}
 
-    /// Clear the canvas, discarding all primitives previously drawn on it.
-    ///
-    /// If the canvas has a [`background_color`], this clears the canvas to that
-    /// color.
-    ///
-    /// [`background_color`]: Canvas::background_color
-    pub fn clear(&mut self) {
-        self.primitives.clear();
-        self.text_layouts.clear(); // FIXME - really?
+/// Map a frame rotated by `rotation` through the linear part of a transform.
+///
+/// Returns the rotation of the transformed frame, and the scale along each of
+/// its axes. The rotation follows the X axis of the frame; if the transform is
+/// a reflection (see [`is_reflection()`]), the Y axis ends up on the other
+/// side, so the caller needs to mirror the shape across its local X axis.
+fn transform_frame(transform: &Affine2, rotation: Rot2) -> (Rot2, Vec2) {
+    let x = transform.transform_vector2(rotation * Vec2::X);
+    let y = transform.transform_vector2(rotation * Vec2::Y);
+    let rotation = if x.length_squared() > 0. {
+        Rot2::radians(x.to_angle())
+    } else {
+        rotation
+    };
+    (rotation, Vec2::new(x.length(), y.length()))
+}
 
-        if let Some(color) = self.background_color {
-            self.draw(RectPrimitive {
-                rect: self.rect,
-                color,
-                ..default()
-            });
-        }
-    }
+/// Half extent of the AABB of a rectangle rotated around its center.
+fn rotated_rect_extent(half_size: Vec2, rotation: Rot2) -> Vec2 {
+    let (sin, cos) = rotation.sin_cos();
+    Vec2::new(
+        cos.abs() * half_size.x + sin.abs() * half_size.y,
+        sin.abs() * half_size.x + cos.abs() * half_size.y,
+    )
+}
 
-    /// Draw a new primitive onto the canvas.
+/// Half extent of the AABB of an ellipse rotated around its center.
+fn rotated_ellipse_extent(radii: Vec2, rotation: Rot2) -> Vec2 {
+    let (sin, cos) = rotation.sin_cos();
+    Vec2::new(
+        Vec2::new(radii.x * cos, radii.y * sin).length(),
+        Vec2::new(radii.x * sin, radii.y * cos).length(),
+    )
+}
+
+/// Map an angle from the X axis through the linear part of a transform.
+fn transform_angle(transform: &Affine2, angle: f32) -> f32 {
+    transform
+        .transform_vector2(Vec2::from_angle(angle))
+        .to_angle()
+}
+
+/// A line between two points, with a color and thickness.
+///
+/// This is essentially an oriented rectangle.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LinePrimitive {
+    /// The starting point of the line.
+    pub start: Vec2,
+    /// The ending point of the line.
+    pub end: Vec2,
+    /// The line color.
+    pub color: Color,
+    /// The line thickness. Must be greater than zero.
     ///
-    /// This is a lower level entry point to canvas drawing; in general, you
-    /// should prefer acquiring a [`RenderContext`] via [`render_context()`]
-    /// and using it to draw primitives.
+    /// The line shape extends equally by `thickness / 2.` on both sides of the
+    /// mathematical (infinitely thin) line joining the start and end points.
+    pub thickness: f32,
+    /// Shape of both ends of the line.
     ///
-    /// [`render_context()`]: crate::canvas::Canvas::render_context
+    /// With [`LineCap::Butt`] the line stops exactly at its start and end
+    /// points, while the other caps extend by `thickness / 2.` beyond them.
+    pub cap: LineCap,
+    /// Size of the border, if any, or zero if no border. Negative values or
+    /// zero mean no border.
+    pub border_width: f32,
+    /// Border color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// Alignment of the border relative to the edge of the line.
+    pub border_alignment: StrokeAlignment,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl LinePrimitive {
+    /// The AABB of the line primitive.
+    pub fn aabb(&self) -> Aabb2d {
+        let dir = (self.end - self.start).normalize_or_zero();
+        let tg = Vec2::new(-dir.y, dir.x);
+        let e = self.thickness / 2.;
+        // Square and round caps extend beyond the endpoints, on both axes for a
+        // zero-length line which draws a dot.
+        let ext = match self.cap {
+            LineCap::Butt => Vec2::ZERO,
+            LineCap::Square | LineCap::Round if dir == Vec2::ZERO => Vec2::splat(e),
+            LineCap::Square | LineCap::Round => dir * e,
+        };
+        let p0 = self.start - ext + tg * e;
+        let p1 = self.start - ext - tg * e;
+        let p2 = self.end + ext + tg * e;
+        let p3 = self.end + ext - tg * e;
+        let outset = self.border_outset();
+        let min = p0.min(p1).min(p2).min(p3) - outset;
+        let max = p0.max(p1).max(p2).max(p3) + outset;
+        Aabb2d { min, max }
+    }
+
+    /// Transform the line, scaling its thickness and border by the scale of
+    /// the transform across the line, so the sides of the line follow any
+    /// affine transform exactly.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        let scale = transform_normal_scale(transform, self.end - self.start);
+        self.start = transform.transform_point2(self.start);
+        self.end = transform.transform_point2(self.end);
+        self.thickness *= scale;
+        self.border_width *= scale;
+    }
+
+    /// Is the primitive bordered?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
+    /// Width of the part of the border extending outside the line.
+    fn border_outset(&self) -> f32 {
+        self.border_alignment.outset(self.border_width)
+    }
+
+    fn info(&self) -> PrimitiveInfo {
+        PrimitiveInfo {
+            row_count: 7 + if self.is_bordered() { 3 } else { 0 },
+            sub_prim_count: 1,
+        }
+    }
+
+    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
+        prim[0].write((self.start.x + canvas_translation.x) * scale_factor);
+        prim[1].write((self.start.y + canvas_translation.y) * scale_factor);
+        prim[2].write((self.end.x + canvas_translation.x) * scale_factor);
+        prim[3].write((self.end.y + canvas_translation.y) * scale_factor);
+        prim[4].write(bytemuck::cast(self.color.to_linear().as_u32()));
+        prim[5].write(self.thickness * scale_factor);
+        prim[6].write(bytemuck::cast(self.cap as u32));
+        if self.is_bordered() {
+            assert_eq!(10, prim.len());
+            write_border(
+                &mut prim[7..],
+                self.border_width,
+                self.border_alignment,
+                self.border_color,
+                scale_factor,
+            );
+        } else {
+            assert_eq!(7, prim.len());
+        }
+    }
+}
+
+/// A rectangle with a color, optional rounded corners, optional rotation, and
+/// optional texture, either filled or stroked.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RectPrimitive {
+    /// Position and size of the rectangle in its canvas space, before
+    /// rotation.
+    ///
+    /// For rounded rectangles, this is the AABB (the radii and borders are
+    /// included).
+    pub rect: Rect,
+    /// Rotation of the rectangle around its center. The texture, if any,
+    /// rotates with the rectangle.
+    pub rotation: Rot2,
+    /// Rounded corners radii. Set to zero to disable rounded corners.
+    ///
+    /// Each radius is clamped to the half size of the rectangle when drawn.
+    pub radii: CornerRadii,
+    /// Uniform rectangle color.
+    pub color: Color,
+    /// Optional handle to the image used for texturing the rectangle.
+    pub image: Option<AssetId<Image>>,
+    /// Image size, populated from actual texture size and scaling.
+    pub image_size: Vec2,
+    /// Scaling for the image (if any).
+    pub image_scaling: ImageScaling,
+    /// Flip the image (if any) along the horizontal axis, mirroring it around
+    /// the center of the rectangle.
+    pub flip_x: bool,
+    /// Flip the image (if any) along the vertical axis, mirroring it around
+    /// the center of the rectangle.
+    pub flip_y: bool,
+    /// Thickness of the stroke along the rectangle's edge. Set to zero to fill
+    /// the rectangle instead.
+    ///
+    /// Square corners stay square on the outer edge of the stroke, while
+    /// rounded corners are offset along with it.
+    pub stroke_width: f32,
+    /// Alignment of the stroke relative to the rectangle's edge.
+    pub stroke_alignment: StrokeAlignment,
+    /// Size of the border, if any, or zero if no border. Negative values or
+    /// zero mean no border.
+    pub border_width: f32,
+    /// Border color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// Alignment of the border relative to the edge of the rectangle, or of
+    /// its stroke if stroked.
+    pub border_alignment: StrokeAlignment,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl RectPrimitive {
+    /// Number of primitive buffer rows (4 bytes) describing the rectangle
+    /// itself. Glyphs share this layout.
+    const ROW_COUNT_RECT: u32 = 11;
+    /// Number of primitive buffer rows (4 bytes) per primitive.
+    const ROW_COUNT_BASE: u32 = Self::ROW_COUNT_RECT + 2;
+    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
+    /// when bordered. Those extra rows follow the base ones.
+    const ROW_COUNT_BORDER: u32 = 3;
+
+    /// Get the AABB of this rectangle.
+    ///
+    /// For rotated rectangles, this is the AABB of the rotated rectangle, which
+    /// is conservative if the corners are rounded. The AABB includes any part
+    /// of the stroke and border extending outside the rectangle.
+    pub fn aabb(&self) -> Aabb2d {
+        let outset = self.stroke_alignment.outset(self.stroke_width)
+            + self.border_alignment.outset(self.border_width);
+        if self.rotation == Rot2::IDENTITY {
+            return Aabb2d {
+                min: self.rect.min - outset,
+                max: self.rect.max + outset,
+            };
+        }
+        let center = self.rect.center();
+        let extent = rotated_rect_extent(self.rect.half_size() + outset, self.rotation);
+        Aabb2d {
+            min: center - extent,
+            max: center + extent,
+        }
+    }
+
+    /// Is the rectangle stroked instead of filled?
+    pub fn is_stroked(&self) -> bool {
+        self.stroke_width > 0.
+    }
+
+    /// Get the region inside the stroke of an axis-aligned stroked rectangle
+    /// where neither the stroke nor its border and effects draw anything.
+    ///
+    /// The hole is conservative: it excludes the rounded corners, and is
+    /// shrunk by the reach of the `effects` of the rectangle in all directions.
+    fn tile_hole(&self, effects: &Effects) -> Option<Aabb2d> {
+        if !self.is_stroked() || self.rotation != Rot2::IDENTITY {
+            return None;
+        }
+        let radii = self.radii.clamped(self.rect.half_size());
+        let radius = radii
+            .top_left
+            .max(radii.top_right)
+            .max(radii.bottom_right)
+            .max(radii.bottom_left);
+        let inset = self.stroke_alignment.inset(self.stroke_width)
+            + self.border_alignment.outset(self.border_width)
+            + radius;
+        let inner = Aabb2d {
+            min: self.rect.min + inset,
+            max: self.rect.max - inset,
+        };
+        let grown = effects.grow_aabb(inner);
+        let reach = (grown.max - inner.max).max(inner.min - grown.min);
+        let hole = Aabb2d {
+            min: inner.min + reach,
+            max: inner.max - reach,
+        };
+        (hole.min.x < hole.max.x && hole.min.y < hole.max.y).then_some(hole)
+    }
+
+    /// Is this primitive textured?
+    ///
+    /// True if [`RectPrimitive::image`] is `Some`.
+    pub const fn is_textured(&self) -> bool {
+        self.image.is_some()
+    }
+
+    /// Transform from the shader's pixel coordinates to the texture UV
+    /// coordinates, centering the image on the rectangle.
+    fn uv_transform(&self, canvas_translation: Vec2, scale_factor: f32) -> Affine2 {
+        let center = (self.rect.center() + canvas_translation) * scale_factor;
+        let (sin, cos) = self.rotation.sin_cos();
+        let unrotate = Mat2::from_cols(Vec2::new(cos, -sin), Vec2::new(sin, cos));
+        let flip = Vec2::new(
+            if self.flip_x { -1. } else { 1. },
+            if self.flip_y { -1. } else { 1. },
+        );
+        let matrix2 = Mat2::from_diagonal(flip / self.image_size.max(Vec2::ONE)) * unrotate;
+        Affine2 {
+            matrix2,
+            translation: Vec2::splat(0.5) - matrix2 * center,
+        }
+    }
+
+    /// Transform the rectangle, rotating it with the transform.
+    ///
+    /// Each side is scaled by the scale of the transform along it, and the
+    /// corner radii, stroke and border by the mean scale of the transform. A
+    /// reflection flips the image vertically and swaps the top and bottom
+    /// corner radii.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        let scale = transform_scale(transform);
+        let (rotation, axis_scale) = transform_frame(transform, self.rotation);
+        let center = transform.transform_point2(self.rect.center());
+        self.rect = Rect::from_center_half_size(center, self.rect.half_size() * axis_scale);
+        self.rotation = rotation;
+        self.radii = self.radii.scaled(scale);
+        if is_reflection(transform) {
+            self.flip_y = !self.flip_y;
+            self.radii = self.radii.flipped_y();
+        }
+        self.stroke_width *= scale;
+        self.border_width *= scale;
+    }
+
+    /// Is the primitive bordered?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
     #[inline]
-    pub fn draw<'a>(&'a mut self, prim: impl Into<Primitive>) -> ShapeRef<'a> {
-        let prim = prim.into();
-        self.primitives.push(prim);
-        let sref = ShapeRef {
-            prim: self.primitives.last_mut().unwrap(),
+    fn row_count(&self) -> u32 {
+        let mut rows = Self::ROW_COUNT_BASE;
+        if self.is_bordered() {
+            rows += Self::ROW_COUNT_BORDER;
+        }
+        rows
+    }
+
+    fn info(&self) -> PrimitiveInfo {
+        PrimitiveInfo {
+            row_count: self.row_count(),
+            sub_prim_count: 1,
+        }
+    }
+
+    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
+        assert_eq!(
+            self.row_count() as usize,
+            prim.len(),
+            "Invalid buffer size {} to write RectPrimitive (needs {})",
+            prim.len(),
+            self.row_count()
+        );
+
+        let half_min = self.rect.min * (0.5 * scale_factor);
+        let half_max = self.rect.max * (0.5 * scale_factor);
+        let center = half_min + half_max + canvas_translation * scale_factor;
+        let half_size = half_max - half_min;
+        prim[0].write(center.x);
+        prim[1].write(center.y);
+        prim[2].write(half_size.x);
+        prim[3].write(half_size.y);
+        prim[4].write(self.rotation.cos);
+        prim[5].write(self.rotation.sin);
+        let radii = self.radii.clamped(self.rect.half_size());
+        prim[6].write(radii.top_left * scale_factor);
+        prim[7].write(radii.top_right * scale_factor);
+        prim[8].write(radii.bottom_right * scale_factor);
+        prim[9].write(radii.bottom_left * scale_factor);
+        prim[10].write(bytemuck::cast(self.color.to_linear().as_u32()));
+        prim[11].write(self.stroke_width.max(0.) * scale_factor);
+        prim[12].write(stroke_offset(self.stroke_width, self.stroke_alignment) * scale_factor);
+        if self.is_bordered() {
+            write_border(
+                &mut prim[13..],
+                self.border_width,
+                self.border_alignment,
+                self.border_color,
+                scale_factor,
+            );
+        }
+    }
+}
+
+/// A reference to a text with a color.
+///
+/// The text primitive is not stored directly inside this struct. Instead, the
+/// struct stores an [`id`] field indexing the text into its [`Canvas`]. This
+/// extra indirection allows storing all texts together for convenience, as they
+/// require extra pre-processing compared to other primitives.
+///
+/// Glyphs are rasterized at their font size, so a scaled text is resampled
+/// from the rasterized glyphs, and looks blurry when scaled up.
+///
+/// [`id`]: crate::canvas::TextPrimitive::id
+#[derive(Debug, Clone, Copy)]
+pub struct TextPrimitive {
+    /// Unique ID of the text inside its owner [`Canvas`].
+    pub id: u32,
+    /// TODO - Vec2 instead?
+    pub rect: Rect,
+    /// Rotation of the text around the origin `rect.min`.
+    pub rotation: Rot2,
+    /// Uniform scale of the text around the origin `rect.min`, relative to
+    /// its font size.
+    pub scale: f32,
+    /// Mirror the text across the horizontal axis through its origin, before
+    /// rotating it, which turns its glyphs upside down.
+    pub flip_y: bool,
+    /// Size of the outline around the glyphs, if any, or zero if no outline.
+    ///
+    /// Unlike other primitives, the outline of a text expands outside of the
+    /// glyphs, so it doesn't eat into thin glyph strokes. Negative values or
+    /// zero mean no outline.
+    pub border_width: f32,
+    /// Outline color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl TextPrimitive {
+    /// Number of elements used by each single glyph in the primitive element
+    /// buffer.
+    pub const ROW_PER_GLYPH: u32 = RectPrimitive::ROW_COUNT_RECT + Self::ROW_COUNT_GLYPH_UV;
+    /// Number of elements for the offset and scale of a glyph in the glyph
+    /// atlas. Those rows follow the rectangle ones of the glyph.
+    const ROW_COUNT_GLYPH_UV: u32 = 4;
+    /// Number of extra elements per glyph when the text is outlined. Those
+    /// extra rows follow the glyph ones.
+    const ROW_PER_GLYPH_BORDER: u32 = 3;
+
+    /// Transform the text, rotating it with the transform and scaling it by
+    /// the mean scale of the transform, around its origin. A reflection
+    /// mirrors the text.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        let scale = transform_scale(transform);
+        let (rotation, _) = transform_frame(transform, self.rotation);
+        let offset = transform.transform_point2(self.rect.min) - self.rect.min;
+        self.rect.min += offset;
+        self.rect.max += offset;
+        self.rotation = rotation;
+        self.scale *= scale;
+        if is_reflection(transform) {
+            self.flip_y = !self.flip_y;
+        }
+        self.border_width *= scale;
+    }
+
+    /// Is the text neither rotated, scaled, nor mirrored?
+    ///
+    /// Such a text is drawn with its glyphs aligned on the pixel grid.
+    fn is_axis_aligned(&self) -> bool {
+        self.rotation == Rot2::IDENTITY && self.scale == 1. && !self.flip_y
+    }
+
+    /// Get the center of a rectangle of the text relative to the text origin,
+    /// given by its offset from that origin and its half size, both before the
+    /// text is rotated, but after it's scaled.
+    fn local_center(&self, offset: Vec2, half_size: Vec2) -> Vec2 {
+        let center = offset * self.scale + half_size;
+        let center = if self.flip_y {
+            Vec2::new(center.x, -center.y)
+        } else {
+            center
+        };
+        self.rotation * center
+    }
+
+    /// Get the AABB of a rectangle of the text, given by its offset from the
+    /// text origin and its size, before the text is rotated and scaled.
+    pub(crate) fn local_aabb(&self, offset: Vec2, size: Vec2) -> Aabb2d {
+        let half_size = size * (self.scale / 2.);
+        let center = self.rect.min + self.local_center(offset, half_size);
+        let extent = rotated_rect_extent(half_size, self.rotation);
+        Aabb2d {
+            min: center - extent,
+            max: center + extent,
+        }
+    }
+
+    /// Is the text outlined?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
+    /// Grow the AABB of a single glyph to cover its outline and effects, then
+    /// cut it by the clip rectangle, if any.
+    pub(crate) fn grow_glyph_aabb(&self, tables: &EffectTables, aabb: Aabb2d) -> Aabb2d {
+        let margin = self.border_width.max(0.);
+        let effects = tables.effects(self.effects);
+        effects.clip_aabb(effects.grow_aabb(Aabb2d {
+            min: aabb.min - margin,
+            max: aabb.max + margin,
+        }))
+    }
+
+    /// Number of rows per glyph, excluding effects.
+    fn glyph_row_count(&self) -> u32 {
+        let mut rows = Self::ROW_PER_GLYPH;
+        if self.is_bordered() {
+            rows += Self::ROW_PER_GLYPH_BORDER;
+        }
+        rows
+    }
+
+    /// Get the AABB of this text.
+    pub fn aabb(&self, canvas: &ExtractedCanvas) -> Aabb2d {
+        let text = &canvas.texts[self.id as usize];
+        let mut aabb = self.local_aabb(Vec2::ZERO, self.rect.size());
+        trace!("Text #{:?} aabb={:?}", self.id, aabb);
+        for glyph in &text.glyphs {
+            let glyph_aabb = self.local_aabb(glyph.offset, glyph.size);
+            aabb.min = aabb.min.min(glyph_aabb.min);
+            aabb.max = aabb.max.max(glyph_aabb.max);
+            trace!(
+                "  > add glyph offset={:?} size={:?}, new aabb {:?}",
+                glyph.offset,
+                glyph.size,
+                aabb
+            );
+        }
+        aabb
+    }
+
+    fn info(&self, texts: &[ExtractedText]) -> PrimitiveInfo {
+        let index = self.id as usize;
+        if index < texts.len() {
+            let glyph_count = texts[index].glyphs.len() as u32;
+            PrimitiveInfo {
+                row_count: self.glyph_row_count(),
+                sub_prim_count: glyph_count,
+            }
+        } else {
+            PrimitiveInfo {
+                row_count: 0,
+                sub_prim_count: 0,
+            }
+        }
+    }
+
+    fn write(
+        &self,
+        texts: &[ExtractedText],
+        tables: &EffectTables,
+        mask_indices: &[u32],
+        prim: &mut [MaybeUninit<f32>],
+        canvas_translation: Vec2,
+        scale_factor: f32,
+    ) {
+        let index = self.id as usize;
+        let glyphs = &texts[index].glyphs;
+        let glyph_count = glyphs.len();
+        // Each glyph is followed by its own copy of the border and effects rows, if
+        // any
+        let glyph_rows = self.glyph_row_count() as usize;
+        let effects = tables.effects(self.effects);
+        let stride = glyph_rows + effects.row_count(tables) as usize;
+        assert_eq!(glyph_count * stride, prim.len());
+        let mut ip = 0;
+        let origin = (self.rect.min + canvas_translation) * scale_factor;
+        let is_axis_aligned = self.is_axis_aligned();
+        //let inv_scale_factor = 1. / scale_factor;
+        for i in 0..glyph_count {
+            let half_size = glyphs[i].size * (self.scale / 2.);
+
+            // let x = x * inv_scale_factor;
+            // let y = y * inv_scale_factor;
+            // let hw = hw * inv_scale_factor;
+            // let hh = hh * inv_scale_factor;
+
+            // Glyph position is center of rect, we need bottom-left corner
+            //let x = x - w / 2.;
+            //let y = y - h / 2.;
+
+            // FIXME - hard-coded texture size
+            let uv_x = glyphs[i].uv_rect.min.x / 1024.0;
+            let uv_y = glyphs[i].uv_rect.min.y / 1024.0;
+            let uv_w = glyphs[i].uv_rect.max.x / 1024.0 - uv_x;
+            let uv_h = glyphs[i].uv_rect.max.y / 1024.0 - uv_y;
+
+            // Glyph UV is flipped vertically
+            // let uv_y = uv_y + uv_h;
+            // let uv_h = -uv_h;
+
+            // center pos
+            // we round() here to work around a bug: if the pixel rect is not aligned on the
+            // screen pixel grid, the UV coordinates may end up being < 0.5 or >
+            // w + 0.5, which then bleeds into adjacent pixels. it looks like
+            // the rasterizing of the glyphs already adds 1 pixel border, so we should
+            // remove that border in the SDF rect, so that we never sample the
+            // texture beyond half that 1 px border, which would linearly blend
+            // with the next pixel (outside the glyph rect). Rotated or scaled glyphs
+            // can't be aligned on the pixel grid anyway.
+            let center = if is_axis_aligned {
+                (origin + glyphs[i].offset).round() + half_size
+            } else {
+                origin + self.local_center(glyphs[i].offset, half_size)
+            };
+            prim[ip + 0].write(center.x);
+            prim[ip + 1].write(center.y);
+
+            // half size
+            prim[ip + 2].write(half_size.x);
+            prim[ip + 3].write(half_size.y);
+
+            // rotation (cos, sin)
+            prim[ip + 4].write(self.rotation.cos);
+            prim[ip + 5].write(self.rotation.sin);
+
+            // corner radii
+            prim[ip + 6].write(0.);
+            prim[ip + 7].write(0.);
+            prim[ip + 8].write(0.);
+            prim[ip + 9].write(0.);
+
+            // color
+            prim[ip + 10].write(bytemuck::cast(glyphs[i].color));
+
+            // uv_offset (at center pos)
+            prim[ip + 11].write(uv_x + uv_w / 2.0);
+            prim[ip + 12].write(uv_y + uv_h / 2.0);
+
+            // uv_scale, negative to sample the glyph upside down if mirrored
+            let uv_scale = 1.0 / (1024.0 * self.scale);
+            prim[ip + 13].write(uv_scale);
+            prim[ip + 14].write(if self.flip_y { -uv_scale } else { uv_scale });
+
+            // outline
+            if self.is_bordered() {
+                write_border(
+                    &mut prim[ip + 15..],
+                    self.border_width,
+                    StrokeAlignment::Outside,
+                    self.border_color,
+                    scale_factor,
+                );
+            }
+
+            // effects
+            effects.write(
+                &mut prim[ip + glyph_rows..ip + stride],
+                tables,
+                mask_indices,
+                canvas_translation,
+                scale_factor,
+            );
+
+            ip += stride;
+        }
+    }
+}
+
+/// A quarter of an ellipse, with a color.
+///
+/// By default the quarter pie covers the quadrant of positive X and Y from its
+/// origin, in its local frame rotated by [`rotation`]. Use [`flip_x`] and
+/// [`flip_y`] to select another quadrant.
+///
+/// [`rotation`]: QuarterPiePrimitive::rotation
+/// [`flip_x`]: QuarterPiePrimitive::flip_x
+/// [`flip_y`]: QuarterPiePrimitive::flip_y
+#[derive(Debug, Clone, Copy)]
+pub struct QuarterPiePrimitive {
+    /// Origin of the pie.
+    pub origin: Vec2,
+    /// Radii of the (elliptical) pie, along its local X and Y axes.
+    pub radii: Vec2,
+    /// Rotation of the pie around its origin.
+    pub rotation: Rot2,
+    /// Uniform rectangle color.
+    pub color: Color,
+    /// Flip the quarter pie along the horizontal axis.
+    pub flip_x: bool,
+    /// Flip the quarter pie along the vertical axis.
+    pub flip_y: bool,
+    /// Size of the border, if any, or zero if no border. Negative values or
+    /// zero mean no border.
+    pub border_width: f32,
+    /// Border color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// Alignment of the border relative to the edge of the quarter pie.
+    pub border_alignment: StrokeAlignment,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl Default for QuarterPiePrimitive {
+    fn default() -> Self {
+        Self {
+            origin: Vec2::ZERO,
+            radii: Vec2::ONE,
+            rotation: Rot2::IDENTITY,
+            color: Color::default(),
+            flip_x: false,
+            flip_y: false,
+            border_width: 0.,
+            border_color: Color::NONE,
+            border_alignment: StrokeAlignment::default(),
+            effects: None,
+        }
+    }
+}
+
+impl QuarterPiePrimitive {
+    /// Number of primitive buffer rows (4 bytes) per primitive.
+    const ROW_COUNT_BASE: u32 = 7;
+    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
+    /// when bordered.
+    const ROW_COUNT_BORDER: u32 = 3;
+
+    /// Get the AABB of the whole ellipse the quarter pie is part of.
+    pub fn aabb(&self) -> Aabb2d {
+        let radii = self.radii + self.border_alignment.outset(self.border_width);
+        let half_size = rotated_ellipse_extent(radii, self.rotation);
+        Aabb2d {
+            min: self.origin - half_size,
+            max: self.origin + half_size,
+        }
+    }
+
+    /// The pie center.
+    pub fn center(&self) -> Vec3 {
+        self.origin.extend(0.)
+    }
+
+    /// Transform the quarter pie, rotating it with the transform.
+    ///
+    /// A reflection moves the pie to the mirrored quadrant.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        let (rotation, axis_scale) = transform_frame(transform, self.rotation);
+        self.origin = transform.transform_point2(self.origin);
+        self.radii *= axis_scale;
+        self.rotation = rotation;
+        if is_reflection(transform) {
+            self.flip_y = !self.flip_y;
+        }
+        self.border_width *= transform_scale(transform);
+    }
+
+    /// Is the primitive bordered?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
+    #[inline]
+    fn row_count(&self) -> u32 {
+        let mut rows = Self::ROW_COUNT_BASE;
+        if self.is_bordered() {
+            rows += Self::ROW_COUNT_BORDER;
+        }
+        rows
+    }
+
+    fn info(&self) -> PrimitiveInfo {
+        PrimitiveInfo {
+            row_count: self.row_count(),
+            sub_prim_count: 1,
+        }
+    }
+
+    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
+        assert_eq!(self.row_count() as usize, prim.len());
+        let radii_mask = BVec2::new(self.flip_x, self.flip_y);
+        let signed_radii = Vec2::select(radii_mask, -self.radii, self.radii);
+        prim[0].write((self.origin.x + canvas_translation.x) * scale_factor);
+        prim[1].write((self.origin.y + canvas_translation.y) * scale_factor);
+        prim[2].write(signed_radii.x * scale_factor);
+        prim[3].write(signed_radii.y * scale_factor);
+        prim[4].write(self.rotation.cos);
+        prim[5].write(self.rotation.sin);
+        prim[6].write(bytemuck::cast(self.color.to_linear().as_u32()));
+        if self.is_bordered() {
+            write_border(
+                &mut prim[7..],
+                self.border_width,
+                self.border_alignment,
+                self.border_color,
+                scale_factor,
+            );
+        }
+    }
+}
+
+/// An ellipse with a color, either filled or stroked.
+///
+/// The ellipse is rendered with an exact signed distance function, so it
+/// supports anti-aliasing and borders like any other primitive.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EllipsePrimitive {
+    /// Center of the ellipse.
+    pub center: Vec2,
+    /// Radii of the ellipse along its local X and Y axes.
+    pub radii: Vec2,
+    /// Rotation of the ellipse around its center.
+    pub rotation: Rot2,
+    /// Uniform ellipse color.
+    pub color: Color,
+    /// Thickness of the stroke along the ellipse's edge. Set to zero to fill
+    /// the ellipse instead.
+    pub stroke_width: f32,
+    /// Alignment of the stroke relative to the ellipse's edge.
+    pub stroke_alignment: StrokeAlignment,
+    /// Size of the border, if any, or zero if no border. Negative values or
+    /// zero mean no border.
+    pub border_width: f32,
+    /// Border color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// Alignment of the border relative to the edge of the ellipse, or of its
+    /// stroke if stroked.
+    pub border_alignment: StrokeAlignment,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl EllipsePrimitive {
+    /// Number of primitive buffer rows (4 bytes) per primitive.
+    const ROW_COUNT_BASE: u32 = 9;
+    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
+    /// when bordered. Those extra rows follow the base ones.
+    const ROW_COUNT_BORDER: u32 = 3;
+
+    /// Get the AABB of this ellipse.
+    pub fn aabb(&self) -> Aabb2d {
+        let radii = self.radii
+            + self.stroke_alignment.outset(self.stroke_width)
+            + self.border_alignment.outset(self.border_width);
+        let half_size = rotated_ellipse_extent(radii, self.rotation);
+        Aabb2d {
+            min: self.center - half_size,
+            max: self.center + half_size,
+        }
+    }
+
+    /// Is the ellipse stroked instead of filled?
+    pub fn is_stroked(&self) -> bool {
+        self.stroke_width > 0.
+    }
+
+    /// Transform the ellipse, rotating it with the transform.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        let scale = transform_scale(transform);
+        let (rotation, axis_scale) = transform_frame(transform, self.rotation);
+        self.center = transform.transform_point2(self.center);
+        self.radii *= axis_scale;
+        self.rotation = rotation;
+        self.stroke_width *= scale;
+        self.border_width *= scale;
+    }
+
+    /// Is the primitive bordered?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
+    #[inline]
+    fn row_count(&self) -> u32 {
+        let mut rows = Self::ROW_COUNT_BASE;
+        if self.is_bordered() {
+            rows += Self::ROW_COUNT_BORDER;
+        }
+        rows
+    }
+
+    fn info(&self) -> PrimitiveInfo {
+        PrimitiveInfo {
+            row_count: self.row_count(),
+            sub_prim_count: 1,
+        }
+    }
+
+    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
+        assert_eq!(
+            self.row_count() as usize,
+            prim.len(),
+            "Invalid buffer size {} to write EllipsePrimitive (needs {})",
+            prim.len(),
+            self.row_count()
+        );
+
+        prim[0].write((self.center.x + canvas_translation.x) * scale_factor);
+        prim[1].write((self.center.y + canvas_translation.y) * scale_factor);
+        prim[2].write(self.radii.x.max(0.) * scale_factor);
+        prim[3].write(self.radii.y.max(0.) * scale_factor);
+        prim[4].write(self.rotation.cos);
+        prim[5].write(self.rotation.sin);
+        prim[6].write(bytemuck::cast(self.color.to_linear().as_u32()));
+        prim[7].write(self.stroke_width.max(0.) * scale_factor);
+        prim[8].write(stroke_offset(self.stroke_width, self.stroke_alignment) * scale_factor);
+        if self.is_bordered() {
+            write_border(
+                &mut prim[9..],
+                self.border_width,
+                self.border_alignment,
+                self.border_color,
+                scale_factor,
+            );
+        }
+    }
+}
+
+/// A single contour of a [`FlattenedPath`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PathContour {
+    /// Number of consecutive points of the contour in
+    /// [`FlattenedPath::points`].
+    pub point_count: u32,
+    /// Is the contour closed? Closed contours have an implicit segment joining
+    /// their last point back to their first one. Contours are always treated
+    /// as closed when filling.
+    pub closed: bool,
+}
+
+/// Path geometry flattened into line segments.
+///
+/// This is the format of a [`Path`] once extracted from its [`Canvas`], where
+/// any curve has been approximated by a sequence of line segments.
+///
+/// [`Path`]: crate::shapes::Path
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct FlattenedPath {
+    /// Points of all contours, in canvas space, contour after contour.
+    pub points: Vec<Vec2>,
+    /// Contours of the path, in order.
+    pub contours: Vec<PathContour>,
+}
+
+/// An arbitrary path with a color, either filled or stroked.
+///
+/// Like [`TextPrimitive`], the path geometry is not stored directly inside this
+/// struct, but inside its [`Canvas`], and referenced by [`id`]. The geometry is
+/// flattened into line segments when extracted, and rendered as a single
+/// primitive with its own signed distance function, so there's no seam between
+/// segments. Filling uses the non-zero winding rule.
+///
+/// [`id`]: crate::canvas::PathPrimitive::id
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PathPrimitive {
+    /// Unique ID of the path geometry inside its owner [`Canvas`].
+    pub id: u32,
+    /// Bounding rectangle of the path geometry, excluding any stroke.
+    pub bounds: Rect,
+    /// Uniform path color.
+    pub color: Color,
+    /// Thickness of the stroke along the path. Set to zero to fill the path
+    /// instead.
+    pub stroke_width: f32,
+    /// Alignment of the stroke relative to the path. The inside of open
+    /// contours is the one of the contour implicitly closed.
+    pub stroke_alignment: StrokeAlignment,
+    /// Size of the border, if any, or zero if no border. Negative values or
+    /// zero mean no border.
+    pub border_width: f32,
+    /// Border color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// Alignment of the border relative to the edge of the path, or of its
+    /// stroke if stroked.
+    pub border_alignment: StrokeAlignment,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl PathPrimitive {
+    /// Number of primitive buffer rows (4 bytes) per primitive, excluding the
+    /// contours and their points.
+    const ROW_COUNT_BASE: u32 = 4;
+    /// Number of primitive buffer rows (4 bytes) per contour header.
+    const ROW_COUNT_CONTOUR: u32 = 1;
+    /// Number of primitive buffer rows (4 bytes) per point.
+    const ROW_COUNT_POINT: u32 = 2;
+    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
+    /// when bordered. Those extra rows follow the points.
+    const ROW_COUNT_BORDER: u32 = 3;
+
+    /// Get the AABB of this path.
+    pub fn aabb(&self) -> Aabb2d {
+        let margin = self.stroke_alignment.outset(self.stroke_width)
+            + self.border_alignment.outset(self.border_width);
+        Aabb2d {
+            min: self.bounds.min - margin,
+            max: self.bounds.max + margin,
+        }
+    }
+
+    /// Is the path stroked instead of filled?
+    pub fn is_stroked(&self) -> bool {
+        self.stroke_width > 0.
+    }
+
+    /// Transform the points of the path geometry stored in the canvas.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2, paths: &mut [Path]) {
+        let scale = transform_scale(transform);
+        if let Some(path) = paths.get_mut(self.id as usize) {
+            path.apply_transform(transform);
+            self.bounds = path.bounds();
+        }
+        self.stroke_width *= scale;
+        self.border_width *= scale;
+    }
+
+    /// Is the primitive bordered?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
+    fn row_count(&self, path: &FlattenedPath) -> u32 {
+        let mut rows = Self::ROW_COUNT_BASE
+            + path.contours.len() as u32 * Self::ROW_COUNT_CONTOUR
+            + path.points.len() as u32 * Self::ROW_COUNT_POINT;
+        if self.is_bordered() {
+            rows += Self::ROW_COUNT_BORDER;
+        }
+        rows
+    }
+
+    fn info(&self, paths: &[FlattenedPath]) -> PrimitiveInfo {
+        if let Some(path) = paths.get(self.id as usize) {
+            PrimitiveInfo {
+                row_count: self.row_count(path),
+                sub_prim_count: 1,
+            }
+        } else {
+            PrimitiveInfo {
+                row_count: 0,
+                sub_prim_count: 0,
+            }
+        }
+    }
+
+    fn write(
+        &self,
+        paths: &[FlattenedPath],
+        prim: &mut [MaybeUninit<f32>],
+        canvas_translation: Vec2,
+        scale_factor: f32,
+    ) {
+        let path = &paths[self.id as usize];
+        assert_eq!(
+            self.row_count(path) as usize,
+            prim.len(),
+            "Invalid buffer size {} to write PathPrimitive (needs {})",
+            prim.len(),
+            self.row_count(path)
+        );
+
+        prim[0].write(bytemuck::cast(self.color.to_linear().as_u32()));
+        prim[1].write(self.stroke_width.max(0.) * scale_factor);
+        prim[2].write(stroke_offset(self.stroke_width, self.stroke_alignment) * scale_factor);
+        prim[3].write(bytemuck::cast(path.contours.len() as u32));
+        let mut idx = 4;
+        let mut points = path.points.iter();
+        for contour in &path.contours {
+            let header = (contour.point_count & 0x7FFF_FFFF) | (contour.closed as u32) << 31;
+            prim[idx].write(bytemuck::cast(header));
+            idx += 1;
+            for p in points.by_ref().take(contour.point_count as usize) {
+                prim[idx].write((p.x + canvas_translation.x) * scale_factor);
+                prim[idx + 1].write((p.y + canvas_translation.y) * scale_factor);
+                idx += 2;
+            }
+        }
+        if self.is_bordered() {
+            write_border(
+                &mut prim[idx..],
+                self.border_width,
+                self.border_alignment,
+                self.border_color,
+                scale_factor,
+            );
+        }
+    }
+}
+
+/// An arc, annulus sector, or pie with a color, either filled or stroked.
+///
+/// The arc is the region between two concentric circles of radii
+/// [`inner_radius`] and [`outer_radius`], limited to the angular range starting
+/// at [`start_angle`] and spanning [`sweep_angle`]. An inner radius of zero
+/// produces a pie. Angles are in radians, and go from the X axis toward the Y
+/// axis.
+///
+/// [`inner_radius`]: ArcPrimitive::inner_radius
+/// [`outer_radius`]: ArcPrimitive::outer_radius
+/// [`start_angle`]: ArcPrimitive::start_angle
+/// [`sweep_angle`]: ArcPrimitive::sweep_angle
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ArcPrimitive {
+    /// Center of the circles the arc is part of.
+    pub center: Vec2,
+    /// Inner radius of the arc. Set to zero to draw a pie.
+    pub inner_radius: f32,
+    /// Outer radius of the arc.
+    pub outer_radius: f32,
+    /// Angle where the arc starts, in radians.
+    pub start_angle: f32,
+    /// Angle spanned by the arc from its start angle, in radians. Negative
+    /// values sweep in the opposite direction. Values larger than a full turn
+    /// draw a full annulus.
+    pub sweep_angle: f32,
+    /// Round the caps at both ends of the arc. The caps are half-disks
+    /// extending the arc beyond its angular range.
+    pub rounded_caps: bool,
+    /// Uniform arc color.
+    pub color: Color,
+    /// Thickness of the stroke along the arc's edge. Set to zero to fill the
+    /// arc instead.
+    pub stroke_width: f32,
+    /// Alignment of the stroke relative to the arc's edge.
+    pub stroke_alignment: StrokeAlignment,
+    /// Size of the border, if any, or zero if no border. Negative values or
+    /// zero mean no border.
+    pub border_width: f32,
+    /// Border color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// Alignment of the border relative to the edge of the arc, or of its
+    /// stroke if stroked.
+    pub border_alignment: StrokeAlignment,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl ArcPrimitive {
+    /// Number of primitive buffer rows (4 bytes) per primitive.
+    const ROW_COUNT_BASE: u32 = 12;
+    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
+    /// when bordered. Those extra rows follow the base ones.
+    const ROW_COUNT_BORDER: u32 = 3;
+
+    /// Get the AABB of this arc.
+    ///
+    /// The AABB encloses the end points of the arc and the extreme points of
+    /// its outer circle inside its angular range, which makes it tight for most
+    /// arcs, and not only for full circles.
+    pub fn aabb(&self) -> Aabb2d {
+        let inner = self.inner_radius.max(0.);
+        let outer = self.outer_radius.max(inner);
+        let sweep = self.sweep_angle.abs().min(std::f32::consts::TAU);
+        let start = if self.sweep_angle < 0. {
+            self.start_angle - sweep
+        } else {
+            self.start_angle
+        };
+        let end = start + sweep;
+
+        let dir0 = Vec2::from_angle(start);
+        let dir1 = Vec2::from_angle(end);
+        let mut min = (dir0 * inner)
+            .min(dir0 * outer)
+            .min(dir1 * inner)
+            .min(dir1 * outer);
+        let mut max = (dir0 * inner)
+            .max(dir0 * outer)
+            .max(dir1 * inner)
+            .max(dir1 * outer);
+
+        // Add any extreme point of the outer circle inside the angular range
+        let first_quadrant = (start / std::f32::consts::FRAC_PI_2).ceil() as i32;
+        let last_quadrant = (end / std::f32::consts::FRAC_PI_2).floor() as i32;
+        for q in first_quadrant..=last_quadrant.min(first_quadrant + 3) {
+            let p = match q.rem_euclid(4) {
+                0 => Vec2::X,
+                1 => Vec2::Y,
+                2 => Vec2::NEG_X,
+                _ => Vec2::NEG_Y,
+            } * outer;
+            min = min.min(p);
+            max = max.max(p);
+        }
+
+        // Round caps extend beyond the angular range by half the arc width
+        let mut margin = self.stroke_alignment.outset(self.stroke_width)
+            + self.border_alignment.outset(self.border_width);
+        if self.rounded_caps {
+            margin += (outer - inner) / 2.;
+        }
+        Aabb2d {
+            min: self.center + min - margin,
+            max: self.center + max + margin,
+        }
+    }
+
+    /// Is the arc stroked instead of filled?
+    pub fn is_stroked(&self) -> bool {
+        self.stroke_width > 0.
+    }
+
+    /// Transform the arc, rotating its start angle with the transform.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        let scale = transform_scale(transform);
+        self.center = transform.transform_point2(self.center);
+        self.inner_radius *= scale;
+        self.outer_radius *= scale;
+        self.start_angle = transform_angle(transform, self.start_angle);
+        if is_reflection(transform) {
+            self.sweep_angle = -self.sweep_angle;
+        }
+        self.stroke_width *= scale;
+        self.border_width *= scale;
+    }
+
+    /// Is the primitive bordered?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
+    #[inline]
+    fn row_count(&self) -> u32 {
+        let mut rows = Self::ROW_COUNT_BASE;
+        if self.is_bordered() {
+            rows += Self::ROW_COUNT_BORDER;
+        }
+        rows
+    }
+
+    fn info(&self) -> PrimitiveInfo {
+        PrimitiveInfo {
+            row_count: self.row_count(),
+            sub_prim_count: 1,
+        }
+    }
+
+    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
+        assert_eq!(
+            self.row_count() as usize,
+            prim.len(),
+            "Invalid buffer size {} to write ArcPrimitive (needs {})",
+            prim.len(),
+            self.row_count()
+        );
+
+        // The shader works in a local frame where the arc is symmetric around the
+        // Y axis, so only needs the direction of the middle of the arc and the
+        // half-aperture of the arc.
+        let inner = self.inner_radius.max(0.);
+        let outer = self.outer_radius.max(inner);
+        let half_aperture = (self.sweep_angle.abs() / 2.).min(std::f32::consts::PI);
+        let mid_dir = Vec2::from_angle(self.start_angle + self.sweep_angle / 2.);
+        let is_full_turn = self.sweep_angle.abs() >= std::f32::consts::TAU;
+        let flags = (self.rounded_caps as u32) | (is_full_turn as u32) << 1;
+        prim[0].write((self.center.x + canvas_translation.x) * scale_factor);
+        prim[1].write((self.center.y + canvas_translation.y) * scale_factor);
+        prim[2].write(inner * scale_factor);
+        prim[3].write(outer * scale_factor);
+        prim[4].write(bytemuck::cast(self.color.to_linear().as_u32()));
+        prim[5].write(mid_dir.x);
+        prim[6].write(mid_dir.y);
+        prim[7].write(half_aperture.sin());
+        prim[8].write(half_aperture.cos());
+        prim[9].write(bytemuck::cast(flags));
+        prim[10].write(self.stroke_width.max(0.) * scale_factor);
+        prim[11].write(stroke_offset(self.stroke_width, self.stroke_alignment) * scale_factor);
+        if self.is_bordered() {
+            write_border(
+                &mut prim[12..],
+                self.border_width,
+                self.border_alignment,
+                self.border_color,
+                scale_factor,
+            );
+        }
+    }
+}
+
+/// Connected line segments with a color and thickness, joins, and caps.
+///
+/// Like [`PathPrimitive`], the points are not stored directly inside this
+/// struct, but inside its [`Canvas`] as a path with a single open contour, and
+/// referenced by [`id`]. All segments are rendered as a single
+/// primitive, so each pixel is drawn once even where segments overlap, which
+/// keeps translucent polylines uniform.
+///
+/// [`id`]: crate::canvas::PolylinePrimitive::id
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PolylinePrimitive {
+    /// Unique ID of the polyline points inside its owner [`Canvas`].
+    pub id: u32,
+    /// Bounding rectangle of the polyline points, excluding its thickness.
+    pub bounds: Rect,
+    /// Uniform polyline color.
+    pub color: Color,
+    /// Thickness of the polyline. The polyline extends equally by
+    /// `thickness / 2.` on both sides of its segments.
+    pub thickness: f32,
+    /// Shape of the joins between consecutive segments.
+    pub join: LineJoin,
+    /// Shape of the two ends of the polyline.
+    pub cap: LineCap,
+    /// Size of the border, if any, or zero if no border. Negative values or
+    /// zero mean no border.
+    pub border_width: f32,
+    /// Border color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// Alignment of the border relative to the edge of the polyline.
+    pub border_alignment: StrokeAlignment,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl PolylinePrimitive {
+    /// Number of primitive buffer rows (4 bytes) per primitive, excluding the
+    /// points.
+    const ROW_COUNT_BASE: u32 = 4;
+    /// Number of primitive buffer rows (4 bytes) per point.
+    const ROW_COUNT_POINT: u32 = 2;
+    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
+    /// when bordered. Those extra rows follow the points.
+    const ROW_COUNT_BORDER: u32 = 3;
+
+    /// Get the AABB of this polyline.
+    ///
+    /// This is conservative, assuming the longest possible miter joins and
+    /// square caps.
+    pub fn aabb(&self) -> Aabb2d {
+        let half_thickness = self.thickness.max(0.) / 2.;
+        let extent = if self.join == LineJoin::Miter {
+            half_thickness * MITER_LIMIT
+        } else {
+            half_thickness * std::f32::consts::SQRT_2
+        };
+        let margin = extent + self.border_alignment.outset(self.border_width);
+        Aabb2d {
+            min: self.bounds.min - margin,
+            max: self.bounds.max + margin,
+        }
+    }
+
+    /// Transform the points of the polyline geometry stored in the canvas.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2, paths: &mut [Path]) {
+        let scale = transform_scale(transform);
+        if let Some(path) = paths.get_mut(self.id as usize) {
+            path.apply_transform(transform);
+            self.bounds = path.bounds();
+        }
+        self.thickness *= scale;
+        self.border_width *= scale;
+    }
+
+    /// Is the primitive bordered?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
+    fn row_count(&self, path: &FlattenedPath) -> u32 {
+        let mut rows = Self::ROW_COUNT_BASE + path.points.len() as u32 * Self::ROW_COUNT_POINT;
+        if self.is_bordered() {
+            rows += Self::ROW_COUNT_BORDER;
+        }
+        rows
+    }
+
+    fn info(&self, paths: &[FlattenedPath]) -> PrimitiveInfo {
+        if let Some(path) = paths.get(self.id as usize) {
+            PrimitiveInfo {
+                row_count: self.row_count(path),
+                sub_prim_count: 1,
+            }
+        } else {
+            PrimitiveInfo {
+                row_count: 0,
+                sub_prim_count: 0,
+            }
+        }
+    }
+
+    fn write(
+        &self,
+        paths: &[FlattenedPath],
+        prim: &mut [MaybeUninit<f32>],
+        canvas_translation: Vec2,
+        scale_factor: f32,
+    ) {
+        let path = &paths[self.id as usize];
+        assert_eq!(
+            self.row_count(path) as usize,
+            prim.len(),
+            "Invalid buffer size {} to write PolylinePrimitive (needs {})",
+            prim.len(),
+            self.row_count(path)
+        );
+
+        let style = self.join as u32 | (self.cap as u32) << 8;
+        prim[0].write(bytemuck::cast(self.color.to_linear().as_u32()));
+        prim[1].write(self.thickness.max(0.) * scale_factor);
+        prim[2].write(bytemuck::cast(style));
+        prim[3].write(bytemuck::cast(path.points.len() as u32));
+        let mut idx = 4;
+        for p in &path.points {
+            prim[idx].write((p.x + canvas_translation.x) * scale_factor);
+            prim[idx + 1].write((p.y + canvas_translation.y) * scale_factor);
+            idx += 2;
+        }
+        if self.is_bordered() {
+            write_border(
+                &mut prim[idx..],
+                self.border_width,
+                self.border_alignment,
+                self.border_color,
+                scale_factor,
+            );
+        }
+    }
+}
+
+/// A polygon with a color, either filled or stroked.
+///
+/// The vertices are stored inline, so the polygon has at most
+/// [`MAX_POLYGON_VERTICES`] vertices. It's rendered with an exact signed
+/// distance function, so it supports anti-aliasing and borders like any other
+/// primitive. Polygons with fewer than 3 vertices are not drawn.
+///
+/// [`MAX_POLYGON_VERTICES`]: crate::shapes::MAX_POLYGON_VERTICES
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PolygonPrimitive {
+    /// The polygon geometry.
+    pub polygon: Polygon,
+    /// Uniform polygon color.
+    pub color: Color,
+    /// Thickness of the stroke along the polygon's edges. Set to zero to fill
+    /// the polygon instead.
+    pub stroke_width: f32,
+    /// Alignment of the stroke relative to the polygon's edges.
+    pub stroke_alignment: StrokeAlignment,
+    /// Size of the border, if any, or zero if no border. Negative values or
+    /// zero mean no border.
+    pub border_width: f32,
+    /// Border color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// Alignment of the border relative to the edge of the polygon, or of its
+    /// stroke if stroked.
+    pub border_alignment: StrokeAlignment,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl PolygonPrimitive {
+    /// Number of primitive buffer rows (4 bytes) per primitive, excluding the
+    /// vertices.
+    const ROW_COUNT_BASE: u32 = 4;
+    /// Number of primitive buffer rows (4 bytes) per vertex.
+    const ROW_COUNT_VERTEX: u32 = 2;
+    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
+    /// when bordered. Those extra rows follow the vertices.
+    const ROW_COUNT_BORDER: u32 = 3;
+
+    /// Get the AABB of this polygon.
+    ///
+    /// The bounds of the vertices are grown by the outset of the stroke plus
+    /// the outset of the border. The distance to the polygon is exact, so the
+    /// stroke and border are rounded around the vertices and never extend past
+    /// that margin.
+    pub fn aabb(&self) -> Aabb2d {
+        let bounds = self.polygon.bounds();
+        let margin = self.stroke_alignment.outset(self.stroke_width)
+            + self.border_alignment.outset(self.border_width);
+        Aabb2d {
+            min: bounds.min - margin,
+            max: bounds.max + margin,
+        }
+    }
+
+    /// Is the polygon stroked instead of filled?
+    pub fn is_stroked(&self) -> bool {
+        self.stroke_width > 0.
+    }
+
+    /// Transform the vertices of the polygon.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        let scale = transform_scale(transform);
+        self.polygon = Polygon::new(
+            self.polygon
+                .vertices()
+                .iter()
+                .map(|v| transform.transform_point2(*v)),
+        );
+        self.stroke_width *= scale;
+        self.border_width *= scale;
+    }
+
+    /// Is the primitive bordered?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
+    fn row_count(&self) -> u32 {
+        let mut rows =
+            Self::ROW_COUNT_BASE + self.polygon.vertices().len() as u32 * Self::ROW_COUNT_VERTEX;
+        if self.is_bordered() {
+            rows += Self::ROW_COUNT_BORDER;
+        }
+        rows
+    }
+
+    fn info(&self) -> PrimitiveInfo {
+        if self.polygon.vertices().len() < 3 {
+            PrimitiveInfo {
+                row_count: 0,
+                sub_prim_count: 0,
+            }
+        } else {
+            PrimitiveInfo {
+                row_count: self.row_count(),
+                sub_prim_count: 1,
+            }
+        }
+    }
+
+    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
+        assert_eq!(
+            self.row_count() as usize,
+            prim.len(),
+            "Invalid buffer size {} to write PolygonPrimitive (needs {})",
+            prim.len(),
+            self.row_count()
+        );
+
+        let vertices = self.polygon.vertices();
+        prim[0].write(bytemuck::cast(self.color.to_linear().as_u32()));
+        prim[1].write(self.stroke_width.max(0.) * scale_factor);
+        prim[2].write(stroke_offset(self.stroke_width, self.stroke_alignment) * scale_factor);
+        prim[3].write(bytemuck::cast(vertices.len() as u32));
+        let mut idx = 4;
+        for p in vertices {
+            prim[idx].write((p.x + canvas_translation.x) * scale_factor);
+            prim[idx + 1].write((p.y + canvas_translation.y) * scale_factor);
+            idx += 2;
+        }
+        if self.is_bordered() {
+            write_border(
+                &mut prim[idx..],
+                self.border_width,
+                self.border_alignment,
+                self.border_color,
+                scale_factor,
+            );
+        }
+    }
+}
+
+/// A star or regular polygon with a color, either filled or stroked.
+///
+/// The star alternates between outer vertices on a circle of radius
+/// [`outer_radius`] and inner vertices on a circle of radius [`inner_radius`],
+/// halfway in between. A regular polygon is a star whose inner vertices are in
+/// the middle of its edges. With a zero rotation, the first outer vertex
+/// points toward -Y, that is up on the Y-down canvas.
+///
+/// [`outer_radius`]: crate::canvas::StarPrimitive::outer_radius
+/// [`inner_radius`]: crate::canvas::StarPrimitive::inner_radius
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StarPrimitive {
+    /// Center of the star.
+    pub center: Vec2,
+    /// Number of outer vertices. Stars with fewer than 2 points are not drawn.
+    pub points: u32,
+    /// Distance from the center to the outer vertices.
+    pub outer_radius: f32,
+    /// Distance from the center to the inner vertices. Clamped to
+    /// [`outer_radius`].
+    ///
+    /// [`outer_radius`]: crate::canvas::StarPrimitive::outer_radius
+    pub inner_radius: f32,
+    /// Rotation of the star around its center.
+    pub rotation: Rot2,
+    /// Radius of the rounded outer corners. Inner corners are never rounded.
+    /// Set to zero for sharp corners.
+    pub corner_radius: f32,
+    /// Uniform star color.
+    pub color: Color,
+    /// Thickness of the stroke along the star's edges. Set to zero to fill
+    /// the star instead.
+    pub stroke_width: f32,
+    /// Alignment of the stroke relative to the star's edges.
+    pub stroke_alignment: StrokeAlignment,
+    /// Size of the border, if any, or zero if no border. Negative values or
+    /// zero mean no border.
+    pub border_width: f32,
+    /// Border color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// Alignment of the border relative to the edge of the star, or of its
+    /// stroke if stroked.
+    pub border_alignment: StrokeAlignment,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl StarPrimitive {
+    /// Number of primitive buffer rows (4 bytes) per primitive.
+    const ROW_COUNT_BASE: u32 = 11;
+    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
+    /// when bordered. Those extra rows follow the base ones.
+    const ROW_COUNT_BORDER: u32 = 3;
+
+    /// Get the AABB of this star.
+    ///
+    /// This is the AABB of the circle through the outer vertices, which is
+    /// conservative for any rotation.
+    pub fn aabb(&self) -> Aabb2d {
+        let half_size = self.outer_radius.max(0.)
+            + self.stroke_alignment.outset(self.stroke_width)
+            + self.border_alignment.outset(self.border_width);
+        Aabb2d {
+            min: self.center - half_size,
+            max: self.center + half_size,
+        }
+    }
+
+    /// Is the star stroked instead of filled?
+    pub fn is_stroked(&self) -> bool {
+        self.stroke_width > 0.
+    }
+
+    /// Transform the star, rotating it with the transform.
+    ///
+    /// The star is symmetric across the axis of its first tip, so a reflection
+    /// only turns it upside down.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        let scale = transform_scale(transform);
+        self.center = transform.transform_point2(self.center);
+        self.rotation = transform_frame(transform, self.rotation).0;
+        if is_reflection(transform) {
+            self.rotation *= Rot2::PI;
+        }
+        self.outer_radius *= scale;
+        self.inner_radius *= scale;
+        self.corner_radius *= scale;
+        self.stroke_width *= scale;
+        self.border_width *= scale;
+    }
+
+    /// Is the primitive bordered?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
+    #[inline]
+    fn row_count(&self) -> u32 {
+        let mut rows = Self::ROW_COUNT_BASE;
+        if self.is_bordered() {
+            rows += Self::ROW_COUNT_BORDER;
+        }
+        rows
+    }
+
+    fn info(&self) -> PrimitiveInfo {
+        if self.points < 2 {
+            PrimitiveInfo {
+                row_count: 0,
+                sub_prim_count: 0,
+            }
+        } else {
+            PrimitiveInfo {
+                row_count: self.row_count(),
+                sub_prim_count: 1,
+            }
+        }
+    }
+
+    /// Outer and inner radii of the star with sharp corners whose outer
+    /// corners, once rounded by the corner radius, give this star.
+    ///
+    /// Rounding moves all edges outward by the corner radius, so the sharp star
+    /// is this star scaled down to move its edges inward by the same distance.
+    /// Returns the radii and the clamped corner radius.
+    fn sharp_radii(&self) -> (f32, f32, f32) {
+        let outer = self.outer_radius.max(0.);
+        let inner = self.inner_radius.clamp(0., outer);
+        let half_angle = std::f32::consts::PI / self.points as f32;
+        let v0 = Vec2::new(outer, 0.);
+        let v1 = Vec2::from_angle(half_angle) * inner;
+        let edge_length = v0.distance(v1);
+        if edge_length <= 0. {
+            return (outer, inner, 0.);
+        }
+        // Distance from the center to the edges
+        let apothem = v0.perp_dot(v1) / edge_length;
+        let corner_radius = self.corner_radius.clamp(0., apothem);
+        if corner_radius <= 0. {
+            return (outer, inner, 0.);
+        }
+        let scale = (apothem - corner_radius) / apothem;
+        (outer * scale, inner * scale, corner_radius)
+    }
+
+    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
+        assert_eq!(
+            self.row_count() as usize,
+            prim.len(),
+            "Invalid buffer size {} to write StarPrimitive (needs {})",
+            prim.len(),
+            self.row_count()
+        );
+
+        let (outer, inner, corner_radius) = self.sharp_radii();
+        prim[0].write((self.center.x + canvas_translation.x) * scale_factor);
+        prim[1].write((self.center.y + canvas_translation.y) * scale_factor);
+        prim[2].write(outer * scale_factor);
+        prim[3].write(inner * scale_factor);
+        prim[4].write(corner_radius * scale_factor);
+        prim[5].write(self.rotation.cos);
+        prim[6].write(self.rotation.sin);
+        prim[7].write(bytemuck::cast(self.points));
+        prim[8].write(bytemuck::cast(self.color.to_linear().as_u32()));
+        prim[9].write(self.stroke_width.max(0.) * scale_factor);
+        prim[10].write(stroke_offset(self.stroke_width, self.stroke_alignment) * scale_factor);
+        if self.is_bordered() {
+            write_border(
+                &mut prim[11..],
+                self.border_width,
+                self.border_alignment,
+                self.border_color,
+                scale_factor,
+            );
+        }
+    }
+}
+
+/// A capsule with a color, either filled or stroked.
+///
+/// The capsule is the set of points closer than [`radius`] to a segment, which
+/// gives a rectangle with two half-disc ends. Before rotation, the segment is
+/// along the X axis.
+///
+/// [`radius`]: crate::canvas::CapsulePrimitive::radius
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CapsulePrimitive {
+    /// Center of the capsule.
+    pub center: Vec2,
+    /// Half the length of the segment, excluding the half-disc ends.
+    pub half_length: f32,
+    /// Radius of the half-disc ends, which is also half the capsule width.
+    pub radius: f32,
+    /// Rotation of the capsule around its center.
+    pub rotation: Rot2,
+    /// Uniform capsule color.
+    pub color: Color,
+    /// Thickness of the stroke along the capsule's edge. Set to zero to fill
+    /// the capsule instead.
+    pub stroke_width: f32,
+    /// Alignment of the stroke relative to the capsule's edge.
+    pub stroke_alignment: StrokeAlignment,
+    /// Size of the border, if any, or zero if no border. Negative values or
+    /// zero mean no border.
+    pub border_width: f32,
+    /// Border color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// Alignment of the border relative to the edge of the capsule, or of its
+    /// stroke if stroked.
+    pub border_alignment: StrokeAlignment,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl CapsulePrimitive {
+    /// Number of primitive buffer rows (4 bytes) per primitive.
+    const ROW_COUNT_BASE: u32 = 8;
+    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
+    /// when bordered. Those extra rows follow the base ones.
+    const ROW_COUNT_BORDER: u32 = 3;
+
+    /// Vector from the center to the end of the segment, after rotation.
+    fn half_axis(&self) -> Vec2 {
+        self.rotation * Vec2::new(self.half_length.max(0.), 0.)
+    }
+
+    /// Get the AABB of this capsule.
+    pub fn aabb(&self) -> Aabb2d {
+        let half_size = self.half_axis().abs()
+            + self.radius.max(0.)
+            + self.stroke_alignment.outset(self.stroke_width)
+            + self.border_alignment.outset(self.border_width);
+        Aabb2d {
+            min: self.center - half_size,
+            max: self.center + half_size,
+        }
+    }
+
+    /// Is the capsule stroked instead of filled?
+    pub fn is_stroked(&self) -> bool {
+        self.stroke_width > 0.
+    }
+
+    /// Transform the capsule, rotating it with the transform.
+    ///
+    /// The length and radius are scaled by the scale of the transform along and
+    /// across the capsule, respectively.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        let scale = transform_scale(transform);
+        let (rotation, axis_scale) = transform_frame(transform, self.rotation);
+        self.center = transform.transform_point2(self.center);
+        self.rotation = rotation;
+        self.half_length *= axis_scale.x;
+        self.radius *= axis_scale.y;
+        self.stroke_width *= scale;
+        self.border_width *= scale;
+    }
+
+    /// Is the primitive bordered?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
+    #[inline]
+    fn row_count(&self) -> u32 {
+        let mut rows = Self::ROW_COUNT_BASE;
+        if self.is_bordered() {
+            rows += Self::ROW_COUNT_BORDER;
+        }
+        rows
+    }
+
+    fn info(&self) -> PrimitiveInfo {
+        PrimitiveInfo {
+            row_count: self.row_count(),
+            sub_prim_count: 1,
+        }
+    }
+
+    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
+        assert_eq!(
+            self.row_count() as usize,
+            prim.len(),
+            "Invalid buffer size {} to write CapsulePrimitive (needs {})",
+            prim.len(),
+            self.row_count()
+        );
+
+        let half_axis = self.half_axis();
+        prim[0].write((self.center.x + canvas_translation.x) * scale_factor);
+        prim[1].write((self.center.y + canvas_translation.y) * scale_factor);
+        prim[2].write(half_axis.x * scale_factor);
+        prim[3].write(half_axis.y * scale_factor);
+        prim[4].write(self.radius.max(0.) * scale_factor);
+        prim[5].write(bytemuck::cast(self.color.to_linear().as_u32()));
+        prim[6].write(self.stroke_width.max(0.) * scale_factor);
+        prim[7].write(stroke_offset(self.stroke_width, self.stroke_alignment) * scale_factor);
+        if self.is_bordered() {
+            write_border(
+                &mut prim[8..],
+                self.border_width,
+                self.border_alignment,
+                self.border_color,
+                scale_factor,
+            );
+        }
+    }
+}
+
+/// An arrow between two points, with a color, thickness, and heads.
+///
+/// The shaft and the heads are rendered as a single primitive, so each pixel
+/// is drawn once even where they overlap, which keeps translucent arrows
+/// uniform.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ArrowPrimitive {
+    /// The starting point of the arrow.
+    pub start: Vec2,
+    /// The ending point of the arrow.
+    pub end: Vec2,
+    /// The arrow color.
+    pub color: Color,
+    /// The thickness of the shaft, and of the strokes of open heads.
+    pub thickness: f32,
+    /// The style of the head(s).
+    pub head: ArrowHead,
+    /// Size of the border, if any, or zero if no border. Negative values or
+    /// zero mean no border.
+    pub border_width: f32,
+    /// Border color, if any (ignored if `border_width <= 0.`).
+    pub border_color: Color,
+    /// Alignment of the border relative to the edge of the arrow.
+    pub border_alignment: StrokeAlignment,
+    /// ID of the optional [`Effects`] applied to the primitive, like a glow, as
+    /// returned by [`Canvas::add_effects()`].
+    pub effects: Option<u32>,
+}
+
+impl ArrowPrimitive {
+    /// Number of primitive buffer rows (4 bytes) per primitive.
+    const ROW_COUNT_BASE: u32 = 9;
+    /// Number of extra primitive buffer rows (4 bytes) per primitive to add
+    /// when bordered. Those extra rows follow the base ones.
+    const ROW_COUNT_BORDER: u32 = 3;
+
+    /// Get the AABB of this arrow.
+    ///
+    /// This is conservative, and assumes the heads extend by their length
+    /// and half their width in any direction from the ends of the arrow.
+    pub fn aabb(&self) -> Aabb2d {
+        let head = self.head.length.max(0.) + self.head.width.max(0.) / 2.;
+        let margin =
+            head + self.thickness.max(0.) / 2. + self.border_alignment.outset(self.border_width);
+        Aabb2d {
+            min: self.start.min(self.end) - margin,
+            max: self.start.max(self.end) + margin,
+        }
+    }
+
+    /// Transform the arrow, scaling its thickness and heads by the mean scale
+    /// of the transform.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        let scale = transform_scale(transform);
+        self.start = transform.transform_point2(self.start);
+        self.end = transform.transform_point2(self.end);
+        self.thickness *= scale;
+        self.head.length *= scale;
+        self.head.width *= scale;
+        self.border_width *= scale;
+    }
+
+    /// Is the primitive bordered?
+    pub fn is_bordered(&self) -> bool {
+        self.border_width > 0.
+    }
+
+    #[inline]
+    fn row_count(&self) -> u32 {
+        let mut rows = Self::ROW_COUNT_BASE;
+        if self.is_bordered() {
+            rows += Self::ROW_COUNT_BORDER;
+        }
+        rows
+    }
+
+    fn info(&self) -> PrimitiveInfo {
+        PrimitiveInfo {
+            row_count: self.row_count(),
+            sub_prim_count: 1,
+        }
+    }
+
+    fn write(&self, prim: &mut [MaybeUninit<f32>], canvas_translation: Vec2, scale_factor: f32) {
+        assert_eq!(
+            self.row_count() as usize,
+            prim.len(),
+            "Invalid buffer size {} to write ArrowPrimitive (needs {})",
+            prim.len(),
+            self.row_count()
+        );
+
+        let style = self.head.shape as u32 | (self.head.ends as u32) << 8;
+        prim[0].write((self.start.x + canvas_translation.x) * scale_factor);
+        prim[1].write((self.start.y + canvas_translation.y) * scale_factor);
+        prim[2].write((self.end.x + canvas_translation.x) * scale_factor);
+        prim[3].write((self.end.y + canvas_translation.y) * scale_factor);
+        prim[4].write(bytemuck::cast(self.color.to_linear().as_u32()));
+        prim[5].write(self.thickness.max(0.) * scale_factor);
+        prim[6].write(bytemuck::cast(style));
+        prim[7].write(self.head.length.max(0.) * scale_factor);
+        prim[8].write(self.head.width.max(0.) * scale_factor);
+        if self.is_bordered() {
+            write_border(
+                &mut prim[9..],
+                self.border_width,
+                self.border_alignment,
+                self.border_color,
+                scale_factor,
+            );
+        }
+    }
+}
+
+/// Maximum number of nested layers composited as a group. Deeper layers are
+/// not grouped.
+///
+/// This must be kept in sync with the value inside the primitive shader.
+pub const MAX_LAYER_DEPTH: usize = 4;
+
+/// Maximum number of textures a single batch of primitives can sample from.
+///
+/// All the primitives of a layer are drawn in the same batch, so a layer can
+/// use at most that many textures, counting all its text as a single font
+/// atlas texture.
+///
+/// This must be kept in sync with the texture bindings of the primitive shader.
+pub const MAX_BATCH_TEXTURES: usize = 4;
+
+/// Marker opening or closing a layer group.
+///
+/// The primitives drawn between the opening and the closing markers of a layer
+/// are composited together first, and the result is blended as a single unit
+/// with the layer opacity, so overlapping primitives of the group don't show
+/// through each other. Layers are usually created by
+/// [`RenderContext::push_layer()`].
+///
+/// Both markers must have the same bounds, so that they're assigned to the
+/// same tiles.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LayerPrimitive {
+    /// Bounds of the layer in canvas space, enclosing all the primitives of the
+    /// group.
+    pub bounds: Rect,
+    /// Opacity of the group, from 0 (transparent) to 1 (opaque).
+    pub opacity: f32,
+    /// Is this the closing marker of the layer, or the opening one?
+    pub is_end: bool,
+    /// ID of the effects of the marker. Markers draw nothing themselves, so
+    /// this is always `None`.
+    pub effects: Option<u32>,
+}
+
+impl LayerPrimitive {
+    /// Number of primitive buffer rows (4 bytes) per primitive.
+    const ROW_COUNT_BASE: u32 = 2;
+
+    /// Get the AABB of this layer.
+    pub fn aabb(&self) -> Aabb2d {
+        Aabb2d {
+            min: self.bounds.min,
+            max: self.bounds.max,
+        }
+    }
+
+    fn info(&self) -> PrimitiveInfo {
+        PrimitiveInfo {
+            row_count: Self::ROW_COUNT_BASE,
+            sub_prim_count: 1,
+        }
+    }
+
+    fn write(&self, prim: &mut [MaybeUninit<f32>]) {
+        prim[0].write(bytemuck::cast(self.is_end as u32));
+        prim[1].write(self.opacity.clamp(0., 1.));
+    }
+}
+
+/// Drawing surface for 2D graphics.
+///
+/// This component should attached to the same entity as a [`Camera`] and an
+/// [`OrthographicProjection`].
+///
+/// By default the dimensions of the canvas are automatically computed and
+/// updated based on that projection.
+#[derive(Component)]
+pub struct Canvas {
+    /// The canvas dimensions relative to its origin.
+    ///
+    /// Currently ignored.
+    rect: Rect,
+    /// Optional background color to clear the canvas with.
+    ///
+    /// This only has an effect starting from the next [`clear()`] call. If a
+    /// background color is set, it's used to clear the canvas each frame.
+    /// Otherwise, the canvas retains its default transparent black color (0.0,
+    /// 0.0, 0.0, 0.0).
+    ///
+    /// [`clear()`]: crate::Canvas::clear
+    pub background_color: Option<Color>,
+    /// Collection of drawn primitives.
+    primitives: Vec<Primitive>,
+    /// Collection of allocated texts.
+    pub(crate) text_layouts: Vec<TextLayout>,
+    /// Collection of paths referenced by [`PathPrimitive`]s and
+    /// [`PolylinePrimitive`]s. Those are only flattened once extracted, at the
+    /// physical resolution of the screen.
+    pub(crate) paths: Vec<Path>,
+    /// Collection of primitives used as clip masks, referenced by the
+    /// [`ClipMasks`] of other primitives. Those primitives are not drawn.
+    pub(crate) masks: Vec<Primitive>,
+    /// Gradients, dashes, and image patterns referenced by the [`Effects`] of
+    /// the primitives.
+    pub(crate) effect_tables: EffectTables,
+    /// Atlas layout. Needs to be a separate asset resource due to Bevy's API
+    /// only.
+    pub(crate) atlas_layout: Handle<TextureAtlasLayout>,
+}
+
+impl Default for Canvas {
+    fn default() -> Self {
+        Self {
+            rect: Rect::default(),
+            background_color: None,
+            primitives: vec![],
+            text_layouts: vec![],
+            paths: vec![],
+            masks: vec![],
+            effect_tables: EffectTables::default(),
+            atlas_layout: Handle::default(),
+        }
+    }
+}
+
+impl Canvas {
+    /// Create a new canvas with given dimensions.
+    ///
+    /// FIXME - Currently the rectangle is ignored; all canvases are
+    /// full-screen.
+    pub fn new(rect: Rect) -> Self {
+        Self { rect, ..default() }
+    }
+
+    /// Change the dimensions of the canvas.
+    ///
+    /// This is called automatically if the [`Canvas`] is on the same entity as
+    /// an [`OrthographicProjection`].
+    pub fn set_rect(&mut self, rect: Rect) {
+        // if let Some(color) = self.background_color {
+        //     if self.rect != rect {
+        //         TODO - clear new area if any? or resize the clear() rect?!
+        //     }
+        // }
+        self.rect = rect;
+    }
+
+    /// Get the dimensions of the canvas relative to its origin.
+    ///
+    /// FIXME - Currently this is always [`OrthographicProjection::area`].
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// Clear the canvas, discarding all primitives previously drawn on it.
+    ///
+    /// If the canvas has a [`background_color`], this clears the canvas to that
+    /// color.
+    ///
+    /// [`background_color`]: Canvas::background_color
+    pub fn clear(&mut self) {
+        self.primitives.clear();
+        self.text_layouts.clear(); // FIXME - really?
+        self.paths.clear();
+        self.masks.clear();
+        self.effect_tables.clear();
+
+        if let Some(color) = self.background_color {
+            self.draw(RectPrimitive {
+                rect: self.rect,
+                color,
+                ..default()
+            });
+        }
+    }
+
+    /// Clear the canvas like [`clear()`], but keep the clip masks, so that the
+    /// masks still in use by a [`RenderContext`] remain valid.
+    ///
+    /// The masks keep their ID, and the paths they reference are kept too.
+    ///
+    /// [`clear()`]: Canvas::clear
+    pub(crate) fn clear_keep_masks(&mut self) {
+        let masks = std::mem::take(&mut self.masks);
+        let paths = std::mem::take(&mut self.paths);
+        self.clear();
+        for mut mask in masks {
+            if let Primitive::Path(PathPrimitive { id, .. })
+            | Primitive::Polyline(PolylinePrimitive { id, .. }) = &mut mask
+            {
+                *id = self.add_path(paths[*id as usize].clone());
+            }
+            self.masks.push(mask);
+        }
+    }
+
+    /// Draw a new primitive onto the canvas.
+    ///
+    /// This is a lower level entry point to canvas drawing; in general, you
+    /// should prefer acquiring a [`RenderContext`] via [`render_context()`]
+    /// and using it to draw primitives.
+    ///
+    /// [`render_context()`]: crate::canvas::Canvas::render_context
+    #[inline]
+    pub fn draw<'a>(&'a mut self, prim: impl Into<Primitive>) -> ShapeRef<'a> {
+        let prim = prim.into();
+        let first = self.primitives.len();
+        self.primitives.push(prim);
+        self.shape_ref(first)
+    }
+
+    /// Get a reference to the shape made of all primitives drawn since the
+    /// given index into the primitive buffer.
+    pub(crate) fn shape_ref(&mut self, first: usize) -> ShapeRef<'_> {
+        ShapeRef {
+            prims: &mut self.primitives[first..],
+            tables: &mut self.effect_tables,
+        }
+    }
+
+    /// Acquire a new render context to draw on this canvas.
+    pub fn render_context(&mut self) -> RenderContext {
+        RenderContext::new(self)
+    }
+
+    /// Apply a transform to all primitives from index `first` onward.
+    pub(crate) fn transform_primitives(&mut self, first: usize, transform: &Affine2) {
+        let first = first.min(self.primitives.len());
+        for prim in &mut self.primitives[first..] {
+            prim.apply_transform(transform, &mut self.paths);
+        }
+        self.effect_tables.apply_transform(
+            self.primitives[first..]
+                .iter()
+                .filter_map(Primitive::effects_id),
+            transform,
+        );
+    }
+
+    /// Clip all primitives from index `first` onward by a rectangle in canvas
+    /// space and some masks, in addition to any clipping they already have.
+    pub(crate) fn clip_primitives(&mut self, first: usize, clip: Option<Rect>, masks: &ClipMasks) {
+        let first = first.min(self.primitives.len());
+        for prim in &mut self.primitives[first..] {
+            let effects = prim.effects_mut(&mut self.effect_tables);
+            if let Some(clip) = clip {
+                effects.clip = Some(effects.clip.map_or(clip, |c| intersect_clip(c, clip)));
+            }
+            for id in masks.ids() {
+                if !effects.masks.push(*id) {
+                    warn!("Too many nested clip masks; only {MAX_CLIP_MASKS} are supported.");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Multiply the opacity of all primitives from index `first` onward.
+    pub(crate) fn fade_primitives(&mut self, first: usize, alpha: f32) {
+        let first = first.min(self.primitives.len());
+        for prim in &mut self.primitives[first..] {
+            let effects = prim.effects_mut(&mut self.effect_tables);
+            effects.opacity = Some(effects.opacity.unwrap_or(1.) * alpha);
+        }
+    }
+
+    /// Turn the primitive drawn at index `first` into a clip mask, and return
+    /// its mask ID.
+    ///
+    /// A mask is a single primitive, so any other primitive drawn after it is
+    /// discarded with a warning. Returns `None` if there is no such primitive,
+    /// or if it has no geometry.
+    pub(crate) fn make_mask(&mut self, first: usize) -> Option<u32> {
+        let count = self.primitives.len().saturating_sub(first);
+        if count > 1 {
+            warn!("Clip shape drew {count} primitives; only the first one is used as a mask.");
+        }
+        let mut mask = self.primitives.drain(first..).next()?;
+        let has_geometry = match &mask {
+            // Paths are only flattened once extracted
+            Primitive::Path(PathPrimitive { id, .. })
+            | Primitive::Polyline(PolylinePrimitive { id, .. }) => self
+                .paths
+                .get(*id as usize)
+                .is_some_and(|path| !path.is_empty()),
+            _ => {
+                let info = mask.info(&[], &[], &self.effect_tables);
+                info.row_count > 0 && info.sub_prim_count > 0
+            }
+        };
+        if !has_geometry {
+            return None;
+        }
+        // Only the geometry matters
+        *mask.effects_id_mut() = None;
+        let id = self.masks.len() as u32;
+        self.masks.push(mask);
+        Some(id)
+    }
+
+    pub(crate) fn masks(&self) -> &[Primitive] {
+        &self.masks[..]
+    }
+
+    /// Open a layer with the given opacity, and return the index of its opening
+    /// marker.
+    ///
+    /// The bounds of the layer are only known once it's closed by
+    /// [`end_layer()`].
+    ///
+    /// [`end_layer()`]: Canvas::end_layer
+    pub(crate) fn begin_layer(&mut self, opacity: f32) -> usize {
+        let index = self.primitives.len();
+        self.primitives.push(Primitive::Layer(LayerPrimitive {
+            bounds: Rect::EMPTY,
+            opacity,
+            ..default()
+        }));
+        index
+    }
+
+    /// Close the layer opened at index `begin`, grouping all primitives drawn
+    /// since then.
+    ///
+    /// The bounds of the layer don't include any text yet, which is added once
+    /// its glyphs are laid out, during extraction.
+    ///
+    /// Returns `false` if the layer uses more than [`MAX_BATCH_TEXTURES`]
+    /// textures, counting all the text as one font atlas texture. Such a layer
+    /// can't be drawn in a single batch, so it's removed instead, and its
+    /// opacity is applied to each of its primitives.
+    pub(crate) fn end_layer(&mut self, begin: usize) -> bool {
+        let Some(Primitive::Layer(mut layer)) = self.primitives.get(begin).copied() else {
+            return true;
+        };
+        let mut images = vec![];
+        let mut has_text = false;
+        for prim in &self.primitives[begin + 1..] {
+            if let Primitive::Text(_) = prim {
+                has_text = true;
+            } else if let Some(image) = prim.image(&self.effect_tables) {
+                if !images.contains(&image) {
+                    images.push(image);
+                }
+            }
+        }
+        if images.len() + has_text as usize > MAX_BATCH_TEXTURES {
+            self.primitives.remove(begin);
+            for prim in &mut self.primitives[begin..] {
+                // Nested layers keep grouping their own primitives
+                if !matches!(prim, Primitive::Layer(_)) {
+                    let effects = prim.effects_mut(&mut self.effect_tables);
+                    effects.opacity = Some(effects.opacity.unwrap_or(1.) * layer.opacity);
+                }
+            }
+            return false;
+        }
+        layer.bounds = self.primitives[begin + 1..]
+            .iter()
+            .fold(Rect::EMPTY, |bounds, prim| {
+                let aabb = match prim {
+                    // The glyphs are only laid out during extraction, which adds them to
+                    // the layer bounds then
+                    Primitive::Text(_) => return bounds,
+                    _ => {
+                        let aabb = prim.tile_aabb(&self.effect_tables);
+                        Rect {
+                            min: aabb.min,
+                            max: aabb.max,
+                        }
+                    }
+                };
+                bounds.union(aabb)
+            });
+        self.primitives[begin] = Primitive::Layer(layer);
+        layer.is_end = true;
+        self.primitives.push(Primitive::Layer(layer));
+        true
+    }
+
+    pub(crate) fn finish(&mut self) {
+        //
+    }
+
+    pub(crate) fn finish_layout(&mut self, mut layout: TextLayout) -> u32 {
+        let id = self.text_layouts.len() as u32;
+        trace!("finish_layout() for text #{}", id);
+        layout.id = id;
+        self.text_layouts.push(layout);
+        id
+    }
+
+    /// Store a path into the canvas, and return its ID for a [`PathPrimitive`]
+    /// or [`PolylinePrimitive`] to reference it.
+    pub(crate) fn add_path(&mut self, path: Path) -> u32 {
+        let id = self.paths.len() as u32;
+        self.paths.push(path);
+        id
+    }
+
+    pub(crate) fn paths(&self) -> &[Path] {
+        &self.paths[..]
+    }
+
+    /// Store a gradient into the canvas, and return its ID for some
+    /// [`Effects`] to reference it.
+    pub fn add_gradient(&mut self, gradient: Gradient) -> u32 {
+        self.effect_tables.add_gradient(gradient)
+    }
+
+    /// Store dashes into the canvas, and return their ID for some [`Effects`]
+    /// to reference them.
+    pub fn add_dash(&mut self, dash: Dash) -> u32 {
+        self.effect_tables.add_dash(dash)
+    }
+
+    /// Store an image pattern into the canvas, and return its ID for some
+    /// [`Effects`] to reference it.
+    pub fn add_pattern(&mut self, pattern: ImagePattern) -> u32 {
+        self.effect_tables.add_pattern(pattern)
+    }
+
+    /// Store the effects of a primitive into the canvas, and return their ID
+    /// for the primitive to reference them, or `None` if there's no effect at
+    /// all.
+    ///
+    /// Each primitive needs its own effects, so the ID returned must not be
+    /// referenced by any other primitive.
+    pub fn add_effects(&mut self, effects: Effects) -> Option<u32> {
+        self.effect_tables.add_effects(effects)
+    }
+
+    /// Store some dashes, then the effects of a primitive dashed with them, and
+    /// return the ID of those effects.
+    pub(crate) fn add_dashed_effects(&mut self, effects: Effects, dash: Dash) -> Option<u32> {
+        let dash = Some(self.add_dash(dash));
+        self.add_effects(Effects { dash, ..effects })
+    }
+
+    pub(crate) fn effect_tables(&self) -> &EffectTables {
+        &self.effect_tables
+    }
+
+    // Workaround for Extract phase without mut access to MainWorld Canvas
+    pub(crate) fn buffer(&self) -> &Vec<Primitive> {
+        &self.primitives
+    }
+
+    pub(crate) fn text_layouts(&self) -> &[TextLayout] {
+        &self.text_layouts[..]
+    }
+
+    pub(crate) fn text_layouts_mut(&mut self) -> &mut [TextLayout] {
+        &mut self.text_layouts[..]
+    }
+
+    pub(crate) fn has_text(&self) -> bool {
+        !self.text_layouts.is_empty()
+    }
+}
+
+/// Update the dimensions of any [`Canvas`] component attached to the same
+/// entity as as an [`OrthographicProjection`] component.
+///
+/// This runs in the [`PreUpdate`] schedule.
+///
+/// [`PreUpdate`]: bevy::app::PreUpdate
+pub fn update_canvas_from_ortho_camera(mut query: Query<(&mut Canvas, &OrthographicProjection)>) {
+    trace!("PreUpdate: update_canvas_from_ortho_camera()");
+    for (mut canvas, ortho) in query.iter_mut() {
+        trace!("ortho canvas rect = {:?}", ortho.area);
+        canvas.set_rect(ortho.area);
+    }
+}
+
+/// Configuration for tile-based rendering.
+///
+/// Currently unused.
+#[derive(Default, Clone, Copy, Component)]
+pub struct TileConfig {}
+
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub(crate) struct OffsetAndCount {
+    /// Base index into [`Tiles::primitives`].
+    pub offset: u32,
+    /// Number of consecutive primitive offsets in [`Tiles::primitives`].
+    pub count: u32,
+}
+
+/// Packed primitive index and extra data.
+///
+/// Contains a primitive index packed inside a `u32` alongside other bits
+/// necessary to drive the shader code:
+/// - Bit 31: Is the primitive textured?
+/// - Bits 27-30: Kind of primitive.
+/// - Bit 26: Is the primitive bordered (has a border)?
+/// - Bit 25: Does the primitive have any [`Effects`]?
+/// - Bits 23-24: Slot of the texture of the primitive among the ones bound by
+///   its batch, if textured.
+/// - Bits 0-22: Index of the first row in the primitive buffer.
+///
+/// The index is limited to [`PackedPrimitiveIndex::MAX_INDEX`], so the
+/// primitive buffer of a canvas can't exceed 8M rows (32 MB). The primitives
+/// past that limit are not drawn, with a warning.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Pod, Zeroable)]
+#[repr(transparent)]
+pub(crate) struct PackedPrimitiveIndex(pub u32);
+
+impl PackedPrimitiveIndex {
+    /// Maximum index of a row in the primitive buffer which can be packed.
+    pub const MAX_INDEX: u32 = 0x007F_FFFF;
+
+    /// Create a new packed index from individual values.
+    ///
+    /// Returns `None` if `index` is greater than
+    /// [`PackedPrimitiveIndex::MAX_INDEX`].
+    pub fn new(
+        index: u32,
+        kind: GpuPrimitiveKind,
+        textured: bool,
+        bordered: bool,
+        effects: bool,
+    ) -> Option<Self> {
+        if index > Self::MAX_INDEX {
+            return None;
+        }
+        let textured = (textured as u32) << 31;
+        let bordered = (bordered as u32) << 26;
+        let effects = (effects as u32) << 25;
+        let value = index | (kind as u32) << 27 | textured | bordered | effects;
+        Some(Self(value))
+    }
+
+    /// Move the index forward by `rows`, keeping all the other bits.
+    ///
+    /// Returns `None` if the new index is greater than
+    /// [`PackedPrimitiveIndex::MAX_INDEX`].
+    pub fn advance(self, rows: u32) -> Option<Self> {
+        let index = (self.0 & Self::MAX_INDEX).checked_add(rows)?;
+        (index <= Self::MAX_INDEX).then_some(Self(self.0 & !Self::MAX_INDEX | index))
+    }
+
+    /// Set the slot of the texture of the primitive among the
+    /// [`MAX_BATCH_TEXTURES`] ones bound by its batch.
+    pub fn with_texture_slot(self, slot: usize) -> Self {
+        debug_assert!(slot < MAX_BATCH_TEXTURES);
+        Self(self.0 & !(0x3 << 23) | (slot as u32 & 0x3) << 23)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct AssignedTile {
+    pub tile_index: i32,
+    pub prim_index: PackedPrimitiveIndex,
+}
+
+/// Component storing per-tile draw data.
+///
+/// This component is automatically added to any [`Camera`] and [`Canvas`]
+/// component pair missing it. It stores per-tile runtime data for rendering the
+/// canvas primitives. Most users can ignore it entirely.
+#[derive(Default, Clone, Component)]
+pub struct Tiles {
+    /// Tile size, in pixels. Currently hard-coded to 8x8 pixels.
+    pub(crate) tile_size: UVec2,
+    /// Dimensions of the canvas, in number of tiles.
+    ///
+    /// 4K, 8x8 => 129'600 tiles
+    /// 1080p, 8x8 => 32'400 tiles
+    pub(crate) dimensions: UVec2,
+    /// Flattened list of primitive indices for each tile. The start of a tile
+    /// is at element [`OffsetAndCount::offset`], and the tile contains
+    /// [`OffsetAndCount::count`] consecutive primitive offsets, each offset
+    /// being the start of the primitive into the primitive buffer of the
+    /// canvas.
+    pub(crate) primitives: Vec<PackedPrimitiveIndex>,
+    /// Offset and count of primitives per tile, into [`Tiles::primitives`].
+    pub(crate) offset_and_count: Vec<OffsetAndCount>,
+    /// Local cache saved frame-to-frame to avoid allocations.
+    assigned_tiles: Vec<AssignedTile>,
+}
+
+impl Tiles {
+    /// Update the tile data based on the current screen (canvas) size.
+    ///
+    /// This recalculates the dimensions of the various buffers and reallocate
+    /// them, to prepare for tiled drawing.
+    pub fn update_size(&mut self, screen_size: UVec2) {
+        // We force a 8x8 pixel tile, which works well with 32- and 64- waves.
+        self.tile_size = UVec2::new(8, 8);
+
+        self.dimensions = (screen_size.as_vec2() / self.tile_size.as_vec2())
+            .ceil()
+            .as_uvec2();
+
+        assert!(self.dimensions.x * self.tile_size.x >= screen_size.x);
+        assert!(self.dimensions.y * self.tile_size.y >= screen_size.y);
+
+        self.primitives.clear();
+        self.offset_and_count.clear();
+        self.offset_and_count
+            .reserve(self.dimensions.x as usize * self.dimensions.y as usize);
+
+        trace!(
+            "Resized Tiles at tile_size={:?} dim={:?} and cleared buffers",
+            self.tile_size,
+            self.dimensions
+        );
+    }
+
+    /// Assign the given primitives to tiles.
+    ///
+    /// This performs the actual binning of primitives into one or more tiles.
+    /// This assumes the various tile buffers are appropriately sized and
+    /// allocated by a previous call to [`update_size()`].
+    ///
+    /// [`update_size()`]: crate::canvas::Tiles::update_size
+    pub(crate) fn assign_to_tiles(&mut self, primitives: &[PreparedPrimitive], screen_size: Vec2) {
+        let tile_size = self.tile_size.as_vec2();
+
+        let oc_extra = self.dimensions.x as usize * self.dimensions.y as usize;
+        self.offset_and_count.reserve(oc_extra);
+
+        // Some semi-random guesswork of average tile overlapping count per primitive,
+        // so we don't start from a stupidly small allocation.
+        self.assigned_tiles.reserve(primitives.len() * 4);
+
+        // Loop over primitives and find tiles they overlap
+        for prim in primitives {
+            // Calculate bounds in terms of tile indices, clamped to the size of the screen
+            let uv_min = (prim.aabb.min.clamp(Vec2::ZERO, screen_size) / tile_size)
+                .floor()
+                .as_ivec2();
+            let mut uv_max = (prim.aabb.max.clamp(Vec2::ZERO, screen_size) / tile_size)
+                .ceil()
+                .as_ivec2();
+            if prim.aabb.max.x == tile_size.x * uv_max.x as f32 {
+                // We ignore tiles which only have a shared edge and no actualy surface overlap
+                uv_max.x -= 1;
+            }
+            if prim.aabb.max.y == tile_size.y * uv_max.y as f32 {
+                // We ignore tiles which only have a shared edge and no actualy surface overlap
+                uv_max.y -= 1;
+            }
+            if uv_max.x < uv_min.x || uv_max.y < uv_min.y {
+                // Primitives entirely clipped out have an empty AABB and overlap no tile
+                continue;
+            }
+
+            self.assigned_tiles
+                .reserve((uv_max.y - uv_min.y + 1) as usize * (uv_max.x - uv_min.x + 1) as usize);
+
+            // Loop on tiles overlapping this primitive. This is generally only a handful,
+            // unless the primitive covers a large part of the screen.
+            for ty in uv_min.y..=uv_max.y {
+                let base_tile_index = ty * self.dimensions.x as i32;
+                for tx in uv_min.x..=uv_max.x {
+                    // Skip tiles where the primitive doesn't draw anything
+                    let tile_min = Vec2::new(tx as f32, ty as f32) * tile_size;
+                    if prim.hole.is_some_and(|hole| {
+                        tile_min.cmpge(hole.min).all()
+                            && (tile_min + tile_size).cmple(hole.max).all()
+                    }) {
+                        continue;
+                    }
+
+                    let tile_index = base_tile_index + tx;
+                    self.assigned_tiles.push(AssignedTile {
+                        tile_index,
+                        prim_index: prim.prim_index,
+                    });
+                }
+            }
+        }
+
+        // Sort the primitive<->tile mapping by tile index. Note that the sort MUST BE
+        // STABLE, to preserve the order of primitives, which preserves what is drawn on
+        // top of what.
+        self.assigned_tiles.sort_by_key(|at| at.tile_index);
+
+        // Build the offset and count list
+        self.primitives.reserve(self.assigned_tiles.len());
+        let mut ti = -1;
+        let mut offset = 0;
+        let mut count = 0;
+        for at in &self.assigned_tiles {
+            if at.tile_index != ti {
+                if count > 0 {
+                    // Write previous tile
+                    self.offset_and_count.push(OffsetAndCount {
+                        offset: offset as u32,
+                        count,
+                    });
+                }
+                // Write empty tile(s)
+                for _ in ti + 1..at.tile_index {
+                    self.offset_and_count.push(OffsetAndCount {
+                        offset: offset as u32,
+                        count: 0,
+                    });
+                }
+                offset = self.primitives.len() as u32;
+                count = 0;
+                ti = at.tile_index;
+            }
+
+            self.primitives.push(at.prim_index);
+            count += 1;
+        }
+        // Write last pending tile
+        if count > 0 {
+            self.offset_and_count.push(OffsetAndCount {
+                offset: offset as u32,
+                count,
+            });
+        }
+        // Write empty tile(s) at the end
+        for _ in ti + 1..oc_extra as i32 {
+            self.offset_and_count.push(OffsetAndCount {
+                offset: offset as u32,
+                count: 0,
+            });
+        }
+
+        // Clear scratch buffer for next call
+        self.assigned_tiles.clear();
+    }
+}
+
+/// Ensure any active [`Camera`] component with a [`Canvas`] component also has
+/// associated [`TileConfig`] and [`Tiles`] components.
+pub fn spawn_missing_tiles_components(
+    mut commands: Commands,
+    cameras: Query<(Entity, Option<&TileConfig>, &Camera), (With<Canvas>, Without<Tiles>)>,
+) {
+    for (entity, config, camera) in &cameras {
+        if !camera.is_active {
+            continue;
+        }
+
+        let config = config.copied().unwrap_or_default();
+        commands.entity(entity).insert((Tiles::default(), config));
+    }
+}
+
+pub fn resize_tiles_to_camera_render_target(
+    mut views: Query<(&Camera, &TileConfig, &mut Tiles), With<Canvas>>,
+) {
+    // Loop on all camera views
+    for (camera, _tile_config, tiles) in &mut views {
+        let Some(screen_size) = camera.physical_viewport_size() else {
+            continue;
+        };
+
+        // Resize tile storage to fit the viewport size
+        let tiles = tiles.into_inner();
+        tiles.update_size(screen_size);
+    }
+}
+
+pub fn allocate_atlas_layouts(
+    mut query: Query<&mut Canvas>,
+    mut layouts: ResMut<Assets<TextureAtlasLayout>>,
+) {
+    for mut canvas in query.iter_mut() {
+        // FIXME
+        let size = UVec2::splat(1024);
+
+        // FIXME - also check for resize...
+        if canvas.atlas_layout == Handle::<TextureAtlasLayout>::default() {
+            canvas.atlas_layout = layouts.add(TextureAtlasLayout::new_empty(size));
+        }
+    }
+}
+
+/// Calculate the width of a fixed-aspect rectangle given a content height.
+fn aspect_width(size: Vec2, content_height: f32) -> f32 {
+    size.x.max(0.) / size.y.max(1.) * content_height.max(0.)
+}
+
+/// Calculate the size of a rectangle such that its width fits in the given
+/// content, and its height either stretches to that content or it keeps its
+/// aspect ratio (and therefore gets clipped).
+fn fit_width(size: Vec2, content_size: Vec2, stretch_height: bool) -> Vec2 {
+    Vec2::new(
+        content_size.x,
+        if stretch_height {
+            content_size.y
+        } else {
+            aspect_height(size, content_size.x)
+        },
+    )
+}
+
+/// Calculate the height of a fixed-aspect rectangle given a content width.
+fn aspect_height(size: Vec2, content_width: f32) -> f32 {
+    size.y.max(0.) / size.x.max(1.) * content_width.max(0.)
+}
+
+/// Calculate the size of a rectangle such that its height fits in the given
+/// content, and its width either stretches to that content or it keeps its
+/// aspect ratio (and therefore gets clipped).
+fn fit_height(size: Vec2, content_size: Vec2, stretch_width: bool) -> Vec2 {
+    Vec2::new(
+        if stretch_width {
+            content_size.x
+        } else {
+            aspect_width(size, content_size.y)
+        },
+        content_size.y,
+    )
+}
+
+/// Calculate the size of a rectangle such that both its width and height fit in
+/// the given content, and the other direction either stretches to that content
+/// or it keeps its aspect ratio. This ensures the returned rectangle covers the
+/// content, possibly clipping in one direction to do so.
+fn fit_any(size: Vec2, content_size: Vec2, stretch_other: bool) -> Vec2 {
+    let aspect = size.x.max(0.) / size.y.max(1.);
+    let content_aspect = content_size.x.max(0.) / content_size.y.max(1.);
+    if aspect >= content_aspect {
+        fit_height(size, content_size, stretch_other)
+    } else {
+        fit_width(size, content_size, stretch_other)
+    }
+}
+
+/// Process all images drawn onto all canvases.
+///
+/// This calculates the proper image size given the content rectangle size and
+/// the window scale factor, applying any image scaling as specified during the
+/// draw call. This also resolves the image size of all [`ImagePattern`]s.
+pub fn process_images(
+    images: Res<Assets<Image>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    mut q_canvas: Query<&mut Canvas>,
+) {
+    // TODO - handle multi-window
+    let Ok(primary_window) = q_window.get_single() else {
+        return;
+    };
+    let scale_factor = primary_window.scale_factor() as f32;
+
+    for mut canvas in q_canvas.iter_mut() {
+        let canvas = &mut *canvas;
+        for pattern in &mut canvas.effect_tables.patterns {
+            if let Some(image) = images.get(pattern.image()) {
+                pattern.image_size = Vec2::new(
+                    image.texture_descriptor.size.width as f32,
+                    image.texture_descriptor.size.height as f32,
+                );
+            }
+        }
+        let tables = &mut canvas.effect_tables;
+        for effects in &mut tables.effects {
+            if let Some(id) = effects.pattern {
+                let image = tables.patterns[id as usize].image();
+                if images.get(image).is_none() {
+                    warn!("Unknown image asset ID {:?}; skipped.", image);
+                    effects.pattern = None;
+                }
+            }
+        }
+        for prim in &mut canvas.primitives {
+            let Primitive::Rect(rect) = prim else {
+                continue;
+            };
+            let Some(id) = rect.image else {
+                continue;
+            };
+            if let Some(image) = images.get(id) {
+                let image_size = Vec2::new(
+                    image.texture_descriptor.size.width as f32,
+                    image.texture_descriptor.size.height as f32,
+                );
+                let content_size = rect.rect.size() * scale_factor;
+                rect.image_size = match rect.image_scaling {
+                    ImageScaling::Uniform(ratio) => image_size * ratio,
+                    ImageScaling::FitWidth(stretch_height) => {
+                        fit_width(image_size, content_size, stretch_height)
+                    }
+                    ImageScaling::FitHeight(stretch_width) => {
+                        fit_height(image_size, content_size, stretch_width)
+                    }
+                    ImageScaling::Fit(stretch_other) => {
+                        fit_any(image_size, content_size, stretch_other)
+                    }
+                    ImageScaling::Stretch => content_size,
+                }
+            } else {
+                warn!("Unknown image asset ID {:?}; skipped.", id);
+                rect.image = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        render_context::Brush,
+        shapes::{PathEl, RoundedRect, Shape, ShapeExt},
+    };
+
+    #[test]
+    fn tiles() {
+        let mut tiles = Tiles::default();
+        tiles.update_size(UVec2::new(32, 64));
+        assert_eq!(tiles.dimensions, UVec2::new(4, 8));
+        assert!(tiles.primitives.is_empty());
+        assert!(tiles.offset_and_count.is_empty());
+        assert_eq!(tiles.offset_and_count.capacity(), 32);
+
+        let prim_index =
+            PackedPrimitiveIndex::new(42, GpuPrimitiveKind::Line, true, false, false).unwrap();
+        tiles.assign_to_tiles(
+            &[PreparedPrimitive {
+                // 8 x 16, exactly aligned on the tile grid => 2 tiles exactly
+                aabb: Aabb2d {
+                    min: Vec2::new(8., 16.),
+                    max: Vec2::new(16., 32.),
+                },
+                hole: None,
+                prim_index,
+            }],
+            // Large screen size, no effect in this test
+            Vec2::new(256., 128.),
+        );
+
+        assert_eq!(tiles.primitives.len(), 2);
+        assert_eq!(tiles.primitives[0], prim_index);
+        assert_eq!(tiles.primitives[1], prim_index);
+
+        assert_eq!(tiles.offset_and_count.len(), 32);
+        for (idx, oc) in tiles.offset_and_count.iter().enumerate() {
+            if idx == 9 || idx == 13 {
+                assert_eq!(oc.count, 1);
+                assert_eq!(oc.offset, if idx == 9 { 0 } else { 1 });
+            } else {
+                assert_eq!(oc.count, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn stroked_rect_tiles() {
+        let mut canvas = Canvas::default();
+        let brush = Brush::from(Color::WHITE);
+        Rect::new(0., 0., 32., 64.).stroke_aligned(
+            &mut canvas,
+            &brush,
+            2.,
+            StrokeAlignment::Inside,
+        );
+        let prim = &canvas.buffer()[0];
+        let hole = prim.tile_hole(canvas.effect_tables()).unwrap();
+        assert_eq!(hole.min, Vec2::splat(2.));
+        assert_eq!(hole.max, Vec2::new(30., 62.));
+
+        // Only the tiles under the stroke get the primitive
+        let mut tiles = Tiles::default();
+        tiles.update_size(UVec2::new(32, 64));
+        let prim_index =
+            PackedPrimitiveIndex::new(0, GpuPrimitiveKind::Rect, false, false, false).unwrap();
+        tiles.assign_to_tiles(
+            &[PreparedPrimitive {
+                aabb: prim.tile_aabb(canvas.effect_tables()),
+                hole: Some(hole),
+                prim_index,
+            }],
+            Vec2::new(32., 64.),
+        );
+        assert_eq!(tiles.primitives.len(), 4 * 8 - 2 * 6);
+
+        // Effects reaching inside the stroke shrink the hole, and rotated
+        // rectangles have none
+        let mut canvas = Canvas::default();
+        Rect::new(0., 0., 32., 64.)
+            .stroke_aligned(&mut canvas, &brush, 2., StrokeAlignment::Inside)
+            .glow(&brush, 4.);
+        let hole = canvas.buffer()[0]
+            .tile_hole(canvas.effect_tables())
+            .unwrap();
+        assert_eq!(hole.min, Vec2::splat(6.));
+        let mut canvas = Canvas::default();
+        RoundedRect::new(Rect::new(0., 0., 32., 64.), 0.)
+            .with_rotation(0.5)
+            .stroke(&mut canvas, &brush, 2.);
+        assert!(canvas.buffer()[0]
+            .tile_hole(canvas.effect_tables())
+            .is_none());
+    }
+
+    #[test]
+    fn packed_index() {
+        let max = PackedPrimitiveIndex::MAX_INDEX;
+        let prim_index =
+            PackedPrimitiveIndex::new(max, GpuPrimitiveKind::Star, true, true, true).unwrap();
+        assert_eq!(prim_index.0 & max, max);
+        assert_eq!(prim_index.0 >> 27 & 0xF, GpuPrimitiveKind::Star as u32);
+        assert_eq!(prim_index.0 >> 25 & 0x7, 0x7);
+        assert_eq!(prim_index.0 >> 23 & 0x3, 0);
+        let prim_index = prim_index.with_texture_slot(MAX_BATCH_TEXTURES - 1);
+        assert_eq!(prim_index.0 & max, max);
+        assert_eq!(prim_index.0 >> 23 & 0x3, MAX_BATCH_TEXTURES as u32 - 1);
+        assert_eq!(prim_index.0 >> 25 & 0x7, 0x7);
+        assert!(
+            PackedPrimitiveIndex::new(max + 1, GpuPrimitiveKind::Rect, false, false, false)
+                .is_none()
+        );
+
+        // Advancing never carries into the texture slot
+        assert!(prim_index.advance(1).is_none());
+        assert!(prim_index.advance(u32::MAX).is_none());
+        let prim_index =
+            PackedPrimitiveIndex::new(max - 10, GpuPrimitiveKind::Rect, true, false, false)
+                .unwrap()
+                .with_texture_slot(2);
+        let next = prim_index.advance(10).unwrap();
+        assert_eq!(next.0 & max, max);
+        assert_eq!(next.0 & !max, prim_index.0 & !max);
+        assert!(next.advance(1).is_none());
+    }
+
+    #[test]
+    fn primitive_size() {
+        // Effects live in the side tables of the canvas, so they don't grow the
+        // primitives; the largest one is a polygon with its inline vertices.
+        assert!(std::mem::size_of::<Primitive>() <= 192);
+    }
+
+    #[test]
+    fn effects() {
+        let mut tables = EffectTables::default();
+        let effects = Effects::default();
+        assert!(effects.is_empty());
+        assert_eq!(effects.row_count(&tables), 0);
+
+        let effects = Effects {
+            glow: Some(Glow {
+                color: Color::WHITE,
+                spread: 4.,
+            }),
+            ..default()
+        };
+        assert!(!effects.is_empty());
+        assert_eq!(effects.row_count(&tables), 3);
+
+        let mut rows = [MaybeUninit::<f32>::uninit(); 3];
+        effects.write(&mut rows[..], &tables, &[], Vec2::ZERO, 2.);
+        let rows = rows.map(|r| unsafe { r.assume_init() });
+        assert_eq!(bytemuck::cast::<f32, u32>(rows[0]), Effects::FLAG_GLOW);
+        assert_eq!(bytemuck::cast::<f32, u32>(rows[1]), 0xFFFF_FFFF);
+        assert_eq!(rows[2], 8.);
+
+        // The glow extends the AABB used for tiling
+        let prim: Primitive = RectPrimitive {
+            rect: Rect::new(0., 0., 10., 20.),
+            effects: tables.add_effects(effects),
+            ..default()
+        }
+        .into();
+        let aabb = prim.tile_aabb(&tables);
+        assert_eq!(aabb.min, Vec2::new(-4., -4.));
+        assert_eq!(aabb.max, Vec2::new(14., 24.));
+
+        // The shadow comes after the glow, and extends the AABB toward its offset
+        let effects = Effects {
+            shadow: Some(Shadow {
+                offset: Vec2::new(10., -2.),
+                blur_radius: 3.,
+                color: Color::BLACK,
+            }),
+            ..effects
+        };
+        assert_eq!(effects.row_count(&tables), 7);
+
+        let mut rows = [MaybeUninit::<f32>::uninit(); 7];
+        effects.write(&mut rows[..], &tables, &[], Vec2::ZERO, 2.);
+        let rows = rows.map(|r| unsafe { r.assume_init() });
+        assert_eq!(
+            bytemuck::cast::<f32, u32>(rows[0]),
+            Effects::FLAG_GLOW | Effects::FLAG_SHADOW
+        );
+        assert_eq!(rows[2], 8.);
+        assert_eq!(rows[3], 20.);
+        assert_eq!(rows[4], -4.);
+        assert_eq!(rows[5], 6.);
+        assert_eq!(bytemuck::cast::<f32, u32>(rows[6]), 0xFF00_0000);
+
+        let aabb = effects.grow_aabb(Aabb2d {
+            min: Vec2::ZERO,
+            max: Vec2::new(10., 20.),
+        });
+        assert_eq!(aabb.min, Vec2::new(-4., -5.));
+        assert_eq!(aabb.max, Vec2::new(23., 24.));
+
+        // The inset shadow comes last, and doesn't change the AABB
+        let inset_shadow = Shadow {
+            offset: Vec2::new(0., -1.),
+            blur_radius: 2.,
+            color: Color::BLACK,
+        };
+        let effects = Effects {
+            inset_shadow: Some(inset_shadow),
+            ..default()
+        };
+        assert!(!effects.is_empty());
+        assert_eq!(effects.row_count(&tables), 5);
+
+        let mut rows = [MaybeUninit::<f32>::uninit(); 5];
+        effects.write(&mut rows[..], &tables, &[], Vec2::ZERO, 1.);
+        let rows = rows.map(|r| unsafe { r.assume_init() });
+        assert_eq!(
+            bytemuck::cast::<f32, u32>(rows[0]),
+            Effects::FLAG_INSET_SHADOW
+        );
+        assert_eq!(rows[2], -1.);
+        assert_eq!(rows[3], 2.);
+
+        let aabb = Aabb2d {
+            min: Vec2::ZERO,
+            max: Vec2::new(10., 20.),
+        };
+        let grown = effects.grow_aabb(aabb);
+        assert_eq!(grown.min, aabb.min);
+        assert_eq!(grown.max, aabb.max);
+    }
+
+    #[test]
+    fn gradient() {
+        use crate::render_context::{ColorStop, GradientKind, MAX_GRADIENT_STOPS};
+
+        // Stops are sorted, and truncated to the maximum count
+        let gradient = Gradient::new(
+            GradientKind::Radial {
+                center: Vec2::new(5., 6.),
+                radius: 10.,
+            },
+            [(1., Color::WHITE), (0., Color::BLACK)],
+        );
+        assert_eq!(
+            gradient.stops(),
+            &[
+                ColorStop {
+                    offset: 0.,
+                    color: Color::BLACK
+                },
+                ColorStop {
+                    offset: 1.,
+                    color: Color::WHITE
+                }
+            ]
+        );
+        let many = Gradient::new(
+            GradientKind::Linear {
+                start: Vec2::ZERO,
+                end: Vec2::X,
+            },
+            (0..20).map(|i| (i as f32 / 20., Color::WHITE)),
+        );
+        assert_eq!(many.stops().len(), MAX_GRADIENT_STOPS);
+
+        // Fill and border gradients follow the fixed-size effects
+        let mut tables = EffectTables::default();
+        let gradient = tables.add_gradient(gradient);
+        let effects = Effects {
+            glow: Some(Glow {
+                color: Color::WHITE,
+                spread: 4.,
+            }),
+            gradient: Some(gradient),
+            border_gradient: Some(gradient),
+            ..default()
         };
-        sref
-    }
+        assert_eq!(effects.row_count(&tables), 1 + 2 + 9 + 9);
 
-    /// Acquire a new render context to draw on this canvas.
-    pub fn render_context(&mut self) -> RenderContext {
-        RenderContext::new(self)
+        let mut rows = [MaybeUninit::<f32>::uninit(); 21];
+        effects.write(&mut rows[..], &tables, &[], Vec2::new(1., 2.), 2.);
+        let rows = rows.map(|r| unsafe { r.assume_init() });
+        assert_eq!(
+            bytemuck::cast::<f32, u32>(rows[0]),
+            Effects::FLAG_GLOW | Effects::FLAG_GRADIENT | Effects::FLAG_BORDER_GRADIENT
+        );
+        for base in [3, 12] {
+            // Kind in the low byte, stop count above
+            assert_eq!(bytemuck::cast::<f32, u32>(rows[base]), 1 | 2 << 8);
+            assert_eq!(rows[base + 1], 12.);
+            assert_eq!(rows[base + 2], 16.);
+            assert_eq!(rows[base + 3], 20.);
+            assert_eq!(rows[base + 5], 0.);
+            assert_eq!(bytemuck::cast::<f32, u32>(rows[base + 6]), 0xFF00_0000);
+            assert_eq!(rows[base + 7], 1.);
+            assert_eq!(bytemuck::cast::<f32, u32>(rows[base + 8]), 0xFFFF_FFFF);
+        }
     }
 
-    pub(crate) fn finish(&mut self) {
-        //
-    }
+    #[test]
+    fn image_pattern() {
+        let image = Handle::<Image>::weak_from_u128(0x1234);
+        let mut pattern = ImagePattern::new(
+            image.clone(),
+            Affine2::from_scale_angle_translation(Vec2::splat(2.), 0., Vec2::new(10., 20.)),
+            ImageRepeat::Mirror,
+        );
+        pattern.image_size = Vec2::new(4., 8.);
+        let mut tables = EffectTables::default();
+        let pattern = tables.add_pattern(pattern);
+        let prim: Primitive = EllipsePrimitive {
+            center: Vec2::new(14., 28.),
+            radii: Vec2::new(4., 8.),
+            effects: tables.add_effects(Effects {
+                pattern: Some(pattern),
+                ..default()
+            }),
+            ..default()
+        }
+        .into();
 
-    pub(crate) fn finish_layout(&mut self, mut layout: TextLayout) -> u32 {
-        let id = self.text_layouts.len() as u32;
-        trace!("finish_layout() for text #{}", id);
-        layout.id = id;
-        self.text_layouts.push(layout);
-        id
-    }
+        // The pattern makes any primitive textured, without adding effects rows
+        assert!(prim.is_textured(&tables));
+        assert_eq!(prim.image(&tables), Some(image.id()));
+        assert!(!prim.has_effects(&tables));
+        let info = prim.info(&[], &[], &tables);
+        assert_eq!(info.row_count, 9 + ROW_COUNT_TEX);
 
-    // Workaround for Extract phase without mut access to MainWorld Canvas
-    pub(crate) fn buffer(&self) -> &Vec<Primitive> {
-        &self.primitives
-    }
+        let mut rows = [MaybeUninit::<f32>::uninit(); 16];
+        prim.write(&[], &[], &tables, &[], &mut rows[..], Vec2::new(1., 2.), 2.);
+        let rows = rows.map(|r| unsafe { r.assume_init() });
+        let uv_transform = Affine2 {
+            matrix2: Mat2::from_cols(Vec2::new(rows[9], rows[10]), Vec2::new(rows[11], rows[12])),
+            translation: Vec2::new(rows[13], rows[14]),
+        };
+        assert_eq!(
+            bytemuck::cast::<f32, u32>(rows[15]),
+            ImageRepeat::Mirror as u32
+        );
+        // Pattern corners, in physical pixels with the canvas translation
+        let uv = uv_transform.transform_point2(Vec2::new(22., 44.));
+        assert!(uv.abs_diff_eq(Vec2::ZERO, 1e-5));
+        let uv = uv_transform.transform_point2(Vec2::new(38., 76.));
+        assert!(uv.abs_diff_eq(Vec2::ONE, 1e-5));
 
-    pub(crate) fn text_layouts(&self) -> &[TextLayout] {
-        &self.text_layouts[..]
-    }
+        // Rectangle images take precedence, and are centered on the rectangle
+        let prim: Primitive = RectPrimitive {
+            rect: Rect::from_center_half_size(Vec2::new(5., 5.), Vec2::ONE),
+            image: Some(AssetId::invalid()),
+            image_size: Vec2::new(16., 16.),
+            effects: tables.add_effects(Effects {
+                pattern: Some(pattern),
+                ..default()
+            }),
+            ..default()
+        }
+        .into();
+        assert_eq!(prim.image(&tables), Some(AssetId::invalid()));
+        let (uv_transform, repeat) = prim.uv_transform(&tables, Vec2::ZERO, 1.).unwrap();
+        assert_eq!(repeat, ImageRepeat::Clamp);
+        let uv = uv_transform.transform_point2(Vec2::new(5., 5.));
+        assert!(uv.abs_diff_eq(Vec2::splat(0.5), 1e-5));
 
-    pub(crate) fn text_layouts_mut(&mut self) -> &mut [TextLayout] {
-        &mut self.text_layouts[..]
+        // Flipping mirrors the image around the center of the rectangle
+        let mut rect = RectPrimitive {
+            rect: Rect::from_center_half_size(Vec2::new(5., 5.), Vec2::splat(8.)),
+            image: Some(AssetId::invalid()),
+            image_size: Vec2::new(16., 16.),
+            ..default()
+        };
+        let uv = rect
+            .uv_transform(Vec2::ZERO, 1.)
+            .transform_point2(Vec2::new(-3., -3.));
+        assert!(uv.abs_diff_eq(Vec2::ZERO, 1e-5));
+        rect.flip_x = true;
+        let uv = rect
+            .uv_transform(Vec2::ZERO, 1.)
+            .transform_point2(Vec2::new(-3., -3.));
+        assert!(uv.abs_diff_eq(Vec2::new(1., 0.), 1e-5));
+        rect.flip_y = true;
+        let uv = rect
+            .uv_transform(Vec2::ZERO, 1.)
+            .transform_point2(Vec2::new(-3., -3.));
+        assert!(uv.abs_diff_eq(Vec2::ONE, 1e-5));
     }
 
-    pub(crate) fn has_text(&self) -> bool {
-        !self.text_layouts.is_empty()
-    }
-}
+    #[test]
+    fn rect_aabb() {
+        let rect = RectPrimitive {
+            rect: Rect::from_center_half_size(Vec2::new(10., 20.), Vec2::new(4., 1.)),
+            ..default()
+        };
+        let aabb = rect.aabb();
+        assert_eq!(aabb.min, Vec2::new(6., 19.));
+        assert_eq!(aabb.max, Vec2::new(14., 21.));
 
-/// Update the dimensions of any [`Canvas`] component attached to the same
-/// entity as as an [`OrthographicProjection`] component.
-///
-/// This runs in the [`PreUpdate`] schedule.
-///
-/// [`PreUpdate`]: bevy::app::PreUpdate
-pub fn update_canvas_from_ortho_camera(mut query: Query<(&mut Canvas, &OrthographicProjection)>) {
-    trace!("PreUpdate: update_canvas_from_ortho_camera()");
-    for (mut canvas, ortho) in query.iter_mut() {
-        trace!("ortho canvas rect = {:?}", ortho.area);
-        canvas.set_rect(ortho.area);
+        // Quarter turn swaps the extents
+        let rect = RectPrimitive {
+            rotation: Rot2::FRAC_PI_2,
+            ..rect
+        };
+        let aabb = rect.aabb();
+        assert!(aabb.min.abs_diff_eq(Vec2::new(9., 16.), 1e-5));
+        assert!(aabb.max.abs_diff_eq(Vec2::new(11., 24.), 1e-5));
+
+        // Eighth of a turn
+        let rect = RectPrimitive {
+            rotation: Rot2::FRAC_PI_4,
+            ..rect
+        };
+        let extent = 5. * std::f32::consts::FRAC_1_SQRT_2;
+        let aabb = rect.aabb();
+        assert!(aabb
+            .min
+            .abs_diff_eq(Vec2::new(10. - extent, 20. - extent), 1e-5));
+        assert!(aabb
+            .max
+            .abs_diff_eq(Vec2::new(10. + extent, 20. + extent), 1e-5));
     }
-}
 
-/// Configuration for tile-based rendering.
-///
-/// Currently unused.
-#[derive(Default, Clone, Copy, Component)]
-pub struct TileConfig {}
+    #[test]
+    fn arc_aabb() {
+        let arc = ArcPrimitive {
+            center: Vec2::new(10., 20.),
+            inner_radius: 1.,
+            outer_radius: 2.,
+            start_angle: 0.,
+            sweep_angle: std::f32::consts::TAU,
+            ..default()
+        };
+        let aabb = arc.aabb();
+        assert!(aabb.min.abs_diff_eq(Vec2::new(8., 18.), 1e-5));
+        assert!(aabb.max.abs_diff_eq(Vec2::new(12., 22.), 1e-5));
 
-#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
-#[repr(C)]
-pub(crate) struct OffsetAndCount {
-    /// Base index into [`Tiles::primitives`].
-    pub offset: u32,
-    /// Number of consecutive primitive offsets in [`Tiles::primitives`].
-    pub count: u32,
-}
+        // First quadrant only
+        let arc = ArcPrimitive {
+            sweep_angle: std::f32::consts::FRAC_PI_2,
+            ..arc
+        };
+        let aabb = arc.aabb();
+        assert!(aabb.min.abs_diff_eq(Vec2::new(10., 20.), 1e-5));
+        assert!(aabb.max.abs_diff_eq(Vec2::new(12., 22.), 1e-5));
 
-/// Packed primitive index and extra data.
-///
-/// Contains a primitive index packed inside a `u32` alongside other bits
-/// necessary to drive the shader code:
-/// - Index of the first row in the primitive buffer.
-/// - Kind of primitive.
-/// - Is the primitive textured?
-/// - Is the primitive bordered (has a border)?
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Pod, Zeroable)]
-#[repr(transparent)]
-pub(crate) struct PackedPrimitiveIndex(pub u32);
+        // Same quadrant swept backward, with rounded caps
+        let arc = ArcPrimitive {
+            start_angle: std::f32::consts::FRAC_PI_2,
+            sweep_angle: -std::f32::consts::FRAC_PI_2,
+            rounded_caps: true,
+            ..arc
+        };
+        let aabb = arc.aabb();
+        assert!(aabb.min.abs_diff_eq(Vec2::new(9.5, 19.5), 1e-5));
+        assert!(aabb.max.abs_diff_eq(Vec2::new(12.5, 22.5), 1e-5));
 
-impl PackedPrimitiveIndex {
-    /// Create a new packed index from individual values.
-    pub fn new(index: u32, kind: GpuPrimitiveKind, textured: bool, bordered: bool) -> Self {
-        let textured = (textured as u32) << 31;
-        let bordered = (bordered as u32) << 27;
-        let value = (index & 0x07FF_FFFF) | (kind as u32) << 28 | textured | bordered;
-        Self(value)
+        // Pie crossing the negative X axis
+        let arc = ArcPrimitive {
+            inner_radius: 0.,
+            start_angle: std::f32::consts::FRAC_PI_2,
+            sweep_angle: std::f32::consts::PI,
+            rounded_caps: false,
+            ..arc
+        };
+        let aabb = arc.aabb();
+        assert!(aabb.min.abs_diff_eq(Vec2::new(8., 18.), 1e-5));
+        assert!(aabb.max.abs_diff_eq(Vec2::new(10., 22.), 1e-5));
     }
-}
 
-#[derive(Clone, Copy)]
-struct AssignedTile {
-    pub tile_index: i32,
-    pub prim_index: PackedPrimitiveIndex,
-}
+    #[test]
+    fn polygon() {
+        let polygon = Polygon::triangle(Vec2::ZERO, Vec2::new(10., 0.), Vec2::new(0., 20.));
+        let prim = PolygonPrimitive {
+            polygon,
+            stroke_width: 2.,
+            stroke_alignment: StrokeAlignment::Outside,
+            ..default()
+        };
+        let aabb = prim.aabb();
+        assert_eq!(aabb.min, Vec2::new(-2., -2.));
+        assert_eq!(aabb.max, Vec2::new(12., 22.));
 
-/// Component storing per-tile draw data.
-///
-/// This component is automatically added to any [`Camera`] and [`Canvas`]
-/// component pair missing it. It stores per-tile runtime data for rendering the
-/// canvas primitives. Most users can ignore it entirely.
-#[derive(Default, Clone, Component)]
-pub struct Tiles {
-    /// Tile size, in pixels. Currently hard-coded to 8x8 pixels.
-    pub(crate) tile_size: UVec2,
-    /// Dimensions of the canvas, in number of tiles.
-    ///
-    /// 4K, 8x8 => 129'600 tiles
-    /// 1080p, 8x8 => 32'400 tiles
-    pub(crate) dimensions: UVec2,
-    /// Flattened list of primitive indices for each tile. The start of a tile
-    /// is at element [`OffsetAndCount::offset`], and the tile contains
-    /// [`OffsetAndCount::count`] consecutive primitive offsets, each offset
-    /// being the start of the primitive into the primitive buffer of the
-    /// canvas.
-    pub(crate) primitives: Vec<PackedPrimitiveIndex>,
-    /// Offset and count of primitives per tile, into [`Tiles::primitives`].
-    pub(crate) offset_and_count: Vec<OffsetAndCount>,
-    /// Local cache saved frame-to-frame to avoid allocations.
-    assigned_tiles: Vec<AssignedTile>,
-}
+        let info = prim.info();
+        assert_eq!(info.row_count, 10);
+        let mut rows = [MaybeUninit::<f32>::uninit(); 10];
+        prim.write(&mut rows[..], Vec2::new(1., 0.), 2.);
+        let rows = rows.map(|r| unsafe { r.assume_init() });
+        assert_eq!(rows[1], 4.);
+        assert_eq!(rows[2], 2.);
+        assert_eq!(bytemuck::cast::<f32, u32>(rows[3]), 3);
+        assert_eq!(&rows[4..], &[2., 0., 22., 0., 2., 40.]);
 
-impl Tiles {
-    /// Update the tile data based on the current screen (canvas) size.
-    ///
-    /// This recalculates the dimensions of the various buffers and reallocate
-    /// them, to prepare for tiled drawing.
-    pub fn update_size(&mut self, screen_size: UVec2) {
-        // We force a 8x8 pixel tile, which works well with 32- and 64- waves.
-        self.tile_size = UVec2::new(8, 8);
+        // Degenerate polygons are not drawn
+        let prim = PolygonPrimitive {
+            polygon: Polygon::new([Vec2::ZERO, Vec2::X]),
+            ..default()
+        };
+        assert_eq!(prim.info().sub_prim_count, 0);
+    }
 
-        self.dimensions = (screen_size.as_vec2() / self.tile_size.as_vec2())
-            .ceil()
-            .as_uvec2();
+    #[test]
+    fn star() {
+        // Hexagon with its inner vertices in the middle of the edges
+        let apothem = 10. * (std::f32::consts::PI / 6.).cos();
+        let star = StarPrimitive {
+            center: Vec2::new(10., 20.),
+            points: 6,
+            outer_radius: 10.,
+            inner_radius: apothem,
+            border_width: 1.,
+            border_alignment: StrokeAlignment::Outside,
+            ..default()
+        };
+        let aabb = star.aabb();
+        assert_eq!(aabb.min, Vec2::new(-1., 9.));
+        assert_eq!(aabb.max, Vec2::new(21., 31.));
+        assert_eq!(star.info().row_count, 14);
 
-        assert!(self.dimensions.x * self.tile_size.x >= screen_size.x);
-        assert!(self.dimensions.y * self.tile_size.y >= screen_size.y);
+        // Rounding the corners moves the edges of the sharp hexagon inward
+        let (outer, inner, corner_radius) = star.sharp_radii();
+        assert!((outer - 10.).abs() < 1e-5);
+        assert!((inner - apothem).abs() < 1e-5);
+        assert_eq!(corner_radius, 0.);
+        let star = StarPrimitive {
+            corner_radius: 1.,
+            ..star
+        };
+        let (outer, inner, corner_radius) = star.sharp_radii();
+        let scale = (apothem - 1.) / apothem;
+        assert!((outer - 10. * scale).abs() < 1e-5);
+        assert!((inner - apothem * scale).abs() < 1e-5);
+        assert_eq!(corner_radius, 1.);
 
-        self.primitives.clear();
-        self.offset_and_count.clear();
-        self.offset_and_count
-            .reserve(self.dimensions.x as usize * self.dimensions.y as usize);
+        // Corners can't be rounded past the center
+        let star = StarPrimitive {
+            corner_radius: 100.,
+            ..star
+        };
+        let (outer, _, corner_radius) = star.sharp_radii();
+        assert!(outer.abs() < 1e-5);
+        assert!((corner_radius - apothem).abs() < 1e-5);
 
-        trace!(
-            "Resized Tiles at tile_size={:?} dim={:?} and cleared buffers",
-            self.tile_size,
-            self.dimensions
-        );
+        // Degenerate stars are not drawn
+        let star = StarPrimitive { points: 1, ..star };
+        assert_eq!(star.info().sub_prim_count, 0);
     }
 
-    /// Assign the given primitives to tiles.
-    ///
-    /// This performs the actual binning of primitives into one or more tiles.
-    /// This assumes the various tile buffers are appropriately sized and
-    /// allocated by a previous call to [`update_size()`].
-    ///
-    /// [`update_size()`]: crate::canvas::Tiles::update_size
-    pub(crate) fn assign_to_tiles(&mut self, primitives: &[PreparedPrimitive], screen_size: Vec2) {
-        let tile_size = self.tile_size.as_vec2();
+    #[test]
+    fn arrow() {
+        use crate::{render_context::Brush, ArrowEnds};
 
-        let oc_extra = self.dimensions.x as usize * self.dimensions.y as usize;
-        self.offset_and_count.reserve(oc_extra);
+        let mut canvas = Canvas::default();
+        let brush = Brush::from(Color::WHITE);
+        let head = ArrowHead::triangle(4., 6.).with_ends(ArrowEnds::Both);
+        canvas
+            .render_context()
+            .arrow(Vec2::ZERO, Vec2::new(10., 0.), &brush, 2., head);
+        let Primitive::Arrow(arrow) = canvas.buffer()[0] else {
+            panic!("Expected an arrow primitive");
+        };
+        assert_eq!(arrow.head, head);
 
-        // Some semi-random guesswork of average tile overlapping count per primitive,
-        // so we don't start from a stupidly small allocation.
-        self.assigned_tiles.reserve(primitives.len() * 4);
+        // Heads may extend in any direction from the ends
+        let aabb = arrow.aabb();
+        assert_eq!(aabb.min, Vec2::new(-8., -8.));
+        assert_eq!(aabb.max, Vec2::new(18., 8.));
 
-        // Loop over primitives and find tiles they overlap
-        for prim in primitives {
-            // Calculate bounds in terms of tile indices, clamped to the size of the screen
-            let uv_min = (prim.aabb.min.clamp(Vec2::ZERO, screen_size) / tile_size)
-                .floor()
-                .as_ivec2();
-            let mut uv_max = (prim.aabb.max.clamp(Vec2::ZERO, screen_size) / tile_size)
-                .ceil()
-                .as_ivec2();
-            if prim.aabb.max.x == tile_size.x * uv_max.x as f32 {
-                // We ignore tiles which only have a shared edge and no actualy surface overlap
-                uv_max.x -= 1;
-            }
-            if prim.aabb.max.y == tile_size.y * uv_max.y as f32 {
-                // We ignore tiles which only have a shared edge and no actualy surface overlap
-                uv_max.y -= 1;
-            }
+        let mut rows = [MaybeUninit::<f32>::uninit(); 9];
+        arrow.write(&mut rows[..], Vec2::ZERO, 2.);
+        let rows = rows.map(|r| unsafe { r.assume_init() });
+        assert_eq!(rows[2], 20.);
+        assert_eq!(rows[5], 4.);
+        assert_eq!(bytemuck::cast::<f32, u32>(rows[6]), 0x0300);
+        assert_eq!(rows[7], 8.);
+        assert_eq!(rows[8], 12.);
+    }
 
-            self.assigned_tiles
-                .reserve((uv_max.y - uv_min.y + 1) as usize * (uv_max.x - uv_min.x + 1) as usize);
+    #[test]
+    fn transform_stack() {
+        use crate::{render_context::Brush, shapes::Ellipse, ShapeExt};
 
-            // Loop on tiles overlapping this primitive. This is generally only a handful,
-            // unless the primitive covers a large part of the screen.
-            for ty in uv_min.y..=uv_max.y {
-                let base_tile_index = ty * self.dimensions.x as i32;
-                for tx in uv_min.x..=uv_max.x {
-                    let tile_index = base_tile_index + tx;
-                    self.assigned_tiles.push(AssignedTile {
-                        tile_index,
-                        prim_index: prim.prim_index,
-                    });
-                }
-            }
+        let mut canvas = Canvas::default();
+        let brush = Brush::from(Color::WHITE);
+        {
+            let mut ctx = canvas.render_context();
+            ctx.push_translation(Vec2::new(10., 20.));
+            ctx.push_scale(Vec2::splat(2.));
+            ctx.fill(Rect::new(0., 0., 4., 2.), &brush)
+                .border(&brush, 1.);
+            ctx.polyline([Vec2::ZERO, Vec2::new(1., 0.)], &brush, 1.);
+            ctx.pop_transform();
+            ctx.push_rotation(std::f32::consts::FRAC_PI_2);
+            ctx.line(Vec2::ZERO, Vec2::new(5., 0.), &brush, 1.);
+            ctx.fill(Ellipse::new(Vec2::new(5., 0.), Vec2::new(4., 2.)), &brush);
+            ctx.push_scale(Vec2::splat(2.));
+            ctx.draw_text(0, Vec2::new(5., 0.));
+            ctx.pop_transform();
+            ctx.pop_transform();
+            ctx.pop_transform();
+            assert_eq!(ctx.transform(), Affine2::IDENTITY);
+            ctx.fill(Rect::new(0., 0., 4., 2.), &brush);
         }
 
-        // Sort the primitive<->tile mapping by tile index. Note that the sort MUST BE
-        // STABLE, to preserve the order of primitives, which preserves what is drawn on
-        // top of what.
-        self.assigned_tiles.sort_by_key(|at| at.tile_index);
+        // Borders added after drawing are transformed too
+        let Primitive::Rect(rect) = canvas.buffer()[0] else {
+            panic!("Expected a rect primitive");
+        };
+        assert_eq!(rect.rect, Rect::new(10., 20., 18., 24.));
+        assert_eq!(rect.border_width, 2.);
 
-        // Build the offset and count list
-        self.primitives.reserve(self.assigned_tiles.len());
-        let mut ti = -1;
-        let mut offset = 0;
-        let mut count = 0;
-        for at in &self.assigned_tiles {
-            if at.tile_index != ti {
-                if count > 0 {
-                    // Write previous tile
-                    self.offset_and_count.push(OffsetAndCount {
-                        offset: offset as u32,
-                        count,
-                    });
-                }
-                // Write empty tile(s)
-                for _ in ti + 1..at.tile_index {
-                    self.offset_and_count.push(OffsetAndCount {
-                        offset: offset as u32,
-                        count: 0,
-                    });
-                }
-                offset = self.primitives.len() as u32;
-                count = 0;
-                ti = at.tile_index;
-            }
+        let Primitive::Polyline(polyline) = canvas.buffer()[1] else {
+            panic!("Expected a polyline primitive");
+        };
+        assert_eq!(polyline.thickness, 2.);
+        assert_eq!(
+            canvas.paths()[polyline.id as usize].elements(),
+            [
+                PathEl::MoveTo(Vec2::new(10., 20.)),
+                PathEl::LineTo(Vec2::new(12., 20.))
+            ]
+        );
 
-            self.primitives.push(at.prim_index);
-            count += 1;
-        }
-        // Write last pending tile
-        if count > 0 {
-            self.offset_and_count.push(OffsetAndCount {
-                offset: offset as u32,
-                count,
-            });
-        }
-        // Write empty tile(s) at the end
-        for _ in ti + 1..oc_extra as i32 {
-            self.offset_and_count.push(OffsetAndCount {
-                offset: offset as u32,
-                count: 0,
-            });
+        let Primitive::Line(line) = canvas.buffer()[2] else {
+            panic!("Expected a line primitive");
+        };
+        assert_eq!(line.start, Vec2::new(10., 20.));
+        assert!(line.end.abs_diff_eq(Vec2::new(10., 25.), 1e-5));
+        assert_eq!(line.thickness, 1.);
+
+        // Ellipses and text are rotated too
+        let Primitive::Ellipse(ellipse) = canvas.buffer()[3] else {
+            panic!("Expected an ellipse primitive");
+        };
+        assert!(ellipse.center.abs_diff_eq(Vec2::new(10., 25.), 1e-5));
+        assert_eq!(ellipse.radii, Vec2::new(4., 2.));
+        assert!((ellipse.rotation.as_radians() - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+        let aabb = canvas.buffer()[3].tile_aabb(canvas.effect_tables());
+        assert!(aabb.min.abs_diff_eq(Vec2::new(8., 21.), 1e-5));
+        assert!(aabb.max.abs_diff_eq(Vec2::new(12., 29.), 1e-5));
+
+        let Primitive::Text(text) = canvas.buffer()[4] else {
+            panic!("Expected a text primitive");
+        };
+        assert!(text.rect.min.abs_diff_eq(Vec2::new(10., 30.), 1e-5));
+        assert!((text.rotation.as_radians() - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+        assert_eq!(text.scale, 2.);
+        let aabb = text.local_aabb(Vec2::ZERO, Vec2::new(4., 1.));
+        assert!(aabb.min.abs_diff_eq(Vec2::new(8., 30.), 1e-5));
+        assert!(aabb.max.abs_diff_eq(Vec2::new(10., 38.), 1e-5));
+
+        // Primitives drawn after popping all transforms are untouched
+        let Primitive::Rect(rect) = canvas.buffer()[5] else {
+            panic!("Expected a rect primitive");
+        };
+        assert_eq!(rect.rect, Rect::new(0., 0., 4., 2.));
+    }
+
+    #[test]
+    fn transform_non_uniform() {
+        use crate::render_context::Brush;
+
+        assert!(is_similarity(&Affine2::from_scale_angle_translation(
+            Vec2::splat(3.),
+            0.5,
+            Vec2::new(1., 2.)
+        )));
+        assert!(!is_similarity(&Affine2::from_scale(Vec2::new(2., 1.))));
+        assert!(!is_similarity(&Affine2::from_cols_array(&[
+            1., 0., 1., 1., 0., 0.
+        ])));
+
+        let mut canvas = Canvas::default();
+        let brush = Brush::from(Color::WHITE);
+        {
+            let mut ctx = canvas.render_context();
+            ctx.push_scale(Vec2::new(2., 1.));
+            ctx.line(Vec2::ZERO, Vec2::new(5., 0.), &brush, 1.);
+            ctx.line(Vec2::ZERO, Vec2::new(0., 5.), &brush, 1.);
+            ctx.pop_transform();
         }
 
-        // Clear scratch buffer for next call
-        self.assigned_tiles.clear();
+        // Lines are scaled across their direction, not by the mean scale
+        let Primitive::Line(line) = canvas.buffer()[0] else {
+            panic!("Expected a line primitive");
+        };
+        assert_eq!(line.end, Vec2::new(10., 0.));
+        assert_eq!(line.thickness, 1.);
+        let Primitive::Line(line) = canvas.buffer()[1] else {
+            panic!("Expected a line primitive");
+        };
+        assert_eq!(line.end, Vec2::new(0., 5.));
+        assert_eq!(line.thickness, 2.);
     }
-}
 
-/// Ensure any active [`Camera`] component with a [`Canvas`] component also has
-/// associated [`TileConfig`] and [`Tiles`] components.
-pub fn spawn_missing_tiles_components(
-    mut commands: Commands,
-    cameras: Query<(Entity, Option<&TileConfig>, &Camera), (With<Canvas>, Without<Tiles>)>,
-) {
-    for (entity, config, camera) in &cameras {
-        if !camera.is_active {
-            continue;
+    #[test]
+    fn transform_reflection() {
+        use crate::shapes::Star;
+
+        let mut canvas = Canvas::default();
+        let brush = Brush::from(Color::WHITE);
+        {
+            let mut ctx = canvas.render_context();
+            ctx.push_scale(Vec2::new(1., -1.));
+            ctx.fill(
+                RoundedRect::new(Rect::new(0., 0., 10., 4.), CornerRadii::top(2.)),
+                &brush,
+            );
+            ctx.draw_text(0, Vec2::new(5., 0.));
+            ctx.pop_transform();
+            ctx.push_scale(Vec2::new(-1., 1.));
+            ctx.fill(Star::new(Vec2::ZERO, 5, 2., 4.), &brush);
+            ctx.pop_transform();
         }
 
-        let config = config.copied().unwrap_or_default();
-        commands.entity(entity).insert((Tiles::default(), config));
-    }
-}
+        // Mirroring vertically swaps the top and bottom corners, and flips the
+        // image instead of turning the rectangle upside down
+        let Primitive::Rect(rect) = canvas.buffer()[0] else {
+            panic!("Expected a rect primitive");
+        };
+        assert_eq!(rect.rect, Rect::new(0., -4., 10., 0.));
+        assert_eq!(rect.rotation, Rot2::IDENTITY);
+        assert!(rect.flip_y);
+        assert!(!rect.flip_x);
+        assert_eq!(rect.radii, CornerRadii::bottom(2.));
 
-pub fn resize_tiles_to_camera_render_target(
-    mut views: Query<(&Camera, &TileConfig, &mut Tiles), With<Canvas>>,
-) {
-    // Loop on all camera views
-    for (camera, _tile_config, tiles) in &mut views {
-        let Some(screen_size) = camera.physical_viewport_size() else {
-            continue;
+        // Glyphs are mirrored too, so they're not drawn upside down
+        let Primitive::Text(text) = canvas.buffer()[1] else {
+            panic!("Expected a text primitive");
         };
+        assert_eq!(text.rotation, Rot2::IDENTITY);
+        assert!(text.flip_y);
+        let aabb = text.local_aabb(Vec2::ZERO, Vec2::new(4., 1.));
+        assert_eq!(aabb.min, Vec2::new(5., -1.));
+        assert_eq!(aabb.max, Vec2::new(9., 0.));
 
-        // Resize tile storage to fit the viewport size
-        let tiles = tiles.into_inner();
-        tiles.update_size(screen_size);
+        // Mirroring horizontally keeps the tip of the star pointing up, instead
+        // of rotating the star by half a turn
+        let Primitive::Star(star) = canvas.buffer()[2] else {
+            panic!("Expected a star primitive");
+        };
+        assert!((star.rotation * Vec2::NEG_Y).abs_diff_eq(Vec2::NEG_Y, 1e-5));
     }
-}
 
-pub fn allocate_atlas_layouts(
-    mut query: Query<&mut Canvas>,
-    mut layouts: ResMut<Assets<TextureAtlasLayout>>,
-) {
-    for mut canvas in query.iter_mut() {
-        // FIXME
-        let size = UVec2::splat(1024);
+    #[test]
+    fn clip_rect_stack() {
+        use crate::{render_context::Brush, ShapeExt};
 
-        // FIXME - also check for resize...
-        if canvas.atlas_layout == Handle::<TextureAtlasLayout>::default() {
-            canvas.atlas_layout = layouts.add(TextureAtlasLayout::new_empty(size));
+        let mut canvas = Canvas::default();
+        let brush = Brush::from(Color::WHITE);
+        {
+            let mut ctx = canvas.render_context();
+            ctx.push_translation(Vec2::new(100., 0.));
+            ctx.push_clip_rect(Rect::new(0., 0., 50., 50.));
+            ctx.fill(Rect::new(-10., 10., 20., 20.), &brush)
+                .glow(&brush, 4.);
+            ctx.push_clip_rect(Rect::new(25., 25., 80., 80.));
+            assert_eq!(ctx.clip(), Some(Rect::new(125., 25., 150., 50.)));
+            ctx.fill(Rect::new(0., 0., 10., 10.), &brush);
+            ctx.pop_clip();
+            ctx.pop_clip();
+            ctx.pop_transform();
+            assert_eq!(ctx.clip(), None);
+            ctx.fill(Rect::new(0., 0., 10., 10.), &brush);
         }
+
+        // The AABB is cut by the clip rectangle, including effects
+        let prim = &canvas.buffer()[0];
+        assert_eq!(
+            prim.effects(canvas.effect_tables()).clip,
+            Some(Rect::new(100., 0., 150., 50.))
+        );
+        let aabb = prim.tile_aabb(canvas.effect_tables());
+        assert_eq!(aabb.min, Vec2::new(100., 6.));
+        assert_eq!(aabb.max, Vec2::new(124., 24.));
+        let rows = Effects::ROW_COUNT_FLAGS + Effects::ROW_COUNT_GLOW + Effects::ROW_COUNT_CLIP;
+        assert_eq!(
+            prim.effects(canvas.effect_tables())
+                .row_count(canvas.effect_tables()),
+            rows
+        );
+
+        // Entirely outside the nested clip rectangles
+        let prim = &canvas.buffer()[1];
+        assert_eq!(
+            prim.effects(canvas.effect_tables()).clip,
+            Some(Rect::new(125., 25., 150., 50.))
+        );
+        let aabb = prim.tile_aabb(canvas.effect_tables());
+        assert!(aabb.min.x > aabb.max.x);
+        let mut tiles = Tiles::default();
+        tiles.update_size(UVec2::new(256, 64));
+        let prim_index =
+            PackedPrimitiveIndex::new(0, GpuPrimitiveKind::Rect, false, false, true).unwrap();
+        tiles.assign_to_tiles(
+            &[PreparedPrimitive {
+                aabb,
+                hole: None,
+                prim_index,
+            }],
+            Vec2::new(256., 64.),
+        );
+        assert!(tiles.primitives.is_empty());
+
+        assert!(!canvas.buffer()[2].has_effects(canvas.effect_tables()));
+
+        // Disjoint clip rectangles clip everything out
+        let clip = intersect_clip(Rect::new(0., 0., 10., 10.), Rect::new(20., 20., 30., 30.));
+        assert!(clip.min.x > clip.max.x && clip.min.y > clip.max.y);
     }
-}
 
-/// Calculate the width of a fixed-aspect rectangle given a content height.
-fn aspect_width(size: Vec2, content_height: f32) -> f32 {
-    size.x.max(0.) / size.y.max(1.) * content_height.max(0.)
-}
+    #[test]
+    fn clip_shape() {
+        use crate::{render_context::Brush, shapes::Ellipse, RoundedRect};
 
-/// Calculate the size of a rectangle such that its width fits in the given
-/// content, and its height either stretches to that content or it keeps its
-/// aspect ratio (and therefore gets clipped).
-fn fit_width(size: Vec2, content_size: Vec2, stretch_height: bool) -> Vec2 {
-    Vec2::new(
-        content_size.x,
-        if stretch_height {
-            content_size.y
-        } else {
-            aspect_height(size, content_size.x)
-        },
-    )
-}
+        let mut canvas = Canvas::default();
+        let brush = Brush::from(Color::WHITE);
+        {
+            let mut ctx = canvas.render_context();
+            ctx.push_translation(Vec2::new(100., 0.));
+            ctx.push_clip_shape(Ellipse::circle(Vec2::new(10., 10.), 10.));
+            ctx.push_clip_shape(RoundedRect::new(Rect::new(5., 5., 40., 40.), 4.));
+            ctx.fill(Rect::new(0., 0., 30., 30.), &brush);
+            ctx.pop_clip();
+            ctx.fill(Rect::new(0., 0., 30., 30.), &brush);
+            ctx.pop_clip();
+            // A degenerate shape clips everything out
+            ctx.push_clip_shape(Polygon::new([Vec2::ZERO, Vec2::ONE]));
+            ctx.fill(Rect::new(0., 0., 30., 30.), &brush);
+            ctx.pop_clip();
+            ctx.pop_transform();
+            ctx.fill(Rect::new(0., 0., 30., 30.), &brush);
+        }
 
-/// Calculate the height of a fixed-aspect rectangle given a content width.
-fn aspect_height(size: Vec2, content_width: f32) -> f32 {
-    size.y.max(0.) / size.x.max(1.) * content_width.max(0.)
-}
+        // Masks are stored apart, transformed, and not drawn
+        assert_eq!(canvas.buffer().len(), 4);
+        assert_eq!(canvas.masks().len(), 2);
+        let Primitive::Ellipse(ellipse) = canvas.masks()[0] else {
+            panic!("Expected an ellipse mask");
+        };
+        assert_eq!(ellipse.center, Vec2::new(110., 10.));
 
-/// Calculate the size of a rectangle such that its height fits in the given
-/// content, and its width either stretches to that content or it keeps its
-/// aspect ratio (and therefore gets clipped).
-fn fit_height(size: Vec2, content_size: Vec2, stretch_width: bool) -> Vec2 {
-    Vec2::new(
-        if stretch_width {
-            content_size.x
-        } else {
-            aspect_width(size, content_size.y)
-        },
-        content_size.y,
-    )
-}
+        // Nested masks intersect, and the AABB is cut by the masks bounds
+        let prim = &canvas.buffer()[0];
+        assert_eq!(prim.effects(canvas.effect_tables()).masks.ids(), &[0, 1]);
+        let aabb = prim.tile_aabb(canvas.effect_tables());
+        assert_eq!(aabb.min, Vec2::new(105., 5.));
+        assert_eq!(aabb.max, Vec2::new(120., 20.));
+        let effects = prim.effects(canvas.effect_tables());
+        let mut rows =
+            vec![MaybeUninit::<f32>::uninit(); effects.row_count(canvas.effect_tables()) as usize];
+        effects.write(
+            &mut rows[..],
+            canvas.effect_tables(),
+            &[7, 42],
+            Vec2::ZERO,
+            1.,
+        );
+        let rows: Vec<u32> = rows
+            .iter()
+            .map(|r| bytemuck::cast(unsafe { r.assume_init() }))
+            .collect();
+        assert_eq!(rows[0], Effects::FLAG_CLIP | Effects::FLAG_MASK);
+        assert_eq!(&rows[rows.len() - 3..], &[2, 7, 42]);
 
-/// Calculate the size of a rectangle such that both its width and height fit in
-/// the given content, and the other direction either stretches to that content
-/// or it keeps its aspect ratio. This ensures the returned rectangle covers the
-/// content, possibly clipping in one direction to do so.
-fn fit_any(size: Vec2, content_size: Vec2, stretch_other: bool) -> Vec2 {
-    let aspect = size.x.max(0.) / size.y.max(1.);
-    let content_aspect = content_size.x.max(0.) / content_size.y.max(1.);
-    if aspect >= content_aspect {
-        fit_height(size, content_size, stretch_other)
-    } else {
-        fit_width(size, content_size, stretch_other)
+        assert_eq!(
+            canvas.buffer()[1]
+                .effects(canvas.effect_tables())
+                .masks
+                .ids(),
+            &[0]
+        );
+        let aabb = canvas.buffer()[2].tile_aabb(canvas.effect_tables());
+        assert!(aabb.min.x > aabb.max.x);
+        assert!(!canvas.buffer()[3].has_effects(canvas.effect_tables()));
+
+        // Only the first primitive drawn becomes a mask, the others are dropped
+        let first = canvas.buffer().len();
+        canvas.draw(Primitive::Rect(RectPrimitive {
+            rect: Rect::new(0., 0., 1., 1.),
+            ..default()
+        }));
+        canvas.draw(Primitive::Rect(RectPrimitive {
+            rect: Rect::new(2., 2., 3., 3.),
+            ..default()
+        }));
+        assert_eq!(canvas.make_mask(first), Some(2));
+        assert_eq!(canvas.buffer().len(), first);
+        assert_eq!(canvas.masks()[2].aabb().max, Vec2::ONE);
+
+        // Clearing the context keeps the masks in use, and the paths they reference
+        let triangle = crate::shapes::Path::new()
+            .move_to(Vec2::ZERO)
+            .line_to(Vec2::new(20., 0.))
+            .line_to(Vec2::new(0., 20.))
+            .close();
+        let mut canvas = Canvas::default();
+        {
+            let mut ctx = canvas.render_context();
+            ctx.fill(&triangle, &brush);
+            ctx.push_clip_shape(&triangle);
+            ctx.clear(None, Color::BLACK);
+            ctx.fill(Rect::new(0., 0., 30., 30.), &brush);
+            ctx.pop_clip();
+        }
+        assert_eq!(canvas.buffer().len(), 2);
+        assert_eq!(canvas.masks().len(), 1);
+        let Primitive::Path(path) = canvas.masks()[0] else {
+            panic!("Expected a path mask");
+        };
+        assert_eq!(path.id, 0);
+        assert_eq!(canvas.paths().len(), 1);
+        let effects = canvas.buffer()[1].effects(canvas.effect_tables());
+        assert_eq!(effects.masks.ids(), &[0]);
+        let mask_indices = vec![42; canvas.masks().len()];
+        let mut rows =
+            vec![MaybeUninit::<f32>::uninit(); effects.row_count(canvas.effect_tables()) as usize];
+        effects.write(
+            &mut rows[..],
+            canvas.effect_tables(),
+            &mask_indices[..],
+            Vec2::ZERO,
+            1.,
+        );
+        let last: u32 = bytemuck::cast(unsafe { rows[rows.len() - 1].assume_init() });
+        assert_eq!(last, 42);
     }
-}
 
-/// Process all images drawn onto all canvases.
-///
-/// This calculates the proper image size given the content rectangle size and
-/// the window scale factor, applying any image scaling as specified during the
-/// draw call.
-pub fn process_images(
-    images: Res<Assets<Image>>,
-    q_window: Query<&Window, With<PrimaryWindow>>,
-    mut q_canvas: Query<&mut Canvas>,
-) {
-    // TODO - handle multi-window
-    let Ok(primary_window) = q_window.get_single() else {
-        return;
-    };
-    let scale_factor = primary_window.scale_factor() as f32;
+    #[test]
+    fn save_restore() {
+        let mut canvas = Canvas::default();
+        {
+            let mut ctx = canvas.render_context();
+            ctx.set_default_brush(Color::WHITE);
+            ctx.save();
+            ctx.push_translation(Vec2::new(10., 0.));
+            ctx.push_clip_rect(Rect::new(0., 0., 50., 50.));
+            ctx.set_global_alpha(0.5);
+            ctx.set_default_brush(Color::BLACK);
+            ctx.fill_default(Rect::new(0., 0., 10., 10.));
+            ctx.save();
+            ctx.set_global_alpha(0.25);
+            ctx.fill_default(Rect::new(0., 0., 10., 10.));
+            ctx.restore();
+            assert_eq!(ctx.global_alpha(), 0.5);
+            // Unbalanced pushes are discarded
+            ctx.restore();
+            assert_eq!(ctx.transform(), Affine2::IDENTITY);
+            assert_eq!(ctx.clip(), None);
+            assert_eq!(ctx.global_alpha(), 1.);
+            ctx.fill_default(Rect::new(0., 0., 10., 10.));
 
-    for mut canvas in q_canvas.iter_mut() {
-        for prim in &mut canvas.primitives {
-            let Primitive::Rect(rect) = prim else {
-                continue;
-            };
-            let Some(id) = rect.image else {
-                continue;
-            };
-            if let Some(image) = images.get(id) {
-                let image_size = Vec2::new(
-                    image.texture_descriptor.size.width as f32,
-                    image.texture_descriptor.size.height as f32,
-                );
-                let content_size = rect.rect.size() * scale_factor;
-                rect.image_size = match rect.image_scaling {
-                    ImageScaling::Uniform(ratio) => image_size * ratio,
-                    ImageScaling::FitWidth(stretch_height) => {
-                        fit_width(image_size, content_size, stretch_height)
-                    }
-                    ImageScaling::FitHeight(stretch_width) => {
-                        fit_height(image_size, content_size, stretch_width)
-                    }
-                    ImageScaling::Fit(stretch_other) => {
-                        fit_any(image_size, content_size, stretch_other)
-                    }
-                    ImageScaling::Stretch => content_size,
-                }
-            } else {
-                warn!("Unknown image asset ID {:?}; skipped.", id);
-                rect.image = None;
-            }
+            // Transforms and clips popped past the save are pushed back
+            let outer = Affine2::from_translation(Vec2::new(5., 0.));
+            ctx.push_translation(Vec2::new(5., 0.));
+            ctx.push_clip_rect(Rect::new(0., 0., 50., 50.));
+            ctx.save();
+            ctx.pop_clip();
+            ctx.pop_transform();
+            ctx.push_translation(Vec2::new(0., 7.));
+            ctx.push_clip_rect(Rect::new(0., 0., 20., 20.));
+            ctx.restore();
+            assert_eq!(ctx.transform(), outer);
+            assert_eq!(ctx.clip(), Some(Rect::new(5., 0., 55., 50.)));
+            ctx.pop_clip();
+            ctx.pop_transform();
+            assert_eq!(ctx.transform(), Affine2::IDENTITY);
+            assert_eq!(ctx.clip(), None);
         }
+
+        let Primitive::Rect(rect) = canvas.buffer()[0] else {
+            panic!("Expected a rect primitive");
+        };
+        assert_eq!(rect.rect, Rect::new(10., 0., 20., 10.));
+        assert_eq!(rect.color, Color::BLACK);
+        let effects = canvas.effect_tables().effects(rect.effects);
+        assert_eq!(effects.clip, Some(Rect::new(10., 0., 60., 50.)));
+        assert_eq!(effects.opacity, Some(0.5));
+
+        // The global alpha is replaced, not multiplied
+        assert_eq!(
+            canvas.buffer()[1].effects(canvas.effect_tables()).opacity,
+            Some(0.25)
+        );
+
+        let Primitive::Rect(rect) = canvas.buffer()[2] else {
+            panic!("Expected a rect primitive");
+        };
+        assert_eq!(rect.rect, Rect::new(0., 0., 10., 10.));
+        assert_eq!(rect.color, Color::WHITE);
+        assert!(rect.effects.is_none());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn layer_text_bounds() {
+        use crate::render::{add_text_to_layer_bounds, ExtractedGlyph};
+
+        let mut canvas = Canvas::default();
+        let begin = canvas.begin_layer(1.);
+        canvas.draw(Primitive::Rect(RectPrimitive {
+            rect: Rect::new(0., 0., 10., 10.),
+            ..default()
+        }));
+        canvas.draw(Primitive::Text(TextPrimitive {
+            id: 0,
+            rect: Rect::new(20., 0., 20., 0.),
+            rotation: Rot2::IDENTITY,
+            scale: 1.,
+            flip_y: false,
+            border_width: 0.,
+            border_color: Color::NONE,
+            effects: default(),
+        }));
+        canvas.end_layer(begin);
+
+        // The text is not laid out yet, so doesn't make the bounds infinite
+        let Primitive::Layer(layer) = canvas.buffer()[0] else {
+            panic!("Expected a layer primitive");
+        };
+        assert_eq!(layer.bounds, Rect::new(0., 0., 10., 10.));
+
+        // Once extracted, the glyphs grow the bounds of both markers
+        let texts = [ExtractedText {
+            glyphs: vec![ExtractedGlyph {
+                offset: Vec2::new(0., 4.),
+                size: Vec2::new(8., 12.),
+                color: 0,
+                handle_id: AssetId::invalid(),
+                uv_rect: Rect::default(),
+            }],
+        }];
+        let mut primitives = canvas.buffer().clone();
+        add_text_to_layer_bounds(&mut primitives[..], &texts, canvas.effect_tables(), 0.5);
+        for index in [0, 3] {
+            let Primitive::Layer(layer) = primitives[index] else {
+                panic!("Expected a layer primitive");
+            };
+            assert_eq!(layer.bounds, Rect::new(0., 0., 24., 10.));
+        }
+    }
 
     #[test]
-    fn tiles() {
-        let mut tiles = Tiles::default();
-        tiles.update_size(UVec2::new(32, 64));
-        assert_eq!(tiles.dimensions, UVec2::new(4, 8));
-        assert!(tiles.primitives.is_empty());
-        assert!(tiles.offset_and_count.is_empty());
-        assert_eq!(tiles.offset_and_count.capacity(), 32);
+    fn layer_single_batch() {
+        use crate::render::ExtractedGlyph;
 
-        let prim_index = PackedPrimitiveIndex::new(42, GpuPrimitiveKind::Line, true, false);
-        tiles.assign_to_tiles(
-            &[PreparedPrimitive {
-                // 8 x 16, exactly aligned on the tile grid => 2 tiles exactly
-                aabb: Aabb2d {
-                    min: Vec2::new(8., 16.),
-                    max: Vec2::new(16., 32.),
-                },
-                prim_index,
+        let image = |id| Handle::<Image>::weak_from_u128(id).id();
+        let image_rect = |id| {
+            Primitive::Rect(RectPrimitive {
+                rect: Rect::new(0., 0., 10., 10.),
+                image: Some(image(id)),
+                ..default()
+            })
+        };
+        let atlas = image(100);
+        let text = Primitive::Text(TextPrimitive {
+            id: 0,
+            rect: Rect::new(0., 0., 0., 0.),
+            rotation: Rot2::IDENTITY,
+            scale: 1.,
+            flip_y: false,
+            border_width: 0.,
+            border_color: Color::NONE,
+            effects: default(),
+        });
+
+        // Some images fill the first batch, so the image and the text of the layer
+        // only fit together in a new batch
+        let mut canvas = Canvas::default();
+        for id in 1..MAX_BATCH_TEXTURES as u128 {
+            canvas.draw(image_rect(id));
+        }
+        let begin = canvas.begin_layer(0.5);
+        canvas.draw(image_rect(MAX_BATCH_TEXTURES as u128));
+        canvas.draw(text);
+        assert!(canvas.end_layer(begin));
+
+        let mut extracted_canvas = ExtractedCanvas::default();
+        extracted_canvas.primitives = canvas.buffer().clone();
+        extracted_canvas.scale_factor = 1.;
+        extracted_canvas.texts = vec![ExtractedText {
+            glyphs: vec![ExtractedGlyph {
+                offset: Vec2::ZERO,
+                size: Vec2::new(8., 12.),
+                color: 0,
+                handle_id: atlas,
+                uv_rect: Rect::default(),
             }],
-            // Large screen size, no effect in this test
-            Vec2::new(256., 128.),
+        }];
+        let mut primitives = vec![];
+        let mut prepared = vec![];
+        let batches =
+            extracted_canvas.batch_primitives(Entity::from_raw(1), &mut primitives, &mut prepared);
+
+        // The whole layer is in the second batch, and only there
+        assert_eq!(batches.len(), 2);
+        let (batch, range) = &batches[1];
+        assert_eq!(*range, MAX_BATCH_TEXTURES - 1..prepared.len());
+        let kinds: Vec<u32> = prepared[range.clone()]
+            .iter()
+            .map(|pp| pp.prim_index.0 >> 27 & 0xF)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                GpuPrimitiveKind::Layer as u32,
+                GpuPrimitiveKind::Rect as u32,
+                GpuPrimitiveKind::Glyph as u32,
+                GpuPrimitiveKind::Layer as u32,
+            ]
         );
 
-        assert_eq!(tiles.primitives.len(), 2);
-        assert_eq!(tiles.primitives[0], prim_index);
-        assert_eq!(tiles.primitives[1], prim_index);
+        // The image and the glyph sample from different slots of the batch
+        let slot = |pp: &PreparedPrimitive| (pp.prim_index.0 >> 23 & 0x3) as usize;
+        let image_slot = slot(&prepared[range.start + 1]);
+        let glyph_slot = slot(&prepared[range.start + 2]);
+        assert_eq!(
+            image_slot,
+            batch.texture_slot(image(MAX_BATCH_TEXTURES as u128))
+        );
+        assert_eq!(glyph_slot, batch.texture_slot(atlas));
+        assert_ne!(image_slot, glyph_slot);
+    }
 
-        assert_eq!(tiles.offset_and_count.len(), 32);
-        for (idx, oc) in tiles.offset_and_count.iter().enumerate() {
-            if idx == 9 || idx == 13 {
-                assert_eq!(oc.count, 1);
-                assert_eq!(oc.offset, if idx == 9 { 0 } else { 1 });
+    #[test]
+    fn batch_overflow() {
+        use crate::render::ExtractedGlyph;
+
+        // A text with too many glyphs to address all their rows
+        let glyph_count = PackedPrimitiveIndex::MAX_INDEX / TextPrimitive::ROW_PER_GLYPH + 2;
+        let text = Primitive::Text(TextPrimitive {
+            id: 0,
+            rect: Rect::new(0., 0., 0., 0.),
+            rotation: Rot2::IDENTITY,
+            scale: 1.,
+            flip_y: false,
+            border_width: 0.,
+            border_color: Color::NONE,
+            effects: default(),
+        });
+        let mut extracted_canvas = ExtractedCanvas::default();
+        extracted_canvas.primitives =
+            vec![Primitive::Rect(default()), text, Primitive::Rect(default())];
+        extracted_canvas.scale_factor = 1.;
+        extracted_canvas.texts = vec![ExtractedText {
+            glyphs: (0..glyph_count)
+                .map(|_| ExtractedGlyph {
+                    offset: Vec2::ZERO,
+                    size: Vec2::new(8., 12.),
+                    color: 0,
+                    handle_id: AssetId::invalid(),
+                    uv_rect: Rect::default(),
+                })
+                .collect(),
+        }];
+        let mut primitives = vec![];
+        let mut prepared = vec![];
+        let batches =
+            extracted_canvas.batch_primitives(Entity::from_raw(1), &mut primitives, &mut prepared);
+
+        // The text is not drawn at all, nor anything after it
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].1, 0..1);
+        assert_eq!(prepared.len(), 1);
+        assert_eq!(primitives.len(), RectPrimitive::ROW_COUNT_BASE as usize);
+    }
+
+    #[test]
+    fn layer() {
+        let mut canvas = Canvas::default();
+        {
+            let mut ctx = canvas.render_context();
+            ctx.set_default_brush(Color::WHITE);
+            ctx.push_layer(0.5);
+            ctx.push_translation(Vec2::new(10., 0.));
+            ctx.fill_default(Rect::new(0., 0., 10., 10.));
+            ctx.fill_default(Rect::new(5., 5., 20., 20.));
+            ctx.pop_transform();
+            ctx.pop_layer();
+            // Empty layer
+            ctx.push_layer(0.5);
+            ctx.pop_layer();
+            // Unbalanced layer, closed on drop
+            ctx.push_layer(2.);
+        }
+
+        let buffer = canvas.buffer();
+        assert_eq!(buffer.len(), 8);
+        let Primitive::Layer(begin) = buffer[0] else {
+            panic!("Expected a layer primitive");
+        };
+        let Primitive::Layer(end) = buffer[3] else {
+            panic!("Expected a layer primitive");
+        };
+        assert!(!begin.is_end);
+        assert!(end.is_end);
+        assert_eq!(begin.opacity, 0.5);
+        assert_eq!(begin.bounds, Rect::new(10., 0., 30., 20.));
+        assert_eq!(end.bounds, begin.bounds);
+        // The group is faded as a whole, not each primitive
+        assert!(buffer[1].effects(canvas.effect_tables()).is_empty());
+        assert!(buffer[2].effects(canvas.effect_tables()).is_empty());
+
+        let Primitive::Layer(empty) = buffer[4] else {
+            panic!("Expected a layer primitive");
+        };
+        assert!(empty.bounds.is_empty());
+
+        let Primitive::Layer(unbalanced) = buffer[7] else {
+            panic!("Expected a layer primitive");
+        };
+        assert!(unbalanced.is_end);
+        assert_eq!(unbalanced.opacity, 1.);
+
+        // Too deeply nested layers fall back to the global alpha
+        let mut canvas = Canvas::default();
+        {
+            let mut ctx = canvas.render_context();
+            for _ in 0..MAX_LAYER_DEPTH {
+                ctx.push_layer(1.);
+            }
+            ctx.push_layer(0.5);
+            assert_eq!(ctx.global_alpha(), 0.5);
+            ctx.fill(Rect::new(0., 0., 10., 10.), &Color::WHITE.into());
+            ctx.pop_layer();
+            assert_eq!(ctx.global_alpha(), 1.);
+        }
+        let buffer = canvas.buffer();
+        assert_eq!(buffer.len(), MAX_LAYER_DEPTH * 2 + 1);
+        assert_eq!(
+            buffer[MAX_LAYER_DEPTH]
+                .effects(canvas.effect_tables())
+                .opacity,
+            Some(0.5)
+        );
+
+        // Clearing the context reopens the layers still open
+        let mut canvas = Canvas::default();
+        {
+            let mut ctx = canvas.render_context();
+            ctx.push_layer(0.5);
+            ctx.fill(Rect::new(0., 0., 10., 10.), &Color::WHITE.into());
+            ctx.fill(Rect::new(0., 0., 10., 10.), &Color::WHITE.into());
+            ctx.clear(None, Color::BLACK);
+            ctx.fill(Rect::new(5., 5., 20., 20.), &Color::WHITE.into());
+            ctx.pop_layer();
+        }
+        let buffer = canvas.buffer();
+        assert_eq!(buffer.len(), 4);
+        let Primitive::Layer(begin) = buffer[1] else {
+            panic!("Expected a layer primitive");
+        };
+        let Primitive::Layer(end) = buffer[3] else {
+            panic!("Expected a layer primitive");
+        };
+        assert!(!begin.is_end);
+        assert!(end.is_end);
+        assert_eq!(begin.opacity, 0.5);
+        assert_eq!(begin.bounds, Rect::new(5., 5., 20., 20.));
+        assert_eq!(end.bounds, begin.bounds);
+
+        // Layers mix up to MAX_BATCH_TEXTURES textures, counting all text as one
+        let image = |id| {
+            Primitive::Rect(RectPrimitive {
+                rect: Rect::new(0., 0., 10., 10.),
+                image: Some(Handle::<Image>::weak_from_u128(id).id()),
+                ..default()
+            })
+        };
+        let text = Primitive::Text(TextPrimitive {
+            id: 0,
+            rect: Rect::new(0., 0., 0., 0.),
+            rotation: Rot2::IDENTITY,
+            scale: 1.,
+            flip_y: false,
+            border_width: 0.,
+            border_color: Color::NONE,
+            effects: default(),
+        });
+        let untextured = Primitive::Rect(default());
+        for (prims, is_kept) in [
+            (vec![untextured, image(1), image(1)], true),
+            (vec![text, untextured, text], true),
+            (vec![image(1), untextured, image(2)], true),
+            (vec![image(1), text], true),
+            (vec![image(1), image(2), image(3), text, image(1)], true),
+            (vec![image(1), image(2), image(3), image(4), text], false),
+        ] {
+            let mut canvas = Canvas::default();
+            canvas.draw(untextured);
+            let count = prims.len();
+            let begin = canvas.begin_layer(0.5);
+            for prim in prims {
+                canvas.draw(prim);
+            }
+            assert_eq!(canvas.end_layer(begin), is_kept);
+            let buffer = canvas.buffer();
+            if is_kept {
+                assert_eq!(buffer.len(), count + 3);
+                assert!(buffer[1..]
+                    .iter()
+                    .all(|prim| prim.effects(canvas.effect_tables()).opacity.is_none()));
             } else {
-                assert_eq!(oc.count, 0);
+                // Rejected layers fade their primitives instead
+                assert_eq!(buffer.len(), count + 1);
+                assert_eq!(buffer[0].effects(canvas.effect_tables()).opacity, None);
+                assert!(buffer[1..]
+                    .iter()
+                    .all(|prim| prim.effects(canvas.effect_tables()).opacity == Some(0.5)));
             }
         }
     }
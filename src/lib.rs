@@ -24,14 +24,36 @@
 //! }
 //! ```
 //!
+//! # Name collisions
+//!
+//! All the shapes are re-exported at the root of the crate and in its
+//! [`prelude`], but some of them share their name with a type commonly
+//! imported alongside:
+//!
+//! - [`Ellipse`], [`Polygon`], and [`RegularPolygon`] with the Bevy primitives
+//!   of the Bevy prelude;
+//! - [`Isometry2d`] with the Bevy isometry of Bevy 0.15 and later;
+//! - [`Arc`] and [`Path`] with `std::sync::Arc` and `std::path::Path`.
+//!
+//! When glob-importing both, those names are ambiguous and Rust rejects them.
+//! Refer to them through the [`shapes`] module instead, _e.g._
+//! `shapes::Ellipse`.
+//!
+//! ```
+//! # use bevy_keith::*;
+//! # use bevy::{prelude::*, color::palettes::css::*};
+//! # let mut canvas = Canvas::default();
+//! # let mut ctx = canvas.render_context();
+//! # let brush = ctx.solid_brush(RED.into());
+//! ctx.fill(shapes::Ellipse::circle(Vec2::ZERO, 16.), &brush);
+//! ```
+//!
 //! # ⚠️ Disclaimer
 //!
 //! 🐕 Bevy Keith is still under development. Some known limitations include:
 //!
 //! - \[Feat\] Currently [`Canvas`] only reasonably works with a 2D orthographic
 //!   camera. Other type of projections may work but are untested.
-//! - \[Feat\] Only solid-color brushes are currently supported; no patterns or
-//!   gradients.
 //! - \[Feat\] The [`Canvas`] is rendered to Bevy's 2D main transparent pass;
 //!   this means in particular that the Bevy UI, which is rendered later, will
 //!   be rendered on top, so you cannot easily mix Bevy UI and this crate.
@@ -51,7 +73,7 @@
 //! triangle-based meshes. An SDF representation is similar to vector graphics,
 //! and offers the advantage that the shape can be arbitrarily zoomed in and out
 //! without any loss of precision or aliasing. SDFs also enable various features
-//! like outlining and glow on any kind of shape (TODO; not yet implemented).
+//! like outlining, glow and drop shadows on any kind of shape (see [`ShapeExt`]).
 //!
 //! Currently, text rendering uses pre-rasterized glyphs stored in a texture
 //! atlas, and therefore can suffer from aliasing if zoomed in too much.
@@ -88,6 +110,10 @@ use render::{
 };
 pub use render_context::{ImageScaling, RenderContext};
 pub use shapes::*;
+// Shapes named like a Bevy primitive are ambiguous with the Bevy prelude glob
+// import above, unless re-exported explicitly. See "Name collisions" in the
+// crate docs.
+pub use shapes::{Ellipse, Polygon, RegularPolygon};
 pub use text::{CanvasTextId, KeithTextPipeline};
 
 /// Main Keith plugin.
@@ -1,4 +1,4 @@
-use std::{fmt::Write as _, num::NonZeroU64};
+use std::{fmt::Write as _, num::NonZeroU64, ops::Range};
 
 use bevy::{
     asset::{Asset, AssetEvent, AssetId},
@@ -26,9 +26,9 @@ use bevy::{
             BindingType, BlendState, Buffer, BufferBinding, BufferBindingType,
             BufferInitDescriptor, BufferSize, BufferUsages, ColorTargetState, ColorWrites,
             FragmentState, FrontFace, MultisampleState, PipelineCache, PolygonMode, PrimitiveState,
-            PrimitiveTopology, RenderPipelineDescriptor, SamplerBindingType, ShaderStages,
+            PrimitiveTopology, RenderPipelineDescriptor, Sampler, SamplerBindingType, ShaderStages,
             ShaderType, SpecializedRenderPipeline, SpecializedRenderPipelines, TextureFormat,
-            TextureSampleType, TextureViewDimension, VertexState,
+            TextureSampleType, TextureView, TextureViewDimension, VertexState,
         },
         renderer::{RenderDevice, RenderQueue},
         texture::{BevyDefault, FallbackImage, GpuImage, Image},
@@ -42,7 +42,11 @@ use bevy::{
 };
 
 use crate::{
-    canvas::{Canvas, OffsetAndCount, PackedPrimitiveIndex, Primitive, PrimitiveInfo, Tiles},
+    canvas::{
+        Canvas, EffectTables, FlattenedPath, OffsetAndCount, PackedPrimitiveIndex, Primitive,
+        PrimitiveInfo, Tiles, MAX_BATCH_TEXTURES,
+    },
+    shapes::FLATTENING_TOLERANCE,
     text::CanvasTextId,
     PRIMITIVE_SHADER_HANDLE,
 };
@@ -132,31 +136,26 @@ impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetPrimitiveTextureBindG
             return RenderCommandResult::Failure;
         };
         let image_bind_groups = image_bind_groups.into_inner();
-        if primitive_batch.image_handle_id != AssetId::<Image>::invalid() {
+        if primitive_batch.is_textured() {
             trace!(
-                "SetPrimitiveTextureBindGroup: I={} image={:?} (valid={})",
+                "SetPrimitiveTextureBindGroup: I={} images={:?}",
                 I,
-                primitive_batch.image_handle_id,
-                if primitive_batch.image_handle_id != AssetId::<Image>::invalid() {
-                    "true"
-                } else {
-                    "false"
-                }
+                primitive_batch.image_handle_ids,
             );
             trace!("image_bind_groups:");
-            for (handle, bind_group) in &image_bind_groups.values {
-                trace!("+ ibg: {:?} = {:?}", handle, bind_group);
+            for (handles, bind_group) in &image_bind_groups.values {
+                trace!("+ ibg: {:?} = {:?}", handles, bind_group);
             }
             let Some(ibg) = image_bind_groups
                 .values
-                .get(&primitive_batch.image_handle_id)
+                .get(&primitive_batch.image_handle_ids)
             else {
                 error!("Failed to find IBG!");
                 return RenderCommandResult::Failure;
             };
             pass.set_bind_group(I, ibg, &[]);
         } else if let Some(ibg) = image_bind_groups.fallback.as_ref() {
-            // We need a texture anyway, bind anything to make the shader happy
+            // We need textures anyway, bind anything to make the shader happy
             pass.set_bind_group(I, ibg, &[]);
         } else {
             // We can't use this shader without a valid bind group
@@ -208,9 +207,9 @@ impl Default for BatchBuffers {
 /// characteristics, and which can be rendered with a single draw call.
 #[derive(Component, Clone)]
 pub struct PrimitiveBatch {
-    /// Handle of the texture for the batch, or [`NIL_HANDLE_ID`] if not
-    /// textured.
-    image_handle_id: AssetId<Image>,
+    /// Handles of the textures bound by the batch, by slot. Slots are filled
+    /// in order, and the unused ones hold an invalid handle.
+    image_handle_ids: [AssetId<Image>; MAX_BATCH_TEXTURES],
     /// Entity holding the [`Canvas`] component this batch is built from.
     canvas_entity: Entity,
     /// Bind group for the primitive buffer and tile buffers used by the batch.
@@ -229,9 +228,14 @@ impl PrimitiveBatch {
     /// This is typically used as an initializing placeholder when doing
     /// incremental batching.
     pub fn invalid() -> Self {
+        Self::new(Entity::PLACEHOLDER)
+    }
+
+    /// Create a new batch for the given canvas, not bound to any texture yet.
+    pub fn new(canvas_entity: Entity) -> Self {
         PrimitiveBatch {
-            image_handle_id: AssetId::<Image>::invalid(),
-            canvas_entity: Entity::PLACEHOLDER,
+            image_handle_ids: [AssetId::<Image>::invalid(); MAX_BATCH_TEXTURES],
+            canvas_entity,
             primitive_bind_group: BatchBuffers::Invalid,
         }
     }
@@ -240,23 +244,60 @@ impl PrimitiveBatch {
         self.canvas_entity == Entity::PLACEHOLDER
     }
 
-    /// Try to merge a batch into the current batch.
+    /// Does the batch bind any texture?
+    pub fn is_textured(&self) -> bool {
+        self.image_handle_ids[0] != AssetId::invalid()
+    }
+
+    /// Try to bind a texture to the batch.
     ///
-    /// Return `true` if the batch was merged, or `false` otherwise.
-    pub fn try_merge(&mut self, other: &PrimitiveBatch) -> bool {
-        if self.is_handle_compatible(other.image_handle_id)
-            && self.canvas_entity == other.canvas_entity
+    /// Return `true` if the texture is bound, either already or in a free slot,
+    /// or if the handle is invalid (non-textured), or `false` if all slots are
+    /// taken by other textures or if the batch is empty.
+    pub fn try_add_texture(&mut self, handle: AssetId<Image>) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        if handle == AssetId::invalid() || self.image_handle_ids.contains(&handle) {
+            return true;
+        }
+        if let Some(slot) = self
+            .image_handle_ids
+            .iter_mut()
+            .find(|id| **id == AssetId::invalid())
         {
-            // Overwrite in case self is invalid
-            if self.image_handle_id == AssetId::invalid() {
-                self.image_handle_id = other.image_handle_id;
-            }
+            *slot = handle;
             true
         } else {
             false
         }
     }
 
+    /// Check if all the given textures can be bound to the batch together.
+    pub fn can_add_textures(&self, handles: &[AssetId<Image>]) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let used = self
+            .image_handle_ids
+            .iter()
+            .filter(|id| **id != AssetId::invalid())
+            .count();
+        let new = handles
+            .iter()
+            .filter(|id| **id != AssetId::invalid() && !self.image_handle_ids.contains(id))
+            .count();
+        used + new <= MAX_BATCH_TEXTURES
+    }
+
+    /// Get the slot where a texture is bound, or zero if it's not bound.
+    pub fn texture_slot(&self, handle: AssetId<Image>) -> usize {
+        self.image_handle_ids
+            .iter()
+            .position(|id| *id == handle)
+            .unwrap_or(0)
+    }
+
     /// Get the bind group for the primitive buffers associated with this batch.
     ///
     /// Returns `Some` if the bind group was successfully prepared (created), or
@@ -267,19 +308,6 @@ impl PrimitiveBatch {
             _ => None,
         }
     }
-
-    /// Check if the given image handle is compatible with the current batch.
-    ///
-    /// The handle is compatible if either the batch's own handle or the
-    /// provided handle is invalid (non-textured), or they are both valid
-    /// and equal.
-    fn is_handle_compatible(&self, handle: AssetId<Image>) -> bool {
-        // Any invalid handle means "no texture", which can be batched with any other
-        // texture. Only different (valid) textures cannot be batched together.
-        return handle == AssetId::invalid()
-            || self.image_handle_id == AssetId::invalid()
-            || self.image_handle_id == handle;
-    }
 }
 
 #[derive(Default, Resource)]
@@ -287,10 +315,11 @@ pub struct PrimitiveMeta {
     view_bind_group: Option<BindGroup>,
 }
 
-/// Shader bind groups for all images currently in use by primitives.
+/// Shader bind groups for all sets of images currently in use by batches of
+/// primitives.
 #[derive(Default, Resource)]
 pub struct ImageBindGroups {
-    values: HashMap<AssetId<Image>, BindGroup>,
+    values: HashMap<[AssetId<Image>; MAX_BATCH_TEXTURES], BindGroup>,
     fallback: Option<BindGroup>,
 }
 
@@ -302,7 +331,7 @@ pub struct PrimitivePipeline {
     view_layout: BindGroupLayout,
     /// Bind group layout for the primitive buffer.
     prim_layout: BindGroupLayout,
-    /// Bind group layout for the texture used by textured primitives.
+    /// Bind group layout for the textures used by textured primitives.
     material_layout: BindGroupLayout,
 }
 
@@ -360,27 +389,31 @@ impl FromWorld for PrimitivePipeline {
             ],
         );
 
-        let material_layout = render_device.create_bind_group_layout(
-            "quad_material_layout",
-            &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Texture {
-                        multisampled: false,
-                        sample_type: TextureSampleType::Float { filterable: true },
-                        view_dimension: TextureViewDimension::D2,
+        // One texture and its sampler per slot of a batch
+        let material_entries: Vec<_> = (0..MAX_BATCH_TEXTURES as u32)
+            .flat_map(|slot| {
+                [
+                    BindGroupLayoutEntry {
+                        binding: slot * 2,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            multisampled: false,
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
                     },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
-        );
+                    BindGroupLayoutEntry {
+                        binding: slot * 2 + 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ]
+            })
+            .collect();
+        let material_layout =
+            render_device.create_bind_group_layout("quad_material_layout", &material_entries[..]);
 
         PrimitivePipeline {
             view_layout,
@@ -485,6 +518,13 @@ pub struct ExtractedCanvas {
     pub scale_factor: f32,
     /// Extracted data for all texts in use, in local text ID order.
     pub(crate) texts: Vec<ExtractedText>,
+    /// Extracted flattened paths, in local path ID order.
+    pub(crate) paths: Vec<FlattenedPath>,
+    /// Extracted clip mask primitives, in local mask ID order.
+    pub(crate) masks: Vec<Primitive>,
+    /// Extracted gradients, dashes, and image patterns referenced by the
+    /// effects of the primitives.
+    pub(crate) effect_tables: EffectTables,
     pub(crate) tiles: Tiles,
 }
 
@@ -729,7 +769,7 @@ pub(crate) fn extract_primitives(
         // Swap render and main app primitive buffer
         // FIXME - Can't swap in Extract phase because main world is read-only; clone
         // instead
-        let primitives = canvas.buffer().clone();
+        let mut primitives = canvas.buffer().clone();
         trace!(
             "Canvas on Entity {:?} has {} primitives and {} text layouts, viewport_origin={:?}, viewport_area={:?}, scale_factor={}, proj.scale={}",
             entity,
@@ -807,6 +847,15 @@ pub(crate) fn extract_primitives(
             extracted_texts[index].glyphs = extracted_glyphs;
         }
 
+        // Now that the glyphs are known, complete the bounds of the layers drawing
+        // some text
+        add_text_to_layer_bounds(
+            &mut primitives[..],
+            &extracted_texts[..],
+            canvas.effect_tables(),
+            inv_scale_factor,
+        );
+
         // Save extracted canvas
         let extracted_canvas = extracted_canvases
             .entry(entity)
@@ -818,6 +867,16 @@ pub(crate) fn extract_primitives(
         extracted_canvas.primitives = primitives;
         extracted_canvas.scale_factor = scale_factor;
         extracted_canvas.texts = extracted_texts;
+        // Flatten paths at the physical resolution of the screen
+        let tolerance = FLATTENING_TOLERANCE * inv_scale_factor;
+        extracted_canvas.paths.clear();
+        extracted_canvas
+            .paths
+            .extend(canvas.paths().iter().map(|path| path.flatten(tolerance)));
+        extracted_canvas.masks = canvas.masks().to_vec();
+        extracted_canvas
+            .effect_tables
+            .clone_from(canvas.effect_tables());
         extracted_canvas.tiles = tiles.clone();
     }
 }
@@ -831,16 +890,24 @@ pub(crate) struct SubPrimIter<'a> {
     index: usize,
     /// Text information for iterating over glyphs.
     texts: &'a [ExtractedText],
+    /// Side tables of the effects, for the image pattern of the primitive.
+    tables: &'a EffectTables,
     /// Inverse scale factor, to convert from physical to logical coordinates.
     inv_scale_factor: f32,
 }
 
 impl<'a> SubPrimIter<'a> {
-    pub fn new(prim: &'a Primitive, texts: &'a [ExtractedText], inv_scale_factor: f32) -> Self {
+    pub fn new(
+        prim: &'a Primitive,
+        texts: &'a [ExtractedText],
+        tables: &'a EffectTables,
+        inv_scale_factor: f32,
+    ) -> Self {
         Self {
             prim: Some(prim),
             index: 0,
             texts,
+            tables,
             inv_scale_factor,
         }
     }
@@ -851,10 +918,6 @@ impl<'a> Iterator for SubPrimIter<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(prim) = self.prim {
-            let PrimitiveInfo {
-                row_count: _,
-                sub_prim_count: _,
-            } = prim.info(self.texts);
             match prim {
                 Primitive::Text(text) => {
                     if text.id as usize >= self.texts.len() {
@@ -866,11 +929,11 @@ impl<'a> Iterator for SubPrimIter<'a> {
                         let image_handle_id = glyph.handle_id;
                         // The AABB returned is in logical coordinates, but the text internally is
                         // always in physical coordinates.
-                        let aabb = Aabb2d {
-                            min: text.rect.min + glyph.offset * self.inv_scale_factor,
-                            max: text.rect.min
-                                + (glyph.offset + glyph.size) * self.inv_scale_factor,
-                        };
+                        let aabb = text.local_aabb(
+                            glyph.offset * self.inv_scale_factor,
+                            glyph.size * self.inv_scale_factor,
+                        );
+                        let aabb = text.grow_glyph_aabb(self.tables, aabb);
                         self.index += 1;
                         Some((image_handle_id, aabb))
                     } else {
@@ -878,19 +941,13 @@ impl<'a> Iterator for SubPrimIter<'a> {
                         None
                     }
                 }
-                Primitive::Rect(rect) => {
-                    let handle_id = if let Some(id) = rect.image {
-                        id
-                    } else {
-                        AssetId::<Image>::invalid()
-                    };
-                    self.prim = None;
-                    Some((handle_id, rect.aabb()))
-                }
                 _ => {
+                    // Rectangles with an image, or any primitive filled with an image pattern
+                    let handle_id = prim
+                        .image(self.tables)
+                        .unwrap_or(AssetId::<Image>::invalid());
                     self.prim = None;
-                    // Currently all other primitives are non-textured
-                    Some((AssetId::<Image>::invalid(), prim.aabb()))
+                    Some((handle_id, prim.tile_aabb(self.tables)))
                 }
             }
         } else {
@@ -899,6 +956,63 @@ impl<'a> Iterator for SubPrimIter<'a> {
     }
 }
 
+/// Grow the bounds of the layers containing some text to enclose its glyphs.
+///
+/// [`Canvas::end_layer()`] can't account for text, because the glyphs are only
+/// laid out during extraction, so this completes the bounds of both markers of
+/// each layer once the `texts` are available. The bounds of a nested layer also
+/// grow its parent layers.
+///
+/// [`Canvas::end_layer()`]: crate::Canvas::end_layer
+pub(crate) fn add_text_to_layer_bounds(
+    primitives: &mut [Primitive],
+    texts: &[ExtractedText],
+    tables: &EffectTables,
+    inv_scale_factor: f32,
+) {
+    // Index of the opening marker of each open layer, and bounds of the text
+    // drawn inside it so far
+    let mut open_layers: Vec<(usize, Rect)> = vec![];
+    for index in 0..primitives.len() {
+        match &mut primitives[index] {
+            Primitive::Layer(layer) if !layer.is_end => {
+                open_layers.push((index, Rect::EMPTY));
+            }
+            Primitive::Layer(layer) => {
+                let Some((begin, text_bounds)) = open_layers.pop() else {
+                    continue;
+                };
+                if text_bounds.is_empty() {
+                    continue;
+                }
+                layer.bounds = layer.bounds.union(text_bounds);
+                let bounds = layer.bounds;
+                if let Primitive::Layer(layer) = &mut primitives[begin] {
+                    layer.bounds = bounds;
+                }
+                if let Some((_, parent_bounds)) = open_layers.last_mut() {
+                    *parent_bounds = parent_bounds.union(text_bounds);
+                }
+            }
+            prim @ Primitive::Text(_) => {
+                let Some((_, text_bounds)) = open_layers.last_mut() else {
+                    continue;
+                };
+                for (_, aabb) in SubPrimIter::new(&*prim, texts, tables, inv_scale_factor) {
+                    let aabb = Rect {
+                        min: aabb.min,
+                        max: aabb.max,
+                    };
+                    if !aabb.is_empty() {
+                        *text_bounds = text_bounds.union(aabb);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 /// Format a list of values as 16 values per row, for more compact `trace!()`.
 ///
 /// ```ignore
@@ -921,95 +1035,132 @@ macro_rules! trace_list {
     };
 }
 
+#[derive(Clone, Copy)]
 pub(crate) struct PreparedPrimitive {
     /// AABB in canvas space, for tile assignment.
     pub aabb: Aabb2d,
+    /// Region inside the AABB, in canvas space, where the primitive draws
+    /// nothing, if any. Tiles entirely inside it are not assigned the
+    /// primitive.
+    pub hole: Option<Aabb2d>,
     /// Primitive index.
     pub prim_index: PackedPrimitiveIndex,
 }
 
-pub(crate) fn prepare_primitives(
-    mut commands: Commands,
-    mut extracted_canvases: ResMut<ExtractedCanvases>,
-    render_device: Res<RenderDevice>,
-    render_queue: Res<RenderQueue>,
-    mut image_bind_groups: ResMut<ImageBindGroups>,
-    events: Res<PrimitiveAssetEvents>,
-    mut prepared_primitives: Local<Vec<PreparedPrimitive>>,
-) {
-    trace!("prepare_primitives()");
-
-    // If an Image has changed, the GpuImage has (probably) changed
-    for event in &events.images {
-        match event {
-            AssetEvent::Added { .. } | AssetEvent::LoadedWithDependencies { .. } => None,
-            AssetEvent::Modified { id }
-            | AssetEvent::Removed { id }
-            | AssetEvent::Unused { id } => {
-                let removed = image_bind_groups.values.remove(id);
-                if removed.is_some() {
-                    debug!("Removed IBG for handle {:?} due to {:?}", id, event);
-                }
-                removed
-            }
+impl ExtractedCanvas {
+    /// Serialize the primitives into the rows of the primitive buffer, and
+    /// group them into batches.
+    ///
+    /// The prepared primitives are appended to `prepared_primitives`, and each
+    /// returned batch is drawn from a consecutive range of them. A batch binds
+    /// at most [`MAX_BATCH_TEXTURES`] textures, so a new batch starts when a
+    /// primitive needs another texture. A new batch also starts before a layer
+    /// whose textures don't all fit in the current one, so that the layer is
+    /// drawn in a single batch and composited as a single unit.
+    pub(crate) fn batch_primitives(
+        &self,
+        canvas_entity: Entity,
+        primitives: &mut Vec<f32>,
+        prepared_primitives: &mut Vec<PreparedPrimitive>,
+    ) -> Vec<(PrimitiveBatch, Range<usize>)> {
+        let canvas_translation = -self.canvas_rect.min;
+        let inv_scale_factor = 1.0 / self.scale_factor;
+
+        // Convert from logical to physical coordinates
+        let to_physical = |aabb: Aabb2d| Aabb2d {
+            min: aabb.min * self.scale_factor + self.canvas_origin,
+            max: aabb.max * self.scale_factor + self.canvas_origin,
         };
-    }
-
-    let oc_align = render_device.limits().min_storage_buffer_offset_alignment;
-
-    let extracted_canvases = &mut extracted_canvases.canvases;
-
-    // Loop on all extracted canvases to process their primitives
-    for (entity, extracted_canvas) in extracted_canvases {
-        trace!(
-            "Canvas on Entity {:?} has {} primitives and {} texts, tile size {:?}, canvas_origin={:?} canvas_rect={:?}",
-            entity,
-            extracted_canvas.primitives.len(),
-            extracted_canvas.texts.len(),
-            extracted_canvas.tiles.tile_size,
-            extracted_canvas.canvas_origin,
-            extracted_canvas.canvas_rect,
-        );
-
-        let mut primitives = vec![];
-
-        prepared_primitives.clear();
-        prepared_primitives.reserve(extracted_canvas.primitives.len());
 
-        extracted_canvas.tiles.offset_and_count.clear();
+        let mut batches = vec![];
+        let mut current_batch = PrimitiveBatch::invalid();
+        let mut batch_start = prepared_primitives.len();
+
+        // Opening markers of the layers open at the current primitive. A layer using
+        // too many textures is split across batches, so those are repeated at the
+        // start of a new batch.
+        let mut open_layers: Vec<PreparedPrimitive> = vec![];
+
+        // Primitives are addressed by the packed index of their first row, which
+        // limits the size of the primitive buffer
+        let warn_overflow = || {
+            warn_once!(
+                "Canvas on Entity {:?} needs more than {} primitive buffer rows; the primitives past that limit are not drawn.",
+                canvas_entity,
+                PackedPrimitiveIndex::MAX_INDEX + 1
+            );
+        };
 
-        let canvas_translation = -extracted_canvas.canvas_rect.min;
-        let inv_scale_factor = 1.0 / extracted_canvas.scale_factor;
+        // Serialize the clip masks first, so that primitives can reference them by
+        // their packed index. Masks are not assigned to any tile, so they're never
+        // drawn themselves.
+        let mut mask_indices = Vec::with_capacity(self.masks.len());
+        for mask in &self.masks {
+            let base_index = primitives.len() as u32;
+            let PrimitiveInfo { row_count, .. } =
+                mask.info(&self.texts[..], &self.paths[..], &self.effect_tables);
+            let row_count = row_count as usize;
+            primitives.reserve(row_count);
+            mask.write(
+                &self.texts[..],
+                &self.paths[..],
+                &self.effect_tables,
+                &[],
+                &mut primitives.spare_capacity_mut()[..row_count],
+                canvas_translation,
+                self.scale_factor,
+            );
+            unsafe { primitives.set_len(primitives.len() + row_count) };
+            let Some(mask_index) = PackedPrimitiveIndex::new(
+                base_index,
+                mask.gpu_kind(),
+                mask.is_textured(&self.effect_tables),
+                mask.is_bordered(),
+                false,
+            ) else {
+                warn_overflow();
+                return batches;
+            };
+            mask_indices.push(mask_index.0);
+        }
 
-        // Serialize primitives into a binary float32 array, to work around the fact
-        // wgpu doesn't have byte arrays. And f32 being the most common type of
-        // data in primitives limits the amount of bitcast in the shader.
-        trace!(
-            "Serialize {} primitives...",
-            extracted_canvas.primitives.len()
-        );
-        let mut current_batch = PrimitiveBatch::invalid();
-        let mut oc_offset = extracted_canvas.tiles.offset_and_count.len() as u32;
-        let mut pp_offset = 0;
-        for prim in &extracted_canvas.primitives {
+        for (index, prim) in self.primitives.iter().enumerate() {
             let base_index = primitives.len() as u32;
-            let is_textured = prim.is_textured();
+            let is_textured = prim.is_textured(&self.effect_tables);
             let is_bordered = prim.is_bordered();
-            let mut prim_index =
-                PackedPrimitiveIndex::new(base_index, prim.gpu_kind(), is_textured, is_bordered);
+            let has_effects = prim.has_effects(&self.effect_tables);
 
             trace!("+ Primitive @ base_index={}", base_index);
 
-            // Serialize the primitive
             let PrimitiveInfo {
                 row_count,
                 sub_prim_count,
-            } = prim.info(&extracted_canvas.texts[..]);
+            } = prim.info(&self.texts[..], &self.paths[..], &self.effect_tables);
             trace!(
                 "  row_count={} sub_prim_count={}",
                 row_count,
                 sub_prim_count
             );
+
+            // Stop before a primitive if the index of any of its sub-primitives
+            // overflows, instead of drawing only part of it
+            let prim_index = PackedPrimitiveIndex::new(
+                base_index,
+                prim.gpu_kind(),
+                is_textured,
+                is_bordered,
+                has_effects,
+            );
+            let last_index = row_count
+                .checked_mul(sub_prim_count.saturating_sub(1))
+                .zip(prim_index)
+                .and_then(|(rows, pi)| pi.advance(rows));
+            let (Some(prim_index), Some(_)) = (prim_index, last_index) else {
+                warn_overflow();
+                break;
+            };
+
+            // Serialize the primitive
             if row_count > 0 && sub_prim_count > 0 {
                 let row_count = row_count as usize;
                 let sub_prim_count = sub_prim_count as usize;
@@ -1021,10 +1172,13 @@ pub(crate) fn prepare_primitives(
 
                 // Write primitives and indices directly into storage
                 prim.write(
-                    &extracted_canvas.texts[..],
+                    &self.texts[..],
+                    &self.paths[..],
+                    &self.effect_tables,
+                    &mask_indices[..],
                     &mut prim_slice[..total_row_count],
                     canvas_translation,
-                    extracted_canvas.scale_factor,
+                    self.scale_factor,
                 );
 
                 // Apply new storage sizes once data is initialized
@@ -1042,111 +1196,205 @@ pub(crate) fn prepare_primitives(
                 prepared_primitives.reserve(sub_prim_count);
             }
 
+            // Start a new batch before a layer whose textures don't fit in the current
+            // one. Nested layers are part of their outermost layer.
+            let mut split = false;
+            if let Primitive::Layer(layer) = prim {
+                if !layer.is_end && open_layers.is_empty() {
+                    let textures = self.layer_textures(index, inv_scale_factor);
+                    if textures.len() > MAX_BATCH_TEXTURES {
+                        // RenderContext::pop_layer() rejects layers with too many images, but
+                        // can't know in how many font atlases their text is stored
+                        warn_once!("Layer using more than {MAX_BATCH_TEXTURES} textures; it's composited separately for each batch.");
+                    }
+                    split = !current_batch.can_add_textures(&textures[..]);
+                }
+            }
+
             // Loop on sub-primitives; Text primitives expand to one Rect primitive
             // per glyph, each of which _can_ have a separate atlas texture so potentially
             // can split the draw into a new batch.
+            let hole = prim.tile_hole(&self.effect_tables).map(to_physical);
+
             trace!("Batch sub-primitives...");
-            let batch_iter = SubPrimIter::new(prim, &extracted_canvas.texts, inv_scale_factor);
-            for (image_handle_id, mut aabb) in batch_iter {
-                let new_batch = PrimitiveBatch {
-                    image_handle_id,
-                    canvas_entity: *entity,
-                    ..default()
+            let batch_iter =
+                SubPrimIter::new(prim, &self.texts, &self.effect_tables, inv_scale_factor);
+            let mut sub_prim_index = Some(prim_index);
+            for (image_handle_id, aabb) in batch_iter {
+                // All the indices were checked above, only the one past the last
+                // sub-primitive can overflow
+                let Some(prim_index) = sub_prim_index else {
+                    break;
                 };
-                trace!(
-                    "New Batch: canvas_entity={:?} image={:?}",
-                    new_batch.canvas_entity,
-                    new_batch.image_handle_id
-                );
-
-                // Convert from logical to physical coordinates
-                aabb.min *= extracted_canvas.scale_factor;
-                aabb.max *= extracted_canvas.scale_factor;
-                aabb.min += extracted_canvas.canvas_origin;
-                aabb.max += extracted_canvas.canvas_origin;
+                let aabb = to_physical(aabb);
+
+                if split || !current_batch.try_add_texture(image_handle_id) {
+                    // Output the previous batch before starting a new one. Skip if batch is
+                    // empty, which may happen on first one (current_batch initialized to an
+                    // invalid empty batch)
+                    if !current_batch.is_empty() {
+                        batches.push((current_batch, batch_start..prepared_primitives.len()));
+                        batch_start = prepared_primitives.len();
+                    }
 
-                if current_batch.try_merge(&new_batch) {
+                    current_batch = PrimitiveBatch::new(canvas_entity);
+                    current_batch.try_add_texture(image_handle_id);
+                    split = false;
                     trace!(
-                        "Merged new batch with current batch: image={:?}",
-                        current_batch.image_handle_id
+                        "New Batch: canvas_entity={:?} image={:?}",
+                        canvas_entity,
+                        image_handle_id
                     );
 
-                    // Calculate once and save the AABB of the primitive, for tile assignment
-                    // purpose. Since there are many more tiles than primitives, it's worth doing
-                    // that calculation only once ahead of time before looping over tiles.
-                    trace!("PreparedPrimitive {aabb:?} {prim_index:?}");
-                    prepared_primitives.push(PreparedPrimitive { aabb, prim_index });
-                    prim_index.0 += row_count;
-
-                    continue;
+                    // Reopen the layers still open, so the rest of their primitives is grouped
+                    // in the new batch too.
+                    prepared_primitives.extend_from_slice(&open_layers[..]);
                 }
 
-                // Batches are different; output the previous one before starting a new one.
-
-                // Skip if batch is empty, which may happen on first one (current_batch
-                // initialized to an invalid empty batch)
-                if !current_batch.is_empty() {
-                    // Assign primitives to tiles
-                    extracted_canvas.tiles.assign_to_tiles(
-                        &prepared_primitives[pp_offset as usize..],
-                        extracted_canvas.screen_size.as_vec2(),
-                    );
-                    // trace!(
-                    //     "{} primitives overlap {} tiles",
-                    //     prepared_primitives.len() as u32 - pp_offset,
-                    //     tile_count
-                    // );
-
-                    let oc_count = extracted_canvas.tiles.offset_and_count.len() as u32 - oc_offset;
-                    current_batch.primitive_bind_group = BatchBuffers::Raw(oc_offset, oc_count);
+                // Calculate once and save the AABB of the primitive, for tile assignment
+                // purpose. Since there are many more tiles than primitives, it's worth doing
+                // that calculation only once ahead of time before looping over tiles.
+                let slot = current_batch.texture_slot(image_handle_id);
+                trace!("PreparedPrimitive {aabb:?} {prim_index:?} slot={slot}");
+                prepared_primitives.push(PreparedPrimitive {
+                    aabb,
+                    hole,
+                    prim_index: prim_index.with_texture_slot(slot),
+                });
+                sub_prim_index = prim_index.advance(row_count);
+            }
 
-                    trace!("Spawned new batch: oc_offset={oc_offset} oc_count={oc_count} pp_offset={pp_offset}");
+            if let Primitive::Layer(layer) = prim {
+                if layer.is_end {
+                    open_layers.pop();
+                } else if let Some(pp) = prepared_primitives.last() {
+                    open_layers.push(*pp);
+                }
+            }
+        }
 
-                    commands.spawn(current_batch);
+        // Output the last batch
+        if !current_batch.is_empty() {
+            batches.push((current_batch, batch_start..prepared_primitives.len()));
+        }
 
-                    oc_offset += oc_count;
-                    pp_offset = prepared_primitives.len() as u32;
+        batches
+    }
 
-                    // Align oc_offset to min_storage_buffer_offset_alignment
-                    oc_offset = oc_offset.next_multiple_of(oc_align);
-                    extracted_canvas
-                        .tiles
-                        .offset_and_count
-                        .resize(oc_offset as usize, OffsetAndCount::default());
+    /// Get the distinct textures used by all the primitives of the layer opened
+    /// at index `begin`, including its nested layers.
+    fn layer_textures(&self, begin: usize, inv_scale_factor: f32) -> Vec<AssetId<Image>> {
+        let mut textures = vec![];
+        let mut depth = 0;
+        for prim in &self.primitives[begin..] {
+            if let Primitive::Layer(layer) = prim {
+                if layer.is_end {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    depth += 1;
                 }
+                continue;
+            }
+            for (handle, _) in
+                SubPrimIter::new(prim, &self.texts, &self.effect_tables, inv_scale_factor)
+            {
+                if handle != AssetId::invalid() && !textures.contains(&handle) {
+                    textures.push(handle);
+                }
+            }
+        }
+        textures
+    }
+}
 
-                current_batch = new_batch;
+pub(crate) fn prepare_primitives(
+    mut commands: Commands,
+    mut extracted_canvases: ResMut<ExtractedCanvases>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut image_bind_groups: ResMut<ImageBindGroups>,
+    events: Res<PrimitiveAssetEvents>,
+    mut prepared_primitives: Local<Vec<PreparedPrimitive>>,
+) {
+    trace!("prepare_primitives()");
 
-                // Calculate once and save the AABB of the primitive, for tile assignment
-                // purpose. Since there are many more tiles than primitives, it's worth doing
-                // that calculation only once ahead of time before looping over tiles.
-                trace!("PreparedPrimitive {aabb:?} {prim_index:?}");
-                prepared_primitives.push(PreparedPrimitive { aabb, prim_index });
-                prim_index.0 += row_count;
+    // If an Image has changed, the GpuImage has (probably) changed
+    for event in &events.images {
+        match event {
+            AssetEvent::Added { .. } | AssetEvent::LoadedWithDependencies { .. } => {}
+            AssetEvent::Modified { id }
+            | AssetEvent::Removed { id }
+            | AssetEvent::Unused { id } => {
+                // Remove the bind groups of all the batches binding that image
+                image_bind_groups.values.retain(|ids, _| {
+                    let is_used = ids.contains(id);
+                    if is_used {
+                        debug!("Removed IBG for handles {:?} due to {:?}", ids, event);
+                    }
+                    !is_used
+                });
             }
         }
+    }
 
-        // Output the last batch
-        if !current_batch.is_empty() {
-            trace!("Output last batch... pp_offset={pp_offset}");
+    let oc_align = render_device.limits().min_storage_buffer_offset_alignment;
+
+    let extracted_canvases = &mut extracted_canvases.canvases;
+
+    // Loop on all extracted canvases to process their primitives
+    for (entity, extracted_canvas) in extracted_canvases {
+        trace!(
+            "Canvas on Entity {:?} has {} primitives and {} texts, tile size {:?}, canvas_origin={:?} canvas_rect={:?}",
+            entity,
+            extracted_canvas.primitives.len(),
+            extracted_canvas.texts.len(),
+            extracted_canvas.tiles.tile_size,
+            extracted_canvas.canvas_origin,
+            extracted_canvas.canvas_rect,
+        );
+
+        let mut primitives = vec![];
+
+        prepared_primitives.clear();
+        prepared_primitives.reserve(extracted_canvas.primitives.len());
+
+        extracted_canvas.tiles.offset_and_count.clear();
+
+        trace!(
+            "Serialize {} primitives...",
+            extracted_canvas.primitives.len()
+        );
+        let batches =
+            extracted_canvas.batch_primitives(*entity, &mut primitives, &mut prepared_primitives);
+
+        let mut oc_offset = extracted_canvas.tiles.offset_and_count.len() as u32;
+        for (mut batch, range) in batches {
+            // Align oc_offset to min_storage_buffer_offset_alignment
+            oc_offset = oc_offset.next_multiple_of(oc_align);
+            extracted_canvas
+                .tiles
+                .offset_and_count
+                .resize(oc_offset as usize, OffsetAndCount::default());
 
             // Assign primitives to tiles
             extracted_canvas.tiles.assign_to_tiles(
-                &prepared_primitives[pp_offset as usize..],
+                &prepared_primitives[range.clone()],
                 extracted_canvas.screen_size.as_vec2(),
             );
-            // trace!(
-            //     "{} primitives overlap {} tiles",
-            //     prepared_primitives.len() as u32 - pp_offset,
-            //     tile_count
-            // );
 
             let oc_count = extracted_canvas.tiles.offset_and_count.len() as u32 - oc_offset;
-            current_batch.primitive_bind_group = BatchBuffers::Raw(oc_offset, oc_count);
+            batch.primitive_bind_group = BatchBuffers::Raw(oc_offset, oc_count);
+
+            trace!(
+                "Spawned new batch: oc_offset={oc_offset} oc_count={oc_count} prepared={range:?}"
+            );
 
-            trace!("Spawned new batch: oc_offset={oc_offset} oc_count={oc_count} pp_offset={pp_offset}");
+            commands.spawn(batch);
 
-            commands.spawn(current_batch);
+            oc_offset += oc_count;
         }
 
         // Check the actual primitives after being assigned to tiles. There might be
@@ -1190,9 +1438,9 @@ pub fn queue_primitives(
     trace!("Looping on batches...");
     for (batch_entity, batch) in batches.iter() {
         trace!(
-            "batch ent={:?} image={:?}",
+            "batch ent={:?} images={:?}",
             batch_entity,
-            batch.image_handle_id
+            batch.image_handle_ids
         );
         if batch.is_empty() {
             // shouldn't happen
@@ -1201,7 +1449,7 @@ pub fn queue_primitives(
 
         let canvas_entity = batch.canvas_entity;
 
-        let is_textured = batch.image_handle_id != AssetId::<Image>::invalid();
+        let is_textured = batch.is_textured();
         trace!("  is_textured={}", is_textured);
 
         let extracted_canvas =
@@ -1227,9 +1475,9 @@ pub fn queue_primitives(
             };
 
             trace!(
-                "Add Transparent2d entity={:?} image={:?} pipeline={:?} (sort={:?})",
+                "Add Transparent2d entity={:?} images={:?} pipeline={:?} (sort={:?})",
                 batch_entity,
-                batch.image_handle_id,
+                batch.image_handle_ids,
                 primitive_pipeline,
                 sort_key
             );
@@ -1266,19 +1514,14 @@ pub fn prepare_bind_groups(
     };
 
     if image_bind_groups.fallback.is_none() {
+        let fallback = [(
+            &fallback_images.d2.texture_view,
+            &fallback_images.d2.sampler,
+        ); MAX_BATCH_TEXTURES];
         image_bind_groups.fallback = Some(render_device.create_bind_group(
             "keith:fallback_primitive_material_bind_group",
             &primitive_pipeline.material_layout,
-            &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(&fallback_images.d2.texture_view),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Sampler(&fallback_images.d2.sampler),
-                },
-            ],
+            &material_entries(&fallback)[..],
         ));
         debug!(
             "Created bind group for fallback primitive texture: {:?}",
@@ -1298,9 +1541,9 @@ pub fn prepare_bind_groups(
     trace!("Looping on {} batches...", batches.iter().len());
     for (batch_entity, mut batch) in batches.iter_mut() {
         trace!(
-            "batch ent={:?} image={:?}",
+            "batch ent={:?} images={:?}",
             batch_entity,
-            batch.image_handle_id
+            batch.image_handle_ids
         );
         if batch.is_empty() {
             // shouldn't happen
@@ -1367,38 +1610,67 @@ pub fn prepare_bind_groups(
         debug!("Created bind group {primitive_bind_group:?} for batch on entity {batch_entity:?} with oc_offset={oc_offset} oc_size={oc_size}...");
         batch.primitive_bind_group = BatchBuffers::Prepared(primitive_bind_group);
 
-        // Set bind group for texture, if any
-        if batch.image_handle_id != AssetId::<Image>::invalid() {
-            if let Some(gpu_image) = gpu_images.get(batch.image_handle_id) {
+        // Set bind group for textures, if any. Unused slots are bound to a
+        // fallback texture.
+        if batch.is_textured()
+            && !image_bind_groups
+                .values
+                .contains_key(&batch.image_handle_ids)
+        {
+            let mut textures = [(
+                &fallback_images.d2.texture_view,
+                &fallback_images.d2.sampler,
+            ); MAX_BATCH_TEXTURES];
+            let mut is_ready = true;
+            for (texture, id) in textures.iter_mut().zip(batch.image_handle_ids) {
+                if id == AssetId::<Image>::invalid() {
+                    continue;
+                }
+                if let Some(gpu_image) = gpu_images.get(id) {
+                    *texture = (&gpu_image.texture_view, &gpu_image.sampler);
+                } else {
+                    warn!(
+                        "GPU image for asset {:?} is not available, cannot create bind group!",
+                        id
+                    );
+                    is_ready = false;
+                }
+            }
+            if is_ready {
+                debug!(
+                    "Insert new bind group for handles={:?}",
+                    batch.image_handle_ids
+                );
+                let bind_group = render_device.create_bind_group(
+                    "keith:primitive_material_bind_group",
+                    &primitive_pipeline.material_layout,
+                    &material_entries(&textures)[..],
+                );
                 image_bind_groups
                     .values
-                    .entry(batch.image_handle_id)
-                    .or_insert_with(|| {
-                        debug!(
-                            "Insert new bind group for handle={:?}",
-                            batch.image_handle_id
-                        );
-                        render_device.create_bind_group(
-                            "keith:primitive_material_bind_group",
-                            &primitive_pipeline.material_layout,
-                            &[
-                                BindGroupEntry {
-                                    binding: 0,
-                                    resource: BindingResource::TextureView(&gpu_image.texture_view),
-                                },
-                                BindGroupEntry {
-                                    binding: 1,
-                                    resource: BindingResource::Sampler(&gpu_image.sampler),
-                                },
-                            ],
-                        )
-                    });
-            } else {
-                warn!(
-                    "GPU image for asset {:?} is not available, cannot create bind group!",
-                    batch.image_handle_id
-                );
+                    .insert(batch.image_handle_ids, bind_group);
             }
         }
     }
 }
+
+/// Build the entries of a material bind group, binding each texture and its
+/// sampler to the slot of the same index.
+fn material_entries<'a>(textures: &[(&'a TextureView, &'a Sampler)]) -> Vec<BindGroupEntry<'a>> {
+    textures
+        .iter()
+        .enumerate()
+        .flat_map(|(slot, (texture_view, sampler))| {
+            [
+                BindGroupEntry {
+                    binding: slot as u32 * 2,
+                    resource: BindingResource::TextureView(texture_view),
+                },
+                BindGroupEntry {
+                    binding: slot as u32 * 2 + 1,
+                    resource: BindingResource::Sampler(sampler),
+                },
+            ]
+        })
+        .collect()
+}
@@ -30,6 +30,61 @@ const PRIM_RECT: u32 = 0u;
 const PRIM_GLYPH: u32 = 1u;
 const PRIM_LINE: u32 = 2u;
 const PRIM_QUARTER_PIE: u32 = 3u;
+const PRIM_ELLIPSE: u32 = 4u;
+const PRIM_PATH: u32 = 5u;
+const PRIM_ARC: u32 = 6u;
+const PRIM_POLYLINE: u32 = 7u;
+const PRIM_POLYGON: u32 = 8u;
+const PRIM_STAR: u32 = 9u;
+const PRIM_CAPSULE: u32 = 10u;
+const PRIM_ARROW: u32 = 11u;
+const PRIM_LAYER: u32 = 12u;
+
+// Keep in sync with MAX_LAYER_DEPTH
+const MAX_LAYER_DEPTH: u32 = 4u;
+
+// Keep in sync with ArcPrimitive::write()
+const ARC_FLAG_ROUNDED_CAPS: u32 = 1u;
+const ARC_FLAG_FULL_TURN: u32 = 2u;
+
+// Keep in sync with LineJoin, MITER_LIMIT and LineCap in shapes.rs
+const LINE_JOIN_MITER: u32 = 0u;
+const LINE_JOIN_ROUND: u32 = 1u;
+const LINE_JOIN_BEVEL: u32 = 2u;
+const MITER_LIMIT: f32 = 4.;
+const LINE_CAP_BUTT: u32 = 0u;
+const LINE_CAP_SQUARE: u32 = 1u;
+const LINE_CAP_ROUND: u32 = 2u;
+
+// Keep in sync with ArrowHeadShape and ArrowEnds
+const ARROW_HEAD_TRIANGLE: u32 = 0u;
+const ARROW_HEAD_OPEN_V: u32 = 1u;
+const ARROW_HEAD_CIRCLE: u32 = 2u;
+const ARROW_END_START: u32 = 1u;
+const ARROW_END_END: u32 = 2u;
+
+// Keep in sync with Effects::write()
+const EFFECT_FLAG_GLOW: u32 = 1u;
+const EFFECT_FLAG_SHADOW: u32 = 2u;
+const EFFECT_FLAG_INSET_SHADOW: u32 = 4u;
+const EFFECT_FLAG_GRADIENT: u32 = 8u;
+const EFFECT_FLAG_BORDER_GRADIENT: u32 = 16u;
+const EFFECT_FLAG_DASH: u32 = 32u;
+const EFFECT_FLAG_CLIP: u32 = 64u;
+const EFFECT_FLAG_MASK: u32 = 128u;
+const EFFECT_FLAG_OPACITY: u32 = 256u;
+
+const GRADIENT_LINEAR: u32 = 0u;
+const GRADIENT_RADIAL: u32 = 1u;
+const GRADIENT_CONIC: u32 = 2u;
+
+const PI: f32 = 3.14159265358979;
+
+// Keep in sync with ImageRepeat in render_context.rs
+const IMAGE_REPEAT_ONCE: u32 = 0u;
+const IMAGE_REPEAT_CLAMP: u32 = 1u;
+const IMAGE_REPEAT_REPEAT: u32 = 2u;
+const IMAGE_REPEAT_MIRROR: u32 = 3u;
 
 /// Serialized primitives buffer.
 struct Primitives {
@@ -60,10 +115,23 @@ var<storage, read> tiles: Tiles;
 @group(1) @binding(2)
 var<storage, read> offsets_and_counts: array<OffsetAndCount>;
 
+// Keep in sync with MAX_BATCH_TEXTURES
 @group(2) @binding(0)
-var quad_texture: texture_2d<f32>;
+var quad_texture0: texture_2d<f32>;
 @group(2) @binding(1)
-var quad_sampler: sampler;
+var quad_sampler0: sampler;
+@group(2) @binding(2)
+var quad_texture1: texture_2d<f32>;
+@group(2) @binding(3)
+var quad_sampler1: sampler;
+@group(2) @binding(4)
+var quad_texture2: texture_2d<f32>;
+@group(2) @binding(5)
+var quad_sampler2: sampler;
+@group(2) @binding(6)
+var quad_texture3: texture_2d<f32>;
+@group(2) @binding(7)
+var quad_sampler3: sampler;
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
@@ -77,6 +145,10 @@ struct Extras {
 struct Rect {
     center: vec2<f32>,
     half_size: vec2<f32>,
+    /// Cosine and sine of the rotation of the rectangle around its center.
+    rotation: vec2<f32>,
+    /// Corner radii, in order top-left, top-right, bottom-right, bottom-left.
+    radii: vec4<f32>,
     extras: Extras,
 }
 
@@ -90,6 +162,67 @@ struct Line {
 struct QPie {
     origin: vec2<f32>,
     radii: vec2<f32>,
+    /// Cosine and sine of the rotation of the quarter pie around its origin.
+    rotation: vec2<f32>,
+    extras: Extras,
+}
+
+struct Ellipse {
+    center: vec2<f32>,
+    radii: vec2<f32>,
+    /// Cosine and sine of the rotation of the ellipse around its center.
+    rotation: vec2<f32>,
+    stroke_width: f32,
+    stroke_offset: f32,
+    extras: Extras,
+}
+
+struct Path {
+    stroke_width: f32,
+    stroke_offset: f32,
+    contour_count: u32,
+    extras: Extras,
+}
+
+struct Polygon {
+    stroke_width: f32,
+    stroke_offset: f32,
+    vertex_count: u32,
+    extras: Extras,
+}
+
+struct Star {
+    center: vec2<f32>,
+    outer_radius: f32,
+    inner_radius: f32,
+    corner_radius: f32,
+    rotation: vec2<f32>,
+    points: u32,
+    stroke_width: f32,
+    stroke_offset: f32,
+    extras: Extras,
+}
+
+struct Capsule {
+    center: vec2<f32>,
+    /// Vector from the center to the end of the capsule segment.
+    half_axis: vec2<f32>,
+    stroke_width: f32,
+    stroke_offset: f32,
+    extras: Extras,
+}
+
+struct Arc {
+    center: vec2<f32>,
+    inner_radius: f32,
+    outer_radius: f32,
+    /// Unit direction of the middle of the arc.
+    mid_dir: vec2<f32>,
+    /// Sine and cosine of the half-aperture of the arc.
+    aperture: vec2<f32>,
+    flags: u32,
+    stroke_width: f32,
+    stroke_offset: f32,
     extras: Extras,
 }
 
@@ -108,17 +241,54 @@ fn get_tile_dim() -> vec2<u32> {
 
 struct PrimitiveInfo {
     index: u32,
+    /// Slot of the texture among the ones bound by the batch.
+    texture: u32,
     kind: u32,
     textured: bool,
     bordered: bool,
+    effects: bool,
 }
 
 fn unpack_primitive_index(value: u32) -> PrimitiveInfo {
-    let index = (value & 0x07FFFFFFu);
-    let bordered = (value & 0x08000000u) != 0u;
-    let kind = (value & 0x70000000u) >> 28u;
+    let index = (value & 0x007FFFFFu);
+    let texture = (value & 0x01800000u) >> 23u;
+    let effects = (value & 0x02000000u) != 0u;
+    let bordered = (value & 0x04000000u) != 0u;
+    let kind = (value & 0x78000000u) >> 27u;
     let textured = (value & 0x80000000u) != 0u;
-    return PrimitiveInfo(index, kind, textured, bordered);
+    return PrimitiveInfo(index, texture, kind, textured, bordered, effects);
+}
+
+/// Sample the texture bound to the given slot of the batch.
+fn sample_slot(slot: u32, uv: vec2<f32>) -> vec4<f32> {
+    switch slot {
+        case 1u { return textureSample(quad_texture1, quad_sampler1, uv); }
+        case 2u { return textureSample(quad_texture2, quad_sampler2, uv); }
+        case 3u { return textureSample(quad_texture3, quad_sampler3, uv); }
+        default { return textureSample(quad_texture0, quad_sampler0, uv); }
+    }
+}
+
+/// Sample the base mip level of the texture bound to the given slot of the
+/// batch.
+fn sample_slot_level0(slot: u32, uv: vec2<f32>) -> vec4<f32> {
+    switch slot {
+        case 1u { return textureSampleLevel(quad_texture1, quad_sampler1, uv, 0.); }
+        case 2u { return textureSampleLevel(quad_texture2, quad_sampler2, uv, 0.); }
+        case 3u { return textureSampleLevel(quad_texture3, quad_sampler3, uv, 0.); }
+        default { return textureSampleLevel(quad_texture0, quad_sampler0, uv, 0.); }
+    }
+}
+
+/// Sample the texture bound to the given slot of the batch with explicit UV
+/// gradients.
+fn sample_slot_grad(slot: u32, uv: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> {
+    switch slot {
+        case 1u { return textureSampleGrad(quad_texture1, quad_sampler1, uv, ddx, ddy); }
+        case 2u { return textureSampleGrad(quad_texture2, quad_sampler2, uv, ddx, ddy); }
+        case 3u { return textureSampleGrad(quad_texture3, quad_sampler3, uv, ddx, ddy); }
+        default { return textureSampleGrad(quad_texture0, quad_sampler0, uv, ddx, ddy); }
+    }
 }
 
 fn get_vertex_pos(vertex_index: u32) -> vec2<f32> {
@@ -141,9 +311,17 @@ fn read_rect(offset: u32) -> Rect {
     let hh = primitives.elems[offset + 3u];
     rect.half_size = vec2<f32>(hw, hh);
 
-    rect.extras.radius = primitives.elems[offset + 4u];
+    let cos = primitives.elems[offset + 4u];
+    let sin = primitives.elems[offset + 5u];
+    rect.rotation = vec2<f32>(cos, sin);
+
+    let r_tl = primitives.elems[offset + 6u];
+    let r_tr = primitives.elems[offset + 7u];
+    let r_br = primitives.elems[offset + 8u];
+    let r_bl = primitives.elems[offset + 9u];
+    rect.radii = vec4<f32>(r_tl, r_tr, r_br, r_bl);
     
-    let c = primitives.elems[offset + 5u];
+    let c = primitives.elems[offset + 10u];
     let uc: u32 = bitcast<u32>(c);
     rect.extras.color = unpack4x8unorm(uc);
 
@@ -167,7 +345,24 @@ fn read_line(offset: u32) -> Line {
 
     line.thickness = primitives.elems[offset + 5u];
 
-    line.extras.radius = 0.0; // TODO
+    // Caps are applied here so that the distance is always the distance to a
+    // butt-ended line, inflated by 'radius'.
+    let cap = bitcast<u32>(primitives.elems[offset + 6u]);
+    let half_thickness = line.thickness * 0.5;
+    if (cap == LINE_CAP_ROUND) {
+        // Distance to the segment itself, inflated into a capsule
+        line.thickness = 0.;
+        line.extras.radius = half_thickness;
+    } else {
+        if (cap == LINE_CAP_SQUARE) {
+            let dir = line.p1 - line.p0;
+            let d = select(vec2<f32>(1., 0.), normalize(dir), any(dir != vec2<f32>(0.)));
+            let ext = d * half_thickness;
+            line.p0 -= ext;
+            line.p1 += ext;
+        }
+        line.extras.radius = 0.;
+    }
 
     return line;
 }
@@ -183,7 +378,11 @@ fn read_qpie(offset: u32) -> QPie {
     let ry = primitives.elems[offset + 3u];
     qpie.radii = vec2<f32>(rx, ry);
 
-    let c = primitives.elems[offset + 4u];
+    let cos = primitives.elems[offset + 4u];
+    let sin = primitives.elems[offset + 5u];
+    qpie.rotation = vec2<f32>(cos, sin);
+
+    let c = primitives.elems[offset + 6u];
     let uc: u32 = bitcast<u32>(c);
     qpie.extras.color = unpack4x8unorm(uc);
 
@@ -192,10 +391,147 @@ fn read_qpie(offset: u32) -> QPie {
     return qpie;
 }
 
-/// Signed distance to an axis-aligned rectangle.
+fn read_ellipse(offset: u32) -> Ellipse {
+    var ellipse: Ellipse;
+
+    let x = primitives.elems[offset];
+    let y = primitives.elems[offset + 1u];
+    ellipse.center = vec2<f32>(x, y);
+
+    let rx = primitives.elems[offset + 2u];
+    let ry = primitives.elems[offset + 3u];
+    ellipse.radii = vec2<f32>(rx, ry);
+
+    let cos = primitives.elems[offset + 4u];
+    let sin = primitives.elems[offset + 5u];
+    ellipse.rotation = vec2<f32>(cos, sin);
+
+    let c = primitives.elems[offset + 6u];
+    let uc: u32 = bitcast<u32>(c);
+    ellipse.extras.color = unpack4x8unorm(uc);
+
+    ellipse.stroke_width = primitives.elems[offset + 7u];
+    ellipse.stroke_offset = primitives.elems[offset + 8u];
+
+    ellipse.extras.radius = 0.0;
+
+    return ellipse;
+}
+
+fn read_path(offset: u32) -> Path {
+    var path: Path;
+
+    let c = primitives.elems[offset];
+    let uc: u32 = bitcast<u32>(c);
+    path.extras.color = unpack4x8unorm(uc);
+
+    path.stroke_width = primitives.elems[offset + 1u];
+    path.stroke_offset = primitives.elems[offset + 2u];
+    path.contour_count = bitcast<u32>(primitives.elems[offset + 3u]);
+
+    path.extras.radius = 0.0;
+
+    return path;
+}
+
+fn read_arc(offset: u32) -> Arc {
+    var arc: Arc;
+
+    let x = primitives.elems[offset];
+    let y = primitives.elems[offset + 1u];
+    arc.center = vec2<f32>(x, y);
+
+    arc.inner_radius = primitives.elems[offset + 2u];
+    arc.outer_radius = primitives.elems[offset + 3u];
+
+    let c = primitives.elems[offset + 4u];
+    let uc: u32 = bitcast<u32>(c);
+    arc.extras.color = unpack4x8unorm(uc);
+
+    let dx = primitives.elems[offset + 5u];
+    let dy = primitives.elems[offset + 6u];
+    arc.mid_dir = vec2<f32>(dx, dy);
+
+    let s = primitives.elems[offset + 7u];
+    let co = primitives.elems[offset + 8u];
+    arc.aperture = vec2<f32>(s, co);
+
+    arc.flags = bitcast<u32>(primitives.elems[offset + 9u]);
+    arc.stroke_width = primitives.elems[offset + 10u];
+    arc.stroke_offset = primitives.elems[offset + 11u];
+
+    arc.extras.radius = 0.0;
+
+    return arc;
+}
+
+fn read_point(offset: u32) -> vec2<f32> {
+    return vec2<f32>(primitives.elems[offset], primitives.elems[offset + 1u]);
+}
+
+/// Rotate a vector by the inverse of the rotation given as (cosine, sine).
+///
+/// This transforms a canvas-space delta into the local frame of a rotated
+/// primitive.
+fn unrotate(v: vec2<f32>, rotation: vec2<f32>) -> vec2<f32> {
+    return mat2x2<f32>(rotation.x, -rotation.y, rotation.y, rotation.x) * v;
+}
+
+/// Signed distance to a rotated rectangle with rounded corners.
+///
+/// The distance is evaluated in the local frame of the rectangle. Each quadrant
+/// uses the radius of its own corner. The top corners are the ones toward -Y,
+/// which is up on screen.
 fn sd_rect(p: vec2<f32>, rect: Rect) -> f32 {
-    let delta = abs(p - rect.center) - rect.half_size + rect.extras.radius;
-    return length(max(delta, vec2<f32>(0))) + max(min(delta.x, 0.), min(delta.y, 0.)) - rect.extras.radius;
+    let q = unrotate(p - rect.center, rect.rotation);
+    // (left, right) radii of the top or bottom edge, with the Y axis pointing
+    // down the screen
+    let side = select(rect.radii.wz, rect.radii.xy, q.y < 0.);
+    let radius = select(side.y, side.x, q.x < 0.);
+    let delta = abs(q) - rect.half_size + radius;
+    return length(max(delta, vec2<f32>(0))) + max(min(delta.x, 0.), min(delta.y, 0.)) - radius;
+}
+
+/// Signed distance to the stroke of a rectangle, or to the rectangle itself if
+/// not stroked.
+///
+/// Unlike `sd_stroke()`, this keeps the outer corners of the stroke square
+/// when the corners of the rectangle are square. The stroke is the difference
+/// of the rectangle grown to the outer edge of the stroke and of the one shrunk
+/// to its inner edge, with the radii of the rounded corners offset by the same
+/// amount.
+fn sd_rect_stroke(p: vec2<f32>, rect: Rect, stroke_width: f32, stroke_offset: f32) -> f32 {
+    if (stroke_width <= 0.) {
+        return sd_rect(p, rect);
+    }
+    let outset = stroke_offset + stroke_width * 0.5;
+    let inset = stroke_width * 0.5 - stroke_offset;
+    let rounded = rect.radii > vec4<f32>(0.);
+
+    var outer = rect;
+    outer.half_size += outset;
+    outer.radii = select(vec4<f32>(0.), rect.radii + outset, rounded);
+    let d_outer = sd_rect(p, outer);
+
+    var inner = rect;
+    inner.half_size -= inset;
+    if (any(inner.half_size <= vec2<f32>(0.))) {
+        return d_outer;
+    }
+    inner.radii = max(rect.radii - inset, vec4<f32>(0.));
+    return max(d_outer, -sd_rect(p, inner));
+}
+
+/// Signed distance to a quarter pie.
+///
+/// The quarter pie is the intersection of an ellipse and of the quadrant
+/// pointed to by the signs of its radii.
+fn sd_quarter_pie(p: vec2<f32>, qpie: QPie) -> f32 {
+    // Local frame where the quarter pie covers the quadrant of positive X and Y
+    let q = unrotate(p - qpie.origin, qpie.rotation) * sign(qpie.radii);
+    let d_ellipse = sd_ellipse(q, abs(qpie.radii));
+    let d_quadrant = length(max(-q, vec2<f32>(0))) + min(max(-q.x, -q.y), 0.);
+    return max(d_ellipse, d_quadrant);
 }
 
 /// Signed distance to an infinitely thin line segment.
@@ -209,13 +545,501 @@ fn sd_segment(p0: vec2<f32>, p1: vec2<f32>, p: vec2<f32>) -> f32 {
 /// Signed distance to a line (thick segment).
 fn sd_line(p0: vec2<f32>, p1: vec2<f32>, thickness: f32, p: vec2<f32>) -> f32 {
     let dir = p1 - p0;
-    let d = normalize(dir);
+    // A zero-length line has no direction; draw it axis-aligned
+    let d = select(vec2<f32>(1., 0.), normalize(dir), any(dir != vec2<f32>(0.)));
     let center = p0 + dir / 2.;
     let rot_delta = mat2x2<f32>(d.x, -d.y, d.y, d.x) * (p - center);
     let delta = abs(rot_delta) - vec2<f32>(length(dir), thickness) * 0.5;
     return length(max(delta, vec2<f32>(0))) + max(min(delta.x, 0.), min(delta.y, 0.));
 }
 
+/// Signed distance to an axis-aligned ellipse centered at the origin.
+///
+/// This finds the closest point on the ellipse with a few iterations of a
+/// trigonometry-free Newton-like solver, which converges fast enough for all
+/// eccentricities, including circles where the analytic solution degenerates.
+fn sd_ellipse(p: vec2<f32>, radii: vec2<f32>) -> f32 {
+    let ab = max(radii, vec2<f32>(1e-5));
+    // Nudge away from the center, where the direction of a circle is undefined
+    let q = max(abs(p), vec2<f32>(1e-4));
+    let e2 = ab.x * ab.x - ab.y * ab.y;
+    var t = vec2<f32>(0.70710678);
+    for (var i = 0; i < 3; i += 1) {
+        // Evolute of the ellipse at the current guess
+        let e = vec2<f32>(e2, -e2) * t * t * t / ab;
+        let r = ab * t - e;
+        let qe = q - e;
+        let rl = length(r);
+        let ql = max(length(qe), 1e-5);
+        t = saturate((qe * rl / ql + e) / ab);
+        t = t / max(length(t), 1e-5);
+    }
+    let closest = ab * t;
+    let d = length(q - closest);
+    let k = dot(q / ab, q / ab);
+    return select(d, -d, k < 1.);
+}
+
+/// Signed distance to an arc (annulus sector), a full annulus, or a pie.
+fn sd_arc(p: vec2<f32>, arc: Arc) -> f32 {
+    // Local frame where the arc is symmetric around the Y axis, so we only need
+    // to consider the half-plane x >= 0.
+    let d = arc.mid_dir;
+    let q = vec2<f32>(abs(dot(p, vec2<f32>(d.y, -d.x))), dot(p, d));
+    let l = length(q);
+    let sc = arc.aperture;
+    let is_full_turn = (arc.flags & ARC_FLAG_FULL_TURN) != 0u;
+
+    if ((arc.flags & ARC_FLAG_ROUNDED_CAPS) != 0u) {
+        let ra = (arc.inner_radius + arc.outer_radius) * 0.5;
+        let rb = (arc.outer_radius - arc.inner_radius) * 0.5;
+        if (!is_full_turn && sc.y * q.x > sc.x * q.y) {
+            // Closest to the cap, which is a disk centered on the arc's end
+            return length(q - sc * ra) - rb;
+        }
+        return abs(l - ra) - rb;
+    }
+
+    // Distance to the annulus, or disk for a pie
+    var radial = l - arc.outer_radius;
+    if (arc.inner_radius > 0.) {
+        radial = max(radial, arc.inner_radius - l);
+    }
+    if (is_full_turn) {
+        return radial;
+    }
+
+    // Rotate by the half-aperture so the cap lies on the Y axis; points inside
+    // the angular range then have a negative X coordinate.
+    let r = vec2<f32>(sc.y * q.x - sc.x * q.y, sc.x * q.x + sc.y * q.y);
+    let cap_y = max(0., max(arc.inner_radius - r.y, r.y - arc.outer_radius));
+    let cap = length(vec2<f32>(r.x, cap_y)) * sign(r.x);
+    return max(radial, cap);
+}
+
+/// Squared distance to an infinitely thin line segment, safe for degenerate
+/// segments where both points are equal.
+fn sd_segment_sq(p0: vec2<f32>, p1: vec2<f32>, p: vec2<f32>) -> f32 {
+    let p0p = p - p0;
+    let p01 = p1 - p0;
+    let h = saturate(dot(p0p, p01) / max(dot(p01, p01), 1e-8));
+    let d = p0p - p01 * h;
+    return dot(d, d);
+}
+
+/// Contribution of a line segment to the winding number of a point.
+fn winding_segment(p0: vec2<f32>, p1: vec2<f32>, p: vec2<f32>) -> i32 {
+    let side = (p1.x - p0.x) * (p.y - p0.y) - (p.x - p0.x) * (p1.y - p0.y);
+    if (p0.y <= p.y) {
+        if (p1.y > p.y && side > 0.) {
+            return 1;
+        }
+    } else if (p1.y <= p.y && side < 0.) {
+        return -1;
+    }
+    return 0;
+}
+
+/// Signed distance to a path made of contours of line segments.
+///
+/// Filled paths are signed according to the non-zero winding rule, and always
+/// have their contours closed. Stroked paths are only closed if the contour
+/// says so, and are the set of points closer than half the stroke width to
+/// the stroke middle, which is offset from the segments toward the outside of
+/// the (implicitly closed) path by the stroke offset.
+fn sd_path(offset: u32, path: Path, p: vec2<f32>) -> f32 {
+    let is_stroked = path.stroke_width > 0.;
+    var d2 = 1e30;
+    var winding = 0;
+    var off = offset + 4u;
+    for (var c = 0u; c < path.contour_count; c += 1u) {
+        let header = bitcast<u32>(primitives.elems[off]);
+        let point_count = header & 0x7FFFFFFFu;
+        let closed = (header & 0x80000000u) != 0u;
+        off += 1u;
+
+        let first = read_point(off);
+        var prev = first;
+        for (var i = 1u; i < point_count; i += 1u) {
+            let cur = read_point(off + 2u * i);
+            d2 = min(d2, sd_segment_sq(prev, cur, p));
+            winding += winding_segment(prev, cur, p);
+            prev = cur;
+        }
+        if (closed || !is_stroked) {
+            d2 = min(d2, sd_segment_sq(prev, first, p));
+        }
+        winding += winding_segment(prev, first, p);
+        off += 2u * point_count;
+    }
+
+    let d = sqrt(d2);
+    let signed_dist = select(d, -d, winding != 0);
+    if (is_stroked) {
+        return abs(signed_dist - path.stroke_offset) - path.stroke_width * 0.5;
+    }
+    return signed_dist;
+}
+
+fn read_polygon(offset: u32) -> Polygon {
+    var polygon: Polygon;
+
+    let c = primitives.elems[offset];
+    let uc: u32 = bitcast<u32>(c);
+    polygon.extras.color = unpack4x8unorm(uc);
+
+    polygon.stroke_width = primitives.elems[offset + 1u];
+    polygon.stroke_offset = primitives.elems[offset + 2u];
+    polygon.vertex_count = bitcast<u32>(primitives.elems[offset + 3u]);
+
+    polygon.extras.radius = 0.0;
+
+    return polygon;
+}
+
+/// Signed distance to a polygon, negative inside.
+///
+/// The vertices follow the polygon header. The sign is given by the parity of
+/// the number of edges crossed by a ray from `p`, so the vertices can be in any
+/// order.
+fn sd_polygon(offset: u32, polygon: Polygon, p: vec2<f32>) -> f32 {
+    let off = offset + 4u;
+    var prev = read_point(off + 2u * (polygon.vertex_count - 1u));
+    var d2 = 1e30;
+    var sign = 1.;
+    for (var i = 0u; i < polygon.vertex_count; i += 1u) {
+        let cur = read_point(off + 2u * i);
+        d2 = min(d2, sd_segment_sq(cur, prev, p));
+        let e = prev - cur;
+        let w = p - cur;
+        let c = vec3<bool>(p.y >= cur.y, p.y < prev.y, e.x * w.y > e.y * w.x);
+        if (all(c) || !any(c)) {
+            sign = -sign;
+        }
+        prev = cur;
+    }
+    return sd_stroke(sign * sqrt(d2), polygon.stroke_width, polygon.stroke_offset);
+}
+
+fn read_star(offset: u32) -> Star {
+    var star: Star;
+
+    let x = primitives.elems[offset];
+    let y = primitives.elems[offset + 1u];
+    star.center = vec2<f32>(x, y);
+
+    star.outer_radius = primitives.elems[offset + 2u];
+    star.inner_radius = primitives.elems[offset + 3u];
+    star.corner_radius = primitives.elems[offset + 4u];
+
+    let co = primitives.elems[offset + 5u];
+    let s = primitives.elems[offset + 6u];
+    star.rotation = vec2<f32>(co, s);
+
+    star.points = bitcast<u32>(primitives.elems[offset + 7u]);
+
+    let c = primitives.elems[offset + 8u];
+    let uc: u32 = bitcast<u32>(c);
+    star.extras.color = unpack4x8unorm(uc);
+
+    star.stroke_width = primitives.elems[offset + 9u];
+    star.stroke_offset = primitives.elems[offset + 10u];
+
+    star.extras.radius = star.corner_radius;
+
+    return star;
+}
+
+/// Signed distance to a star, with its outer corners rounded.
+///
+/// The star is symmetric around the lines through its center and any of its
+/// vertices, so the point is folded into the sector between the first outer
+/// vertex and the next inner vertex, where the star edge is a single segment.
+fn sd_star(p: vec2<f32>, star: Star) -> f32 {
+    let half_angle = PI / f32(star.points);
+    let q = unrotate(p - star.center, star.rotation);
+
+    // Angle from the first outer vertex, which points toward -Y (up on the
+    // Y-down canvas), folded into [0:half_angle]
+    let a = atan2(q.x, -q.y);
+    let b = a - 2. * half_angle * round(a / (2. * half_angle));
+    let f = length(q) * vec2<f32>(cos(b), abs(sin(b)));
+
+    let v0 = vec2<f32>(star.outer_radius, 0.);
+    let v1 = star.inner_radius * vec2<f32>(cos(half_angle), sin(half_angle));
+    let e = v1 - v0;
+    let w = f - v0;
+    let d = length(w - e * saturate(dot(w, e) / max(dot(e, e), 1e-8)));
+    // The center is on the left of the edge
+    let inside = e.x * w.y - e.y * w.x > 0.;
+    return select(d, -d, inside) - star.extras.radius;
+}
+
+fn read_capsule(offset: u32) -> Capsule {
+    var capsule: Capsule;
+
+    let x = primitives.elems[offset];
+    let y = primitives.elems[offset + 1u];
+    capsule.center = vec2<f32>(x, y);
+
+    let ax = primitives.elems[offset + 2u];
+    let ay = primitives.elems[offset + 3u];
+    capsule.half_axis = vec2<f32>(ax, ay);
+
+    capsule.extras.radius = primitives.elems[offset + 4u];
+
+    let c = primitives.elems[offset + 5u];
+    let uc: u32 = bitcast<u32>(c);
+    capsule.extras.color = unpack4x8unorm(uc);
+
+    capsule.stroke_width = primitives.elems[offset + 6u];
+    capsule.stroke_offset = primitives.elems[offset + 7u];
+
+    return capsule;
+}
+
+/// Signed distance to a capsule, which is the distance to its segment minus
+/// its radius.
+fn sd_capsule(p: vec2<f32>, capsule: Capsule) -> f32 {
+    let q = p - capsule.center;
+    let a = capsule.half_axis;
+    let h = clamp(dot(q, a) / max(dot(a, a), 1e-8), -1., 1.);
+    return length(q - a * h) - capsule.extras.radius;
+}
+
+/// Signed distance to an arrow head with its tip at `tip`, pointing along the
+/// unit direction `dir`.
+fn sd_arrow_head(p: vec2<f32>, tip: vec2<f32>, dir: vec2<f32>, shape: u32, head_length: f32, head_width: f32, thickness: f32) -> f32 {
+    let n = vec2<f32>(-dir.y, dir.x);
+    let base = tip - dir * head_length;
+    let w0 = base + n * (head_width * 0.5);
+    let w1 = base - n * (head_width * 0.5);
+    switch shape {
+        case ARROW_HEAD_OPEN_V {
+            let d2 = min(sd_segment_sq(tip, w0, p), sd_segment_sq(tip, w1, p));
+            return sqrt(d2) - thickness * 0.5;
+        }
+        case ARROW_HEAD_CIRCLE {
+            return length(p - tip) - head_width * 0.5;
+        }
+        default {
+            // Triangle, as a quad with a degenerate edge
+            return sd_quad(p, tip, w0, w1, w1);
+        }
+    }
+}
+
+/// Signed distance to an arrow, which is the union of its shaft and heads.
+///
+/// Filled triangle heads shorten the shaft so it doesn't poke through their
+/// tip when thicker than them.
+fn sd_arrow(offset: u32, p: vec2<f32>) -> f32 {
+    let p0 = read_point(offset);
+    let p1 = read_point(offset + 2u);
+    let thickness = primitives.elems[offset + 5u];
+    let style = bitcast<u32>(primitives.elems[offset + 6u]);
+    let head_length = primitives.elems[offset + 7u];
+    let head_width = primitives.elems[offset + 8u];
+    let shape = style & 0xFFu;
+    let ends = style >> 8u;
+
+    let dir = normalize(p1 - p0);
+    let inset = select(0., head_length, shape == ARROW_HEAD_TRIANGLE);
+    var s0 = p0;
+    var s1 = p1;
+    var dist = 1e30;
+    if ((ends & ARROW_END_START) != 0u) {
+        dist = min(dist, sd_arrow_head(p, p0, -dir, shape, head_length, head_width, thickness));
+        s0 += dir * inset;
+    }
+    if ((ends & ARROW_END_END) != 0u) {
+        dist = min(dist, sd_arrow_head(p, p1, dir, shape, head_length, head_width, thickness));
+        s1 -= dir * inset;
+    }
+    // Only draw the shaft if the heads don't cover it entirely
+    if (dot(s1 - s0, dir) > 0.) {
+        dist = min(dist, sd_line(s0, s1, thickness, p));
+    }
+    return dist;
+}
+
+/// Get the offset of the first row after the contours of a path.
+fn path_end_offset(offset: u32, path: Path) -> u32 {
+    var off = offset + 4u;
+    for (var c = 0u; c < path.contour_count; c += 1u) {
+        let header = bitcast<u32>(primitives.elems[off]);
+        let point_count = header & 0x7FFFFFFFu;
+        off += 1u + 2u * point_count;
+    }
+    return off;
+}
+
+/// Signed distance to a quadrilateral, which may be degenerate.
+fn sd_quad(p: vec2<f32>, v0: vec2<f32>, v1: vec2<f32>, v2: vec2<f32>, v3: vec2<f32>) -> f32 {
+    var v = array<vec2<f32>, 4>(v0, v1, v2, v3);
+    var d2 = dot(p - v0, p - v0);
+    var sign = 1.;
+    for (var i = 0u; i < 4u; i += 1u) {
+        let j = (i + 3u) % 4u;
+        let e = v[j] - v[i];
+        let w = p - v[i];
+        let b = w - e * saturate(dot(w, e) / max(dot(e, e), 1e-8));
+        d2 = min(d2, dot(b, b));
+        let c = vec3<bool>(p.y >= v[i].y, p.y < v[j].y, e.x * w.y > e.y * w.x);
+        if (all(c) || !any(c)) {
+            sign = -sign;
+        }
+    }
+    return sign * sqrt(d2);
+}
+
+/// Signed distance to a polyline, with its joins and caps.
+///
+/// The segments are butt-ended thick lines. Joins fill the wedge left between
+/// two consecutive segments on the outer side of the turn, and caps extend the
+/// two end segments.
+fn sd_polyline(offset: u32, p: vec2<f32>) -> f32 {
+    let thickness = primitives.elems[offset + 1u];
+    let style = bitcast<u32>(primitives.elems[offset + 2u]);
+    let join = style & 0xFFu;
+    let cap = (style >> 8u) & 0xFFu;
+    let point_count = bitcast<u32>(primitives.elems[offset + 3u]);
+    let points = offset + 4u;
+    let half_thickness = thickness * 0.5;
+    var dist = 1e30;
+    if (point_count < 2u) {
+        return dist;
+    }
+    let last = point_count - 1u;
+
+    for (var i = 0u; i < last; i += 1u) {
+        var p0 = read_point(points + 2u * i);
+        var p1 = read_point(points + 2u * i + 2u);
+        if (cap == LINE_CAP_SQUARE) {
+            let dir = normalize(p1 - p0) * half_thickness;
+            if (i == 0u) {
+                p0 -= dir;
+            }
+            if (i + 1u == last) {
+                p1 += dir;
+            }
+        }
+        dist = min(dist, sd_line(p0, p1, thickness, p));
+    }
+
+    if (cap == LINE_CAP_ROUND) {
+        dist = min(dist, length(p - read_point(points)) - half_thickness);
+        dist = min(dist, length(p - read_point(points + 2u * last)) - half_thickness);
+    }
+
+    for (var i = 1u; i < last; i += 1u) {
+        let prev = read_point(points + 2u * i - 2u);
+        let cur = read_point(points + 2u * i);
+        let next = read_point(points + 2u * i + 2u);
+        if (join == LINE_JOIN_ROUND) {
+            dist = min(dist, length(p - cur) - half_thickness);
+            continue;
+        }
+
+        // Normals on the outer side of the turn
+        let d0 = normalize(cur - prev);
+        let d1 = normalize(next - cur);
+        let side = select(1., -1., d0.x * d1.y - d0.y * d1.x > 0.);
+        let n0 = side * vec2<f32>(-d0.y, d0.x);
+        let n1 = side * vec2<f32>(-d1.y, d1.x);
+        let a = cur + n0 * half_thickness;
+        let b = cur + n1 * half_thickness;
+
+        // Bevel by default, or miter if not too sharp
+        var tip = (a + b) * 0.5;
+        let bisector = n0 + n1;
+        if (join == LINE_JOIN_MITER && dot(bisector, bisector) > 1e-6) {
+            let m = normalize(bisector);
+            let cos_half = dot(m, n0);
+            if (cos_half * MITER_LIMIT >= 1.) {
+                tip = cur + m * (half_thickness / cos_half);
+            }
+        }
+        dist = min(dist, sd_quad(p, cur, a, tip, b));
+    }
+    return dist;
+}
+
+/// Get the offset of the first row after the points of a polyline.
+fn polyline_end_offset(offset: u32) -> u32 {
+    let point_count = bitcast<u32>(primitives.elems[offset + 3u]);
+    return offset + 4u + 2u * point_count;
+}
+
+/// Turn the signed distance to a shape into the one to its stroke, if stroked.
+///
+/// The middle of the stroke is offset from the edge of the shape by
+/// `stroke_offset`, positive toward the outside.
+fn sd_stroke(dist: f32, stroke_width: f32, stroke_offset: f32) -> f32 {
+    if (stroke_width > 0.) {
+        return abs(dist - stroke_offset) - stroke_width * 0.5;
+    }
+    return dist;
+}
+
+/// Signed distance to any primitive.
+///
+/// The primitive is read from the primitive buffer at the index stored in
+/// `prim_info`. For glyphs, this is the distance to the glyph rectangle; see
+/// `sd_glyph()` for the distance to the glyph outline.
+fn sd_primitive(prim_info: PrimitiveInfo, p: vec2<f32>) -> f32 {
+    let offset = prim_info.index;
+    var dist = 1e30;
+    switch prim_info.kind {
+        case PRIM_RECT {
+            let stroke_width = primitives.elems[offset + 11u];
+            let stroke_offset = primitives.elems[offset + 12u];
+            dist = sd_rect_stroke(p, read_rect(offset), stroke_width, stroke_offset);
+        }
+        case PRIM_GLYPH {
+            dist = sd_rect(p, read_rect(offset));
+        }
+        case PRIM_LINE {
+            let line = read_line(offset);
+            dist = sd_line(line.p0, line.p1, line.thickness, p) - line.extras.radius;
+        }
+        case PRIM_QUARTER_PIE {
+            dist = sd_quarter_pie(p, read_qpie(offset));
+        }
+        case PRIM_ELLIPSE {
+            let ellipse = read_ellipse(offset);
+            let q = unrotate(p - ellipse.center, ellipse.rotation);
+            dist = sd_stroke(sd_ellipse(q, ellipse.radii), ellipse.stroke_width, ellipse.stroke_offset);
+        }
+        case PRIM_PATH {
+            dist = sd_path(offset, read_path(offset), p);
+        }
+        case PRIM_ARC {
+            let arc = read_arc(offset);
+            dist = sd_stroke(sd_arc(p - arc.center, arc), arc.stroke_width, arc.stroke_offset);
+        }
+        case PRIM_POLYLINE {
+            dist = sd_polyline(offset, p);
+        }
+        case PRIM_POLYGON {
+            dist = sd_polygon(offset, read_polygon(offset), p);
+        }
+        case PRIM_STAR {
+            let star = read_star(offset);
+            dist = sd_stroke(sd_star(p, star), star.stroke_width, star.stroke_offset);
+        }
+        case PRIM_CAPSULE {
+            let capsule = read_capsule(offset);
+            dist = sd_stroke(sd_capsule(p, capsule), capsule.stroke_width, capsule.stroke_offset);
+        }
+        case PRIM_ARROW {
+            dist = sd_arrow(offset, p);
+        }
+        default {}
+    }
+    return dist;
+}
+
 /// Calculate the anti-aliased coverage of a pixel based on its SDF distance.
 fn aa_coverage(dist: f32) -> f32 {
     // The mathematical border is exactly at 'dist'. But we want a smooth edge between the two pixels
@@ -225,6 +1049,143 @@ fn aa_coverage(dist: f32) -> f32 {
     return smoothstep(1., 0., dist + 0.5);
 }
 
+/// Blend a color over another one.
+fn blend(dst: vec4<f32>, src: vec4<f32>) -> vec4<f32> {
+    let alpha = mix(dst.a, 1.0, src.a);
+    let rgb = mix(dst.rgb, src.rgb, src.a);
+    return vec4<f32>(rgb, alpha);
+}
+
+/// Blend a layer group faded by its opacity over the color under the layer.
+///
+/// Unlike `blend()`, the group color is premultiplied, like the color under it.
+fn composite_layer(dst: vec4<f32>, group: vec4<f32>, opacity: f32) -> vec4<f32> {
+    return group * opacity + dst * (1. - group.a * opacity);
+}
+
+/// Sample the coverage of a glyph at a given position.
+///
+/// Positions outside the glyph rectangle have no coverage, to prevent sampling
+/// the neighboring glyphs of the atlas.
+fn glyph_coverage(slot: u32, p: vec2<f32>, rect: Rect, uv_origin: vec2<f32>, uv_scale: vec2<f32>) -> f32 {
+    let delta = unrotate(p - rect.center, rect.rotation);
+    if (any(abs(delta) > rect.half_size)) {
+        return 0.;
+    }
+    let uv = delta * uv_scale + uv_origin;
+    return sample_slot_level0(slot, uv).a;
+}
+
+/// Approximate signed distance to the outline of a glyph.
+///
+/// The glyph atlas only stores coverage, not distances. Outside the glyph, this
+/// samples the coverage on concentric rings around `p`, every pixel for small
+/// reaches and with at most 8 rings, up to `reach`, and returns the distance to
+/// the nearest covered sample. Beyond `reach`, the distance returned is only
+/// known to be larger than it.
+fn sd_glyph(offset: u32, slot: u32, p: vec2<f32>, reach: f32) -> f32 {
+    let rect = read_rect(offset);
+    let uv_x = primitives.elems[offset + 11u];
+    let uv_y = primitives.elems[offset + 12u];
+    let uv_sx = primitives.elems[offset + 13u];
+    let uv_sy = primitives.elems[offset + 14u];
+    let uv_origin = vec2<f32>(uv_x, uv_y);
+    let uv_scale = vec2<f32>(uv_sx, uv_sy);
+
+    let a0 = glyph_coverage(slot, p, rect, uv_origin, uv_scale);
+    if (a0 > 0.) {
+        return 0.5 - a0;
+    }
+
+    var dirs = array<vec2<f32>, 8>(
+        vec2<f32>(1., 0.),
+        vec2<f32>(0.70710678, 0.70710678),
+        vec2<f32>(0., 1.),
+        vec2<f32>(-0.70710678, 0.70710678),
+        vec2<f32>(-1., 0.),
+        vec2<f32>(-0.70710678, -0.70710678),
+        vec2<f32>(0., -1.),
+        vec2<f32>(0.70710678, -0.70710678),
+    );
+    // Rotation by 1/16th of a turn, to interleave the samples of odd rings
+    // between the ones of even rings.
+    let interleave = vec2<f32>(0.92387953, 0.38268343);
+
+    let ring_count = clamp(u32(ceil(reach)), 1u, 8u);
+    let ring_step = reach / f32(ring_count);
+    var dist = reach + 1.;
+    for (var ring = 1u; ring <= ring_count; ring += 1u) {
+        let r = ring_step * f32(ring);
+        for (var k = 0u; k < 8u; k += 1u) {
+            var dir = dirs[k];
+            if ((ring & 1u) != 0u) {
+                dir = unrotate(dir, interleave);
+            }
+            let a = glyph_coverage(slot, p + dir * r, rect, uv_origin, uv_scale);
+            if (a > 0.) {
+                dist = min(dist, r + 0.5 - a);
+            }
+        }
+        // Farther rings cannot get any closer
+        if (dist <= r + 0.5) {
+            break;
+        }
+    }
+    return dist;
+}
+
+/// Draw the effects of a primitive underneath it.
+///
+/// The effects rows start at `offset`, and the distance to the primitive is
+/// `dist` (ignored for glyphs, which have their own approximation). Effects are
+/// drawn back to front: shadow, then glow.
+fn draw_effects(color: vec4<f32>, offset: u32, prim_info: PrimitiveInfo, canvas_pos: vec2<f32>, dist: f32) -> vec4<f32> {
+    var out = color;
+    let flags = bitcast<u32>(primitives.elems[offset]);
+    var off = offset + 1u;
+    let is_glyph = prim_info.kind == PRIM_GLYPH;
+
+    let has_glow = (flags & EFFECT_FLAG_GLOW) != 0u;
+    let glow_offset = off;
+    if (has_glow) {
+        off += 2u;
+    }
+
+    if ((flags & EFFECT_FLAG_SHADOW) != 0u) {
+        let shadow_offset = vec2<f32>(primitives.elems[off], primitives.elems[off + 1u]);
+        let blur_radius = primitives.elems[off + 2u];
+        let shadow_color = unpack4x8unorm(bitcast<u32>(primitives.elems[off + 3u]));
+        off += 4u;
+
+        // Evaluate the SDF of the primitive at the position offset backward, which
+        // is equivalent to offsetting the primitive itself.
+        let p = canvas_pos - shadow_offset;
+        var shadow_dist: f32;
+        if (is_glyph) {
+            shadow_dist = sd_glyph(prim_info.index, prim_info.texture, p, blur_radius + 1.);
+        } else {
+            shadow_dist = sd_primitive(prim_info, p);
+        }
+        // Below half a pixel, this degenerates into the regular anti-aliasing.
+        let blur = max(blur_radius, 0.5);
+        let alpha = 1. - smoothstep(-blur, blur, shadow_dist);
+        out = blend(out, vec4<f32>(shadow_color.rgb, shadow_color.a * alpha));
+    }
+
+    if (has_glow) {
+        let glow_color = unpack4x8unorm(bitcast<u32>(primitives.elems[glow_offset]));
+        let spread = primitives.elems[glow_offset + 1u];
+        var glow_dist = dist;
+        if (is_glyph) {
+            glow_dist = sd_glyph(prim_info.index, prim_info.texture, canvas_pos, spread);
+        }
+        let falloff = 1. - smoothstep(0., spread, glow_dist);
+        out = blend(out, vec4<f32>(glow_color.rgb, glow_color.a * falloff));
+    }
+
+    return out;
+}
+
 fn sdf_rect(offset: u32, canvas_pos: vec2<f32>) -> vec4<f32> {
     let rect = read_rect(offset);
     let dist = sd_rect(canvas_pos, rect);
@@ -232,19 +1193,19 @@ fn sdf_rect(offset: u32, canvas_pos: vec2<f32>) -> vec4<f32> {
     return vec4<f32>(rect.extras.color.rgb, alpha);
 }
 
-fn sdf_glyph(offset: u32, canvas_pos: vec2<f32>) -> vec4<f32> {
+fn sdf_glyph(offset: u32, slot: u32, canvas_pos: vec2<f32>) -> vec4<f32> {
     let rect = read_rect(offset);
     let dist = sd_rect(canvas_pos, rect);
     let alpha = rect.extras.color.a * aa_coverage(dist);
 
-    let uv_x = primitives.elems[offset + 6u];
-    let uv_y = primitives.elems[offset + 7u];
-    let uv_sx = primitives.elems[offset + 8u];
-    let uv_sy = primitives.elems[offset + 9u];
+    let uv_x = primitives.elems[offset + 11u];
+    let uv_y = primitives.elems[offset + 12u];
+    let uv_sx = primitives.elems[offset + 13u];
+    let uv_sy = primitives.elems[offset + 14u];
     let uv_origin = vec2<f32>(uv_x, uv_y);
     let uv_scale = vec2<f32>(uv_sx, uv_sy);
-    let uv = (canvas_pos - rect.center) * uv_scale + uv_origin;
-    let tex = textureSample(quad_texture, quad_sampler, uv);
+    let uv = unrotate(canvas_pos - rect.center, rect.rotation) * uv_scale + uv_origin;
+    let tex = sample_slot(slot, uv);
 
     return vec4<f32>(rect.extras.color.rgb, alpha * tex.a * rect.extras.color.a);
 }
@@ -265,6 +1226,232 @@ fn vertex(
     return out;
 }
 
+/// Draw the inset shadow of a rectangle, if any, on top of its fill.
+///
+/// The effects rows start at `offset`. The shadow is cast by the outside of the
+/// rectangle moved by the shadow offset, and is clipped to the inside of the
+/// border by shrinking the rectangle by `border_inset`, the part of the border
+/// inside the rectangle.
+fn draw_inset_shadow(color: vec4<f32>, offset: u32, prim_info: PrimitiveInfo, canvas_pos: vec2<f32>, border_inset: f32, coverage: f32) -> vec4<f32> {
+    let flags = bitcast<u32>(primitives.elems[offset]);
+    if ((flags & EFFECT_FLAG_INSET_SHADOW) == 0u) {
+        return color;
+    }
+    var off = offset + 1u;
+    if ((flags & EFFECT_FLAG_GLOW) != 0u) {
+        off += 2u;
+    }
+    if ((flags & EFFECT_FLAG_SHADOW) != 0u) {
+        off += 4u;
+    }
+
+    let shadow_offset = vec2<f32>(primitives.elems[off], primitives.elems[off + 1u]);
+    let blur_radius = primitives.elems[off + 2u];
+    let shadow_color = unpack4x8unorm(bitcast<u32>(primitives.elems[off + 3u]));
+
+    // Positive outside the moved rectangle, that is where the shadow is cast
+    let d = sd_primitive(prim_info, canvas_pos - shadow_offset) + border_inset;
+    let blur = max(blur_radius, 0.5);
+    let alpha = smoothstep(-blur, blur, d) * coverage;
+    return blend(color, vec4<f32>(shadow_color.rgb, shadow_color.a * alpha));
+}
+
+/// Get the offset of the clip rows of a primitive, which follow the glow and
+/// shadow rows.
+fn clip_offset(offset: u32, flags: u32) -> u32 {
+    var off = offset + 1u;
+    if ((flags & EFFECT_FLAG_GLOW) != 0u) {
+        off += 2u;
+    }
+    if ((flags & EFFECT_FLAG_SHADOW) != 0u) {
+        off += 4u;
+    }
+    if ((flags & EFFECT_FLAG_INSET_SHADOW) != 0u) {
+        off += 4u;
+    }
+    return off;
+}
+
+/// Get the offset of the opacity row of a primitive, which follows the clip
+/// rows.
+fn opacity_offset(offset: u32, flags: u32) -> u32 {
+    var off = clip_offset(offset, flags);
+    if ((flags & EFFECT_FLAG_CLIP) != 0u) {
+        off += 4u;
+    }
+    return off;
+}
+
+/// Get the offset of the first gradient row of a primitive, which follows all
+/// fixed-size effects rows.
+fn gradient_offset(offset: u32, flags: u32) -> u32 {
+    var off = opacity_offset(offset, flags);
+    if ((flags & EFFECT_FLAG_OPACITY) != 0u) {
+        off += 1u;
+    }
+    return off;
+}
+
+/// Get the offset of the mask rows of a primitive, which follow all other
+/// effects rows.
+fn mask_offset(offset: u32, flags: u32) -> u32 {
+    var off = gradient_offset(offset, flags);
+    if ((flags & EFFECT_FLAG_GRADIENT) != 0u) {
+        off += gradient_row_count(off);
+    }
+    if ((flags & EFFECT_FLAG_BORDER_GRADIENT) != 0u) {
+        off += gradient_row_count(off);
+    }
+    if ((flags & EFFECT_FLAG_DASH) != 0u) {
+        off += 8u + bitcast<u32>(primitives.elems[off + 7u]);
+    }
+    return off;
+}
+
+/// Coverage of the clip masks at the given offset for a canvas position. Each
+/// mask row is the packed index of a mask primitive, whose coverage multiplies
+/// the others.
+fn mask_coverage(offset: u32, p: vec2<f32>) -> f32 {
+    let count = bitcast<u32>(primitives.elems[offset]);
+    var coverage = 1.;
+    for (var i = 0u; i < count; i += 1u) {
+        let mask_info = unpack_primitive_index(bitcast<u32>(primitives.elems[offset + 1u + i]));
+        coverage *= aa_coverage(sd_primitive(mask_info, p));
+    }
+    return coverage;
+}
+
+/// Coverage of the clip rectangle at the given offset for a canvas position.
+fn clip_coverage(offset: u32, p: vec2<f32>) -> f32 {
+    let clip_min = vec2<f32>(primitives.elems[offset], primitives.elems[offset + 1u]);
+    let clip_max = vec2<f32>(primitives.elems[offset + 2u], primitives.elems[offset + 3u]);
+    let q = max(clip_min - p, p - clip_max);
+    let dist = length(max(q, vec2<f32>(0.))) + min(max(q.x, q.y), 0.);
+    return aa_coverage(dist);
+}
+
+/// Get the number of rows of the gradient at the given offset.
+fn gradient_row_count(offset: u32) -> u32 {
+    let stop_count = bitcast<u32>(primitives.elems[offset]) >> 8u;
+    return 5u + 2u * stop_count;
+}
+
+/// Evaluate the color of the gradient at the given offset for a canvas position.
+fn eval_gradient(offset: u32, p: vec2<f32>) -> vec4<f32> {
+    let header = bitcast<u32>(primitives.elems[offset]);
+    let kind = header & 0xFFu;
+    let stop_count = header >> 8u;
+    let a = vec2<f32>(primitives.elems[offset + 1u], primitives.elems[offset + 2u]);
+    let b = vec2<f32>(primitives.elems[offset + 3u], primitives.elems[offset + 4u]);
+
+    // Gradient parameter 't' at the position, where stops are placed
+    var t = 0.;
+    switch kind {
+        case GRADIENT_LINEAR {
+            let d = b - a;
+            t = dot(p - a, d) / max(dot(d, d), 1e-6);
+        }
+        case GRADIENT_RADIAL {
+            t = length(p - a) / max(b.x, 1e-6);
+        }
+        case GRADIENT_CONIC {
+            let v = p - a;
+            t = fract((atan2(v.y, v.x) - b.x) / (2. * PI));
+        }
+        default {}
+    }
+
+    // Interpolate between the two stops around 't', or clamp to the nearest one
+    let stops = offset + 5u;
+    var color = unpack4x8unorm(bitcast<u32>(primitives.elems[stops + 1u]));
+    var prev_offset = primitives.elems[stops];
+    for (var i = 1u; i < stop_count; i += 1u) {
+        let stop_offset = primitives.elems[stops + 2u * i];
+        let stop_color = unpack4x8unorm(bitcast<u32>(primitives.elems[stops + 2u * i + 1u]));
+        if (t > prev_offset) {
+            let s = clamp((t - prev_offset) / max(stop_offset - prev_offset, 1e-6), 0., 1.);
+            color = mix(color, stop_color, s);
+        }
+        prev_offset = stop_offset;
+    }
+    return color;
+}
+
+/// Signed distance to the nearest dash of the dash pattern at the given offset,
+/// measured along the stroke. Negative inside a dash.
+fn sd_dash(offset: u32, p: vec2<f32>) -> f32 {
+    let start = primitives.elems[offset];
+    let origin = vec2<f32>(primitives.elems[offset + 1u], primitives.elems[offset + 2u]);
+    let dir = vec2<f32>(primitives.elems[offset + 3u], primitives.elems[offset + 4u]);
+    let radius = primitives.elems[offset + 5u];
+    let phase = primitives.elems[offset + 6u];
+    let count = bitcast<u32>(primitives.elems[offset + 7u]);
+    let lengths = offset + 8u;
+
+    // Arc length along the stroke, either straight or circular
+    let v = p - origin;
+    var s = dot(v, dir);
+    if (radius != 0.) {
+        s = radius * atan2(dir.x * v.y - dir.y * v.x, s);
+    }
+    s += start + phase;
+
+    var period = 0.;
+    for (var i = 0u; i < count; i += 1u) {
+        period += primitives.elems[lengths + i];
+    }
+    if (period <= 0.) {
+        return -1e9;
+    }
+    let t = s - floor(s / period) * period;
+
+    // Even lengths are dashes, odd ones gaps. Check the dashes of the previous and
+    // next periods too, for the distance across the period boundaries.
+    var dist = 1e9;
+    var dash_start = 0.;
+    for (var i = 0u; i < count; i += 2u) {
+        let dash_end = dash_start + primitives.elems[lengths + i];
+        dist = min(dist, max(dash_start - t, t - dash_end));
+        dist = min(dist, max(dash_start + period - t, t - dash_end - period));
+        dist = min(dist, max(dash_start - period - t, t - dash_end + period));
+        dash_start = dash_end + primitives.elems[lengths + i + 1u];
+    }
+    return dist;
+}
+
+/// Sample the texture of a textured primitive at position 'p', given the slot
+/// of its texture and the offset of its texture rows.
+fn sample_texture(slot: u32, off: u32, p: vec2<f32>) -> vec4<f32> {
+    let uv_transform = mat2x2<f32>(
+        primitives.elems[off + 0u],
+        primitives.elems[off + 1u],
+        primitives.elems[off + 2u],
+        primitives.elems[off + 3u],
+    );
+    let uv_offset = vec2<f32>(primitives.elems[off + 4u], primitives.elems[off + 5u]);
+    let repeat = bitcast<u32>(primitives.elems[off + 6u]);
+    let uv = uv_transform * p + uv_offset;
+    var wrapped_uv = uv;
+    switch repeat {
+        case IMAGE_REPEAT_CLAMP {
+            wrapped_uv = clamp(uv, vec2<f32>(0.), vec2<f32>(1.));
+        }
+        case IMAGE_REPEAT_REPEAT {
+            wrapped_uv = fract(uv);
+        }
+        case IMAGE_REPEAT_MIRROR {
+            wrapped_uv = 1. - abs(2. * fract(uv * 0.5) - 1.);
+        }
+        default {}
+    }
+    // Use the gradients of the unwrapped UVs to avoid mip seams at tile edges
+    let color = sample_slot_grad(slot, wrapped_uv, dpdx(uv), dpdy(uv));
+    if (repeat == IMAGE_REPEAT_ONCE && any(uv != clamp(uv, vec2<f32>(0.), vec2<f32>(1.)))) {
+        return vec4<f32>();
+    }
+    return color;
+}
+
 @fragment
 fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
     // Find the tile this fragment is part of
@@ -275,83 +1462,213 @@ fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
     let canvas_pos = in.position.xy;
     var color = vec4<f32>();
 
+    // Colors under the open layers, and their opacity
+    var layer_colors: array<vec4<f32>, MAX_LAYER_DEPTH>;
+    var layer_opacities: array<f32, MAX_LAYER_DEPTH>;
+    var layer_depth = 0u;
+
     // Loop over all primitives for that tile, and accumulate color
     let prim_offset = offsets_and_counts[tile_index].offset;
     let prim_count = offsets_and_counts[tile_index].count;
     for (var i = prim_offset; i < prim_offset + prim_count; i += 1u) {
         let prim_info = unpack_primitive_index(tiles.primitives[i]);
+
+        // Layer markers draw nothing; the primitives between them accumulate into
+        // their own color, which is blended at once with the layer opacity.
+        if (prim_info.kind == PRIM_LAYER) {
+            let is_end = bitcast<u32>(primitives.elems[prim_info.index]) != 0u;
+            if (!is_end) {
+                if (layer_depth < MAX_LAYER_DEPTH) {
+                    layer_colors[layer_depth] = color;
+                    layer_opacities[layer_depth] = primitives.elems[prim_info.index + 1u];
+                    color = vec4<f32>();
+                }
+                layer_depth += 1u;
+            } else if (layer_depth > 0u) {
+                layer_depth -= 1u;
+                if (layer_depth < MAX_LAYER_DEPTH) {
+                    color = composite_layer(layer_colors[layer_depth], color, layer_opacities[layer_depth]);
+                }
+            }
+            continue;
+        }
+
+        let color_under = color;
+        var dist = sd_primitive(prim_info, canvas_pos);
         var offset: u32;
-        var dist: f32;
-        var new_color: vec4<f32>;
-        var uv_origin: vec2<f32>;
-        var coverage: f32;
+        var color_base: vec4<f32>;
         switch prim_info.kind {
             case PRIM_RECT {
                 let rect = read_rect(prim_info.index);
-                uv_origin = rect.center;
-                dist = sd_rect(canvas_pos, rect);
-                coverage = aa_coverage(dist);
-                let alpha = rect.extras.color.a * coverage;
-                new_color = vec4<f32>(rect.extras.color.rgb, alpha);
-                offset = 6u + prim_info.index;
+                color_base = rect.extras.color;
+                offset = 13u + prim_info.index;
             }
             case PRIM_GLYPH {
                 let rect = read_rect(prim_info.index);
-                uv_origin = rect.center;
-                dist = sd_rect(canvas_pos, rect);
-                coverage = aa_coverage(dist);
-                let alpha = rect.extras.color.a * coverage;
-
-                let uv_x = primitives.elems[prim_info.index + 6u];
-                let uv_y = primitives.elems[prim_info.index + 7u];
-                let uv_sx = primitives.elems[prim_info.index + 8u];
-                let uv_sy = primitives.elems[prim_info.index + 9u];
+
+                let uv_x = primitives.elems[prim_info.index + 11u];
+                let uv_y = primitives.elems[prim_info.index + 12u];
+                let uv_sx = primitives.elems[prim_info.index + 13u];
+                let uv_sy = primitives.elems[prim_info.index + 14u];
                 let uv_origin0 = vec2<f32>(uv_x, uv_y);
                 let uv_scale = vec2<f32>(uv_sx, uv_sy);
-                let uv = (canvas_pos - rect.center) * uv_scale + uv_origin0;
-                let tex = textureSample(quad_texture, quad_sampler, uv);
+                let uv = unrotate(canvas_pos - rect.center, rect.rotation) * uv_scale + uv_origin0;
+                let tex = sample_slot(prim_info.texture, uv);
 
-                new_color = vec4<f32>(rect.extras.color.rgb, alpha * tex.a * rect.extras.color.a);
-                offset = 10u + prim_info.index;
+                color_base = vec4<f32>(rect.extras.color.rgb, tex.a * rect.extras.color.a * rect.extras.color.a);
+                offset = 15u + prim_info.index;
             }
             case PRIM_LINE {
                 let line = read_line(prim_info.index);
-                dist = sd_line(line.p0, line.p1, line.thickness, canvas_pos) - line.extras.radius;
-                coverage = aa_coverage(dist);
-                let alpha = line.extras.color.a * coverage;
-                new_color = vec4<f32>(line.extras.color.rgb, alpha);
-                uv_origin = (line.p0 + line.p1) / 2.;
-                offset = 6u + prim_info.index;
+                color_base = line.extras.color;
+                offset = 7u + prim_info.index;
+            }
+            case PRIM_QUARTER_PIE {
+                let qpie = read_qpie(prim_info.index);
+                color_base = qpie.extras.color;
+                offset = 7u + prim_info.index;
+            }
+            case PRIM_ELLIPSE {
+                let ellipse = read_ellipse(prim_info.index);
+                color_base = ellipse.extras.color;
+                offset = 9u + prim_info.index;
+            }
+            case PRIM_PATH {
+                let path = read_path(prim_info.index);
+                color_base = path.extras.color;
+                offset = path_end_offset(prim_info.index, path);
+            }
+            case PRIM_ARC {
+                let arc = read_arc(prim_info.index);
+                color_base = arc.extras.color;
+                offset = 12u + prim_info.index;
+            }
+            case PRIM_POLYLINE {
+                color_base = unpack4x8unorm(bitcast<u32>(primitives.elems[prim_info.index]));
+                offset = polyline_end_offset(prim_info.index);
+            }
+            case PRIM_POLYGON {
+                let polygon = read_polygon(prim_info.index);
+                color_base = polygon.extras.color;
+                offset = prim_info.index + 4u + 2u * polygon.vertex_count;
+            }
+            case PRIM_STAR {
+                let star = read_star(prim_info.index);
+                color_base = star.extras.color;
+                offset = 11u + prim_info.index;
+            }
+            case PRIM_CAPSULE {
+                let capsule = read_capsule(prim_info.index);
+                color_base = capsule.extras.color;
+                offset = 8u + prim_info.index;
+            }
+            case PRIM_ARROW {
+                color_base = unpack4x8unorm(bitcast<u32>(primitives.elems[prim_info.index + 4u]));
+                offset = 9u + prim_info.index;
             }
             default {}
         }
-
-        let new_alpha = mix(color.a, 1.0, new_color.a);
-        let rgb = mix(color.rgb, new_color.rgb, new_color.a);
-        color = vec4<f32>(rgb, new_alpha);
-            
+        // Optional rows trailing the primitive, in order: border, texture, effects
         var off = offset;
-        if (prim_info.textured) {
-            let uv_x = primitives.elems[off + 0u];
-            let uv_y = primitives.elems[off + 1u];
-            let uv_sx = primitives.elems[off + 2u];
-            let uv_sy = primitives.elems[off + 3u];
-            let uv_offset = vec2<f32>(uv_x, uv_y);
-            let uv_scale = vec2<f32>(uv_sx, uv_sy);
-            let uv = fma(canvas_pos - uv_origin, uv_scale, uv_offset);
-            let tex_color = textureSample(quad_texture, quad_sampler, uv).rgb;
-            color = vec4<f32>(tex_color * color.rgb, color.a);
-            off += 4u;
-        }
 
+        // The border spans from 'border_outset' outside the edge of the primitive to
+        // 'border_width - border_outset' inside it.
+        var border_width = 0.;
+        var border_outset = 0.;
+        var border_color = vec4<f32>();
         if (prim_info.bordered) {
-            let border_width = primitives.elems[off + 0u];
-            let bc = primitives.elems[off + 1u];
+            border_width = primitives.elems[off + 0u];
+            border_outset = primitives.elems[off + 1u];
+            let bc = primitives.elems[off + 2u];
             let ubc: u32 = bitcast<u32>(bc);
-            let border_color = unpack4x8unorm(ubc);
-            let dist2 = dist + border_width;
-            let alpha2 = aa_coverage(dist2);
-            color = vec4<f32>(mix(color.rgb, border_color.rgb, (1. - alpha2) * coverage), color.a);
+            border_color = unpack4x8unorm(ubc);
+            off += 3u;
+        }
+        let border_inset = border_width - border_outset;
+
+        let tex_offset = off;
+        if (prim_info.textured) {
+            off += 7u;
+        }
+
+        // Gradients replace the uniform colors of the primitive, and dashes cut it
+        // along its stroke.
+        let is_glyph = prim_info.kind == PRIM_GLYPH;
+        if (prim_info.effects) {
+            let flags = bitcast<u32>(primitives.elems[off]);
+            var gradient_off = gradient_offset(off, flags);
+            if ((flags & EFFECT_FLAG_GRADIENT) != 0u) {
+                if (!is_glyph) {
+                    color_base = eval_gradient(gradient_off, canvas_pos);
+                }
+                gradient_off += gradient_row_count(gradient_off);
+            }
+            if ((flags & EFFECT_FLAG_BORDER_GRADIENT) != 0u) {
+                border_color = eval_gradient(gradient_off, canvas_pos);
+                gradient_off += gradient_row_count(gradient_off);
+            }
+            if ((flags & EFFECT_FLAG_DASH) != 0u && !is_glyph) {
+                dist = max(dist, sd_dash(gradient_off, canvas_pos));
+            }
+        }
+
+        let coverage = aa_coverage(dist);
+        var new_color = vec4<f32>(color_base.rgb, color_base.a * coverage);
+        if (prim_info.textured) {
+            let tex_color = sample_texture(prim_info.texture, tex_offset, canvas_pos);
+            new_color = vec4<f32>(tex_color.rgb * new_color.rgb, tex_color.a * new_color.a);
+        }
+
+        if (prim_info.effects) {
+            color = draw_effects(color, off, prim_info, canvas_pos, dist);
+        }
+
+        // Glyph outlines expand outside the glyph, underneath it, so they don't eat
+        // into thin glyph strokes.
+        if (prim_info.bordered && is_glyph) {
+            let outline_dist = sd_glyph(prim_info.index, prim_info.texture, canvas_pos, border_outset + 1.);
+            let outline_alpha = aa_coverage(outline_dist - border_outset);
+            color = blend(color, vec4<f32>(border_color.rgb, border_color.a * outline_alpha));
+        }
+
+        color = blend(color, new_color);
+
+        if (prim_info.effects && prim_info.kind == PRIM_RECT) {
+            color = draw_inset_shadow(color, off, prim_info, canvas_pos, border_inset, coverage);
+        }
+
+        if (prim_info.bordered && !is_glyph) {
+            let outer = aa_coverage(dist - border_outset);
+            let inner = aa_coverage(dist + border_inset);
+            let border_alpha = border_color.a * max(outer - inner, 0.);
+            color = blend(color, vec4<f32>(border_color.rgb, border_alpha));
+        }
+
+        // The clip rectangle and masks cut everything drawn by the primitive,
+        // including its effects, and the opacity fades all of it, so scale its
+        // whole contribution.
+        if (prim_info.effects) {
+            let flags = bitcast<u32>(primitives.elems[off]);
+            var fade = 1.;
+            if ((flags & EFFECT_FLAG_CLIP) != 0u) {
+                fade = clip_coverage(clip_offset(off, flags), canvas_pos);
+            }
+            if ((flags & EFFECT_FLAG_MASK) != 0u) {
+                fade *= mask_coverage(mask_offset(off, flags), canvas_pos);
+            }
+            if ((flags & EFFECT_FLAG_OPACITY) != 0u) {
+                fade *= primitives.elems[opacity_offset(off, flags)];
+            }
+            color = mix(color_under, color, fade);
+        }
+    }
+
+    // Close the layers still open, whose other primitives are drawn by another
+    // batch if the layer uses more textures than a batch can bind
+    while (layer_depth > 0u) {
+        layer_depth -= 1u;
+        if (layer_depth < MAX_LAYER_DEPTH) {
+            color = composite_layer(layer_colors[layer_depth], color, layer_opacities[layer_depth]);
         }
     }
 
@@ -1,52 +1,370 @@
 //! Rendering context exposing convenience functions to draw into a [`Canvas`].
 
-use std::str;
+use std::{mem::MaybeUninit, str};
 
-//use bevy::math::Affine2;
-use bevy::math::{Rect, Vec2};
+use bevy::math::{Affine2, Rect, Vec2};
 use bevy::prelude::*;
 use bevy::sprite::Anchor;
 use bevy::text::TextLayoutInfo;
 
 use crate::{
-    canvas::{Canvas, LinePrimitive, RectPrimitive, TextPrimitive},
-    shapes::Shape,
+    canvas::{
+        intersect_clip, is_similarity, transform_scale, ArrowPrimitive, Canvas, ClipMasks, Dash,
+        Effects, LinePrimitive, PolylinePrimitive, RectPrimitive, TextPrimitive,
+        MAX_BATCH_TEXTURES, MAX_CLIP_MASKS, MAX_LAYER_DEPTH,
+    },
+    shapes::{ArrowHead, DashPattern, Path, Shape},
     ShapeRef,
 };
 
+/// Maximum number of color stops of a [`Gradient`]. Extra stops are ignored.
+pub const MAX_GRADIENT_STOPS: usize = 8;
+
+/// A color stop of a [`Gradient`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ColorStop {
+    /// Position of the stop along the gradient, generally in `[0:1]`.
+    pub offset: f32,
+    /// Color of the gradient at the stop position.
+    pub color: Color,
+}
+
+impl From<(f32, Color)> for ColorStop {
+    fn from((offset, color): (f32, Color)) -> Self {
+        Self { offset, color }
+    }
+}
+
+/// Geometry of a [`Gradient`], in canvas space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GradientKind {
+    /// Gradient along the line from `start` (offset 0) to `end` (offset 1).
+    Linear {
+        /// Position of offset 0.
+        start: Vec2,
+        /// Position of offset 1.
+        end: Vec2,
+    },
+    /// Gradient along concentric circles, from `center` (offset 0) to the
+    /// circle of the given `radius` (offset 1).
+    Radial {
+        /// Center of the circles.
+        center: Vec2,
+        /// Radius of the circle at offset 1.
+        radius: f32,
+    },
+    /// Gradient sweeping around `center`, starting at `start_angle` (offset 0)
+    /// and completing a full turn (offset 1). Angles are in radians, and go
+    /// from the X axis toward the Y axis.
+    Conic {
+        /// Center of the rotation.
+        center: Vec2,
+        /// Angle of offset 0, in radians.
+        start_angle: f32,
+    },
+}
+
+/// Color gradient, interpolating between color stops.
+///
+/// Colors are interpolated in linear space. Before the first stop and after
+/// the last one, the gradient takes the color of the nearest stop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gradient {
+    /// Geometry of the gradient.
+    kind: GradientKind,
+    /// Color stops, sorted by offset. Only the first `stop_count` are valid.
+    stops: [ColorStop; MAX_GRADIENT_STOPS],
+    /// Number of valid stops in `stops`.
+    stop_count: usize,
+}
+
+impl Gradient {
+    /// Number of primitive buffer rows (4 bytes) for the gradient header.
+    const ROW_COUNT_HEADER: u32 = 5;
+    /// Number of primitive buffer rows (4 bytes) per color stop.
+    const ROW_COUNT_STOP: u32 = 2;
+
+    /// Create a new gradient from its geometry and color stops.
+    ///
+    /// The stops are sorted by offset. Only the first [`MAX_GRADIENT_STOPS`]
+    /// stops are retained.
+    pub fn new(kind: GradientKind, stops: impl IntoIterator<Item = impl Into<ColorStop>>) -> Self {
+        let mut gradient = Self {
+            kind,
+            stops: [ColorStop::default(); MAX_GRADIENT_STOPS],
+            stop_count: 0,
+        };
+        for stop in stops.into_iter().take(MAX_GRADIENT_STOPS) {
+            gradient.stops[gradient.stop_count] = stop.into();
+            gradient.stop_count += 1;
+        }
+        gradient.stops[..gradient.stop_count].sort_by(|a, b| a.offset.total_cmp(&b.offset));
+        gradient
+    }
+
+    /// Geometry of the gradient.
+    pub fn kind(&self) -> GradientKind {
+        self.kind
+    }
+
+    /// Color stops of the gradient, sorted by offset.
+    pub fn stops(&self) -> &[ColorStop] {
+        &self.stops[..self.stop_count]
+    }
+
+    /// Number of primitive buffer rows (4 bytes) needed to store the gradient.
+    pub(crate) fn row_count(&self) -> u32 {
+        Self::ROW_COUNT_HEADER + self.stop_count as u32 * Self::ROW_COUNT_STOP
+    }
+
+    /// Transform the gradient geometry along with the primitive it fills.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        self.kind = match self.kind {
+            GradientKind::Linear { start, end } => GradientKind::Linear {
+                start: transform.transform_point2(start),
+                end: transform.transform_point2(end),
+            },
+            GradientKind::Radial { center, radius } => GradientKind::Radial {
+                center: transform.transform_point2(center),
+                radius: radius * transform_scale(transform),
+            },
+            GradientKind::Conic {
+                center,
+                start_angle,
+            } => GradientKind::Conic {
+                center: transform.transform_point2(center),
+                start_angle: transform
+                    .transform_vector2(Vec2::from_angle(start_angle))
+                    .to_angle(),
+            },
+        };
+    }
+
+    /// Write the gradient rows. This must be kept in sync with the primitive
+    /// shader.
+    pub(crate) fn write(
+        &self,
+        prim: &mut [MaybeUninit<f32>],
+        canvas_translation: Vec2,
+        scale_factor: f32,
+    ) {
+        let to_gpu = |p: Vec2| (p + canvas_translation) * scale_factor;
+        let (kind, a, b) = match self.kind {
+            GradientKind::Linear { start, end } => (0u32, to_gpu(start), to_gpu(end)),
+            GradientKind::Radial { center, radius } => (
+                1u32,
+                to_gpu(center),
+                Vec2::new(radius.max(0.) * scale_factor, 0.),
+            ),
+            GradientKind::Conic {
+                center,
+                start_angle,
+            } => (2u32, to_gpu(center), Vec2::new(start_angle, 0.)),
+        };
+        prim[0].write(bytemuck::cast(kind | (self.stop_count as u32) << 8));
+        prim[1].write(a.x);
+        prim[2].write(a.y);
+        prim[3].write(b.x);
+        prim[4].write(b.y);
+        for (i, stop) in self.stops().iter().enumerate() {
+            let idx = (Self::ROW_COUNT_HEADER + i as u32 * Self::ROW_COUNT_STOP) as usize;
+            prim[idx].write(stop.offset);
+            prim[idx + 1].write(bytemuck::cast(stop.color.to_linear().as_u32()));
+        }
+    }
+}
+
+/// Repeat mode of an [`ImagePattern`], defining how the pattern extends
+/// outside of its image.
+///
+/// # Note
+///
+/// The enum values must be kept in sync with the values inside the primitive
+/// shader.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ImageRepeat {
+    /// Draw the image once. The shape is transparent outside of the image.
+    #[default]
+    Once = 0,
+    /// Extend the edge pixels of the image outside of it.
+    Clamp = 1,
+    /// Tile the image indefinitely.
+    Repeat = 2,
+    /// Tile the image indefinitely, mirroring every other tile such that
+    /// adjacent tiles join seamlessly.
+    Mirror = 3,
+}
+
+/// Image filling a shape, in place of a uniform color.
+///
+/// The image is defined in its own pattern space, where it covers the
+/// rectangle from the origin to its size in pixels. The pattern transform maps
+/// that pattern space into canvas space, allowing to position, scale, and
+/// rotate the image independently of the shape it fills.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImagePattern {
+    /// Image to sample.
+    image: AssetId<Image>,
+    /// Transform from pattern space to canvas space.
+    transform: Affine2,
+    /// Repeat mode outside of the image.
+    repeat: ImageRepeat,
+    /// Image size in pixels, populated from actual texture size.
+    pub(crate) image_size: Vec2,
+}
+
+impl ImagePattern {
+    /// Create a new image pattern.
+    ///
+    /// The `transform` maps the pattern space, where the image covers the
+    /// rectangle from the origin to its size in pixels, into canvas space.
+    pub fn new(image: Handle<Image>, transform: Affine2, repeat: ImageRepeat) -> Self {
+        Self {
+            image: image.id(),
+            transform,
+            repeat,
+            image_size: Vec2::ZERO,
+        }
+    }
+
+    /// Image sampled by the pattern.
+    pub fn image(&self) -> AssetId<Image> {
+        self.image
+    }
+
+    /// Transform from pattern space to canvas space.
+    pub fn transform(&self) -> Affine2 {
+        self.transform
+    }
+
+    /// Repeat mode outside of the image.
+    pub fn repeat(&self) -> ImageRepeat {
+        self.repeat
+    }
+
+    /// Transform the pattern along with the primitive it fills.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        self.transform = *transform * self.transform;
+    }
+
+    /// Transform from the shader's pixel coordinates to the texture UV
+    /// coordinates.
+    pub(crate) fn uv_transform(&self, canvas_translation: Vec2, scale_factor: f32) -> Affine2 {
+        let to_canvas = Affine2::from_scale(Vec2::splat(1. / scale_factor))
+            * Affine2::from_translation(-canvas_translation * scale_factor);
+        Affine2::from_scale(1. / self.image_size.max(Vec2::ONE))
+            * self.transform.inverse()
+            * to_canvas
+    }
+}
+
 /// Abstraction of a brush to draw shapes.
 ///
-/// Currently only support solid colors (no pattern or gradient yet).
+/// A brush is either a solid color, a [`Gradient`], or an [`ImagePattern`].
 #[derive(Debug, Clone)]
 pub struct Brush {
     color: Color,
+    gradient: Option<Gradient>,
+    pattern: Option<ImagePattern>,
 }
 
 impl Default for Brush {
     fn default() -> Self {
         Self {
             color: Color::BLACK,
+            gradient: None,
+            pattern: None,
         }
     }
 }
 
 impl From<Color> for Brush {
     fn from(color: Color) -> Self {
-        Self { color }
+        Self {
+            color,
+            gradient: None,
+            pattern: None,
+        }
     }
 }
 
 impl From<&Color> for Brush {
     fn from(color: &Color) -> Self {
-        Self { color: *color }
+        (*color).into()
+    }
+}
+
+impl From<Gradient> for Brush {
+    fn from(gradient: Gradient) -> Self {
+        Self {
+            color: gradient
+                .stops()
+                .first()
+                .map(|stop| stop.color)
+                .unwrap_or(Color::NONE),
+            gradient: Some(gradient),
+            pattern: None,
+        }
+    }
+}
+
+impl From<ImagePattern> for Brush {
+    fn from(pattern: ImagePattern) -> Self {
+        Self {
+            color: Color::WHITE,
+            gradient: None,
+            pattern: Some(pattern),
+        }
     }
 }
 
 impl Brush {
+    /// Create a brush filling shapes with an image.
+    ///
+    /// The `transform` maps the image, covering the rectangle from the origin
+    /// to its size in pixels, into canvas space. See [`ImagePattern`] for
+    /// details.
+    pub fn image(image: Handle<Image>, transform: Affine2, repeat: ImageRepeat) -> Self {
+        ImagePattern::new(image, transform, repeat).into()
+    }
+
     /// Get the brush color.
+    ///
+    /// For gradients, this is the color of the first stop, and for image
+    /// patterns this is white. This color is used where gradients and patterns
+    /// are not supported, like for glows.
     pub fn color(&self) -> Color {
         self.color.clone()
     }
+
+    /// Get the brush gradient, if any.
+    pub fn gradient(&self) -> Option<&Gradient> {
+        self.gradient.as_ref()
+    }
+
+    /// Get the brush image pattern, if any.
+    pub fn pattern(&self) -> Option<&ImagePattern> {
+        self.pattern.as_ref()
+    }
+
+    /// Effects painting a primitive with this brush.
+    ///
+    /// The gradient and image pattern, if any, are stored into the canvas.
+    pub(crate) fn paint(&self, canvas: &mut Canvas) -> Effects {
+        Effects {
+            gradient: self.gradient.map(|gradient| canvas.add_gradient(gradient)),
+            pattern: self.pattern.map(|pattern| canvas.add_pattern(pattern)),
+            ..default()
+        }
+    }
+
+    /// Store the effects painting a primitive with this brush into the canvas,
+    /// and return their ID, if any.
+    pub(crate) fn effects(&self, canvas: &mut Canvas) -> Option<u32> {
+        let effects = self.paint(canvas);
+        canvas.add_effects(effects)
+    }
 }
 
 // impl<'c> IntoBrush<RenderContext<'c>> for Brush {
@@ -94,7 +412,7 @@ pub struct TextLayout {
     pub(crate) anchor: Anchor,
     /// Text justifying. This only affects multiline text.
     pub(crate) justify: JustifyText,
-    /// Text bounds, used for glyph clipping.
+    /// Text bounds, used for line wrapping.
     pub(crate) bounds: Vec2,
     /// Calculated text size based on glyphs alone, updated by
     /// [`process_glyphs()`].
@@ -161,11 +479,10 @@ impl<'c> TextLayoutBuilder<'c> {
 
     /// Set some bounds around the text.
     ///
-    /// The text will be formatted with line wrapping and clipped to fit in
-    /// those bounds.
-    ///
-    /// FIXME - Currently no clipping for partially visible glyphs, only
-    /// completely outside ones are clipped.
+    /// The text will be formatted with line wrapping to fit in those bounds.
+    /// Glyphs completely outside the bounds are dropped, but partially visible
+    /// ones are not cut; to do so, draw the text inside a clip rectangle with
+    /// [`RenderContext::push_clip_rect()`].
     pub fn bounds(mut self, bounds: Vec2) -> Self {
         self.bounds = bounds;
         self
@@ -271,9 +588,157 @@ impl Default for ImageScaling {
 }
 
 /// Rendering context providing a higher level API to draw on a [`Canvas`].
+///
+/// # Transforms
+///
+/// The render context maintains a current transform, applied to everything
+/// drawn through it, and a stack of saved transforms. This allows drawing in
+/// local coordinates, for example for reusable widgets:
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(RED.into());
+/// # let outline = ctx.solid_brush(BLACK.into());
+/// ctx.push_translation(Vec2::new(100., 50.));
+/// ctx.push_rotation(std::f32::consts::FRAC_PI_4);
+/// // Drawn centered on (100, 50), rotated by 45 degrees
+/// ctx.fill(RoundedRect::new(Rect::new(-20., -10., 20., 10.), 4.), &brush)
+///     .border(&outline, 1.);
+/// ctx.pop_transform();
+/// ctx.pop_transform();
+/// ```
+///
+/// Transforms are applied to primitives once they're complete, that is when
+/// the next operation is made on the render context or when it's dropped, so
+/// any modification made through the returned [`ShapeRef`], like adding a
+/// border or a glow, is transformed too.
+///
+/// Positions are transformed exactly. Paths, polylines, polygons, and the
+/// sides of lines follow any affine transform, while other shapes are only
+/// exact for similarity transforms (translation, rotation, and uniform scale).
+/// Under a non-uniform scale or a skew, their corner radii, stroke and border
+/// widths, glow spread, shadow blur, and text size are all scaled by the mean
+/// scale of the transform, so a 2×1 scale makes a rectangle border about 1.41×
+/// thicker on all sides; [`push_transform()`] logs a warning the first time
+/// it gets such a transform. Text is rotated and scaled by resampling its
+/// glyphs, which are rasterized at their font size, so scaled up text looks
+/// blurry. A reflection, like a negative scale on one axis, mirrors all shapes,
+/// including images and text.
+///
+/// # Clipping
+///
+/// The render context also maintains a stack of clip rectangles. Everything
+/// drawn while a clip rectangle is active is cut by that rectangle, and nested
+/// clip rectangles intersect each other:
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(RED.into());
+/// # let scroll_offset = 0.;
+/// let viewport = Rect::new(0., 0., 200., 100.);
+/// ctx.push_clip_rect(viewport);
+/// // Only the part of the content inside the viewport is visible
+/// ctx.fill(Rect::new(10., scroll_offset, 190., scroll_offset + 400.), &brush);
+/// ctx.pop_clip();
+/// ```
+///
+/// Any [`Shape`] can also clip with [`push_clip_shape()`], which cuts with
+/// anti-aliased edges, for example to draw a circular minimap.
+///
+/// # Drawing state
+///
+/// The transform, the clip, the global alpha, and the default brush form the
+/// drawing state of the render context. [`save()`] pushes a copy of that state
+/// onto a stack, and [`restore()`] pops it back, undoing any change made in
+/// between, including unbalanced pushes of transforms and clips. This allows
+/// widget functions to freely change the state without leaking it to their
+/// caller:
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// fn draw_badge(ctx: &mut RenderContext, pos: Vec2) {
+///     ctx.save();
+///     ctx.push_translation(pos);
+///     ctx.set_global_alpha(0.8);
+///     ctx.set_default_brush(Color::from(RED));
+///     ctx.fill_default(RoundedRect::new(Rect::new(0., 0., 24., 16.), 8.));
+///     ctx.restore();
+/// }
+/// ```
+///
+/// # Layers
+///
+/// The global alpha fades each primitive separately, so overlapping primitives
+/// show through each other. To fade a group of primitives as a whole, draw it
+/// inside a layer with [`push_layer()`] and [`pop_layer()`]:
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let panel = ctx.solid_brush(GRAY.into());
+/// # let button = ctx.solid_brush(BLUE.into());
+/// # let fade = 0.5;
+/// ctx.push_layer(fade);
+/// ctx.fill(RoundedRect::new(Rect::new(0., 0., 200., 100.), 8.), &panel);
+/// // The panel doesn't show through the button
+/// ctx.fill(RoundedRect::new(Rect::new(20., 60., 100., 90.), 4.), &button);
+/// ctx.pop_layer();
+/// ```
+///
+/// [`push_transform()`]: RenderContext::push_transform
+/// [`push_clip_shape()`]: RenderContext::push_clip_shape
+/// [`save()`]: RenderContext::save
+/// [`restore()`]: RenderContext::restore
+/// [`push_layer()`]: RenderContext::push_layer
+/// [`pop_layer()`]: RenderContext::pop_layer
 pub struct RenderContext<'c> {
     /// Transform applied to all operations on this render context.
-    //transform: Affine2,
+    transform: Affine2,
+    /// Transforms saved by [`push_transform()`], restored by
+    /// [`pop_transform()`].
+    ///
+    /// [`push_transform()`]: RenderContext::push_transform
+    /// [`pop_transform()`]: RenderContext::pop_transform
+    transform_stack: Vec<Affine2>,
+    /// Current clip rectangle in canvas space, if any.
+    clip: Option<Rect>,
+    /// Current clip masks, if any.
+    masks: ClipMasks,
+    /// Clip rectangles and masks saved by [`push_clip_rect()`] and
+    /// [`push_clip_shape()`], restored by [`pop_clip()`].
+    ///
+    /// [`push_clip_rect()`]: RenderContext::push_clip_rect
+    /// [`push_clip_shape()`]: RenderContext::push_clip_shape
+    /// [`pop_clip()`]: RenderContext::pop_clip
+    clip_stack: Vec<(Option<Rect>, ClipMasks)>,
+    /// Opacity applied to all operations on this render context.
+    global_alpha: f32,
+    /// Brush used by [`fill_default()`].
+    ///
+    /// [`fill_default()`]: RenderContext::fill_default
+    default_brush: Brush,
+    /// Drawing states saved by [`save()`], restored by [`restore()`].
+    ///
+    /// [`save()`]: RenderContext::save
+    /// [`restore()`]: RenderContext::restore
+    saved_states: Vec<SavedState>,
+    /// Layers opened by [`push_layer()`], closed by [`pop_layer()`].
+    ///
+    /// [`push_layer()`]: RenderContext::push_layer
+    /// [`pop_layer()`]: RenderContext::pop_layer
+    layers: Vec<Layer>,
+    /// Index of the first primitive of the canvas not transformed and clipped
+    /// yet.
+    first_pending: usize,
     /// Underlying canvas render operations are directed to.
     canvas: &'c mut Canvas,
 }
@@ -282,34 +747,466 @@ impl<'c> RenderContext<'c> {
     /// Create a new render context to draw on an existing canvas.
     pub fn new(canvas: &'c mut Canvas) -> Self {
         Self {
-            //transform: Affine2::IDENTITY, // FIXME - unused
+            transform: Affine2::IDENTITY,
+            transform_stack: vec![],
+            clip: None,
+            masks: default(),
+            clip_stack: vec![],
+            global_alpha: 1.,
+            default_brush: default(),
+            saved_states: vec![],
+            layers: vec![],
+            first_pending: canvas.buffer().len(),
             canvas,
         }
     }
 
+    /// Current transform applied to everything drawn through this context.
+    pub fn transform(&self) -> Affine2 {
+        self.transform
+    }
+
+    /// Save the current transform and compose it with a new one.
+    ///
+    /// The `transform` maps the local space of the shapes drawn afterward into
+    /// the space of the current transform. The previous transform is restored
+    /// by the matching [`pop_transform()`].
+    ///
+    /// Transforms other than similarities (translation, rotation, and uniform
+    /// scale) are only followed exactly by some shapes, and log a warning once;
+    /// see [`RenderContext`].
+    ///
+    /// [`pop_transform()`]: RenderContext::pop_transform
+    pub fn push_transform(&mut self, transform: Affine2) {
+        self.flush();
+        self.transform_stack.push(self.transform);
+        self.transform *= transform;
+        if !is_similarity(&transform) {
+            warn_once!("Non-uniform scale or skew pushed as transform; only paths, polylines, polygons, and the sides of lines follow it exactly, other widths and radii use the mean scale.");
+        }
+    }
+
+    /// Save the current transform and translate it.
+    ///
+    /// This is a shortcut for [`push_transform()`] with a translation.
+    ///
+    /// [`push_transform()`]: RenderContext::push_transform
+    pub fn push_translation(&mut self, translation: Vec2) {
+        self.push_transform(Affine2::from_translation(translation));
+    }
+
+    /// Save the current transform and rotate it by an angle in radians, from
+    /// the X axis toward the Y axis.
+    ///
+    /// This is a shortcut for [`push_transform()`] with a rotation.
+    ///
+    /// [`push_transform()`]: RenderContext::push_transform
+    pub fn push_rotation(&mut self, angle: f32) {
+        self.push_transform(Affine2::from_angle(angle));
+    }
+
+    /// Save the current transform and scale it.
+    ///
+    /// This is a shortcut for [`push_transform()`] with a scale. Non-uniform
+    /// scales are only supported exactly by some shapes; see [`RenderContext`].
+    ///
+    /// [`push_transform()`]: RenderContext::push_transform
+    pub fn push_scale(&mut self, scale: Vec2) {
+        self.push_transform(Affine2::from_scale(scale));
+    }
+
+    /// Restore the transform saved by the last [`push_transform()`] or one of
+    /// its shortcuts.
+    ///
+    /// [`push_transform()`]: RenderContext::push_transform
+    pub fn pop_transform(&mut self) {
+        self.flush();
+        if let Some(transform) = self.transform_stack.pop() {
+            self.transform = transform;
+        } else {
+            warn!("pop_transform() called without a matching push_transform(); ignored.");
+        }
+    }
+
+    /// Current clip rectangle in canvas space, if any.
+    pub fn clip(&self) -> Option<Rect> {
+        self.clip
+    }
+
+    /// Save the current clip rectangle and intersect it with a new one.
+    ///
+    /// Everything drawn afterward, including effects like glows and shadows, is
+    /// cut by the clip rectangle until the matching [`pop_clip()`]. Tiles
+    /// outside the clip rectangle are skipped entirely.
+    ///
+    /// The `rect` is transformed by the current transform. Clip rectangles are
+    /// always axis-aligned in canvas space, so a rotated `rect` clips to its
+    /// bounding box.
+    ///
+    /// [`pop_clip()`]: RenderContext::pop_clip
+    pub fn push_clip_rect(&mut self, rect: Rect) {
+        self.flush();
+        self.clip_stack.push((self.clip, self.masks));
+        let corners = [
+            rect.min,
+            Vec2::new(rect.max.x, rect.min.y),
+            rect.max,
+            Vec2::new(rect.min.x, rect.max.y),
+        ]
+        .map(|p| self.transform.transform_point2(p));
+        let rect = corners
+            .iter()
+            .fold(Rect::from_corners(corners[0], corners[0]), |r, p| {
+                r.union_point(*p)
+            });
+        self.intersect_clip(rect);
+    }
+
+    /// Save the current clip and intersect it with an arbitrary shape.
+    ///
+    /// Everything drawn afterward has its coverage multiplied by the coverage
+    /// of the filled `shape`, which gives anti-aliased clipping edges, until the
+    /// matching [`pop_clip()`]. The shape is transformed by the current
+    /// transform. Tiles outside the bounding box of the shape are skipped
+    /// entirely.
+    ///
+    /// The shape must fill as a single primitive. Shapes which fill as several
+    /// primitives only use the first one as a mask, and log a warning.
+    ///
+    /// At most [`MAX_CLIP_MASKS`] clip shapes can be nested; extra ones are
+    /// ignored.
+    ///
+    /// ```no_run
+    /// # use bevy_keith::*;
+    /// # use bevy::{prelude::*, color::palettes::css::*};
+    /// # let mut canvas = Canvas::default();
+    /// # let mut ctx = RenderContext::new(&mut canvas);
+    /// # let brush = ctx.solid_brush(RED.into());
+    /// ctx.push_clip_shape(shapes::Ellipse::circle(Vec2::new(100., 100.), 64.));
+    /// // Only the part of the map inside the circle is visible
+    /// ctx.fill(Rect::new(0., 0., 200., 200.), &brush);
+    /// ctx.pop_clip();
+    /// ```
+    ///
+    /// [`pop_clip()`]: RenderContext::pop_clip
+    pub fn push_clip_shape(&mut self, shape: impl Shape) {
+        self.flush();
+        self.clip_stack.push((self.clip, self.masks));
+        let first = self.canvas.buffer().len();
+        shape.fill(self.canvas, &Brush::default());
+        if self.transform != Affine2::IDENTITY {
+            self.canvas.transform_primitives(first, &self.transform);
+        }
+        let Some(id) = self.canvas.make_mask(first) else {
+            // Nothing is visible inside an empty shape
+            self.intersect_clip(Rect {
+                min: Vec2::INFINITY,
+                max: Vec2::NEG_INFINITY,
+            });
+            return;
+        };
+        let aabb = self.canvas.masks()[id as usize].aabb();
+        self.intersect_clip(Rect {
+            min: aabb.min,
+            max: aabb.max,
+        });
+        if !self.masks.push(id) {
+            warn!("Too many nested clip masks; only {MAX_CLIP_MASKS} are supported.");
+        }
+    }
+
+    /// Restore the clip saved by the last [`push_clip_rect()`] or
+    /// [`push_clip_shape()`].
+    ///
+    /// [`push_clip_rect()`]: RenderContext::push_clip_rect
+    /// [`push_clip_shape()`]: RenderContext::push_clip_shape
+    pub fn pop_clip(&mut self) {
+        self.flush();
+        if let Some((clip, masks)) = self.clip_stack.pop() {
+            self.clip = clip;
+            self.masks = masks;
+        } else {
+            warn!("pop_clip() called without a matching push_clip_*(); ignored.");
+        }
+    }
+
+    /// Current global alpha, multiplying the opacity of everything drawn
+    /// through this context.
+    pub fn global_alpha(&self) -> f32 {
+        self.global_alpha
+    }
+
+    /// Set the global alpha, from 0 (transparent) to 1 (opaque).
+    ///
+    /// The global alpha multiplies the opacity of everything drawn afterward,
+    /// including borders and effects. Each primitive is faded as a whole, so a
+    /// primitive's own glow or shadow doesn't show through it.
+    pub fn set_global_alpha(&mut self, alpha: f32) {
+        self.flush();
+        self.global_alpha = alpha.clamp(0., 1.);
+    }
+
+    /// Current default brush, used by [`fill_default()`].
+    ///
+    /// [`fill_default()`]: RenderContext::fill_default
+    pub fn default_brush(&self) -> &Brush {
+        &self.default_brush
+    }
+
+    /// Set the default brush, used by [`fill_default()`].
+    ///
+    /// [`fill_default()`]: RenderContext::fill_default
+    pub fn set_default_brush(&mut self, brush: impl Into<Brush>) {
+        self.default_brush = brush.into();
+    }
+
+    /// Save the current drawing state onto the state stack.
+    ///
+    /// The drawing state is made of the transform, the clip, the global alpha,
+    /// and the default brush. It's restored by the matching [`restore()`].
+    ///
+    /// [`restore()`]: RenderContext::restore
+    pub fn save(&mut self) {
+        self.saved_states.push(SavedState {
+            transform: self.transform,
+            transform_stack: self.transform_stack.clone(),
+            clip: self.clip,
+            masks: self.masks,
+            clip_stack: self.clip_stack.clone(),
+            global_alpha: self.global_alpha,
+            default_brush: self.default_brush.clone(),
+        });
+    }
+
+    /// Restore the drawing state saved by the last [`save()`].
+    ///
+    /// The transform and clip stacks are restored as a whole, so any transform
+    /// or clip pushed since then and not popped yet is discarded, and any
+    /// popped since then is pushed back.
+    ///
+    /// [`save()`]: RenderContext::save
+    pub fn restore(&mut self) {
+        self.flush();
+        let Some(state) = self.saved_states.pop() else {
+            warn!("restore() called without a matching save(); ignored.");
+            return;
+        };
+        self.transform = state.transform;
+        self.transform_stack = state.transform_stack;
+        self.clip = state.clip;
+        self.masks = state.masks;
+        self.clip_stack = state.clip_stack;
+        self.global_alpha = state.global_alpha;
+        self.default_brush = state.default_brush;
+    }
+
+    /// Open a layer, compositing everything drawn until the matching
+    /// [`pop_layer()`] as a single unit with the given opacity, from 0
+    /// (transparent) to 1 (opaque).
+    ///
+    /// The primitives of the layer are blended together first, and only the
+    /// result is faded, so overlapping primitives don't show through each
+    /// other. The transform, clip, and global alpha still apply to each
+    /// primitive as usual. Layers are not part of the drawing state, so they're
+    /// not affected by [`save()`] and [`restore()`].
+    ///
+    /// At most [`MAX_LAYER_DEPTH`] layers can be nested; deeper layers only
+    /// multiply the global alpha instead.
+    ///
+    /// All the primitives of a layer are drawn in a single batch, which can
+    /// sample from at most [`MAX_BATCH_TEXTURES`] textures, so a layer can mix
+    /// that many different images and text, counting all the text as one font
+    /// atlas texture. For example a panel with an icon and a label fades as a
+    /// whole.
+    ///
+    /// A layer using more than [`MAX_BATCH_TEXTURES`] textures is not
+    /// supported. It's removed when closed, with a warning logged once, and its
+    /// opacity is applied to each of its primitives instead, so overlapping
+    /// children show through each other as if there were no layer. Likewise,
+    /// if the text of a layer spans more font atlas textures than fit in a
+    /// batch, which is only known when rendering, the layer is composited
+    /// separately for each batch.
+    ///
+    /// [`pop_layer()`]: RenderContext::pop_layer
+    /// [`save()`]: RenderContext::save
+    /// [`restore()`]: RenderContext::restore
+    pub fn push_layer(&mut self, opacity: f32) {
+        self.flush();
+        let opacity = opacity.clamp(0., 1.);
+        let begin = if self.layers.iter().filter(|l| l.begin.is_some()).count() < MAX_LAYER_DEPTH {
+            let begin = self.canvas.begin_layer(opacity);
+            self.first_pending = self.canvas.buffer().len();
+            Some(begin)
+        } else {
+            warn!("Too many nested layers; only {MAX_LAYER_DEPTH} are supported.");
+            None
+        };
+        self.layers.push(Layer {
+            begin,
+            opacity,
+            global_alpha: self.global_alpha,
+        });
+        if begin.is_none() {
+            self.global_alpha *= opacity;
+        }
+    }
+
+    /// Close the layer opened by the last [`push_layer()`].
+    ///
+    /// [`push_layer()`]: RenderContext::push_layer
+    pub fn pop_layer(&mut self) {
+        self.flush();
+        let Some(layer) = self.layers.pop() else {
+            warn!("pop_layer() called without a matching push_layer(); ignored.");
+            return;
+        };
+        if let Some(begin) = layer.begin {
+            if !self.canvas.end_layer(begin) {
+                warn_once!("Layer using more than {MAX_BATCH_TEXTURES} textures; its opacity is applied to each primitive instead.");
+            }
+            self.first_pending = self.canvas.buffer().len();
+        } else {
+            self.global_alpha = layer.global_alpha;
+        }
+    }
+
+    /// Intersect the current clip rectangle with another one in canvas space.
+    fn intersect_clip(&mut self, rect: Rect) {
+        self.clip = Some(self.clip.map_or(rect, |clip| intersect_clip(clip, rect)));
+    }
+
+    /// Apply the current transform, clip, and global alpha to the primitives
+    /// drawn since the last call, which are complete by now.
+    fn flush(&mut self) {
+        if self.transform != Affine2::IDENTITY {
+            self.canvas
+                .transform_primitives(self.first_pending, &self.transform);
+        }
+        if self.clip.is_some() || !self.masks.is_empty() {
+            self.canvas
+                .clip_primitives(self.first_pending, self.clip, &self.masks);
+        }
+        if self.global_alpha < 1. {
+            self.canvas
+                .fade_primitives(self.first_pending, self.global_alpha);
+        }
+        self.first_pending = self.canvas.buffer().len();
+    }
+
     /// Create a solid-color brush.
     pub fn solid_brush(&mut self, color: Color) -> Brush {
         color.into()
     }
 
+    /// Create a linear gradient brush.
+    ///
+    /// The gradient goes from `start` (offset 0) to `end` (offset 1), in canvas
+    /// space, and is constant along lines perpendicular to that direction.
+    ///
+    /// ```no_run
+    /// # use bevy_keith::*;
+    /// # use bevy::{prelude::*, color::palettes::css::*};
+    /// # let mut canvas = Canvas::default();
+    /// # let mut ctx = RenderContext::new(&mut canvas);
+    /// let rect = Rect::new(0., 0., 200., 40.);
+    /// let brush = ctx.linear_gradient(
+    ///     rect.min,
+    ///     Vec2::new(rect.max.x, rect.min.y),
+    ///     [(0., RED.into()), (0.5, YELLOW.into()), (1., GREEN.into())],
+    /// );
+    /// ctx.fill(RoundedRect::new(rect, 8.), &brush);
+    /// ```
+    pub fn linear_gradient(
+        &mut self,
+        start: Vec2,
+        end: Vec2,
+        stops: impl IntoIterator<Item = impl Into<ColorStop>>,
+    ) -> Brush {
+        Gradient::new(GradientKind::Linear { start, end }, stops).into()
+    }
+
+    /// Create a radial gradient brush.
+    ///
+    /// The gradient goes from `center` (offset 0) to the circle of the given
+    /// `radius` (offset 1), in canvas space.
+    pub fn radial_gradient(
+        &mut self,
+        center: Vec2,
+        radius: f32,
+        stops: impl IntoIterator<Item = impl Into<ColorStop>>,
+    ) -> Brush {
+        Gradient::new(GradientKind::Radial { center, radius }, stops).into()
+    }
+
+    /// Create a conic gradient brush.
+    ///
+    /// The gradient sweeps around `center`, from `start_angle` (offset 0) and
+    /// for a full turn (offset 1). Angles are in radians, and go from the X
+    /// axis toward the Y axis.
+    pub fn conic_gradient(
+        &mut self,
+        center: Vec2,
+        start_angle: f32,
+        stops: impl IntoIterator<Item = impl Into<ColorStop>>,
+    ) -> Brush {
+        Gradient::new(
+            GradientKind::Conic {
+                center,
+                start_angle,
+            },
+            stops,
+        )
+        .into()
+    }
+
     /// Clear an area of the render context with a specific color.
     ///
+    /// The `region` is transformed and clipped like any other shape. Without a
+    /// region, the entire canvas is cleared regardless of the current transform
+    /// and clip. The drawing state is kept, including any clip shape, so
+    /// primitives drawn afterward are still clipped by it. Layers still open
+    /// are reopened, so primitives drawn afterward are still grouped.
+    ///
     /// To clear the entire underlying canvas, prefer using [`Canvas::clear()`].
     pub fn clear(&mut self, region: Option<Rect>, color: Color) {
         if let Some(rect) = region {
             // TODO - delete primitives covered by region
-            self.fill(rect, &Brush { color });
+            self.fill(rect, &color.into());
         } else {
-            self.canvas.clear();
-            self.fill(self.canvas.rect(), &Brush { color });
+            self.canvas.clear_keep_masks();
+            let rect = self.canvas.rect();
+            self.canvas.draw(RectPrimitive {
+                rect,
+                color,
+                ..default()
+            });
+            // Reopen the layers still open, since their opening markers were cleared
+            for layer in &mut self.layers {
+                if let Some(begin) = &mut layer.begin {
+                    *begin = self.canvas.begin_layer(layer.opacity);
+                }
+            }
+            self.first_pending = self.canvas.buffer().len();
         }
     }
 
     /// Fill a shape with a given brush.
     pub fn fill(&mut self, shape: impl Shape, brush: &Brush) -> ShapeRef {
+        self.flush();
         shape.fill(self.canvas, brush)
     }
 
+    /// Fill a shape with the default brush.
+    ///
+    /// The default brush is part of the drawing state, and is set with
+    /// [`set_default_brush()`].
+    ///
+    /// [`set_default_brush()`]: RenderContext::set_default_brush
+    pub fn fill_default(&mut self, shape: impl Shape) -> ShapeRef<'_> {
+        self.flush();
+        shape.fill(self.canvas, &self.default_brush)
+    }
+
     // Stroke a shape with a given brush.
     // pub fn stroke(&mut self, shape: impl Shape, brush: &Brush, thickness: f32) {
     //     shape.stroke(self.canvas, brush, thickness);
@@ -318,13 +1215,112 @@ impl<'c> RenderContext<'c> {
     /// Draw a line between two points with the given brush.
     ///
     /// The line thickness is centered on the mathematical line between the two
-    /// endpoints, spanning `thickness / 2.` on each side.
+    /// endpoints, spanning `thickness / 2.` on each side. By default the line
+    /// has [`LineCap::Butt`] caps; use [`ShapeExt::cap()`] to change them.
+    ///
+    /// [`LineCap::Butt`]: crate::LineCap::Butt
+    /// [`ShapeExt::cap()`]: crate::ShapeExt::cap
     pub fn line(&mut self, p0: Vec2, p1: Vec2, brush: &Brush, thickness: f32) -> ShapeRef {
+        self.flush();
+        let effects = brush.effects(self.canvas);
         self.canvas.draw(LinePrimitive {
             start: p0,
             end: p1,
             color: brush.color(),
             thickness,
+            effects,
+            ..default()
+        })
+    }
+
+    /// Draw an arrow from `from` to `to` with the given brush.
+    ///
+    /// The shaft is a line of the given thickness, and the head(s) are drawn
+    /// at the end(s) selected by the [`ArrowHead`]. The shaft and heads are
+    /// drawn as a single shape, so each pixel is covered once, which keeps
+    /// translucent arrows uniform.
+    pub fn arrow(
+        &mut self,
+        from: Vec2,
+        to: Vec2,
+        brush: &Brush,
+        thickness: f32,
+        head: ArrowHead,
+    ) -> ShapeRef<'_> {
+        self.flush();
+        let effects = brush.effects(self.canvas);
+        self.canvas.draw(ArrowPrimitive {
+            start: from,
+            end: to,
+            color: brush.color(),
+            thickness,
+            head,
+            effects,
+            ..default()
+        })
+    }
+
+    /// Draw a polyline through the given points with the given brush.
+    ///
+    /// The polyline is drawn as a single shape, so each pixel is covered once
+    /// even where consecutive segments overlap, which keeps translucent
+    /// polylines uniform. By default the segments have [`LineJoin::Miter`]
+    /// joins and the ends have [`LineCap::Butt`] caps; use
+    /// [`ShapeExt::join()`] and [`ShapeExt::cap()`] to change them.
+    ///
+    /// [`LineJoin::Miter`]: crate::LineJoin::Miter
+    /// [`LineCap::Butt`]: crate::LineCap::Butt
+    /// [`ShapeExt::join()`]: crate::ShapeExt::join
+    /// [`ShapeExt::cap()`]: crate::ShapeExt::cap
+    pub fn polyline(
+        &mut self,
+        points: impl IntoIterator<Item = Vec2>,
+        brush: &Brush,
+        thickness: f32,
+    ) -> ShapeRef<'_> {
+        self.flush();
+        // Repeated points would produce degenerate segments without a direction
+        let mut points: Vec<Vec2> = points.into_iter().collect();
+        points.dedup();
+        let path = Path::from_points(points);
+        let bounds = path.bounds();
+        let id = self.canvas.add_path(path);
+        let effects = brush.effects(self.canvas);
+        self.canvas.draw(PolylinePrimitive {
+            id,
+            bounds,
+            color: brush.color(),
+            thickness,
+            effects,
+            ..default()
+        })
+    }
+
+    /// Draw a dashed line between two points with the given brush.
+    ///
+    /// The line is the same as with [`line()`], cut into dashes by the given
+    /// pattern, starting from `p0`.
+    ///
+    /// [`line()`]: RenderContext::line
+    pub fn dashed_line(
+        &mut self,
+        p0: Vec2,
+        p1: Vec2,
+        brush: &Brush,
+        thickness: f32,
+        dash: &DashPattern,
+    ) -> ShapeRef<'_> {
+        self.flush();
+        let effects = brush.paint(self.canvas);
+        let effects = self
+            .canvas
+            .add_dashed_effects(effects, Dash::straight(*dash, 0., p0, p1 - p0));
+        self.canvas.draw(LinePrimitive {
+            start: p0,
+            end: p1,
+            color: brush.color(),
+            thickness,
+            effects,
             ..default()
         })
     }
@@ -364,12 +1360,23 @@ impl<'c> RenderContext<'c> {
     /// ctx.draw_text(text, Vec2::new(100., 20.));
     /// ```
     ///
+    /// The returned [`ShapeRef`] allows outlining the glyphs with
+    /// [`ShapeExt::border()`].
+    ///
     /// [`new_layout()`]: RenderContext::new_layout
-    pub fn draw_text(&mut self, text_id: u32, pos: Vec2) {
+    /// [`ShapeExt::border()`]: crate::ShapeExt::border
+    pub fn draw_text(&mut self, text_id: u32, pos: Vec2) -> ShapeRef<'_> {
+        self.flush();
         self.canvas.draw(TextPrimitive {
             id: text_id,
             rect: Rect { min: pos, max: pos },
-        });
+            rotation: Rot2::IDENTITY,
+            scale: 1.,
+            flip_y: false,
+            border_width: 0.,
+            border_color: Color::NONE,
+            effects: default(),
+        })
     }
 
     /// Draw an image inside a given rectangle.
@@ -377,6 +1384,7 @@ impl<'c> RenderContext<'c> {
     /// The image is drawn inside the given rectangle shape, centered on it and
     /// scaled according to the given [`ImageScaling`].
     pub fn draw_image(&mut self, shape: Rect, image: Handle<Image>, scaling: ImageScaling) {
+        self.flush();
         self.canvas.draw(RectPrimitive {
             rect: shape,
             color: Color::WHITE,
@@ -387,8 +1395,42 @@ impl<'c> RenderContext<'c> {
     }
 }
 
+/// Drawing state of a [`RenderContext`] saved by [`RenderContext::save()`].
+struct SavedState {
+    /// Current transform.
+    transform: Affine2,
+    /// Transforms saved on the transform stack.
+    transform_stack: Vec<Affine2>,
+    /// Current clip rectangle.
+    clip: Option<Rect>,
+    /// Current clip masks.
+    masks: ClipMasks,
+    /// Clips saved on the clip stack.
+    clip_stack: Vec<(Option<Rect>, ClipMasks)>,
+    /// Current global alpha.
+    global_alpha: f32,
+    /// Current default brush.
+    default_brush: Brush,
+}
+
+/// Layer opened by [`RenderContext::push_layer()`].
+struct Layer {
+    /// Index of the opening marker of the layer in the canvas, or `None` if the
+    /// layer is nested too deeply to be composited as a group.
+    begin: Option<usize>,
+    /// Opacity of the layer, from 0 (transparent) to 1 (opaque).
+    opacity: f32,
+    /// Global alpha when the layer was opened.
+    global_alpha: f32,
+}
+
 impl<'c> Drop for RenderContext<'c> {
     fn drop(&mut self) {
+        // Close any layer left open, so the shader sees balanced markers
+        while !self.layers.is_empty() {
+            self.pop_layer();
+        }
+        self.flush();
         self.canvas.finish();
     }
 }
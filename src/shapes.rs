@@ -5,16 +5,42 @@
 //! | Shape | Description |
 //! |---|---|
 //! | [`Rect`] | Axis-aligned rectangle. |
-//! | [`RoundedRect`] | Axis-aligned rectangle with rounded corners. |
+//! | [`RoundedRect`] | Rectangle with rounded corners and optional rotation. |
+//! | [`Capsule`] | Pill shape, with optional rotation. |
+//! | [`Ellipse`] | Ellipse, including circles, with optional rotation. |
+//! | [`Path`] | Arbitrary outline made of lines and Bézier curves. |
+//! | [`Arc`] | Arc, annulus sector, or pie, with arbitrary angles. |
+//! | [`Polygon`] | Polygon with a bounded number of vertices. |
+//! | [`RegularPolygon`] | Regular polygon, with optional rounded corners. |
+//! | [`Star`] | Star, with optional rounded tips. |
+//! | [`Placed`] | Bevy primitive shape placed by an [`Isometry2d`]. |
+//!
+//! The [`Ellipse`], [`Polygon`], and [`RegularPolygon`] shapes share their
+//! name with a Bevy primitive, which makes them ambiguous when glob-importing
+//! both the Bevy prelude and this crate. Use them through this module in that
+//! case, _e.g._ `shapes::Ellipse`.
+
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
 
 use bevy::{
     color::Color,
-    prelude::{Rect, Vec2},
+    log::warn,
+    math::{
+        primitives::{
+            self, Annulus, Capsule2d, Circle, Primitive2d, Rectangle, Segment2d, Triangle2d,
+        },
+        Affine2,
+    },
+    prelude::{Rect, Rot2, Vec2},
     utils::default,
 };
 
 use crate::{
-    canvas::{QuarterPiePrimitive, RectPrimitive},
+    canvas::{
+        ArcPrimitive, CapsulePrimitive, Dash, EffectTables, EllipsePrimitive, FlattenedPath, Glow,
+        LinePrimitive, PathContour, PathPrimitive, PolygonPrimitive, RectPrimitive, Shadow,
+        StarPrimitive,
+    },
     render_context::Brush,
     Canvas, Primitive,
 };
@@ -36,9 +62,14 @@ use crate::{
 /// ctx.fill(rect, &brush).border(&border_brush, border_width);
 /// ```
 ///
+/// Shapes drawn with several primitives, like dashed strokes, return a
+/// reference to all of them, and the builder methods apply to each one.
+///
 /// [`RenderContext::fill()`]: crate::render_context::RenderContext::fill
 pub struct ShapeRef<'c> {
-    pub(crate) prim: &'c mut Primitive,
+    pub(crate) prims: &'c mut [Primitive],
+    /// Side tables of the canvas, to store the border gradient.
+    pub(crate) tables: &'c mut EffectTables,
 }
 
 /// Extension trait to tweak shapes built by the [`RenderContext`].
@@ -62,31 +93,545 @@ pub struct ShapeRef<'c> {
 /// [`RenderContext::fill()`]: crate::render_context::RenderContext::fill
 pub trait ShapeExt {
     /// Add a border to the shape.
-    fn border(&mut self, brush: &Brush, thickness: f32) -> &mut Self;
+    ///
+    /// The border expands inside the shape. For text, the border is an outline
+    /// drawn around the glyphs instead, which expands outside of them.
+    ///
+    /// This is equivalent to [`border_aligned()`] with
+    /// [`StrokeAlignment::Inside`].
+    ///
+    /// [`border_aligned()`]: ShapeExt::border_aligned
+    fn border(&mut self, brush: &Brush, thickness: f32) -> &mut Self {
+        self.border_aligned(brush, thickness, StrokeAlignment::Inside)
+    }
+
+    /// Add a border to the shape, with the given alignment relative to the
+    /// edge of the shape.
+    ///
+    /// Borders aligned [`Outside`] leave the filled area of the shape untouched,
+    /// while borders aligned [`Inside`] leave its footprint untouched. Text
+    /// outlines always expand outside the glyphs, and ignore the alignment.
+    ///
+    /// [`Outside`]: StrokeAlignment::Outside
+    /// [`Inside`]: StrokeAlignment::Inside
+    fn border_aligned(
+        &mut self,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> &mut Self;
 
     /// Add a glow effect to the shape.
+    ///
+    /// The glow is drawn underneath the shape with the color of the given
+    /// brush, and fades out smoothly with the distance to the edge of the
+    /// shape, until it vanishes at `spread`. A `spread` of zero or less removes
+    /// the glow.
+    ///
+    /// For text, the glyphs don't have an exact distance function, so the glow
+    /// follows an approximation of the glyph outlines.
     fn glow(&mut self, brush: &Brush, spread: f32) -> &mut Self;
+
+    /// Add a drop shadow to the shape.
+    ///
+    /// The shadow is a copy of the shape drawn underneath it with the given
+    /// color, moved by `offset`, and with edges blurred over `blur_radius` on
+    /// each side. The canvas Y axis points down the screen, so a positive Y
+    /// offset moves the shadow down.
+    ///
+    /// ```no_run
+    /// # use bevy_keith::*;
+    /// # use bevy::{prelude::*, color::palettes::css::*};
+    /// # let mut canvas = Canvas::default();
+    /// # let mut ctx = RenderContext::new(&mut canvas);
+    /// # let rect = Rect::new(0., 0., 100., 30.);
+    /// # let brush = ctx.solid_brush(BEIGE.into());
+    /// // Shadow cast toward the bottom right
+    /// ctx.fill(RoundedRect::new(rect, 4.), &brush).shadow(
+    ///     Vec2::new(2., 2.),
+    ///     6.,
+    ///     Color::srgba(0., 0., 0., 0.5),
+    /// );
+    /// ```
+    fn shadow(&mut self, offset: Vec2, blur_radius: f32, color: Color) -> &mut Self;
+
+    /// Add an inset shadow to the shape.
+    ///
+    /// The inset shadow darkens the inside of the shape along its edge, as if
+    /// the shape was recessed, fading out over `blur_radius` on each side of
+    /// the edge. The shadow is cast by the outside of the shape moved by
+    /// `offset`. The canvas Y axis points down the screen, so a positive Y
+    /// offset moves the shadow down and darkens the top edge. If the shape
+    /// has a border, the shadow starts at the inner edge of the border.
+    ///
+    /// Only rectangles and rounded rectangles support an inset shadow. This
+    /// has no effect on other shapes.
+    ///
+    /// ```no_run
+    /// # use bevy_keith::*;
+    /// # use bevy::{prelude::*, color::palettes::css::*};
+    /// # let mut canvas = Canvas::default();
+    /// # let mut ctx = RenderContext::new(&mut canvas);
+    /// # let rect = Rect::new(0., 0., 200., 24.);
+    /// # let brush = ctx.solid_brush(WHITE.into());
+    /// # let border_brush = ctx.solid_brush(GRAY.into());
+    /// // Recessed text input field
+    /// ctx.fill(RoundedRect::new(rect, 3.), &brush)
+    ///     .border(&border_brush, 1.)
+    ///     .inset_shadow(Vec2::new(0., 2.), 3., Color::srgba(0., 0., 0., 0.4));
+    /// ```
+    fn inset_shadow(&mut self, offset: Vec2, blur_radius: f32, color: Color) -> &mut Self;
+
+    /// Set the shape of the joins between consecutive segments of the shape.
+    ///
+    /// Only polylines have joins. This has no effect on other shapes.
+    ///
+    /// ```no_run
+    /// # use bevy_keith::*;
+    /// # use bevy::{prelude::*, color::palettes::css::*};
+    /// # let mut canvas = Canvas::default();
+    /// # let mut ctx = RenderContext::new(&mut canvas);
+    /// # let brush = ctx.solid_brush(Color::srgba(1., 0., 0., 0.5));
+    /// let points = [Vec2::ZERO, Vec2::new(50., 20.), Vec2::new(100., 0.)];
+    /// ctx.polyline(points, &brush, 4.)
+    ///     .join(LineJoin::Round)
+    ///     .cap(LineCap::Round);
+    /// ```
+    fn join(&mut self, join: LineJoin) -> &mut Self;
+
+    /// Set the shape of the ends of the shape.
+    ///
+    /// Only lines and polylines have caps. This has no effect on other shapes.
+    ///
+    /// ```no_run
+    /// # use bevy_keith::*;
+    /// # use bevy::{prelude::*, color::palettes::css::*};
+    /// # let mut canvas = Canvas::default();
+    /// # let mut ctx = RenderContext::new(&mut canvas);
+    /// # let brush = ctx.solid_brush(RED.into());
+    /// ctx.line(Vec2::ZERO, Vec2::new(100., 40.), &brush, 6.)
+    ///     .cap(LineCap::Round);
+    /// ```
+    fn cap(&mut self, cap: LineCap) -> &mut Self;
 }
 
 impl<'a> ShapeExt for ShapeRef<'a> {
-    fn border(&mut self, brush: &Brush, thickness: f32) -> &mut Self {
-        match self.prim {
-            Primitive::Rect(r) => {
-                r.border_color = brush.color();
-                r.border_width = thickness.max(0.);
+    fn border_aligned(
+        &mut self,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> &mut Self {
+        let border_gradient = brush
+            .gradient()
+            .map(|gradient| self.tables.add_gradient(*gradient));
+        for prim in self.prims.iter_mut() {
+            match prim {
+                Primitive::Rect(r) => {
+                    r.border_color = brush.color();
+                    r.border_width = thickness.max(0.);
+                    r.border_alignment = alignment;
+                }
+                Primitive::Line(l) => {
+                    l.border_color = brush.color();
+                    l.border_width = thickness.max(0.);
+                    l.border_alignment = alignment;
+                }
+                Primitive::Text(t) => {
+                    t.border_color = brush.color();
+                    t.border_width = thickness.max(0.);
+                }
+                Primitive::QuarterPie(q) => {
+                    q.border_color = brush.color();
+                    q.border_width = thickness.max(0.);
+                    q.border_alignment = alignment;
+                }
+                Primitive::Ellipse(e) => {
+                    e.border_color = brush.color();
+                    e.border_width = thickness.max(0.);
+                    e.border_alignment = alignment;
+                }
+                Primitive::Path(p) => {
+                    p.border_color = brush.color();
+                    p.border_width = thickness.max(0.);
+                    p.border_alignment = alignment;
+                }
+                Primitive::Arc(a) => {
+                    a.border_color = brush.color();
+                    a.border_width = thickness.max(0.);
+                    a.border_alignment = alignment;
+                }
+                Primitive::Polyline(p) => {
+                    p.border_color = brush.color();
+                    p.border_width = thickness.max(0.);
+                    p.border_alignment = alignment;
+                }
+                Primitive::Polygon(p) => {
+                    p.border_color = brush.color();
+                    p.border_width = thickness.max(0.);
+                    p.border_alignment = alignment;
+                }
+                Primitive::Star(s) => {
+                    s.border_color = brush.color();
+                    s.border_width = thickness.max(0.);
+                    s.border_alignment = alignment;
+                }
+                Primitive::Capsule(c) => {
+                    c.border_color = brush.color();
+                    c.border_width = thickness.max(0.);
+                    c.border_alignment = alignment;
+                }
+                Primitive::Arrow(a) => {
+                    a.border_color = brush.color();
+                    a.border_width = thickness.max(0.);
+                    a.border_alignment = alignment;
+                }
+                Primitive::Layer(_) => {}
+            };
+            prim.effects_mut(self.tables).border_gradient = border_gradient;
+        }
+        self
+    }
+
+    fn glow(&mut self, brush: &Brush, spread: f32) -> &mut Self {
+        let glow = if spread > 0. {
+            Some(Glow {
+                color: brush.color(),
+                spread,
+            })
+        } else {
+            None
+        };
+        for prim in self.prims.iter_mut() {
+            prim.effects_mut(self.tables).glow = glow;
+        }
+        self
+    }
+
+    fn shadow(&mut self, offset: Vec2, blur_radius: f32, color: Color) -> &mut Self {
+        let shadow = Shadow {
+            offset,
+            blur_radius: blur_radius.max(0.),
+            color,
+        };
+        for prim in self.prims.iter_mut() {
+            prim.effects_mut(self.tables).shadow = Some(shadow);
+        }
+        self
+    }
+
+    fn inset_shadow(&mut self, offset: Vec2, blur_radius: f32, color: Color) -> &mut Self {
+        let shadow = Shadow {
+            offset,
+            blur_radius: blur_radius.max(0.),
+            color,
+        };
+        for prim in self.prims.iter_mut() {
+            if let Primitive::Rect(_) = prim {
+                prim.effects_mut(self.tables).inset_shadow = Some(shadow);
+            }
+        }
+        self
+    }
+
+    fn join(&mut self, join: LineJoin) -> &mut Self {
+        for prim in self.prims.iter_mut() {
+            if let Primitive::Polyline(p) = prim {
+                p.join = join;
             }
-            Primitive::Line(l) => {
-                l.border_color = brush.color();
-                l.border_width = thickness.max(0.);
+        }
+        self
+    }
+
+    fn cap(&mut self, cap: LineCap) -> &mut Self {
+        for prim in self.prims.iter_mut() {
+            match prim {
+                Primitive::Line(l) => l.cap = cap,
+                Primitive::Polyline(p) => p.cap = cap,
+                _ => {}
             }
-            Primitive::Text(_t) => todo!(),
-            Primitive::QuarterPie(_q) => todo!(),
-        };
+        }
+        self
+    }
+}
+
+/// Alignment of a stroke or border relative to the edge of a shape.
+///
+/// The default is [`Inside`], which is where borders have always been drawn.
+/// Strokes drawn with [`Shape::stroke()`] are instead centered on the edge.
+///
+/// [`Inside`]: StrokeAlignment::Inside
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(RED.into());
+/// # let border_brush = ctx.solid_brush(BLACK.into());
+/// // Outline a rectangle without covering any pixel outside of it
+/// let rect = Rect::new(0., 0., 100., 30.);
+/// ctx.fill(rect, &brush)
+///     .border_aligned(&border_brush, 2., StrokeAlignment::Inside);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StrokeAlignment {
+    /// The stroke lies entirely inside the shape, which keeps the footprint
+    /// of the shape unchanged.
+    #[default]
+    Inside,
+    /// The stroke is centered on the edge of the shape, and extends by half
+    /// its thickness on each side.
+    Center,
+    /// The stroke lies entirely outside the shape, which keeps the inside of
+    /// the shape unchanged.
+    Outside,
+}
+
+impl StrokeAlignment {
+    /// Width of the part of a stroke of the given thickness lying outside the
+    /// edge of the shape.
+    pub fn outset(&self, thickness: f32) -> f32 {
+        let thickness = thickness.max(0.);
+        match self {
+            Self::Inside => 0.,
+            Self::Center => thickness / 2.,
+            Self::Outside => thickness,
+        }
+    }
+
+    /// Width of the part of a stroke of the given thickness lying inside the
+    /// edge of the shape.
+    pub fn inset(&self, thickness: f32) -> f32 {
+        thickness.max(0.) - self.outset(thickness)
+    }
+}
+
+/// Shape of the join between two consecutive segments of a stroke.
+///
+/// # Note
+///
+/// The enum values must be kept in sync with the values inside the primitive
+/// shader.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum LineJoin {
+    /// Extend the outer edges of both segments until they meet in a sharp
+    /// corner. Corners sharper than [`MITER_LIMIT`] fall back to [`Bevel`].
+    ///
+    /// [`Bevel`]: LineJoin::Bevel
+    #[default]
+    Miter = 0,
+    /// Round the outer corner with a circle centered on the joint.
+    Round = 1,
+    /// Cut the outer corner with a straight edge.
+    Bevel = 2,
+}
+
+/// Maximum ratio of the length of a [`LineJoin::Miter`] corner, from the inner
+/// to the outer corner, to the stroke thickness. This is the default SVG value,
+/// which bevels corners sharper than about 29 degrees.
+pub const MITER_LIMIT: f32 = 4.;
+
+/// Shape of the ends of an open stroke.
+///
+/// # Note
+///
+/// The enum values must be kept in sync with the values inside the primitive
+/// shader.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum LineCap {
+    /// Stop the stroke exactly at the end point.
+    #[default]
+    Butt = 0,
+    /// Extend the stroke past the end point by half its thickness.
+    Square = 1,
+    /// Extend the stroke past the end point with a half circle.
+    Round = 2,
+}
+
+/// Shape of the head of an arrow.
+///
+/// # Note
+///
+/// The enum values must be kept in sync with the values inside the primitive
+/// shader.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum ArrowHeadShape {
+    /// Filled triangle, with its tip at the end of the arrow.
+    #[default]
+    Triangle = 0,
+    /// Open V made of two strokes as thick as the arrow shaft, with its tip at
+    /// the end of the arrow.
+    OpenV = 1,
+    /// Filled disc centered on the end of the arrow.
+    Circle = 2,
+}
+
+/// Ends of an arrow which have a head.
+///
+/// # Note
+///
+/// The enum values must be kept in sync with the values inside the primitive
+/// shader.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum ArrowEnds {
+    /// Head at the start of the arrow only.
+    Start = 1,
+    /// Head at the end of the arrow only.
+    #[default]
+    End = 2,
+    /// Heads at both ends of the arrow.
+    Both = 3,
+}
+
+/// Style of the head(s) of an arrow drawn with [`RenderContext::arrow()`].
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(WHITE.into());
+/// let head = ArrowHead::open_v(8., 8.).with_ends(ArrowEnds::Both);
+/// ctx.arrow(Vec2::ZERO, Vec2::new(100., 50.), &brush, 2., head);
+/// ```
+///
+/// [`RenderContext::arrow()`]: crate::render_context::RenderContext::arrow
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ArrowHead {
+    /// The shape of the head(s).
+    pub shape: ArrowHeadShape,
+    /// The ends of the arrow which have a head.
+    pub ends: ArrowEnds,
+    /// The length of the head along the arrow, from its tip to its base.
+    /// Unused for [`ArrowHeadShape::Circle`].
+    pub length: f32,
+    /// The width of the head across the arrow, which for
+    /// [`ArrowHeadShape::Circle`] is the diameter of the disc.
+    pub width: f32,
+}
+
+impl ArrowHead {
+    /// Create a filled triangle head at the end of the arrow.
+    pub fn triangle(length: f32, width: f32) -> Self {
+        Self {
+            shape: ArrowHeadShape::Triangle,
+            ends: ArrowEnds::End,
+            length,
+            width,
+        }
+    }
+
+    /// Create an open V head at the end of the arrow.
+    pub fn open_v(length: f32, width: f32) -> Self {
+        Self {
+            shape: ArrowHeadShape::OpenV,
+            ends: ArrowEnds::End,
+            length,
+            width,
+        }
+    }
+
+    /// Create a disc head at the end of the arrow.
+    pub fn circle(diameter: f32) -> Self {
+        Self {
+            shape: ArrowHeadShape::Circle,
+            ends: ArrowEnds::End,
+            length: 0.,
+            width: diameter,
+        }
+    }
+
+    /// Set the ends of the arrow which have a head.
+    pub fn with_ends(mut self, ends: ArrowEnds) -> Self {
+        self.ends = ends;
         self
     }
+}
+
+/// Maximum number of lengths of a [`DashPattern`]. Extra lengths are ignored.
+pub const MAX_DASH_LENGTHS: usize = 8;
+
+/// Pattern of dashes and gaps along a stroke.
+///
+/// The pattern is a list of lengths alternating between dashes and gaps,
+/// starting with a dash, which repeats indefinitely along the stroke. Like in
+/// SVG, a list with an odd number of lengths is repeated twice, so `[4.]`
+/// draws dashes and gaps both 4 units long.
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let brush: render_context::Brush = Color::WHITE.into();
+/// # let time = 0.;
+/// // Marching ants around a selection rectangle
+/// let dash = DashPattern::new([4., 4.], time * 16.);
+/// let rect = Rect::new(0., 0., 100., 30.);
+/// rect.stroke_dashed(&mut canvas, &brush, 1., StrokeAlignment::Center, &dash);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DashPattern {
+    /// Alternating dash and gap lengths. Only the first `count` are valid.
+    lengths: [f32; 2 * MAX_DASH_LENGTHS],
+    /// Number of valid lengths in `lengths`. Always even.
+    count: usize,
+    /// Offset into the pattern at the start of the stroke.
+    phase: f32,
+}
+
+impl DashPattern {
+    /// Create a new dash pattern from its dash and gap lengths, and its phase.
+    ///
+    /// Negative lengths are clamped to zero. Only the first
+    /// [`MAX_DASH_LENGTHS`] lengths are retained. The phase offsets the
+    /// pattern along the stroke; animating it makes the dashes crawl.
+    pub fn new(lengths: impl IntoIterator<Item = f32>, phase: f32) -> Self {
+        let mut pattern = Self { phase, ..default() };
+        for length in lengths.into_iter().take(MAX_DASH_LENGTHS) {
+            pattern.lengths[pattern.count] = length.max(0.);
+            pattern.count += 1;
+        }
+        if pattern.count & 1 != 0 {
+            let count = pattern.count;
+            pattern.lengths.copy_within(..count, count);
+            pattern.count *= 2;
+        }
+        pattern
+    }
+
+    /// Dash and gap lengths, alternating and starting with a dash.
+    ///
+    /// This always contains an even number of lengths, as odd lists passed to
+    /// [`new()`] are repeated twice.
+    ///
+    /// [`new()`]: DashPattern::new
+    pub fn lengths(&self) -> &[f32] {
+        &self.lengths[..self.count]
+    }
+
+    /// Offset into the pattern at the start of the stroke.
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+    /// Length of a single repetition of the pattern.
+    pub fn period(&self) -> f32 {
+        self.lengths().iter().sum()
+    }
 
-    fn glow(&mut self, _brush: &Brush, _spread: f32) -> &mut Self {
-        todo!()
+    /// Scale all the lengths of the pattern and its phase by the same factor.
+    pub(crate) fn scaled(&self, scale: f32) -> Self {
+        let mut pattern = *self;
+        for length in &mut pattern.lengths[..pattern.count] {
+            *length *= scale;
+        }
+        pattern.phase *= scale;
+        pattern
     }
 }
 
@@ -95,6 +640,16 @@ impl<'a> ShapeExt for ShapeRef<'a> {
 /// Available shapes:
 /// - Bevy's own [`Rect`] (rectangle).
 /// - [`RoundedRect`], which includes circles (see [`RoundedRect::circle()`]).
+/// - [`Ellipse`], which includes circles (see [`Ellipse::circle()`]).
+/// - [`Path`], for arbitrary outlines made of lines and Bézier curves.
+/// - [`Capsule`], a pill shape with optional rotation.
+/// - [`Arc`], which includes annuli and pies (see [`Arc::ring()`] and
+///   [`Arc::pie()`]).
+/// - [`Polygon`], with a bounded number of vertices.
+/// - [`RegularPolygon`], with optional rounded corners.
+/// - [`Star`], with optional rounded tips.
+/// - [`Placed`], a Bevy primitive shape placed by an [`Isometry2d`] (see
+///   [`Primitive2dExt::at()`]).
 pub trait Shape {
     /// Fill the shape with the given [`Brush`].
     ///
@@ -108,79 +663,326 @@ pub trait Shape {
     /// efficient for some shapes (e.g. rectangle) when the overall shape is
     /// large, and you don't need to fill it.
     ///
-    /// DISCLAIMER: Only implemented for [`Rect`]; this is more efficient than
-    /// drawing a [`Rect`] with a border and transparent color, because this
-    /// draws only the edges so doesn't touch any tile inside the rectangle.
-    /// However if you want to both fill and stroke a same [`Rect`], consider
-    /// instead using [`fill()`] and [`ShapeRef::border()`].
+    /// For a [`Rect`], this is more efficient than drawing it with a border
+    /// and transparent color, because this draws only the edges so doesn't
+    /// touch any tile inside the rectangle. However if you want to both fill
+    /// and stroke a same [`Rect`], consider instead using [`fill()`] and
+    /// [`ShapeRef::border()`].
+    ///
+    /// This is equivalent to [`stroke_aligned()`] with
+    /// [`StrokeAlignment::Center`].
     ///
     /// [`fill()`]: Shape::fill
-    fn stroke<'c>(&self, canvas: &'c mut Canvas, brush: &Brush, thickness: f32) -> ShapeRef<'c>;
+    /// [`stroke_aligned()`]: Shape::stroke_aligned
+    fn stroke<'c>(&self, canvas: &'c mut Canvas, brush: &Brush, thickness: f32) -> ShapeRef<'c> {
+        self.stroke_aligned(canvas, brush, thickness, StrokeAlignment::Center)
+    }
+
+    /// Stroke the shape with the given [`Brush`] and thickness, with the given
+    /// alignment relative to the edge of the shape.
+    ///
+    /// For a [`Path`], the inside of any open contour is the one of the
+    /// contour implicitly closed.
+    fn stroke_aligned<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> ShapeRef<'c>;
+
+    /// Stroke the shape with dashes, with the given [`Brush`], thickness, and
+    /// alignment relative to the edge of the shape.
+    ///
+    /// The dashes are evaluated in the shader from the position along the
+    /// stroke, so a dashed stroke needs no primitive per dash. Rectangles are
+    /// dashed with one primitive per edge and corner, and the returned
+    /// [`ShapeRef`] covers all of them.
+    ///
+    /// DISCLAIMER: Only implemented for [`Rect`] and [`RoundedRect`], and
+    /// their [`Placed`] Bevy primitives. Other shapes ([`Capsule`],
+    /// [`Ellipse`], [`Arc`], [`Polygon`], [`RegularPolygon`], [`Star`], and
+    /// [`Path`]) ignore the dash pattern, log a warning, and draw a solid
+    /// stroke. For dashed lines, see [`RenderContext::dashed_line()`].
+    ///
+    /// [`RenderContext::dashed_line()`]: crate::render_context::RenderContext::dashed_line
+    fn stroke_dashed<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+        dash: &DashPattern,
+    ) -> ShapeRef<'c> {
+        warn!("Dashed strokes are not supported by this shape; ignored {dash:?}.");
+        self.stroke_aligned(canvas, brush, thickness, alignment)
+    }
 }
 
 impl Shape for Rect {
     fn fill<'c>(&self, canvas: &'c mut Canvas, brush: &Brush) -> ShapeRef<'c> {
+        let effects = brush.effects(canvas);
         canvas.draw(RectPrimitive {
             rect: *self,
             color: brush.color(),
+            effects,
             ..Default::default()
         })
     }
 
-    fn stroke<'c>(&self, canvas: &'c mut Canvas, brush: &Brush, thickness: f32) -> ShapeRef<'c> {
-        let eps = thickness / 2.;
-
-        // Top (including corners)
-        let mut prim = RectPrimitive {
-            rect: Rect {
-                min: Vec2::new(self.min.x - eps, self.max.y - eps),
-                max: Vec2::new(self.max.x + eps, self.max.y + eps),
-            },
-            radius: 0.,
+    fn stroke_aligned<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        let effects = brush.effects(canvas);
+        canvas.draw(RectPrimitive {
+            rect: *self,
             color: brush.color(),
-            flip_x: false,
-            flip_y: false,
-            image: None,
-            image_size: Vec2::ZERO,
-            image_scaling: default(),
-            border_width: 0.,
-            border_color: Color::NONE,
-        };
-        canvas.draw(prim);
+            effects,
+            stroke_width: thickness.max(0.),
+            stroke_alignment: alignment,
+            ..Default::default()
+        })
+    }
 
-        // Bottom (including corners)
-        prim.rect = Rect {
-            min: Vec2::new(self.min.x - eps, self.min.y - eps),
-            max: Vec2::new(self.max.x + eps, self.min.y + eps),
-        };
-        canvas.draw(prim);
+    fn stroke_dashed<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+        dash: &DashPattern,
+    ) -> ShapeRef<'c> {
+        stroke_rect_dashed(self, canvas, brush, thickness, alignment, dash)
+    }
+}
 
-        // Left (excluding corners)
-        prim.rect = Rect {
-            min: Vec2::new(self.min.x - eps, self.min.y + eps),
-            max: Vec2::new(self.min.x + eps, self.max.y - eps),
-        };
-        canvas.draw(prim);
+/// Stroke a [`Rect`] with four dashed edge pieces.
+///
+/// The dashes run clockwise on screen from the top-left corner, along the
+/// middle of the stroke, like for [`RoundedRect`]. The returned reference
+/// covers all four pieces.
+fn stroke_rect_dashed<'c>(
+    rect: &Rect,
+    canvas: &'c mut Canvas,
+    brush: &Brush,
+    thickness: f32,
+    alignment: StrokeAlignment,
+    dash: &DashPattern,
+) -> ShapeRef<'c> {
+    let first = canvas.buffer().len();
+    let outset = alignment.outset(thickness);
+    let inset = alignment.inset(thickness);
 
-        // Right (excluding corners)
-        prim.rect = Rect {
-            min: Vec2::new(self.max.x - eps, self.min.y + eps),
-            max: Vec2::new(self.max.x + eps, self.max.y - eps),
-        };
-        canvas.draw(prim)
+    // Middle line of the stroke, along which dashes are measured
+    let middle = (outset - inset) / 2.;
+    let min = rect.min - middle;
+    let max = rect.max + middle;
+    let size = max - min;
+    let effects = brush.paint(canvas);
+    let dashed = |canvas: &mut Canvas, start: f32, origin: Vec2, direction: Vec2| {
+        canvas.add_dashed_effects(effects, Dash::straight(*dash, start, origin, direction))
+    };
+
+    // Top (including corners)
+    let mut prim = RectPrimitive {
+        rect: Rect {
+            min: Vec2::new(rect.min.x - outset, rect.min.y - outset),
+            max: Vec2::new(rect.max.x + outset, rect.min.y + inset),
+        },
+        rotation: Rot2::IDENTITY,
+        radii: CornerRadii::ZERO,
+        color: brush.color(),
+        flip_x: false,
+        flip_y: false,
+        image: None,
+        image_size: Vec2::ZERO,
+        image_scaling: default(),
+        stroke_width: 0.,
+        stroke_alignment: StrokeAlignment::Inside,
+        border_width: 0.,
+        border_color: Color::NONE,
+        border_alignment: StrokeAlignment::Inside,
+        effects: dashed(canvas, 0., min, Vec2::X),
+    };
+    canvas.draw(prim);
+
+    // Bottom (including corners)
+    prim.rect = Rect {
+        min: Vec2::new(rect.min.x - outset, rect.max.y - inset),
+        max: Vec2::new(rect.max.x + outset, rect.max.y + outset),
+    };
+    prim.effects = dashed(canvas, size.x + size.y, max, Vec2::NEG_X);
+    canvas.draw(prim);
+
+    // Left (excluding corners)
+    prim.rect = Rect {
+        min: Vec2::new(rect.min.x - outset, rect.min.y + inset),
+        max: Vec2::new(rect.min.x + inset, rect.max.y - inset),
+    };
+    prim.effects = dashed(
+        canvas,
+        size.x * 2. + size.y,
+        Vec2::new(min.x, max.y),
+        Vec2::NEG_Y,
+    );
+    canvas.draw(prim);
+
+    // Right (excluding corners)
+    prim.rect = Rect {
+        min: Vec2::new(rect.max.x - inset, rect.min.y + inset),
+        max: Vec2::new(rect.max.x + outset, rect.max.y - inset),
+    };
+    prim.effects = dashed(canvas, size.x, Vec2::new(max.x, min.y), Vec2::Y);
+    canvas.draw(prim);
+    canvas.shape_ref(first)
+}
+
+/// Radii of the four corners of a [`RoundedRect`].
+///
+/// The canvas Y axis points down the screen, so the top corners are the ones on
+/// the `min.y` edge of the rectangle, while the bottom corners are the ones on
+/// its `max.y` edge.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CornerRadii {
+    /// Radius of the top-left corner, at `(min.x, min.y)`.
+    pub top_left: f32,
+    /// Radius of the top-right corner, at `(max.x, min.y)`.
+    pub top_right: f32,
+    /// Radius of the bottom-right corner, at `(max.x, max.y)`.
+    pub bottom_right: f32,
+    /// Radius of the bottom-left corner, at `(min.x, max.y)`.
+    pub bottom_left: f32,
+}
+
+impl CornerRadii {
+    /// All radii set to zero, for square corners.
+    pub const ZERO: Self = Self::all(0.);
+
+    /// Create a new set of radii, listed clockwise from the top-left corner.
+    pub const fn new(top_left: f32, top_right: f32, bottom_right: f32, bottom_left: f32) -> Self {
+        Self {
+            top_left,
+            top_right,
+            bottom_right,
+            bottom_left,
+        }
+    }
+
+    /// Use the same radius for all four corners.
+    pub const fn all(radius: f32) -> Self {
+        Self::new(radius, radius, radius, radius)
+    }
+
+    /// Round only the two top corners, like a tab.
+    pub const fn top(radius: f32) -> Self {
+        Self::new(radius, radius, 0., 0.)
+    }
+
+    /// Round only the two bottom corners.
+    pub const fn bottom(radius: f32) -> Self {
+        Self::new(0., 0., radius, radius)
+    }
+
+    /// Round only the two left corners.
+    pub const fn left(radius: f32) -> Self {
+        Self::new(radius, 0., 0., radius)
+    }
+
+    /// Round only the two right corners.
+    pub const fn right(radius: f32) -> Self {
+        Self::new(0., radius, radius, 0.)
+    }
+
+    /// Clamp all radii between zero and the smallest component of the given
+    /// half size, so that corners never overlap.
+    pub(crate) fn clamped(&self, half_size: Vec2) -> Self {
+        let max = half_size.x.min(half_size.y).max(0.);
+        Self {
+            top_left: self.top_left.clamp(0., max),
+            top_right: self.top_right.clamp(0., max),
+            bottom_right: self.bottom_right.clamp(0., max),
+            bottom_left: self.bottom_left.clamp(0., max),
+        }
+    }
+
+    /// Scale all radii by the same factor.
+    pub(crate) fn scaled(&self, scale: f32) -> Self {
+        Self::new(
+            self.top_left * scale,
+            self.top_right * scale,
+            self.bottom_right * scale,
+            self.bottom_left * scale,
+        )
+    }
+
+    /// Swap the top and bottom radii, for a rectangle mirrored vertically.
+    pub(crate) fn flipped_y(&self) -> Self {
+        Self::new(
+            self.bottom_left,
+            self.bottom_right,
+            self.top_right,
+            self.top_left,
+        )
+    }
+}
+
+impl From<f32> for CornerRadii {
+    fn from(radius: f32) -> Self {
+        Self::all(radius)
     }
 }
 
 /// Rounded rectangle shape.
+///
+/// Each corner can have a different radius, see [`CornerRadii`]. The rectangle
+/// can also be rotated around its center. Use zero radii for a rotated
+/// rectangle with square corners.
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(RED.into());
+/// let rect = Rect::from_center_size(Vec2::ZERO, Vec2::new(40., 20.));
+/// let card = RoundedRect::new(rect, 4.).with_rotation(0.3);
+/// ctx.fill(card, &brush);
+/// ```
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct RoundedRect {
-    /// The rectangle itself, inclusive of the rounded corners.
+    /// The rectangle itself, inclusive of the rounded corners, before
+    /// rotation.
     pub rect: Rect,
-    /// The radius of the corners.
-    pub radius: f32,
+    /// The radii of the corners.
+    pub radii: CornerRadii,
+    /// The rotation of the rectangle around its center.
+    pub rotation: Rot2,
 }
 
 impl RoundedRect {
+    /// Create a new rounded rectangle.
+    ///
+    /// The radii can be a single `f32` for all corners, or a [`CornerRadii`].
+    pub fn new(rect: Rect, radii: impl Into<CornerRadii>) -> Self {
+        Self {
+            rect,
+            radii: radii.into(),
+            rotation: Rot2::IDENTITY,
+        }
+    }
+
+    /// Set the rotation of the rectangle around its center.
+    ///
+    /// The rotation can be a [`Rot2`], or an `f32` angle in radians.
+    pub fn with_rotation(mut self, rotation: impl Into<Rot2>) -> Self {
+        self.rotation = rotation.into();
+        self
+    }
+
     /// Create a circle shape.
     ///
     /// This creates a rounded "rectangle" (square, really) where the corner
@@ -189,94 +991,1988 @@ impl RoundedRect {
     pub fn circle(center: Vec2, radius: f32) -> Self {
         Self {
             rect: Rect::from_center_half_size(center, Vec2::splat(radius)),
-            radius,
+            radii: CornerRadii::all(radius),
+            rotation: Rot2::IDENTITY,
         }
     }
 }
 
 impl Shape for RoundedRect {
     fn fill<'c>(&self, canvas: &'c mut Canvas, brush: &Brush) -> ShapeRef<'c> {
+        let effects = brush.effects(canvas);
         canvas.draw(RectPrimitive {
             rect: self.rect,
-            radius: self.radius,
+            rotation: self.rotation,
+            radii: self.radii,
             color: brush.color(),
+            effects,
             ..Default::default()
         })
     }
 
-    fn stroke<'c>(&self, canvas: &'c mut Canvas, brush: &Brush, thickness: f32) -> ShapeRef<'c> {
-        let eps = thickness / 2.;
+    fn stroke_aligned<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        let effects = brush.effects(canvas);
+        canvas.draw(RectPrimitive {
+            rect: self.rect,
+            rotation: self.rotation,
+            radii: self.radii,
+            color: brush.color(),
+            effects,
+            stroke_width: thickness.max(0.),
+            stroke_alignment: alignment,
+            ..Default::default()
+        })
+    }
+
+    fn stroke_dashed<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+        dash: &DashPattern,
+    ) -> ShapeRef<'c> {
+        self.stroke_dashed_pieces(canvas, brush, thickness, alignment, dash)
+    }
+}
+
+impl RoundedRect {
+    /// Stroke the rectangle with four dashed edge pieces and four dashed
+    /// corner pieces.
+    ///
+    /// The dashes run clockwise from the top-left corner, along the middle of
+    /// the stroke. The returned reference covers all eight pieces.
+    fn stroke_dashed_pieces<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+        dash: &DashPattern,
+    ) -> ShapeRef<'c> {
+        let first = canvas.buffer().len();
+        let outset = alignment.outset(thickness);
+        let inset = alignment.inset(thickness);
         let color = brush.color();
-        let half_size = self.rect.half_size();
-        let radii = Vec2::splat(self.radius).min(half_size);
+        let effects = brush.paint(canvas);
+        let min = self.rect.min;
+        let max = self.rect.max;
+        let radii = self.radii.clamped(self.rect.half_size());
 
-        // Top
-        let mut prim = RectPrimitive {
-            rect: Rect {
-                min: Vec2::new(self.rect.min.x + radii.x, self.rect.max.y - eps),
-                max: Vec2::new(self.rect.max.x - radii.x, self.rect.max.y + eps),
-            },
-            radius: 0.,
+        // All pieces are built in the local frame of the rectangle, then rotated
+        // around its center.
+        let center = self.rect.center();
+        let rotation = self.rotation;
+        let place = |p: Vec2| center + rotation * (p - center);
+        let piece = |rect: Rect, effects: Option<u32>| RectPrimitive {
+            rect: Rect::from_center_half_size(place(rect.center()), rect.half_size()),
+            rotation,
             color,
+            effects,
             ..Default::default()
         };
-        canvas.draw(prim);
 
-        // Bottom
-        prim.rect = Rect {
-            min: Vec2::new(self.rect.min.x + radii.x, self.rect.min.y - eps),
-            max: Vec2::new(self.rect.max.x - radii.x, self.rect.min.y + eps),
+        // Arc length along the middle of the stroke at the start of each edge
+        // and corner, clockwise from the start of the top edge. Square corners
+        // have a zero length.
+        let middle = (outset - inset) / 2.;
+        let corner_length = |radius: f32| {
+            if radius > 0. {
+                (radius + middle).max(0.) * FRAC_PI_2
+            } else {
+                0.
+            }
         };
-        canvas.draw(prim);
-
-        // Left
-        prim.rect = Rect {
-            min: Vec2::new(self.rect.min.x - eps, self.rect.min.y + radii.y),
-            max: Vec2::new(self.rect.min.x + eps, self.rect.max.y - radii.y),
+        let top_right_start = max.x - radii.top_right - min.x - radii.top_left;
+        let right_start = top_right_start + corner_length(radii.top_right);
+        let bottom_right_start = right_start + max.y - radii.top_right - min.y - radii.bottom_right;
+        let bottom_start = bottom_right_start + corner_length(radii.bottom_right);
+        let bottom_left_start =
+            bottom_start + max.x - radii.bottom_right - min.x - radii.bottom_left;
+        let left_start = bottom_left_start + corner_length(radii.bottom_left);
+        let top_left_start = left_start + max.y - radii.top_left - min.y - radii.bottom_left;
+        let straight = |start: f32, origin: Vec2, direction: Vec2| {
+            Dash::straight(*dash, start, place(origin), rotation * direction)
         };
-        canvas.draw(prim);
 
-        // Right (excluding corners)
-        prim.rect = Rect {
-            min: Vec2::new(self.rect.max.x - eps, self.rect.min.y + radii.y),
-            max: Vec2::new(self.rect.max.x + eps, self.rect.max.y - radii.y),
-        };
-        canvas.draw(prim);
+        // Top
+        let top_effects = canvas.add_dashed_effects(
+            effects,
+            straight(0., Vec2::new(min.x + radii.top_left, min.y), Vec2::X),
+        );
+        canvas.draw(piece(
+            Rect {
+                min: Vec2::new(min.x + radii.top_left, min.y - outset),
+                max: Vec2::new(max.x - radii.top_right, min.y + inset),
+            },
+            top_effects,
+        ));
 
-        // Top-left corner
-        canvas.draw(QuarterPiePrimitive {
-            origin: Vec2::new(self.rect.min.x + radii.x, self.rect.max.y - radii.y),
-            radii,
-            color,
-            flip_x: true,
-            flip_y: false,
-        });
+        // Bottom
+        let bottom_effects = canvas.add_dashed_effects(
+            effects,
+            straight(
+                bottom_start,
+                Vec2::new(max.x - radii.bottom_right, max.y),
+                Vec2::NEG_X,
+            ),
+        );
+        canvas.draw(piece(
+            Rect {
+                min: Vec2::new(min.x + radii.bottom_left, max.y - inset),
+                max: Vec2::new(max.x - radii.bottom_right, max.y + outset),
+            },
+            bottom_effects,
+        ));
 
-        // Top-right corner
-        canvas.draw(QuarterPiePrimitive {
-            origin: self.rect.max - radii,
-            radii,
-            color,
-            flip_x: false,
-            flip_y: false,
-        });
+        // Left
+        let left_effects = canvas.add_dashed_effects(
+            effects,
+            straight(
+                left_start,
+                Vec2::new(min.x, max.y - radii.bottom_left),
+                Vec2::NEG_Y,
+            ),
+        );
+        canvas.draw(piece(
+            Rect {
+                min: Vec2::new(min.x - outset, min.y + radii.top_left),
+                max: Vec2::new(min.x + inset, max.y - radii.bottom_left),
+            },
+            left_effects,
+        ));
 
-        // Bottom-left corner
-        canvas.draw(QuarterPiePrimitive {
-            origin: self.rect.min + radii,
+        // Right
+        let right_effects = canvas.add_dashed_effects(
+            effects,
+            straight(
+                right_start,
+                Vec2::new(max.x, min.y + radii.top_right),
+                Vec2::Y,
+            ),
+        );
+        canvas.draw(piece(
+            Rect {
+                min: Vec2::new(max.x - inset, min.y + radii.top_right),
+                max: Vec2::new(max.x + outset, max.y - radii.bottom_right),
+            },
+            right_effects,
+        ));
+
+        // Corners, as quarter rings if rounded, or as small squares joining both
+        // edges otherwise. Each corner is given by its position, the diagonal
+        // direction pointing outward, its radius, the start angle of its arc, the
+        // arc length at its start, and the direction of the incoming edge. With
+        // the Y axis pointing down, angles increase clockwise on screen.
+        let angle = rotation.as_radians();
+        let [top_right, top_left, bottom_left, bottom_right] = [
+            (
+                Vec2::new(max.x, min.y),
+                Vec2::new(1., -1.),
+                radii.top_right,
+                -FRAC_PI_2,
+                top_right_start,
+                Vec2::X,
+            ),
+            (
+                min,
+                Vec2::NEG_ONE,
+                radii.top_left,
+                PI,
+                top_left_start,
+                Vec2::NEG_Y,
+            ),
+            (
+                Vec2::new(min.x, max.y),
+                Vec2::new(-1., 1.),
+                radii.bottom_left,
+                FRAC_PI_2,
+                bottom_left_start,
+                Vec2::NEG_X,
+            ),
+            (
+                max,
+                Vec2::ONE,
+                radii.bottom_right,
+                0.,
+                bottom_right_start,
+                Vec2::Y,
+            ),
+        ]
+        .map(
+            |(corner, dir, radius, start_angle, dash_start, edge_dir)| -> Primitive {
+                if radius > 0. {
+                    // The dashes run clockwise around the corner, starting from the
+                    // outward normal of the incoming edge.
+                    let center = place(corner - dir * radius);
+                    let effects = canvas.add_dashed_effects(
+                        effects,
+                        Dash::circular(
+                            *dash,
+                            dash_start,
+                            center,
+                            rotation * -edge_dir.perp(),
+                            (radius + middle).max(0.),
+                        ),
+                    );
+                    ArcPrimitive {
+                        center,
+                        inner_radius: (radius - inset).max(0.),
+                        outer_radius: radius + outset,
+                        start_angle: start_angle + angle,
+                        sweep_angle: FRAC_PI_2,
+                        color,
+                        effects,
+                        ..Default::default()
+                    }
+                    .into()
+                } else {
+                    let center = corner + dir * ((outset - inset) / 2.);
+                    piece(
+                        Rect::from_center_half_size(center, Vec2::splat((outset + inset) / 2.)),
+                        canvas.add_dashed_effects(effects, straight(dash_start, corner, edge_dir)),
+                    )
+                    .into()
+                }
+            },
+        );
+        canvas.draw(top_right);
+        canvas.draw(top_left);
+        canvas.draw(bottom_left);
+        canvas.draw(bottom_right);
+        canvas.shape_ref(first)
+    }
+}
+
+/// Capsule (pill) shape.
+///
+/// The capsule is a rectangle with two half-disc ends. Unlike a
+/// [`RoundedRect`] whose radii equal half its height, the capsule is rendered
+/// with its own exact signed distance function, so its stroke and border
+/// follow its edge exactly. This covers progress bars, toggles, tags, _etc._
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(DARK_GRAY.into());
+/// # let border_brush = ctx.solid_brush(WHITE.into());
+/// // Toggle background
+/// let rect = Rect::new(0., 0., 40., 20.);
+/// ctx.fill(Capsule::from_rect(rect), &brush)
+///     .border(&border_brush, 1.);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Capsule {
+    /// The center of the capsule.
+    pub center: Vec2,
+    /// Half the length of the capsule, excluding the half-disc ends.
+    pub half_length: f32,
+    /// The radius of the half-disc ends, which is also half the capsule
+    /// width.
+    pub radius: f32,
+    /// The rotation of the capsule around its center. With a zero rotation,
+    /// the capsule is horizontal.
+    pub rotation: Rot2,
+}
+
+impl Capsule {
+    /// Create a new horizontal capsule.
+    ///
+    /// The half length excludes the half-disc ends, so the capsule spans
+    /// `half_length + radius` on each side of the center.
+    pub fn new(center: Vec2, half_length: f32, radius: f32) -> Self {
+        Self {
+            center,
+            half_length,
+            radius,
+            rotation: Rot2::IDENTITY,
+        }
+    }
+
+    /// Create a new vertical capsule.
+    ///
+    /// The half length excludes the half-disc ends, so the capsule spans
+    /// `half_length + radius` above and below the center.
+    pub fn vertical(center: Vec2, half_length: f32, radius: f32) -> Self {
+        Self::new(center, half_length, radius).with_rotation(Rot2::FRAC_PI_2)
+    }
+
+    /// Create the capsule inscribed in the given rectangle.
+    ///
+    /// The capsule is horizontal if the rectangle is wider than tall, and
+    /// vertical otherwise.
+    pub fn from_rect(rect: Rect) -> Self {
+        let half_size = rect.half_size();
+        if half_size.x >= half_size.y {
+            Self::new(rect.center(), half_size.x - half_size.y, half_size.y)
+        } else {
+            Self::vertical(rect.center(), half_size.y - half_size.x, half_size.x)
+        }
+    }
+
+    /// Set the rotation of the capsule around its center.
+    ///
+    /// The rotation can be a [`Rot2`], or an `f32` angle in radians.
+    pub fn with_rotation(mut self, rotation: impl Into<Rot2>) -> Self {
+        self.rotation = rotation.into();
+        self
+    }
+
+    fn draw<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        stroke_width: f32,
+        stroke_alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        let effects = brush.effects(canvas);
+        canvas.draw(CapsulePrimitive {
+            center: self.center,
+            half_length: self.half_length,
+            radius: self.radius,
+            rotation: self.rotation,
+            color: brush.color(),
+            effects,
+            stroke_width,
+            stroke_alignment,
+            ..Default::default()
+        })
+    }
+}
+
+impl Shape for Capsule {
+    fn fill<'c>(&self, canvas: &'c mut Canvas, brush: &Brush) -> ShapeRef<'c> {
+        self.draw(canvas, brush, 0., default())
+    }
+
+    fn stroke_aligned<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        self.draw(canvas, brush, thickness.max(0.), alignment)
+    }
+}
+
+/// Ellipse shape.
+///
+/// Unlike [`RoundedRect::circle()`], the ellipse is rendered with its own
+/// exact signed distance function, and can be stroked as a single primitive.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Ellipse {
+    /// The center of the ellipse.
+    pub center: Vec2,
+    /// The radii of the ellipse along its local X and Y axes.
+    pub radii: Vec2,
+    /// The rotation of the ellipse around its center.
+    pub rotation: Rot2,
+}
+
+impl Ellipse {
+    /// Create a new axis-aligned ellipse from its center and radii.
+    pub fn new(center: Vec2, radii: Vec2) -> Self {
+        Self {
+            center,
             radii,
-            color,
-            flip_x: true,
-            flip_y: true,
+            rotation: Rot2::IDENTITY,
+        }
+    }
+
+    /// Create a circle shape.
+    ///
+    /// This creates an ellipse whose two radii are equal.
+    pub fn circle(center: Vec2, radius: f32) -> Self {
+        Self::new(center, Vec2::splat(radius))
+    }
+
+    /// Create the ellipse inscribed in the given rectangle.
+    pub fn from_rect(rect: Rect) -> Self {
+        Self::new(rect.center(), rect.half_size())
+    }
+
+    /// Set the rotation of the ellipse around its center.
+    ///
+    /// The rotation can be a [`Rot2`], or an `f32` angle in radians.
+    pub fn with_rotation(mut self, rotation: impl Into<Rot2>) -> Self {
+        self.rotation = rotation.into();
+        self
+    }
+}
+
+impl Shape for Ellipse {
+    fn fill<'c>(&self, canvas: &'c mut Canvas, brush: &Brush) -> ShapeRef<'c> {
+        let effects = brush.effects(canvas);
+        canvas.draw(EllipsePrimitive {
+            center: self.center,
+            radii: self.radii,
+            rotation: self.rotation,
+            color: brush.color(),
+            effects,
+            ..Default::default()
+        })
+    }
+
+    fn stroke_aligned<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        let effects = brush.effects(canvas);
+        canvas.draw(EllipsePrimitive {
+            center: self.center,
+            radii: self.radii,
+            rotation: self.rotation,
+            color: brush.color(),
+            effects,
+            stroke_width: thickness.max(0.),
+            stroke_alignment: alignment,
+            ..Default::default()
+        })
+    }
+}
+
+/// Arc shape, which is a sector of an annulus.
+///
+/// The arc is the region between two concentric circles, limited to an angular
+/// range. Angles are in radians, and go from the X axis toward the Y axis. This
+/// covers progress rings, cooldown timers, donut chart slices, _etc._
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(RED.into());
+/// # let progress = 0.3;
+/// let ring = bevy_keith::Arc::new(Vec2::ZERO, 20., 24., 0., progress * std::f32::consts::TAU)
+///     .with_rounded_caps(true);
+/// ctx.fill(ring, &brush);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Arc {
+    /// The center of the circles the arc is part of.
+    pub center: Vec2,
+    /// The inner radius of the arc. Zero for a pie.
+    pub inner_radius: f32,
+    /// The outer radius of the arc.
+    pub outer_radius: f32,
+    /// The angle where the arc starts, in radians.
+    pub start_angle: f32,
+    /// The angle spanned by the arc, in radians. Negative values sweep in the
+    /// opposite direction.
+    pub sweep_angle: f32,
+    /// Round the caps at both ends of the arc.
+    pub rounded_caps: bool,
+}
+
+impl Arc {
+    /// Create a new arc from its center, radii, and angular range.
+    pub fn new(
+        center: Vec2,
+        inner_radius: f32,
+        outer_radius: f32,
+        start_angle: f32,
+        sweep_angle: f32,
+    ) -> Self {
+        Self {
+            center,
+            inner_radius,
+            outer_radius,
+            start_angle,
+            sweep_angle,
+            rounded_caps: false,
+        }
+    }
+
+    /// Create a pie, which is an arc with a zero inner radius.
+    pub fn pie(center: Vec2, radius: f32, start_angle: f32, sweep_angle: f32) -> Self {
+        Self::new(center, 0., radius, start_angle, sweep_angle)
+    }
+
+    /// Create a full annulus (ring).
+    pub fn ring(center: Vec2, inner_radius: f32, outer_radius: f32) -> Self {
+        Self::new(center, inner_radius, outer_radius, 0., TAU)
+    }
+
+    /// Set whether the caps at both ends of the arc are rounded.
+    pub fn with_rounded_caps(mut self, rounded_caps: bool) -> Self {
+        self.rounded_caps = rounded_caps;
+        self
+    }
+
+    fn draw<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        stroke_width: f32,
+        stroke_alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        let effects = brush.effects(canvas);
+        canvas.draw(ArcPrimitive {
+            center: self.center,
+            inner_radius: self.inner_radius,
+            outer_radius: self.outer_radius,
+            start_angle: self.start_angle,
+            sweep_angle: self.sweep_angle,
+            rounded_caps: self.rounded_caps,
+            color: brush.color(),
+            effects,
+            stroke_width,
+            stroke_alignment,
+            ..Default::default()
+        })
+    }
+}
+
+impl Shape for Arc {
+    fn fill<'c>(&self, canvas: &'c mut Canvas, brush: &Brush) -> ShapeRef<'c> {
+        self.draw(canvas, brush, 0., default())
+    }
+
+    fn stroke_aligned<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        self.draw(canvas, brush, thickness.max(0.), alignment)
+    }
+}
+
+/// Maximum number of vertices of a [`Polygon`]. Extra vertices are ignored.
+pub const MAX_POLYGON_VERTICES: usize = 16;
+
+/// Polygon shape, with up to [`MAX_POLYGON_VERTICES`] vertices.
+///
+/// The polygon is closed implicitly between its last and first vertices. It's
+/// rendered as a single primitive with an exact signed distance function, which
+/// makes it cheaper than the equivalent [`Path`]. The vertices can be in any
+/// order, but the edges should not cross each other. This covers triangles,
+/// quads, arrowheads, chevrons, isometric tiles, _etc._
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(RED.into());
+/// # let border_brush = ctx.solid_brush(WHITE.into());
+/// // Isometric tile
+/// let tile = shapes::Polygon::quad(
+///     Vec2::new(0., 16.),
+///     Vec2::new(32., 0.),
+///     Vec2::new(0., -16.),
+///     Vec2::new(-32., 0.),
+/// );
+/// ctx.fill(tile, &brush).border(&border_brush, 1.);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Polygon {
+    vertices: [Vec2; MAX_POLYGON_VERTICES],
+    count: u32,
+}
+
+impl Polygon {
+    /// Create a new polygon from its vertices.
+    ///
+    /// Only the first [`MAX_POLYGON_VERTICES`] vertices are used. A polygon
+    /// with fewer than 3 vertices is not drawn.
+    pub fn new(vertices: impl IntoIterator<Item = Vec2>) -> Self {
+        let mut polygon = Self::default();
+        for v in vertices.into_iter().take(MAX_POLYGON_VERTICES) {
+            polygon.vertices[polygon.count as usize] = v;
+            polygon.count += 1;
+        }
+        polygon
+    }
+
+    /// Create a triangle.
+    pub fn triangle(a: Vec2, b: Vec2, c: Vec2) -> Self {
+        Self::new([a, b, c])
+    }
+
+    /// Create a quadrilateral.
+    pub fn quad(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> Self {
+        Self::new([a, b, c, d])
+    }
+
+    /// The polygon vertices.
+    pub fn vertices(&self) -> &[Vec2] {
+        &self.vertices[..self.count as usize]
+    }
+
+    /// The bounding rectangle of the vertices.
+    pub fn bounds(&self) -> Rect {
+        let vertices = self.vertices();
+        if vertices.is_empty() {
+            return Rect::default();
+        }
+        let (min, max) = vertices
+            .iter()
+            .fold((vertices[0], vertices[0]), |(min, max), v| {
+                (min.min(*v), max.max(*v))
+            });
+        Rect { min, max }
+    }
+}
+
+impl Shape for Polygon {
+    fn fill<'c>(&self, canvas: &'c mut Canvas, brush: &Brush) -> ShapeRef<'c> {
+        let effects = brush.effects(canvas);
+        canvas.draw(PolygonPrimitive {
+            polygon: *self,
+            color: brush.color(),
+            effects,
+            ..Default::default()
+        })
+    }
+
+    fn stroke_aligned<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        let effects = brush.effects(canvas);
+        canvas.draw(PolygonPrimitive {
+            polygon: *self,
+            color: brush.color(),
+            effects,
+            stroke_width: thickness.max(0.),
+            stroke_alignment: alignment,
+            ..Default::default()
+        })
+    }
+}
+
+/// Regular polygon shape.
+///
+/// All the vertices are on a circle, and all the edges have the same length.
+/// With a zero rotation, a vertex points up, toward -Y. Unlike the equivalent
+/// [`Polygon`], the regular polygon is rendered with its own exact signed
+/// distance function, has any number of sides, and supports rounded corners.
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(RED.into());
+/// let hexagon = shapes::RegularPolygon::new(Vec2::ZERO, 20., 6).with_corner_radius(3.);
+/// ctx.fill(hexagon, &brush);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RegularPolygon {
+    /// The center of the polygon.
+    pub center: Vec2,
+    /// The distance from the center to the vertices.
+    pub radius: f32,
+    /// The number of sides. Polygons with fewer than 3 sides are not drawn.
+    pub sides: u32,
+    /// The rotation of the polygon around its center.
+    pub rotation: Rot2,
+    /// The radius of the rounded corners. Zero for sharp corners.
+    pub corner_radius: f32,
+}
+
+impl RegularPolygon {
+    /// Create a new regular polygon from its center, circumradius, and number
+    /// of sides.
+    pub fn new(center: Vec2, radius: f32, sides: u32) -> Self {
+        Self {
+            center,
+            radius,
+            sides,
+            rotation: Rot2::IDENTITY,
+            corner_radius: 0.,
+        }
+    }
+
+    /// Set the rotation of the polygon around its center.
+    ///
+    /// The rotation can be a [`Rot2`], or an `f32` angle in radians.
+    pub fn with_rotation(mut self, rotation: impl Into<Rot2>) -> Self {
+        self.rotation = rotation.into();
+        self
+    }
+
+    /// Set the radius of the rounded corners.
+    ///
+    /// The rounded corners stay inside the polygon with sharp corners.
+    pub fn with_corner_radius(mut self, corner_radius: f32) -> Self {
+        self.corner_radius = corner_radius;
+        self
+    }
+
+    fn draw<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        stroke_width: f32,
+        stroke_alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        // The inner vertices of the equivalent star are the middles of the edges
+        let points = if self.sides >= 3 { self.sides } else { 0 };
+        let inner_radius = self.radius * (PI / self.sides.max(1) as f32).cos();
+        let effects = brush.effects(canvas);
+        canvas.draw(StarPrimitive {
+            center: self.center,
+            points,
+            outer_radius: self.radius,
+            inner_radius,
+            rotation: self.rotation,
+            corner_radius: self.corner_radius,
+            color: brush.color(),
+            effects,
+            stroke_width,
+            stroke_alignment,
+            ..Default::default()
+        })
+    }
+}
+
+impl Shape for RegularPolygon {
+    fn fill<'c>(&self, canvas: &'c mut Canvas, brush: &Brush) -> ShapeRef<'c> {
+        self.draw(canvas, brush, 0., default())
+    }
+
+    fn stroke_aligned<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        self.draw(canvas, brush, thickness.max(0.), alignment)
+    }
+}
+
+/// Star shape.
+///
+/// The star alternates between outer vertices, its tips, and inner vertices
+/// halfway in between. With a zero rotation, a tip points up, toward -Y. The star
+/// is rendered with its own exact signed distance function.
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(GOLD.into());
+/// # let border_brush = ctx.solid_brush(ORANGE.into());
+/// // Rating star
+/// let star = Star::new(Vec2::ZERO, 5, 6., 15.).with_corner_radius(1.5);
+/// ctx.fill(star, &brush).border(&border_brush, 1.);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Star {
+    /// The center of the star.
+    pub center: Vec2,
+    /// The number of tips. Stars with fewer than 2 tips are not drawn.
+    pub points: u32,
+    /// The distance from the center to the inner vertices.
+    pub inner_radius: f32,
+    /// The distance from the center to the tips.
+    pub outer_radius: f32,
+    /// The rotation of the star around its center.
+    pub rotation: Rot2,
+    /// The radius of the rounded tips. Zero for sharp tips. The inner
+    /// vertices are never rounded.
+    pub corner_radius: f32,
+}
+
+impl Star {
+    /// Create a new star from its center, number of tips, and radii.
+    pub fn new(center: Vec2, points: u32, inner_radius: f32, outer_radius: f32) -> Self {
+        Self {
+            center,
+            points,
+            inner_radius,
+            outer_radius,
+            rotation: Rot2::IDENTITY,
+            corner_radius: 0.,
+        }
+    }
+
+    /// Set the rotation of the star around its center.
+    ///
+    /// The rotation can be a [`Rot2`], or an `f32` angle in radians.
+    pub fn with_rotation(mut self, rotation: impl Into<Rot2>) -> Self {
+        self.rotation = rotation.into();
+        self
+    }
+
+    /// Set the radius of the rounded tips.
+    ///
+    /// The rounded tips stay inside the star with sharp tips.
+    pub fn with_corner_radius(mut self, corner_radius: f32) -> Self {
+        self.corner_radius = corner_radius;
+        self
+    }
+
+    fn draw<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        stroke_width: f32,
+        stroke_alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        let effects = brush.effects(canvas);
+        canvas.draw(StarPrimitive {
+            center: self.center,
+            points: self.points,
+            outer_radius: self.outer_radius,
+            inner_radius: self.inner_radius,
+            rotation: self.rotation,
+            corner_radius: self.corner_radius,
+            color: brush.color(),
+            effects,
+            stroke_width,
+            stroke_alignment,
+            ..Default::default()
+        })
+    }
+}
+
+impl Shape for Star {
+    fn fill<'c>(&self, canvas: &'c mut Canvas, brush: &Brush) -> ShapeRef<'c> {
+        self.draw(canvas, brush, 0., default())
+    }
+
+    fn stroke_aligned<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        self.draw(canvas, brush, thickness.max(0.), alignment)
+    }
+}
+
+/// Maximum distance, in physical pixels, between a curve of a [`Path`] and the
+/// line segments approximating it once flattened.
+pub(crate) const FLATTENING_TOLERANCE: f32 = 0.1;
+
+/// Maximum number of line segments a single curve of a [`Path`] is flattened
+/// into, to bound the shader cost of very large curves.
+const MAX_CURVE_SEGMENTS: u32 = 64;
+
+/// Element of a [`Path`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathEl {
+    /// Start a new contour at the given point.
+    MoveTo(Vec2),
+    /// Line segment from the current point to the given point.
+    LineTo(Vec2),
+    /// Quadratic Bézier curve from the current point, with a control point and
+    /// an end point.
+    QuadTo(Vec2, Vec2),
+    /// Cubic Bézier curve from the current point, with two control points and
+    /// an end point.
+    CubicTo(Vec2, Vec2, Vec2),
+    /// Close the current contour, joining its last point back to its first one.
+    ClosePath,
+}
+
+/// Arbitrary outline made of lines and Bézier curves.
+///
+/// A path is made of one or more contours, each started by
+/// [`move_to()`](Path::move_to) and optionally terminated by
+/// [`close()`](Path::close). Curves are flattened into line segments when the
+/// canvas is rendered, at the physical resolution of the screen and after any
+/// transform, but the path is still rendered as a single primitive, so there's
+/// no seam between its segments. Filling uses the non-zero winding rule, and
+/// implicitly closes all contours.
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(RED.into());
+/// let path = Path::new()
+///     .move_to(Vec2::new(0., 0.))
+///     .line_to(Vec2::new(100., 0.))
+///     .quad_to(Vec2::new(150., 50.), Vec2::new(100., 100.))
+///     .cubic_to(Vec2::new(70., 130.), Vec2::new(30., 70.), Vec2::new(0., 100.))
+///     .close();
+/// ctx.fill(&path, &brush);
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Path {
+    /// Path elements, in order.
+    elements: Vec<PathEl>,
+}
+
+impl Path {
+    /// Create a new empty path.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a new contour at the given point.
+    pub fn move_to(mut self, p: Vec2) -> Self {
+        self.elements.push(PathEl::MoveTo(p));
+        self
+    }
+
+    /// Add a line segment from the current point to the given point.
+    ///
+    /// If there's no current contour, this starts a new one at the given point
+    /// instead.
+    pub fn line_to(mut self, p: Vec2) -> Self {
+        self.elements.push(PathEl::LineTo(p));
+        self
+    }
+
+    /// Add a quadratic Bézier curve from the current point to the given end
+    /// point, using the given control point.
+    pub fn quad_to(mut self, ctrl: Vec2, p: Vec2) -> Self {
+        self.elements.push(PathEl::QuadTo(ctrl, p));
+        self
+    }
+
+    /// Add a cubic Bézier curve from the current point to the given end point,
+    /// using the given two control points.
+    pub fn cubic_to(mut self, ctrl0: Vec2, ctrl1: Vec2, p: Vec2) -> Self {
+        self.elements.push(PathEl::CubicTo(ctrl0, ctrl1, p));
+        self
+    }
+
+    /// Close the current contour.
+    ///
+    /// This adds an implicit line segment from the current point back to the
+    /// first point of the contour, which becomes the new current point.
+    pub fn close(mut self) -> Self {
+        self.elements.push(PathEl::ClosePath);
+        self
+    }
+
+    /// Get the elements of the path.
+    pub fn elements(&self) -> &[PathEl] {
+        &self.elements[..]
+    }
+
+    /// Is the path empty?
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Create an open path joining the given points with line segments.
+    pub(crate) fn from_points(points: impl IntoIterator<Item = Vec2>) -> Self {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Self::new();
+        };
+        let mut elements = vec![PathEl::MoveTo(first)];
+        elements.extend(points.map(PathEl::LineTo));
+        Self { elements }
+    }
+
+    /// Get the bounding rectangle of all the points of the path, including the
+    /// control points of its curves.
+    ///
+    /// Curves are contained in the convex hull of their control points, so this
+    /// encloses the entire path.
+    pub(crate) fn bounds(&self) -> Rect {
+        let mut points = self.elements.iter().flat_map(|el| match *el {
+            PathEl::MoveTo(p) | PathEl::LineTo(p) => [Some(p), None, None],
+            PathEl::QuadTo(c, p) => [Some(c), Some(p), None],
+            PathEl::CubicTo(c0, c1, p) => [Some(c0), Some(c1), Some(p)],
+            PathEl::ClosePath => [None, None, None],
         });
+        let Some(first) = points.by_ref().flatten().next() else {
+            return Rect::default();
+        };
+        points
+            .flatten()
+            .fold(Rect::from_corners(first, first), |rect, p| {
+                rect.union_point(p)
+            })
+    }
 
-        // Bottom-right corner
-        canvas.draw(QuarterPiePrimitive {
-            origin: Vec2::new(self.rect.max.x - radii.x, self.rect.min.y + radii.y),
-            radii,
-            color,
-            flip_x: false,
-            flip_y: true,
+    /// Transform all the points of the path.
+    ///
+    /// Bézier curves are transformed exactly by transforming their control
+    /// points, so this supports any affine transform.
+    pub(crate) fn apply_transform(&mut self, transform: &Affine2) {
+        for el in &mut self.elements {
+            match el {
+                PathEl::MoveTo(p) | PathEl::LineTo(p) => *p = transform.transform_point2(*p),
+                PathEl::QuadTo(c, p) => {
+                    *c = transform.transform_point2(*c);
+                    *p = transform.transform_point2(*p);
+                }
+                PathEl::CubicTo(c0, c1, p) => {
+                    *c0 = transform.transform_point2(*c0);
+                    *c1 = transform.transform_point2(*c1);
+                    *p = transform.transform_point2(*p);
+                }
+                PathEl::ClosePath => {}
+            }
+        }
+    }
+
+    /// Flatten the path into line segments, approximating curves with the
+    /// given tolerance.
+    pub(crate) fn flatten(&self, tolerance: f32) -> FlattenedPath {
+        let mut path = FlattenedPath::default();
+        // First point of the current contour
+        let mut start = Vec2::ZERO;
+        // Current point, if any
+        let mut current: Option<Vec2> = None;
+        // Number of points in the current contour
+        let mut count = 0;
+        for el in &self.elements {
+            match *el {
+                PathEl::MoveTo(p) => {
+                    Self::finish_contour(&mut path, &mut count, false);
+                    current = Some(p);
+                }
+                PathEl::LineTo(p) => {
+                    // Without a current point, this behaves like move_to()
+                    if let Some(from) = current {
+                        Self::begin_contour(&mut path, &mut count, &mut start, from);
+                        path.points.push(p);
+                        count += 1;
+                    }
+                    current = Some(p);
+                }
+                PathEl::QuadTo(c, p) => {
+                    // Without a current point, start from the control point
+                    let from = current.unwrap_or(c);
+                    Self::begin_contour(&mut path, &mut count, &mut start, from);
+                    let dd = (from - 2. * c + p).length();
+                    let n = Self::segment_count(dd / (4. * tolerance));
+                    for i in 1..=n {
+                        let t = i as f32 / n as f32;
+                        let mt = 1. - t;
+                        path.points
+                            .push(from * (mt * mt) + c * (2. * mt * t) + p * (t * t));
+                    }
+                    count += n;
+                    current = Some(p);
+                }
+                PathEl::CubicTo(c0, c1, p) => {
+                    // Without a current point, start from the first control point
+                    let from = current.unwrap_or(c0);
+                    Self::begin_contour(&mut path, &mut count, &mut start, from);
+                    let dd = (from - 2. * c0 + c1)
+                        .length()
+                        .max((c0 - 2. * c1 + p).length());
+                    let n = Self::segment_count(3. * dd / (4. * tolerance));
+                    for i in 1..=n {
+                        let t = i as f32 / n as f32;
+                        let mt = 1. - t;
+                        path.points.push(
+                            from * (mt * mt * mt)
+                                + c0 * (3. * mt * mt * t)
+                                + c1 * (3. * mt * t * t)
+                                + p * (t * t * t),
+                        );
+                    }
+                    count += n;
+                    current = Some(p);
+                }
+                PathEl::ClosePath => {
+                    if count > 0 {
+                        current = Some(start);
+                    }
+                    Self::finish_contour(&mut path, &mut count, true);
+                }
+            }
+        }
+        Self::finish_contour(&mut path, &mut count, false);
+        path
+    }
+
+    /// Begin a new contour at the given point, unless a contour is already
+    /// being built.
+    fn begin_contour(path: &mut FlattenedPath, count: &mut u32, start: &mut Vec2, from: Vec2) {
+        if *count == 0 {
+            path.points.push(from);
+            *count = 1;
+            *start = from;
+        }
+    }
+
+    /// Number of line segments to flatten a curve into, given the ratio of its
+    /// squared flatness over the tolerance.
+    fn segment_count(ratio: f32) -> u32 {
+        (ratio.sqrt().ceil() as u32).clamp(1, MAX_CURVE_SEGMENTS)
+    }
+
+    /// Finish the current contour made of the last `count` points. Contours
+    /// with less than 2 points don't have any segment, and are discarded.
+    fn finish_contour(path: &mut FlattenedPath, count: &mut u32, closed: bool) {
+        if *count >= 2 {
+            path.contours.push(PathContour {
+                point_count: *count,
+                closed,
+            });
+        } else {
+            let len = path.points.len() - *count as usize;
+            path.points.truncate(len);
+        }
+        *count = 0;
+    }
+
+    fn draw<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        stroke_width: f32,
+        stroke_alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        let bounds = self.bounds();
+        let id = canvas.add_path(self.clone());
+        let effects = brush.effects(canvas);
+        canvas.draw(PathPrimitive {
+            id,
+            bounds,
+            color: brush.color(),
+            effects,
+            stroke_width,
+            stroke_alignment,
+            ..Default::default()
         })
     }
 }
+
+impl Shape for Path {
+    fn fill<'c>(&self, canvas: &'c mut Canvas, brush: &Brush) -> ShapeRef<'c> {
+        self.draw(canvas, brush, 0., default())
+    }
+
+    fn stroke_aligned<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        self.draw(canvas, brush, thickness.max(0.), alignment)
+    }
+}
+
+impl Shape for &Path {
+    fn fill<'c>(&self, canvas: &'c mut Canvas, brush: &Brush) -> ShapeRef<'c> {
+        (*self).fill(canvas, brush)
+    }
+
+    fn stroke_aligned<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        thickness: f32,
+        alignment: StrokeAlignment,
+    ) -> ShapeRef<'c> {
+        (*self).stroke_aligned(canvas, brush, thickness, alignment)
+    }
+}
+
+/// Rigid transform in 2D, made of a rotation followed by a translation.
+///
+/// This places a Bevy primitive shape on the canvas, see [`Placed`].
+///
+/// This is a stand-in for `bevy::math::Isometry2d`, which only exists from
+/// Bevy 0.15. It will be replaced by the Bevy type when migrating to that
+/// version, so it already follows the same layout and naming.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Isometry2d {
+    /// The rotation, applied first around the origin.
+    pub rotation: Rot2,
+    /// The translation, applied after the rotation.
+    pub translation: Vec2,
+}
+
+impl Isometry2d {
+    /// The identity isometry, which leaves points unchanged.
+    pub const IDENTITY: Self = Self {
+        rotation: Rot2::IDENTITY,
+        translation: Vec2::ZERO,
+    };
+
+    /// Create a new isometry from a translation and a rotation.
+    ///
+    /// The rotation can be a [`Rot2`], or an `f32` angle in radians.
+    pub fn new(translation: Vec2, rotation: impl Into<Rot2>) -> Self {
+        Self {
+            rotation: rotation.into(),
+            translation,
+        }
+    }
+
+    /// Create a new isometry from a translation only.
+    pub fn from_translation(translation: Vec2) -> Self {
+        Self {
+            rotation: Rot2::IDENTITY,
+            translation,
+        }
+    }
+
+    /// Create a new isometry from a rotation only.
+    ///
+    /// The rotation can be a [`Rot2`], or an `f32` angle in radians.
+    pub fn from_rotation(rotation: impl Into<Rot2>) -> Self {
+        Self {
+            rotation: rotation.into(),
+            translation: Vec2::ZERO,
+        }
+    }
+
+    /// Transform a point by the isometry.
+    pub fn transform_point(&self, p: Vec2) -> Vec2 {
+        self.rotation * p + self.translation
+    }
+}
+
+impl From<Vec2> for Isometry2d {
+    fn from(translation: Vec2) -> Self {
+        Self::from_translation(translation)
+    }
+}
+
+impl From<Rot2> for Isometry2d {
+    fn from(rotation: Rot2) -> Self {
+        Self::from_rotation(rotation)
+    }
+}
+
+/// A Bevy primitive shape placed on the canvas by an [`Isometry2d`].
+///
+/// Bevy's primitive shapes are defined around the origin. This places them on
+/// the canvas so they can be filled or stroked like any other [`Shape`]. The
+/// supported primitives are [`Circle`], [`Ellipse`](primitives::Ellipse),
+/// [`Rectangle`], [`Capsule2d`], [`Triangle2d`],
+/// [`RegularPolygon`](primitives::RegularPolygon),
+/// [`Annulus`] and [`Segment2d`].
+///
+/// The easiest way to build a `Placed` is [`Primitive2dExt::at()`]:
+///
+/// ```no_run
+/// # use bevy_keith::*;
+/// # use bevy::{prelude::*, color::palettes::css::*};
+/// # let mut canvas = Canvas::default();
+/// # let mut ctx = RenderContext::new(&mut canvas);
+/// # let brush = ctx.solid_brush(RED.into());
+/// let capsule = Capsule2d::new(5., 20.);
+/// ctx.fill(capsule.at(Isometry2d::new(Vec2::new(50., 30.), 0.4)), &brush);
+/// ctx.fill(Circle::new(8.).at(Vec2::new(-20., 0.)), &brush);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Placed<P: Primitive2d> {
+    /// The primitive shape, defined around the origin.
+    pub primitive: P,
+    /// The isometry placing the primitive on the canvas.
+    pub isometry: Isometry2d,
+}
+
+impl<P: Primitive2d> Placed<P> {
+    /// Place a primitive shape with the given isometry.
+    ///
+    /// The isometry can be an [`Isometry2d`], or a [`Vec2`] translation, or a
+    /// [`Rot2`] rotation.
+    pub fn new(primitive: P, isometry: impl Into<Isometry2d>) -> Self {
+        Self {
+            primitive,
+            isometry: isometry.into(),
+        }
+    }
+}
+
+/// Extension trait to place Bevy primitive shapes on a canvas.
+pub trait Primitive2dExt: Primitive2d + Sized {
+    /// Place the primitive shape with the given isometry.
+    ///
+    /// This is a shorthand for [`Placed::new()`].
+    fn at(self, isometry: impl Into<Isometry2d>) -> Placed<Self> {
+        Placed::new(self, isometry)
+    }
+}
+
+impl<P: Primitive2d> Primitive2dExt for P {}
+
+/// Shape equivalent to a [`Placed`] primitive.
+enum PlacedShape {
+    Rect(RoundedRect),
+    Capsule(Capsule),
+    Ellipse(Ellipse),
+    Arc(Arc),
+    Polygon(Polygon),
+    RegularPolygon(RegularPolygon),
+    Path(Path),
+    Segment(Vec2, Vec2),
+}
+
+impl PlacedShape {
+    /// Closed polygon through the given points, transformed by the isometry.
+    ///
+    /// Polygons with too many vertices for a [`Polygon`] fall back to a path.
+    fn polygon(points: impl IntoIterator<Item = Vec2>, isometry: &Isometry2d) -> Self {
+        let points: Vec<Vec2> = points
+            .into_iter()
+            .map(|p| isometry.transform_point(p))
+            .collect();
+        if points.len() <= MAX_POLYGON_VERTICES {
+            return Self::Polygon(Polygon::new(points));
+        }
+        let mut path = Path::new().move_to(points[0]);
+        for p in &points[1..] {
+            path = path.line_to(*p);
+        }
+        Self::Path(path.close())
+    }
+
+    fn draw<'c>(
+        &self,
+        canvas: &'c mut Canvas,
+        brush: &Brush,
+        stroke: Option<(f32, StrokeAlignment)>,
+        dash: Option<&DashPattern>,
+    ) -> ShapeRef<'c> {
+        let shape: &dyn Shape = match self {
+            Self::Rect(r) => r,
+            Self::Capsule(c) => c,
+            Self::Ellipse(e) => e,
+            Self::Arc(a) => a,
+            Self::Polygon(p) => p,
+            Self::RegularPolygon(p) => p,
+            Self::Path(p) => p,
+            Self::Segment(p0, p1) => {
+                // A segment has no inside, so the stroke is always centered,
+                // and filling draws a hairline.
+                let thickness = stroke.map_or(1., |(thickness, _)| thickness.max(0.));
+                let effects = match dash {
+                    Some(dash) => {
+                        let effects = brush.paint(canvas);
+                        canvas
+                            .add_dashed_effects(effects, Dash::straight(*dash, 0., *p0, *p1 - *p0))
+                    }
+                    None => brush.effects(canvas),
+                };
+                return canvas.draw(LinePrimitive {
+                    start: *p0,
+                    end: *p1,
+                    color: brush.color(),
+                    thickness,
+                    effects,
+                    ..default()
+                });
+            }
+        };
+        match (stroke, dash) {
+            (None, _) => shape.fill(canvas, brush),
+            (Some((thickness, alignment)), None) => {
+                shape.stroke_aligned(canvas, brush, thickness, alignment)
+            }
+            (Some((thickness, alignment)), Some(dash)) => {
+                shape.stroke_dashed(canvas, brush, thickness, alignment, dash)
+            }
+        }
+    }
+}
+
+/// Implement [`Shape`] for a [`Placed`] primitive, from an expression
+/// converting the primitive and its isometry into a [`PlacedShape`].
+macro_rules! impl_placed_shape {
+    ($primitive:ty, |$prim:ident, $iso:ident| $shape:expr) => {
+        impl Placed<$primitive> {
+            fn shape(&self) -> PlacedShape {
+                let $prim = &self.primitive;
+                let $iso = &self.isometry;
+                $shape
+            }
+        }
+
+        impl Shape for Placed<$primitive> {
+            fn fill<'c>(&self, canvas: &'c mut Canvas, brush: &Brush) -> ShapeRef<'c> {
+                self.shape().draw(canvas, brush, None, None)
+            }
+
+            fn stroke_aligned<'c>(
+                &self,
+                canvas: &'c mut Canvas,
+                brush: &Brush,
+                thickness: f32,
+                alignment: StrokeAlignment,
+            ) -> ShapeRef<'c> {
+                let stroke = Some((thickness, alignment));
+                self.shape().draw(canvas, brush, stroke, None)
+            }
+
+            fn stroke_dashed<'c>(
+                &self,
+                canvas: &'c mut Canvas,
+                brush: &Brush,
+                thickness: f32,
+                alignment: StrokeAlignment,
+                dash: &DashPattern,
+            ) -> ShapeRef<'c> {
+                let stroke = Some((thickness, alignment));
+                self.shape().draw(canvas, brush, stroke, Some(dash))
+            }
+        }
+    };
+}
+
+impl_placed_shape!(Circle, |c, iso| {
+    PlacedShape::Ellipse(Ellipse::circle(iso.translation, c.radius))
+});
+
+impl_placed_shape!(primitives::Ellipse, |e, iso| {
+    PlacedShape::Ellipse(Ellipse::new(iso.translation, e.half_size).with_rotation(iso.rotation))
+});
+
+impl_placed_shape!(Rectangle, |r, iso| {
+    let rect = Rect::from_center_half_size(iso.translation, r.half_size);
+    PlacedShape::Rect(RoundedRect::new(rect, 0.).with_rotation(iso.rotation))
+});
+
+impl_placed_shape!(Capsule2d, |c, iso| {
+    // Bevy capsules are vertical
+    let rotation = iso.rotation * Rot2::FRAC_PI_2;
+    PlacedShape::Capsule(
+        Capsule::new(iso.translation, c.half_length, c.radius).with_rotation(rotation),
+    )
+});
+
+impl_placed_shape!(Triangle2d, |t, iso| {
+    PlacedShape::polygon(t.vertices, iso)
+});
+
+impl_placed_shape!(primitives::RegularPolygon, |p, iso| {
+    // Bevy places the first vertex toward +Y, opposite to the canvas shape
+    PlacedShape::RegularPolygon(
+        RegularPolygon::new(iso.translation, p.circumcircle.radius, p.sides as u32)
+            .with_rotation(iso.rotation * Rot2::PI),
+    )
+});
+
+impl_placed_shape!(Annulus, |a, iso| {
+    PlacedShape::Arc(Arc::ring(
+        iso.translation,
+        a.inner_circle.radius,
+        a.outer_circle.radius,
+    ))
+});
+
+impl_placed_shape!(Segment2d, |s, iso| {
+    PlacedShape::Segment(
+        iso.transform_point(s.point1()),
+        iso.transform_point(s.point2()),
+    )
+});
+
+#[cfg(test)]
+mod tests {
+    use std::{f32::consts::SQRT_2, mem::MaybeUninit};
+
+    use bevy::math::Dir2;
+
+    use super::*;
+    use crate::canvas::{PolylinePrimitive, PrimitiveInfo};
+
+    #[test]
+    fn border_any_primitive() {
+        use crate::canvas::{QuarterPiePrimitive, TextPrimitive};
+
+        let mut canvas = Canvas::default();
+        let brush = Brush::from(Color::WHITE);
+        let prims: [Primitive; 3] = [
+            TextPrimitive {
+                id: 0,
+                rect: Rect::default(),
+                rotation: Rot2::IDENTITY,
+                scale: 1.,
+                flip_y: false,
+                border_width: 0.,
+                border_color: Color::NONE,
+                effects: default(),
+            }
+            .into(),
+            QuarterPiePrimitive::default().into(),
+            RectPrimitive::default().into(),
+        ];
+        for prim in prims {
+            let mut sref = canvas.draw(prim);
+            assert!(!sref.prims[0].is_bordered());
+            sref.border(&brush, 2.);
+            assert!(sref.prims[0].is_bordered());
+        }
+    }
+
+    #[test]
+    fn corner_radii_clamped() {
+        let radii = CornerRadii::new(1., 8., -2., 3.).clamped(Vec2::new(10., 4.));
+        assert_eq!(radii, CornerRadii::new(1., 4., 0., 3.));
+
+        // Degenerate rectangle
+        let radii = CornerRadii::all(5.).clamped(Vec2::new(-1., 4.));
+        assert_eq!(radii, CornerRadii::ZERO);
+
+        assert_eq!(CornerRadii::from(2.), CornerRadii::all(2.));
+        assert_eq!(CornerRadii::top(2.), CornerRadii::new(2., 2., 0., 0.));
+    }
+
+    #[test]
+    fn corner_radii_screen_space() {
+        // The Y axis points down the screen, so the top-left corner is at the min
+        // corner of the rectangle
+        let rect = Rect::new(0., 0., 100., 50.);
+        let brush = Brush::from(Color::WHITE);
+        let mut canvas = Canvas::default();
+        // Dashed strokes are drawn in pieces, which shows where each corner lies
+        RoundedRect::new(rect, CornerRadii::new(8., 0., 0., 0.)).stroke_dashed(
+            &mut canvas,
+            &brush,
+            2.,
+            StrokeAlignment::Center,
+            &DashPattern::new([4.], 0.),
+        );
+        let arcs: Vec<_> = canvas
+            .buffer()
+            .iter()
+            .filter_map(|prim| match prim {
+                Primitive::Arc(arc) => Some(*arc),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(arcs.len(), 1);
+        assert_eq!(arcs[0].center, Vec2::new(8., 8.));
+        // The quarter ring points toward the top-left of the screen
+        let mid = Vec2::from_angle(arcs[0].start_angle + arcs[0].sweep_angle / 2.);
+        assert!(mid.abs_diff_eq(Vec2::NEG_ONE.normalize(), 1e-5));
+
+        // The top edge is on the min.y edge, and starts after the rounded corner
+        let Primitive::Rect(top) = canvas.buffer()[0] else {
+            panic!("Expected a rect for the top edge");
+        };
+        assert_eq!(top.rect, Rect::new(8., -1., 100., 1.));
+    }
+
+    #[test]
+    fn rect_stroke() {
+        // Solid strokes are a single stroked rectangle, so a border follows the
+        // whole outline of the stroke
+        let rect = Rect::new(0., 0., 100., 50.);
+        let brush = Brush::from(Color::WHITE);
+        for shape in [RoundedRect::new(rect, 0.), RoundedRect::new(rect, 8.)] {
+            let mut canvas = Canvas::default();
+            shape.stroke(&mut canvas, &brush, 4.).border(&brush, 1.);
+            assert_eq!(canvas.buffer().len(), 1);
+            let Primitive::Rect(prim) = canvas.buffer()[0] else {
+                panic!("Expected a rect for the stroke");
+            };
+            assert!(prim.is_stroked());
+            assert!(prim.is_bordered());
+            assert_eq!(prim.stroke_width, 4.);
+        }
+        let mut canvas = Canvas::default();
+        rect.stroke(&mut canvas, &brush, 4.);
+        assert_eq!(canvas.buffer().len(), 1);
+        assert!(matches!(canvas.buffer()[0], Primitive::Rect(r) if r.is_stroked()));
+    }
+
+    #[test]
+    fn stroke_alignment() {
+        assert_eq!(StrokeAlignment::Inside.outset(4.), 0.);
+        assert_eq!(StrokeAlignment::Center.outset(4.), 2.);
+        assert_eq!(StrokeAlignment::Outside.outset(4.), 4.);
+        assert_eq!(StrokeAlignment::Outside.inset(4.), 0.);
+        assert_eq!(StrokeAlignment::Center.outset(-1.), 0.);
+
+        // The union of the AABBs of all stroke pieces is the footprint of the stroke
+        let rect = Rect::new(0., 0., 100., 50.);
+        let brush = Brush::from(Color::WHITE);
+        for (alignment, outset) in [
+            (StrokeAlignment::Inside, 0.),
+            (StrokeAlignment::Center, 2.),
+            (StrokeAlignment::Outside, 4.),
+        ] {
+            for shape in [RoundedRect::new(rect, 0.), RoundedRect::new(rect, 8.)] {
+                let mut canvas = Canvas::default();
+                shape.stroke_aligned(&mut canvas, &brush, 4., alignment);
+                let mut min = Vec2::MAX;
+                let mut max = Vec2::MIN;
+                for prim in canvas.buffer() {
+                    let aabb = prim.aabb();
+                    min = min.min(aabb.min);
+                    max = max.max(aabb.max);
+                }
+                assert!(min.abs_diff_eq(rect.min - outset, 1e-4));
+                assert!(max.abs_diff_eq(rect.max + outset, 1e-4));
+            }
+
+            // Borders extending outside the shape grow the AABB used for tiling
+            let mut canvas = Canvas::default();
+            canvas
+                .draw(RectPrimitive { rect, ..default() })
+                .border_aligned(&brush, 4., alignment);
+            let aabb = canvas.buffer()[0].aabb();
+            assert_eq!(aabb.min, rect.min - outset);
+            assert_eq!(aabb.max, rect.max + outset);
+        }
+
+        // Borders set directly on a primitive stay inside it, like they always did
+        let prim: Primitive = RectPrimitive {
+            rect,
+            border_width: 4.,
+            ..default()
+        }
+        .into();
+        assert_eq!(prim.aabb().min, rect.min);
+        assert_eq!(prim.aabb().max, rect.max);
+        let PrimitiveInfo { row_count, .. } = prim.info(&[], &[], &EffectTables::default());
+        let mut rows = vec![MaybeUninit::uninit(); row_count as usize];
+        prim.write(
+            &[],
+            &[],
+            &EffectTables::default(),
+            &[],
+            &mut rows[..],
+            Vec2::ZERO,
+            1.,
+        );
+        let border = &rows[row_count as usize - 3..];
+        // Border width, then outset beyond the edge of the rectangle
+        assert_eq!(unsafe { border[0].assume_init() }, 4.);
+        assert_eq!(unsafe { border[1].assume_init() }, 0.);
+    }
+
+    #[test]
+    fn dashed_stroke() {
+        // Odd lists are repeated, and negative lengths clamped
+        let pattern = DashPattern::new([4., -1., 2.], 1.);
+        assert_eq!(pattern.lengths(), &[4., 0., 2., 4., 0., 2.]);
+        assert_eq!(pattern.period(), 12.);
+        assert_eq!(pattern.phase(), 1.);
+        let many = DashPattern::new((0..20).map(|i| i as f32), 0.);
+        assert_eq!(many.lengths().len(), MAX_DASH_LENGTHS);
+
+        // Rectangle pieces continue the pattern clockwise from the top-left corner
+        let pattern = DashPattern::new([4.], 0.);
+        let rect = Rect::new(0., 0., 100., 50.);
+        let brush = Brush::from(Color::WHITE);
+        let mut canvas = Canvas::default();
+        rect.stroke_dashed(&mut canvas, &brush, 4., StrokeAlignment::Outside, &pattern);
+        let dashes: Vec<_> = canvas
+            .buffer()
+            .iter()
+            .map(|prim| {
+                canvas.effect_tables().dashes
+                    [prim.effects(canvas.effect_tables()).dash.unwrap() as usize]
+            })
+            .collect();
+        // Top, bottom, left, right; the middle of the stroke is 2 units outside
+        let starts: Vec<_> = dashes.iter().map(|dash| dash.start).collect();
+        assert_eq!(starts, [0., 158., 262., 104.]);
+        assert_eq!(dashes[0].origin, Vec2::new(-2., -2.));
+        assert_eq!(dashes[0].direction, Vec2::X);
+        assert_eq!(dashes[1].origin, Vec2::new(102., 52.));
+        assert_eq!(dashes[2].direction, Vec2::NEG_Y);
+        assert_eq!(dashes[3].origin, Vec2::new(102., -2.));
+        assert_eq!(dashes[3].direction, Vec2::Y);
+        // The top piece is on the min.y edge of the Y-down canvas
+        assert_eq!(canvas.buffer()[0].aabb().min, Vec2::new(-4., -4.));
+        assert_eq!(canvas.buffer()[0].aabb().max, Vec2::new(104., 0.));
+        assert!(dashes.iter().all(|dash| dash.radius == 0.));
+
+        // Rounded corners are dashed along circles, clockwise
+        let mut canvas = Canvas::default();
+        RoundedRect::new(rect, 8.).stroke_dashed(
+            &mut canvas,
+            &brush,
+            4.,
+            StrokeAlignment::Center,
+            &pattern,
+        );
+        assert_eq!(canvas.buffer().len(), 8);
+        let Primitive::Arc(bottom_right) = canvas.buffer()[7] else {
+            panic!("Expected an arc for a rounded corner");
+        };
+        let tables = canvas.effect_tables();
+        let dash = tables.dashes[tables.effects(bottom_right.effects).dash.unwrap() as usize];
+        assert!((dash.start - (84. + 34. + 8. * FRAC_PI_2)).abs() < 1e-4);
+        assert_eq!(dash.origin, Vec2::new(92., 42.));
+        assert!(dash.direction.abs_diff_eq(Vec2::X, 1e-6));
+        assert_eq!(dash.radius, 8.);
+
+        // Decorations apply to all pieces of a dashed stroke
+        let mut canvas = Canvas::default();
+        RoundedRect::new(rect, 8.)
+            .stroke_dashed(&mut canvas, &brush, 4., StrokeAlignment::Center, &pattern)
+            .border(&brush, 1.)
+            .glow(&brush, 2.);
+        assert!(canvas
+            .buffer()
+            .iter()
+            .all(|prim| prim.is_bordered() && prim.effects(canvas.effect_tables()).glow.is_some()));
+
+        // Shapes without dash support fall back to a solid stroke
+        let mut canvas = Canvas::default();
+        Ellipse::circle(Vec2::ZERO, 10.).stroke_dashed(
+            &mut canvas,
+            &brush,
+            2.,
+            StrokeAlignment::Center,
+            &pattern,
+        );
+        assert!(canvas.buffer()[0]
+            .effects(canvas.effect_tables())
+            .dash
+            .is_none());
+    }
+
+    #[test]
+    fn polyline() {
+        let mut canvas = Canvas::default();
+        let brush = Brush::from(Color::WHITE);
+        let points = [
+            Vec2::ZERO,
+            Vec2::new(10., 0.),
+            Vec2::new(10., 0.),
+            Vec2::new(10., 20.),
+        ];
+        canvas
+            .render_context()
+            .polyline(points, &brush, 2.)
+            .join(LineJoin::Bevel)
+            .cap(LineCap::Round);
+
+        // A single primitive, without repeated points
+        assert_eq!(canvas.buffer().len(), 1);
+        let Primitive::Polyline(polyline) = canvas.buffer()[0] else {
+            panic!("Expected a polyline primitive");
+        };
+        assert_eq!(polyline.join, LineJoin::Bevel);
+        assert_eq!(polyline.cap, LineCap::Round);
+        let path = canvas.paths()[polyline.id as usize].flatten(FLATTENING_TOLERANCE);
+        assert_eq!(
+            path.points,
+            [Vec2::ZERO, Vec2::new(10., 0.), Vec2::new(10., 20.)]
+        );
+        assert_eq!(polyline.bounds, Rect::new(0., 0., 10., 20.));
+
+        // Miter joins may extend far from the points
+        let aabb = canvas.buffer()[0].aabb();
+        assert!(aabb.min.abs_diff_eq(Vec2::splat(-SQRT_2), 1e-5));
+        let miter = PolylinePrimitive {
+            join: LineJoin::Miter,
+            ..polyline
+        };
+        assert_eq!(miter.aabb().min, Vec2::splat(-MITER_LIMIT));
+    }
+
+    #[test]
+    fn line_cap() {
+        let mut canvas = Canvas::default();
+        let brush = Brush::from(Color::WHITE);
+        {
+            let mut ctx = canvas.render_context();
+            ctx.line(Vec2::ZERO, Vec2::new(10., 0.), &brush, 2.);
+            ctx.line(Vec2::ZERO, Vec2::new(10., 0.), &brush, 2.)
+                .cap(LineCap::Round);
+        }
+
+        let Primitive::Line(butt) = canvas.buffer()[0] else {
+            panic!("Expected a line primitive");
+        };
+        let Primitive::Line(round) = canvas.buffer()[1] else {
+            panic!("Expected a line primitive");
+        };
+        assert_eq!(butt.cap, LineCap::Butt);
+        assert_eq!(round.cap, LineCap::Round);
+
+        // Only non-butt caps extend past the endpoints
+        let aabb = butt.aabb();
+        assert_eq!(aabb.min, Vec2::new(0., -1.));
+        assert_eq!(aabb.max, Vec2::new(10., 1.));
+        let aabb = round.aabb();
+        assert_eq!(aabb.min, Vec2::new(-1., -1.));
+        assert_eq!(aabb.max, Vec2::new(11., 1.));
+
+        // A zero-length line draws a dot with non-butt caps
+        let dot = LinePrimitive {
+            end: round.start,
+            ..round
+        };
+        let aabb = dot.aabb();
+        assert_eq!(aabb.min, Vec2::new(-1., -1.));
+        assert_eq!(aabb.max, Vec2::new(1., 1.));
+        let aabb = LinePrimitive {
+            cap: LineCap::Square,
+            ..dot
+        }
+        .aabb();
+        assert_eq!(aabb.min, Vec2::new(-1., -1.));
+        assert_eq!(aabb.max, Vec2::new(1., 1.));
+        let aabb = LinePrimitive {
+            cap: LineCap::Butt,
+            ..dot
+        }
+        .aabb();
+        assert!(!aabb.min.is_nan() && !aabb.max.is_nan());
+        assert_eq!(aabb.min, aabb.max);
+    }
+
+    #[test]
+    fn capsule_from_rect() {
+        let capsule = Capsule::from_rect(Rect::new(0., 0., 40., 10.));
+        assert_eq!(capsule.center, Vec2::new(20., 5.));
+        assert_eq!(capsule.half_length, 15.);
+        assert_eq!(capsule.radius, 5.);
+        assert_eq!(capsule.rotation, Rot2::IDENTITY);
+
+        let capsule = Capsule::from_rect(Rect::new(0., 0., 10., 40.));
+        assert_eq!(capsule.center, Vec2::new(5., 20.));
+        assert_eq!(capsule.half_length, 15.);
+        assert_eq!(capsule.radius, 5.);
+        assert_eq!(capsule.rotation, Rot2::FRAC_PI_2);
+
+        let mut canvas = Canvas::default();
+        canvas
+            .render_context()
+            .fill(capsule, &Brush::from(Color::WHITE));
+        let aabb = canvas.buffer()[0].aabb();
+        assert!(aabb.min.abs_diff_eq(Vec2::ZERO, 1e-5));
+        assert!(aabb.max.abs_diff_eq(Vec2::new(10., 40.), 1e-5));
+    }
+
+    #[test]
+    fn placed_primitives() {
+        let mut canvas = Canvas::default();
+        let brush = Brush::from(Color::WHITE);
+        let iso = Isometry2d::new(Vec2::new(10., 20.), FRAC_PI_2);
+        {
+            let mut ctx = canvas.render_context();
+            ctx.fill(Capsule2d::new(2., 10.).at(iso), &brush);
+            ctx.fill(primitives::Ellipse::new(4., 3.).at(iso), &brush);
+            ctx.fill(Triangle2d::default().at(iso), &brush);
+            ctx.fill(Segment2d::new(Dir2::X, 12.).at(iso), &brush);
+            ctx.fill(primitives::RegularPolygon::new(5., 3).at(iso), &brush);
+        }
+        assert_eq!(canvas.buffer().len(), 5);
+
+        // Bevy capsules are vertical
+        let Primitive::Capsule(capsule) = canvas.buffer()[0] else {
+            panic!("Expected a capsule primitive");
+        };
+        assert_eq!(capsule.center, iso.translation);
+        assert_eq!(capsule.half_length, 5.);
+        assert_eq!(capsule.radius, 2.);
+        let aabb = canvas.buffer()[0].aabb();
+        assert!(aabb.min.abs_diff_eq(Vec2::new(3., 18.), 1e-5));
+        assert!(aabb.max.abs_diff_eq(Vec2::new(17., 22.), 1e-5));
+
+        // Ellipses are rotated with the isometry
+        let Primitive::Ellipse(ellipse) = canvas.buffer()[1] else {
+            panic!("Expected an ellipse primitive");
+        };
+        assert_eq!(ellipse.center, iso.translation);
+        assert_eq!(ellipse.radii, Vec2::new(4., 3.));
+        assert_eq!(ellipse.rotation, iso.rotation);
+        let aabb = canvas.buffer()[1].aabb();
+        assert!(aabb.min.abs_diff_eq(Vec2::new(7., 16.), 1e-5));
+        assert!(aabb.max.abs_diff_eq(Vec2::new(13., 24.), 1e-5));
+
+        let Primitive::Polygon(polygon) = canvas.buffer()[2] else {
+            panic!("Expected a polygon primitive");
+        };
+        let vertices = polygon.polygon.vertices();
+        assert_eq!(vertices.len(), 3);
+        assert!(vertices[0].abs_diff_eq(Vec2::new(9.5, 20.), 1e-5));
+
+        let Primitive::Line(line) = canvas.buffer()[3] else {
+            panic!("Expected a line primitive");
+        };
+        assert!(line.start.abs_diff_eq(Vec2::new(10., 14.), 1e-5));
+        assert!(line.end.abs_diff_eq(Vec2::new(10., 26.), 1e-5));
+
+        // Bevy regular polygons have a vertex toward +Y, while the canvas ones
+        // have it toward -Y
+        let Primitive::Star(star) = canvas.buffer()[4] else {
+            panic!("Expected a star primitive");
+        };
+        assert_eq!(star.points, 3);
+        let tip = star.rotation * Vec2::new(0., -star.outer_radius);
+        assert!(tip.abs_diff_eq(iso.rotation * Vec2::new(0., 5.), 1e-5));
+    }
+
+    #[test]
+    fn flatten_path() {
+        // Empty path
+        let path = Path::new().flatten(FLATTENING_TOLERANCE);
+        assert!(path.points.is_empty());
+        assert!(path.contours.is_empty());
+
+        // Lone move_to() are discarded
+        let path = Path::new()
+            .move_to(Vec2::ONE)
+            .move_to(Vec2::ZERO)
+            .line_to(Vec2::X)
+            .line_to(Vec2::ONE)
+            .close()
+            .flatten(FLATTENING_TOLERANCE);
+        assert_eq!(path.points, vec![Vec2::ZERO, Vec2::X, Vec2::ONE]);
+        assert_eq!(
+            path.contours,
+            vec![PathContour {
+                point_count: 3,
+                closed: true
+            }]
+        );
+
+        // Drawing after close() restarts from the first point of the contour
+        let path = Path::new()
+            .move_to(Vec2::ONE)
+            .line_to(Vec2::X)
+            .close()
+            .line_to(Vec2::Y)
+            .flatten(FLATTENING_TOLERANCE);
+        assert_eq!(path.points, vec![Vec2::ONE, Vec2::X, Vec2::ONE, Vec2::Y]);
+        assert_eq!(path.contours.len(), 2);
+        assert!(path.contours[0].closed);
+        assert!(!path.contours[1].closed);
+
+        // Curves are subdivided, and end exactly at their end point
+        let p0 = Vec2::new(0., 0.);
+        let c = Vec2::new(50., 100.);
+        let p1 = Vec2::new(100., 0.);
+        let path = Path::new()
+            .move_to(p0)
+            .quad_to(c, p1)
+            .flatten(FLATTENING_TOLERANCE);
+        assert!(path.points.len() > 3);
+        assert_eq!(*path.points.first().unwrap(), p0);
+        assert_eq!(*path.points.last().unwrap(), p1);
+        // The curve apex is at t=0.5
+        let apex = (p0 + 2. * c + p1) / 4.;
+        let bounds = Path::from_points(path.points).bounds();
+        assert_eq!(bounds.min, p0);
+        assert!(bounds.max.x == p1.x && (bounds.max.y - apex.y).abs() < FLATTENING_TOLERANCE);
+
+        let path = Path::new()
+            .move_to(p0)
+            .cubic_to(c, c, p1)
+            .flatten(FLATTENING_TOLERANCE);
+        assert!(path.points.len() > 3);
+        assert!(path.points.len() <= 1 + MAX_CURVE_SEGMENTS as usize);
+        assert_eq!(*path.points.last().unwrap(), p1);
+    }
+}